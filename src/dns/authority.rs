@@ -0,0 +1,648 @@
+use super::{
+    builders::DnsResponseBuilder, DnsParser, DomainName, PrimaryZone, QuestionClass, QuestionType,
+    Resource, ResourcePayload, ResourceType, ResponseCode, TransferServer, ZoneAnswer,
+};
+use crate::error::Error;
+
+/// CNAMEs and DNAMEs within a zone are followed at most this many times
+const MAXIMUM_CHAIN_LENGTH: usize = 16;
+/// The longest a domain name can be in wire format
+const MAXIMUM_NAME_LENGTH: usize = 255;
+
+/// The name an NS, MX or SRV record points at, its addresses go in the additional section
+pub fn target<'r, 'a>(record: &'r Resource<'a>) -> Option<&'r DomainName<'a>> {
+    match &record.payload {
+        ResourcePayload::NameServer(target)
+        | ResourcePayload::MailExchange {
+            exchange: target, ..
+        }
+        | ResourcePayload::Service { target, .. } => Some(target),
+        _ => None,
+    }
+}
+
+impl PrimaryZone {
+    /// Answers a question from the records of the zone, following CNAMEs and DNAMEs while they stay inside it and expanding wildcards
+    /// Names below a delegation get a referral to the child zone with any glue the zone holds
+    /// The question type is taken as its code, so that a type we have no name for gets NODATA like any other
+    pub fn answer(&self, name: &DomainName, question_type: u16) -> ZoneAnswer {
+        let mut answer = ZoneAnswer {
+            response_code: ResponseCode::NOERROR,
+            authoritative: true,
+            answers: Vec::new(),
+            authority: Vec::new(),
+            additional: Vec::new(),
+        };
+        let mut name = name.clone().into_owned();
+        for _ in 0..MAXIMUM_CHAIN_LENGTH {
+            // A CNAME that leaves the zone is followed by the resolver
+            if !name.is_subdomain_of(&self.zone) {
+                break;
+            }
+            if let Some(cut) = self.delegation(&name, question_type) {
+                // The CNAMEs leading to the referral are still ours
+                answer.authoritative = !answer.answers.is_empty();
+                answer.authority.extend(
+                    self.rrset(&cut, u16::from(ResourceType::NameServer))
+                        .chain(self.rrset(&cut, u16::from(ResourceType::DelegationSigner)))
+                        .cloned(),
+                );
+                break;
+            }
+            if let Some((redirection, target)) = self.redirection(&name) {
+                // RFC 6672 section 3.2, the DNAME is answered along with a CNAME for the name it redirects
+                if !answer.answers.contains(redirection) {
+                    answer.answers.push(redirection.clone());
+                }
+                let prefix = &name.labels()[..name.len() - redirection.resource_name.len()];
+                let redirected = DomainName::new(prefix.to_vec()).append(target);
+                if redirected.wire_length() > MAXIMUM_NAME_LENGTH {
+                    answer.response_code = ResponseCode::YXDOMAIN;
+                    break;
+                }
+                let looped = answer
+                    .answers
+                    .iter()
+                    .any(|answered| answered.resource_name.eq_ignore_case(&redirected));
+                answer.answers.push(Resource::new(
+                    name,
+                    redirection.resource_class,
+                    redirection.time_to_live,
+                    ResourcePayload::CanonicalName(redirected.clone()),
+                ));
+                if looped {
+                    break;
+                }
+                name = redirected;
+                continue;
+            }
+            let mut owned: Vec<Resource<'static>> = self
+                .records
+                .iter()
+                .filter(|record| record.resource_name.eq_ignore_case(&name))
+                .cloned()
+                .collect();
+            if owned.is_empty() {
+                // An empty non-terminal exists even though it owns no records, RFC 8020, so no wildcard matches it
+                let exists = self.exists(&name);
+                if !exists {
+                    owned = self.synthesize(&name);
+                }
+                if owned.is_empty() {
+                    if !exists {
+                        answer.response_code = ResponseCode::NXDOMAIN;
+                    }
+                    answer.authority.push(self.negative_start_authority());
+                    break;
+                }
+            }
+            let matching = owned.iter().filter(|record| {
+                question_type == u16::from(QuestionType::All)
+                    || record.payload.type_code() == question_type
+            });
+            let count = answer.answers.len();
+            answer.answers.extend(matching.cloned());
+            if answer.answers.len() > count {
+                break;
+            }
+            let alias = owned.iter().find_map(|record| match &record.payload {
+                ResourcePayload::CanonicalName(target) => Some((record, target)),
+                _ => None,
+            });
+            match alias {
+                Some((record, target)) => {
+                    // A target that has already been answered means the CNAMEs loop
+                    let looped = answer
+                        .answers
+                        .iter()
+                        .any(|answered| answered.resource_name.eq_ignore_case(target));
+                    answer.answers.push(record.clone());
+                    if looped {
+                        break;
+                    }
+                    name = target.clone();
+                }
+                None => {
+                    answer.authority.push(self.negative_start_authority());
+                    break;
+                }
+            }
+        }
+        self.add_additional(&mut answer);
+        answer
+    }
+
+    /// The highest zone cut between the apex and the name, the apex itself isn't a delegation
+    /// A DS question at a cut is answered by this zone as the DS records live on the parent side
+    fn delegation(&self, name: &DomainName, question_type: u16) -> Option<DomainName<'static>> {
+        let name_server = u16::from(ResourceType::NameServer);
+        (self.zone.len() + 1..=name.len())
+            .map(|length| DomainName::new(name.labels()[name.len() - length..].to_vec()))
+            .filter(|cut| {
+                !(cut.len() == name.len()
+                    && question_type == u16::from(QuestionType::DelegationSigner))
+            })
+            .find(|cut| self.rrset(cut, name_server).next().is_some())
+            .map(DomainName::into_owned)
+    }
+
+    /// The highest DNAME above the name along with its target, the owner of a DNAME isn't redirected itself
+    fn redirection(&self, name: &DomainName) -> Option<(&Resource<'static>, &DomainName<'static>)> {
+        (self.zone.len()..name.len())
+            .map(|length| DomainName::new(name.labels()[name.len() - length..].to_vec()))
+            .find_map(|ancestor| {
+                self.records
+                    .iter()
+                    .find_map(|record| match &record.payload {
+                        ResourcePayload::DelegationName(target)
+                            if record.resource_name.eq_ignore_case(&ancestor) =>
+                        {
+                            Some((record, target))
+                        }
+                        _ => None,
+                    })
+            })
+    }
+
+    /// Whether the name owns records or has names below it
+    fn exists(&self, name: &DomainName) -> bool {
+        self.records
+            .iter()
+            .any(|record| record.resource_name.is_subdomain_of(name))
+    }
+
+    /// The records of the wildcard at the closest encloser of a name that doesn't exist, owned by the name, RFC 4592 section 3.3.1
+    fn synthesize(&self, name: &DomainName) -> Vec<Resource<'static>> {
+        let closest_encloser = (self.zone.len()..name.len())
+            .rev()
+            .map(|length| DomainName::new(name.labels()[name.len() - length..].to_vec()))
+            .find(|ancestor| self.exists(ancestor));
+        let source = match closest_encloser {
+            Some(closest_encloser) => DomainName::new(vec!["*"]).append(&closest_encloser),
+            None => return Vec::new(),
+        };
+        self.records
+            .iter()
+            .filter(|record| record.resource_name.eq_ignore_case(&source))
+            .map(|record| {
+                let mut record = record.clone();
+                record.resource_name = name.clone().into_owned();
+                record
+            })
+            .collect()
+    }
+
+    fn rrset<'z>(
+        &'z self,
+        name: &'z DomainName,
+        type_code: u16,
+    ) -> impl Iterator<Item = &'z Resource<'static>> {
+        self.records.iter().filter(move |record| {
+            record.resource_name.eq_ignore_case(name) && record.payload.type_code() == type_code
+        })
+    }
+
+    /// The SOA put in the authority section of negative answers, its TTL is capped by the minimum field, RFC 2308 section 3
+    fn negative_start_authority(&self) -> Resource<'static> {
+        let mut start_authority = self.start_authority().clone();
+        if let ResourcePayload::StartAuthority { minimum, .. } = start_authority.payload {
+            start_authority.time_to_live = start_authority.time_to_live.min(minimum);
+        }
+        start_authority
+    }
+
+    /// Adds the addresses of the targets of NS, MX and SRV records in the zone, RFC 1034 section 3.6.2
+    fn add_additional(&self, answer: &mut ZoneAnswer) {
+        let address_types = [
+            u16::from(ResourceType::Address),
+            u16::from(ResourceType::Ipv6Address),
+        ];
+        let mut additional: Vec<Resource<'static>> = Vec::new();
+        for target in answer
+            .answers
+            .iter()
+            .chain(&answer.authority)
+            .filter_map(target)
+        {
+            if !target.is_subdomain_of(&self.zone) {
+                continue;
+            }
+            let addresses = address_types
+                .iter()
+                .flat_map(|type_code| self.rrset(target, *type_code))
+                .filter(|record| !answer.answers.contains(record) && !additional.contains(record))
+                .cloned()
+                .collect::<Vec<_>>();
+            additional.extend(addresses);
+        }
+        answer.additional = additional;
+    }
+}
+
+impl TransferServer {
+    /// The zone holding the name, the most specific one when zones are nested
+    pub fn zone_for(&self, name: &DomainName) -> Option<&PrimaryZone> {
+        self.zones
+            .iter()
+            .filter(|primary| name.is_subdomain_of(&primary.zone))
+            .max_by_key(|primary| primary.zone.len())
+    }
+
+    /// Answers a query from the zones with the AA bit set, names outside every zone are REFUSED
+    pub fn answer_query(&self, request: &[u8]) -> Result<Vec<u8>, Error> {
+        let packet = DnsParser::new().parse_packet(request)?;
+        let mut builder = DnsResponseBuilder::new(packet.header.id);
+        builder.recursion_desired(packet.header.recursion_desired);
+        let question = match packet.questions.as_slice() {
+            [question] => question,
+            _ => {
+                return builder
+                    .response_code(ResponseCode::FORMERR)
+                    .build_response()
+            }
+        };
+        builder.add_question(question.clone());
+        let zone = self.zone_for(&question.domain_name).filter(|_| {
+            matches!(
+                question.question_class,
+                QuestionClass::Internet | QuestionClass::Any
+            )
+        });
+        let zone = match zone {
+            Some(zone) => zone,
+            None => {
+                return builder
+                    .response_code(ResponseCode::REFUSED)
+                    .build_response()
+            }
+        };
+        let answer = zone.answer(&question.domain_name, question.question_type.code());
+        builder
+            .authoritative(answer.authoritative)
+            .response_code(answer.response_code);
+        for record in answer.answers {
+            builder.add_answer(record);
+        }
+        for record in answer.authority {
+            builder.add_authority(record);
+        }
+        for record in answer.additional {
+            builder.add_additional(record);
+        }
+        builder.build_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{
+        builders::PacketWriter, tests::name, zone::ZoneParser, Header, Question, ResourceClass,
+    };
+
+    const ZONE: &str = "
+$ORIGIN example.com.
+$TTL 3600
+@           SOA     ns1 hostmaster 2024010101 7200 1800 1209600 300
+@           NS      ns1
+@           NS      ns2.example.net.
+@           MX      10 mail
+ns1         A       192.0.2.1
+mail        A       192.0.2.2
+mail        AAAA    2001:db8::2
+www         A       192.0.2.10
+alias       CNAME   www
+chain       CNAME   alias
+outside     CNAME   www.example.net.
+loop1       CNAME   loop2
+loop2       CNAME   loop1
+a.b.c       TXT     \"deep\"
+_sip._udp   SRV     0 5 5060 mail
+child       NS      ns.child
+child       DS      12345 13 2 0123456789ABCDEF
+ns.child    A       192.0.2.53
+";
+
+    fn zone() -> PrimaryZone {
+        let records = ZoneParser::new(None).parse_str(ZONE, "test.zone").unwrap();
+        PrimaryZone::new(name("example.com"), records).unwrap()
+    }
+
+    fn owners(records: &[Resource<'static>]) -> Vec<String> {
+        records
+            .iter()
+            .map(|record| {
+                format!(
+                    "{} {}",
+                    record.resource_name,
+                    record.payload.resource_type().mnemonic()
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_zone_answers() {
+        let zone = zone();
+
+        let answer = zone.answer(&name("www.example.com"), QuestionType::Address.code());
+        assert_eq!(answer.response_code, ResponseCode::NOERROR);
+        assert!(answer.authoritative);
+        assert_eq!(owners(&answer.answers), ["www.example.com. A"]);
+        assert!(answer.authority.is_empty());
+
+        // The apex answers for itself and MX targets come with their addresses
+        let answer = zone.answer(&name("example.com"), QuestionType::MailExchange.code());
+        assert_eq!(owners(&answer.answers), ["example.com. MX"]);
+        assert_eq!(
+            owners(&answer.additional),
+            ["mail.example.com. A", "mail.example.com. AAAA"]
+        );
+        let answer = zone.answer(&name("example.com"), QuestionType::NameServer.code());
+        assert_eq!(owners(&answer.additional), ["ns1.example.com. A"]);
+        let answer = zone.answer(&name("_sip._udp.example.com"), QuestionType::Service.code());
+        assert_eq!(answer.additional.len(), 2);
+
+        let answer = zone.answer(&name("chain.example.com"), QuestionType::Address.code());
+        assert_eq!(
+            owners(&answer.answers),
+            [
+                "chain.example.com. CNAME",
+                "alias.example.com. CNAME",
+                "www.example.com. A"
+            ]
+        );
+        let answer = zone.answer(
+            &name("alias.example.com"),
+            QuestionType::CanonicalName.code(),
+        );
+        assert_eq!(owners(&answer.answers), ["alias.example.com. CNAME"]);
+        let answer = zone.answer(&name("outside.example.com"), QuestionType::Address.code());
+        assert_eq!(owners(&answer.answers), ["outside.example.com. CNAME"]);
+        assert_eq!(answer.response_code, ResponseCode::NOERROR);
+        let answer = zone.answer(&name("loop1.example.com"), QuestionType::Address.code());
+        assert_eq!(answer.answers.len(), 2);
+
+        // NODATA and NXDOMAIN both carry the SOA, its TTL capped by the minimum
+        let answer = zone.answer(&name("www.example.com"), QuestionType::Ipv6Address.code());
+        assert_eq!(answer.response_code, ResponseCode::NOERROR);
+        assert!(answer.answers.is_empty());
+        assert_eq!(owners(&answer.authority), ["example.com. SOA"]);
+        assert_eq!(answer.authority[0].time_to_live, 300);
+        let answer = zone.answer(&name("www.example.com"), 99);
+        assert_eq!(answer.response_code, ResponseCode::NOERROR);
+        assert!(answer.answers.is_empty());
+        assert_eq!(owners(&answer.authority), ["example.com. SOA"]);
+        let answer = zone.answer(&name("b.c.example.com"), QuestionType::Address.code());
+        assert_eq!(answer.response_code, ResponseCode::NOERROR);
+        let answer = zone.answer(&name("missing.example.com"), QuestionType::Address.code());
+        assert_eq!(answer.response_code, ResponseCode::NXDOMAIN);
+        assert!(answer.authoritative);
+        assert_eq!(owners(&answer.authority), ["example.com. SOA"]);
+
+        // Below a delegation the answer is a referral with glue, except for DS at the cut
+        let answer = zone.answer(&name("www.child.example.com"), QuestionType::Address.code());
+        assert_eq!(answer.response_code, ResponseCode::NOERROR);
+        assert!(!answer.authoritative);
+        assert!(answer.answers.is_empty());
+        assert_eq!(
+            owners(&answer.authority),
+            ["child.example.com. NS", "child.example.com. DS"]
+        );
+        assert_eq!(owners(&answer.additional), ["ns.child.example.com. A"]);
+        let answer = zone.answer(
+            &name("child.example.com"),
+            QuestionType::DelegationSigner.code(),
+        );
+        assert!(answer.authoritative);
+        assert_eq!(owners(&answer.answers), ["child.example.com. DS"]);
+    }
+
+    #[test]
+    fn test_answer_query() {
+        let mut server = TransferServer::new();
+        server.add_zone(zone());
+        let query = |domain: &str| {
+            let mut header = Header::new();
+            header.id = 0x0101;
+            header.recursion_desired = true;
+            header.question_count = 1;
+            let mut request = Vec::new();
+            header.write_header(&mut request).unwrap();
+            let question = Question {
+                domain_name: name(domain),
+                question_type: QuestionType::Address,
+                question_class: QuestionClass::Internet,
+            };
+            let mut writer = PacketWriter::with_data(request);
+            writer.write_question(&question).unwrap();
+            let response = server.answer_query(&writer.into_inner()).unwrap();
+            let packet = DnsParser::new().parse_packet(&response).unwrap();
+            assert_eq!(packet.header.id, 0x0101);
+            assert!(packet.header.recursion_desired);
+            (
+                packet.header.response_code,
+                packet.header.authorative,
+                packet.answers.len(),
+            )
+        };
+        assert_eq!(query("alias.example.com"), (ResponseCode::NOERROR, true, 2));
+        assert_eq!(query("nope.example.com"), (ResponseCode::NXDOMAIN, true, 0));
+        assert_eq!(
+            query("www.child.example.com"),
+            (ResponseCode::NOERROR, false, 0)
+        );
+        assert_eq!(query("www.example.org"), (ResponseCode::REFUSED, false, 0));
+    }
+
+    #[test]
+    fn test_wildcards() {
+        let text = "
+$ORIGIN example.internal.
+$TTL 60
+@               SOA     ns hostmaster 1 7200 1800 1209600 60
+@               NS      ns
+ns              A       192.0.2.1
+*.preview       A       192.0.2.80
+*.preview       TXT     \"ingress\"
+app.preview     A       192.0.2.81
+*.alias         CNAME   app.preview
+host.empty      A       192.0.2.2
+";
+        let records = ZoneParser::new(None).parse_str(text, "test.zone").unwrap();
+        let mut zone = PrimaryZone::new(name("example.internal"), records).unwrap();
+        let addresses = |zone: &PrimaryZone, owner: &str| {
+            let answer = zone.answer(&name(owner), QuestionType::Address.code());
+            let answers = answer
+                .answers
+                .iter()
+                .map(|record| format!("{} {}", record.resource_name, record.payload))
+                .collect::<Vec<_>>();
+            (answer.response_code, answers)
+        };
+
+        // The owner of a synthesized record is the query name
+        assert_eq!(
+            addresses(&zone, "pr-42.preview.example.internal"),
+            (
+                ResponseCode::NOERROR,
+                vec![String::from("pr-42.preview.example.internal. 192.0.2.80")]
+            )
+        );
+        assert_eq!(
+            addresses(&zone, "a.b.preview.example.internal").1,
+            ["a.b.preview.example.internal. 192.0.2.80"]
+        );
+        assert_eq!(
+            addresses(&zone, "*.preview.example.internal").1,
+            ["*.preview.example.internal. 192.0.2.80"]
+        );
+        assert_eq!(
+            addresses(&zone, "app.preview.example.internal").1,
+            ["app.preview.example.internal. 192.0.2.81"]
+        );
+        let answer = zone.answer(
+            &name("pr-42.preview.example.internal"),
+            QuestionType::Ipv6Address.code(),
+        );
+        assert_eq!(answer.response_code, ResponseCode::NOERROR);
+        assert!(answer.answers.is_empty());
+        assert_eq!(answer.authority.len(), 1);
+        assert_eq!(
+            addresses(&zone, "x.alias.example.internal").1,
+            [
+                "x.alias.example.internal. app.preview.example.internal.",
+                "app.preview.example.internal. 192.0.2.81"
+            ]
+        );
+
+        // The closest encloser of these names has no wildcard
+        assert_eq!(
+            addresses(&zone, "x.app.preview.example.internal").0,
+            ResponseCode::NXDOMAIN
+        );
+        assert_eq!(
+            addresses(&zone, "other.example.internal").0,
+            ResponseCode::NXDOMAIN
+        );
+
+        // A wildcard added in code works the same way, but never matches the empty non-terminal
+        let mut records = zone.records().to_vec();
+        for record in &mut records {
+            if let ResourcePayload::StartAuthority { serial, .. } = &mut record.payload {
+                *serial += 1;
+            }
+        }
+        records.push(Resource::new(
+            DomainName::new(vec!["*", "example", "internal"]),
+            ResourceClass::Internet,
+            60,
+            ResourcePayload::Address("192.0.2.99".parse().unwrap()),
+        ));
+        zone.update(records).unwrap();
+        assert_eq!(
+            addresses(&zone, "other.example.internal").1,
+            ["other.example.internal. 192.0.2.99"]
+        );
+        assert_eq!(
+            addresses(&zone, "empty.example.internal"),
+            (ResponseCode::NOERROR, Vec::new())
+        );
+        assert_eq!(
+            addresses(&zone, "x.empty.example.internal").0,
+            ResponseCode::NXDOMAIN
+        );
+    }
+
+    #[test]
+    fn test_delegation_names() {
+        let text = "
+$ORIGIN example.com.
+$TTL 3600
+@           SOA     ns1 hostmaster 1 7200 1800 1209600 300
+@           NS      ns1
+ns1         A       192.0.2.1
+old         DNAME   example.net.
+legacy      DNAME   current
+host.current A      192.0.2.20
+loop-a      DNAME   loop-b
+loop-b      DNAME   loop-a
+long        DNAME   aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa.bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb.cccccccccccccccccccccccccccccccccccccccccccccccccc.example.net.
+";
+        let records = ZoneParser::new(None).parse_str(text, "test.zone").unwrap();
+        let zone = PrimaryZone::new(name("example.com"), records).unwrap();
+        let answers = |owner: &str, question_type: QuestionType| {
+            let answer = zone.answer(&name(owner), question_type.code());
+            let answers = answer
+                .answers
+                .iter()
+                .map(|record| format!("{} {}", record.resource_name, record.payload))
+                .collect::<Vec<_>>();
+            (answer.response_code, answers)
+        };
+
+        // The DNAME is answered with a CNAME synthesized for the query name, which is followed inside the zone
+        assert_eq!(
+            answers("www.old.example.com", QuestionType::Address),
+            (
+                ResponseCode::NOERROR,
+                vec![
+                    String::from("old.example.com. example.net."),
+                    String::from("www.old.example.com. www.example.net.")
+                ]
+            )
+        );
+        assert_eq!(
+            answers("host.legacy.example.com", QuestionType::Address).1,
+            [
+                "legacy.example.com. current.example.com.",
+                "host.legacy.example.com. host.current.example.com.",
+                "host.current.example.com. 192.0.2.20"
+            ]
+        );
+        let answer = zone.answer(&name("www.old.example.com"), QuestionType::Address.code());
+        assert!(answer.authoritative);
+        assert_eq!(answer.answers[1].time_to_live, 3600);
+
+        // The owner itself isn't redirected
+        assert_eq!(
+            answers("old.example.com", QuestionType::DelegationName).1,
+            ["old.example.com. example.net."]
+        );
+        assert_eq!(
+            answers("old.example.com", QuestionType::Address),
+            (ResponseCode::NOERROR, Vec::new())
+        );
+
+        // Redirections that loop stop once a name repeats
+        let (response_code, looped) = answers("x.loop-a.example.com", QuestionType::Address);
+        assert_eq!(response_code, ResponseCode::NOERROR);
+        assert_eq!(looped.len(), 4);
+
+        let (response_code, too_long) =
+            answers("xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx.yyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyy.long.example.com", QuestionType::Address);
+        assert_eq!(response_code, ResponseCode::YXDOMAIN);
+        assert_eq!(too_long.len(), 1);
+
+        // DNAME goes over the wire without compressing its target
+        let mut builder = DnsResponseBuilder::new(1);
+        builder.add_answer(
+            zone.answer(
+                &name("old.example.com"),
+                QuestionType::DelegationName.code(),
+            )
+            .answers[0]
+                .clone(),
+        );
+        let response = builder.build_response().unwrap();
+        let packet = DnsParser::new().parse_packet(&response).unwrap();
+        assert_eq!(
+            packet.answers[0].payload,
+            ResourcePayload::DelegationName(name("example.net"))
+        );
+        assert_eq!(
+            packet.answers[0].to_string(),
+            "old.example.com.\t3600\tIN\tDNAME\texample.net."
+        );
+    }
+}
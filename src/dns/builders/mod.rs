@@ -1,12 +1,17 @@
 use crate::error::{Error, ErrorKind};
 use byteorder::{NativeEndian, NetworkEndian, WriteBytesExt};
 use std::{
+    borrow::Cow,
     fmt::Display,
     io::{Cursor, Seek, SeekFrom, Write},
 };
 
-use super::{DnsPacket, DomainName, Question, QuestionClass, QuestionType};
+use super::{
+    spoofing, DnsPacket, DomainName, ExtendedError, Header, Question, QuestionClass, QuestionType,
+    Resource, TsigSession,
+};
 
+mod packet_writer;
 mod query_builder;
 mod question_builder;
 mod response_builder;
@@ -22,6 +27,25 @@ pub struct DnsQueryBuilder<'a> {
     // Create writers here, ie return a header creator
 }
 
+/// Responsible for building a DnsPacket that answers a query
+pub struct DnsResponseBuilder<'a> {
+    header: Header,
+    questions: Vec<Question<'a>>,
+    answers: Vec<Resource<'a>>,
+    authority: Vec<Resource<'a>>,
+    additional: Vec<Resource<'a>>,
+    // Written in an OPT record at the end of the additional section
+    extended_error: Option<ExtendedError>,
+}
+
+/// Writes questions and resources in wire format, remembering where each name was written so later names can point to it
+pub struct PacketWriter<'a> {
+    packet_data: Vec<u8>,
+    previous_names: Vec<DomainNameBuilder<'a>>,
+    // Canonical writers never compress and write names in lower case, see RFC 4034 section 6.2
+    canonical: bool,
+}
+
 pub struct QuestionBuilder<'a> {
     // Basically a question but stores the offset of said question and can calculate the offset of a given label relative to the start of the question
     domain_name: DomainNameBuilder<'a>,
@@ -33,7 +57,7 @@ pub struct QuestionBuilder<'a> {
 #[derive(Debug, PartialEq, Clone)]
 pub enum DomainNamePointer<'a> {
     Pointer(usize),
-    LabelsThenPointer(&'a [&'a str], usize),
+    LabelsThenPointer(&'a [Cow<'a, str>], usize),
 }
 
 pub struct DomainNameBuilder<'a> {
@@ -66,7 +90,7 @@ impl<'a> DomainNameBuilder<'a> {
         let res = labels
             .iter()
             .zip(lengths)
-            .map(|(label, length)| (*label, length))
+            .map(|(label, length)| (label.as_ref(), length))
             .collect();
         DomainNameBuilder {
             labels: res,
@@ -113,22 +137,19 @@ impl<'a> DnsQueryBuilder<'a> {
         }
     }
 
+    /// Without an ID a random one is written, RFC 5452 section 9.2
     pub fn write_id(&mut self, id: Option<u16>) -> Result<&mut Self, Error> {
         let mut writer = Cursor::new(&mut self.packet_data[..]);
         writer
             .seek(SeekFrom::Start(0))
             .map_err(|err| Error::new(ErrorKind::ReadPacketDataFailed))?;
-        match id {
-            Some(id) => writer
-                .write_u16::<NetworkEndian>(id)
-                .map_err(|err| Error::new(ErrorKind::WritePacketDataFailed))?,
-            None => {
-                // TODO: generate id
-                writer
-                    .write_u16::<NetworkEndian>(12)
-                    .map_err(|err| Error::new(ErrorKind::WritePacketDataFailed))?;
-            }
-        }
+        let id = match id {
+            Some(id) => id,
+            None => spoofing::random_id()?,
+        };
+        writer
+            .write_u16::<NetworkEndian>(id)
+            .map_err(|err| Error::new(ErrorKind::WritePacketDataFailed))?;
         Ok(self)
     }
 
@@ -230,43 +251,38 @@ impl<'a> DnsQueryBuilder<'a> {
         &mut self,
         domain_name: &'a str,
         question_type: QuestionType,
-    ) -> Result<(), Error> {
-        // TODO: We need to pool all the domain names together before writing to the packet so we can support compression
-        // TODO: We also need to know how many questions there are before we write the header
-        // The class must be IN for internet I guess
-        // Type determines the type of questions being asked
-        // We dont need to parse much we can just ensure it uses valid characters and split into labels
-        let labels: Vec<&'a str> = domain_name.split('.').collect();
-        // We need to write the question once the packet is built so we can perform compression.
-        // let mut cursor = Cursor::new(&mut self.packet_data[self.packet_end..]);
-        // for label in labels.iter() {
-        //     cursor.write_u8(label.len() as u8).map_err(|err|  Error::new(ErrorKind::WritePacketDataFailed))?;
-        //     cursor.write_all(label.as_bytes()).map_err(|err|  Error::new(ErrorKind::WritePacketDataFailed))?;
-        // }
-        // // Write a zero length label to end the domain name
-        // cursor.write_u8(0).map_err(|err| Error::new(ErrorKind::WritePacketDataFailed))?;
-        // cursor.write_u16::<NetworkEndian>(u16::from(question_type))
-        //     .map_err(|err| Error::new(ErrorKind::WritePacketDataFailed))?;
-        // cursor.write_u16::<NetworkEndian>(u16::from(QuestionClass::Internet))
-        //     .map_err(|err| Error::new(ErrorKind::WritePacketDataFailed))?;
-        // total length = domain_name length + 5
-
-        Ok(())
-    }
-
-    /// Adds a question to the packet that requests the address of the given domain name
-
-    pub fn request_address(&mut self, domain_name: &'a str) -> Result<&mut Self, Error> {
+    ) -> Result<&mut Self, Error> {
+        // Questions are written once the packet is built so that compression can be performed across all of them
         let labels: Vec<&'a str> = domain_name.split('.').collect();
         let question = Question {
             domain_name: DomainName::new(labels),
             question_class: QuestionClass::Internet,
-            question_type: QuestionType::Address,
+            question_type,
         };
         self.current_questions.push(question);
         Ok(self)
     }
 
+    /// Adds a question to the packet that requests the address of the given domain name
+    pub fn request_address(&mut self, domain_name: &'a str) -> Result<&mut Self, Error> {
+        self.add_question(domain_name, QuestionType::Address)
+    }
+
+    /// Adds a question requesting the SRV records of a service name, ie _sip._udp.example.com
+    pub fn request_service(&mut self, service_name: &'a str) -> Result<&mut Self, Error> {
+        self.add_question(service_name, QuestionType::Service)
+    }
+
+    /// Adds a question requesting the NAPTR records of the given domain name
+    pub fn request_naming_authority(&mut self, domain_name: &'a str) -> Result<&mut Self, Error> {
+        self.add_question(domain_name, QuestionType::NamingAuthorityPointer)
+    }
+
+    /// Adds a question requesting the URI records of a service name, ie _ftp._tcp.example.com
+    pub fn request_uri(&mut self, service_name: &'a str) -> Result<&mut Self, Error> {
+        self.add_question(service_name, QuestionType::UniformResourceIdentifier)
+    }
+
     pub fn build_query(&mut self) -> Result<[u8; 512], Error> {
         // TODO: Ideally when creating these packets we write to an a single section of memory that is just reused as packets are sent
         // TODO: Ie a queue like data structure, where when we free a packet we dont unallocate memory we just mark it as free, does Vec do this?
@@ -353,17 +369,27 @@ impl<'a> DnsQueryBuilder<'a> {
                 .write_u16::<NetworkEndian>(question_class)
                 .map_err(|err| Error::new(ErrorKind::WritePacketDataFailed))?;
         }
+        self.packet_end = writer.position() as usize;
 
         Ok(self.packet_data)
     }
 
-    fn write_labels(
-        labels: &[&str],
+    /// Builds the query and appends a TSIG record signed with the session key
+    pub fn build_signed_query(&mut self, session: &mut TsigSession) -> Result<Vec<u8>, Error> {
+        self.build_query()?;
+        let mut query = self.packet_data[..self.packet_end].to_vec();
+        session.sign(&mut query)?;
+        Ok(query)
+    }
+
+    fn write_labels<L: AsRef<str>>(
+        labels: &[L],
         writer: &mut Cursor<&mut [u8]>,
         pointer_to_write: Option<u16>,
     ) -> Result<(), Error> {
         // TODO: Needs to support writing a set of labels that ends with a pointer as well as just a label?
         for label in labels {
+            let label = label.as_ref();
             let label_length = label.len() as u8;
             writer
                 .write_u8(label_length)
@@ -416,6 +442,27 @@ mod test_builders {
         println!("Packet: {}", packet);
     }
 
+    #[test]
+    fn test_request_service() {
+        let mut query_builder = DnsQueryBuilder::new();
+        let res = query_builder
+            .request_service("_sip._udp.example.com")
+            .unwrap()
+            .request_uri("_ftp._tcp.example.com")
+            .unwrap()
+            .build_query()
+            .unwrap();
+        let mut parser = DnsParser::new();
+        let packet = parser.parse_packet(&res[..]).unwrap();
+        let mut question_types: Vec<u16> = packet
+            .questions
+            .iter()
+            .map(|question| u16::from(question.question_type))
+            .collect();
+        question_types.sort_unstable();
+        assert_eq!(question_types, vec![33, 256]);
+    }
+
     #[test]
     fn test_set_bit_position() {
         let mut query_builder = DnsQueryBuilder::new();
@@ -466,4 +513,17 @@ mod test_builders {
         // No bits should be set
         assert_eq!(read_bits, 0b0000000000000000); // wrong byte order, we read this as network endian so the first byte is the least significant
     }
+
+    #[test]
+    fn test_random_id() {
+        // Eight random IDs are all the same only by a very unlikely chance
+        let ids = (0..8)
+            .map(|_| {
+                let mut query_builder = DnsQueryBuilder::new();
+                query_builder.write_id(None).unwrap();
+                u16::from_be_bytes([query_builder.packet_data[0], query_builder.packet_data[1]])
+            })
+            .collect::<std::collections::HashSet<_>>();
+        assert!(ids.len() > 1);
+    }
 }
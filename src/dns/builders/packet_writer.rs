@@ -0,0 +1,446 @@
+use super::{DomainNameBuilder, DomainNamePointer, PacketWriter};
+use crate::dns::{
+    edns::{OPTIONS_TYPE, PAYLOAD_SIZE},
+    DomainName, Question, Resource, ResourcePayload,
+};
+use crate::error::{Error, ErrorKind};
+use byteorder::{NetworkEndian, WriteBytesExt};
+use std::io::Write;
+
+// Compression pointers only have 14 bits for the offset
+const MAXIMUM_POINTER: usize = 0x3fff;
+
+impl<'a> PacketWriter<'a> {
+    pub fn new() -> PacketWriter<'a> {
+        PacketWriter {
+            packet_data: Vec::with_capacity(512),
+            previous_names: Vec::new(),
+            canonical: false,
+        }
+    }
+
+    /// Writes records in the canonical form used when signing, names are never compressed and are written in lower case
+    pub fn canonical() -> PacketWriter<'a> {
+        PacketWriter {
+            packet_data: Vec::new(),
+            previous_names: Vec::new(),
+            canonical: true,
+        }
+    }
+
+    /// Continues writing after data that has already been written, ie a header
+    pub fn with_data(packet_data: Vec<u8>) -> PacketWriter<'a> {
+        PacketWriter {
+            packet_data,
+            previous_names: Vec::new(),
+            canonical: false,
+        }
+    }
+
+    pub fn position(&self) -> usize {
+        self.packet_data.len()
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.packet_data
+    }
+
+    pub fn write_u8(&mut self, value: u8) -> Result<(), Error> {
+        self.packet_data
+            .write_u8(value)
+            .map_err(|_| Error::new(ErrorKind::WritePacketDataFailed))
+    }
+
+    pub fn write_u16(&mut self, value: u16) -> Result<(), Error> {
+        self.packet_data
+            .write_u16::<NetworkEndian>(value)
+            .map_err(|_| Error::new(ErrorKind::WritePacketDataFailed))
+    }
+
+    pub fn write_u32(&mut self, value: u32) -> Result<(), Error> {
+        self.packet_data
+            .write_u32::<NetworkEndian>(value)
+            .map_err(|_| Error::new(ErrorKind::WritePacketDataFailed))
+    }
+
+    pub fn write_bytes(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.packet_data
+            .write_all(data)
+            .map_err(|_| Error::new(ErrorKind::WritePacketDataFailed))
+    }
+
+    /// Writes a length prefixed character string, these are limited to 255 bytes
+    pub fn write_character_string(&mut self, string: &[u8]) -> Result<(), Error> {
+        if string.len() > 255 {
+            return Err(Error::new(ErrorKind::WritePacketDataFailed));
+        }
+        self.write_u8(string.len() as u8)?;
+        self.write_bytes(string)
+    }
+
+    /// Overwrites a u16 that was written earlier, used to fill in lengths once they are known
+    pub fn set_u16(&mut self, position: usize, value: u16) {
+        self.packet_data[position..position + 2].copy_from_slice(&value.to_be_bytes());
+    }
+
+    /// Writes a domain name, when compress is true the name can be replaced with a pointer to a name written earlier
+    pub fn write_name(&mut self, name: &'a DomainName<'a>, compress: bool) -> Result<(), Error> {
+        if name.is_root() {
+            return self.write_u8(0);
+        }
+        if compress && !self.canonical {
+            match name.has_suitable_pointer(&self.previous_names) {
+                Some(DomainNamePointer::Pointer(pointer)) if pointer <= MAXIMUM_POINTER => {
+                    return self.write_u16(pointer as u16 | 0b1100000000000000);
+                }
+                Some(DomainNamePointer::LabelsThenPointer(labels, pointer))
+                    if pointer <= MAXIMUM_POINTER =>
+                {
+                    self.write_labels(labels, false)?;
+                    return self.write_u16(pointer as u16 | 0b1100000000000000);
+                }
+                _ => (),
+            }
+        }
+        // Names written in full can be pointed to by later names even if they were not compressed themselves
+        let position = self.position();
+        self.write_labels(name.labels(), self.canonical)?;
+        self.write_u8(0)?;
+        if position <= MAXIMUM_POINTER {
+            self.previous_names
+                .push(DomainNameBuilder::new(name, position));
+        }
+        Ok(())
+    }
+
+    /// Writes a name in full keeping its case, used for the names RFC 6840 excludes from the canonical form
+    fn write_exact_name(&mut self, name: &'a DomainName<'a>) -> Result<(), Error> {
+        if !self.canonical {
+            return self.write_name(name, false);
+        }
+        self.write_labels(name.labels(), false)?;
+        self.write_u8(0)
+    }
+
+    fn write_labels<L: AsRef<str>>(&mut self, labels: &[L], lower_case: bool) -> Result<(), Error> {
+        for label in labels {
+            let label = label.as_ref();
+            if label.is_empty() || label.len() > 63 {
+                return Err(Error::new(ErrorKind::InvalidLabel));
+            }
+            self.write_u8(label.len() as u8)?;
+            if lower_case {
+                self.write_bytes(label.to_ascii_lowercase().as_bytes())?;
+            } else {
+                self.write_bytes(label.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_question(&mut self, question: &'a Question<'a>) -> Result<(), Error> {
+        self.write_name(&question.domain_name, true)?;
+        self.write_u16(u16::from(question.question_type))?;
+        self.write_u16(u16::from(question.question_class))
+    }
+
+    pub fn write_resource(&mut self, resource: &'a Resource<'a>) -> Result<(), Error> {
+        self.write_name(&resource.resource_name, true)?;
+        self.write_u16(resource.payload.type_code())?;
+        self.write_u16(u16::from(resource.resource_class))?;
+        self.write_u32(resource.time_to_live)?;
+        // The length of the payload is only known once it has been written
+        let length_position = self.position();
+        self.write_u16(0)?;
+        self.write_payload(&resource.payload)?;
+        let payload_length = self.position() - length_position - 2;
+        if payload_length > u16::MAX as usize {
+            return Err(Error::new(ErrorKind::WritePacketDataFailed));
+        }
+        self.set_u16(length_position, payload_length as u16);
+        Ok(())
+    }
+
+    /// Writes an RFC 6891 OPT record holding the given options, the extended response code and flags are left as zero
+    pub fn write_options(&mut self, options: &[u8]) -> Result<(), Error> {
+        if options.len() > u16::MAX as usize {
+            return Err(Error::new(ErrorKind::WritePacketDataFailed));
+        }
+        self.write_u8(0)?;
+        self.write_u16(OPTIONS_TYPE)?;
+        self.write_u16(PAYLOAD_SIZE)?;
+        self.write_u32(0)?;
+        self.write_u16(options.len() as u16)?;
+        self.write_bytes(options)
+    }
+
+    /// Writes the RDATA of a resource, only the names in RFC 1035 types are compressed as required by RFC 3597
+    pub fn write_payload(&mut self, payload: &'a ResourcePayload<'a>) -> Result<(), Error> {
+        match payload {
+            ResourcePayload::Address(address) => self.write_bytes(&address.octets()),
+            ResourcePayload::Ipv6Address(address) => self.write_bytes(&address.octets()),
+            ResourcePayload::NameServer(name)
+            | ResourcePayload::MailDestination(name)
+            | ResourcePayload::MailForwarder(name)
+            | ResourcePayload::CanonicalName(name)
+            | ResourcePayload::MailBox(name)
+            | ResourcePayload::MailGroup(name)
+            | ResourcePayload::MailRename(name)
+            | ResourcePayload::DomainName(name) => self.write_name(name, true),
+            ResourcePayload::StartAuthority {
+                primary_name_server,
+                responsible_mailbox,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => {
+                self.write_name(primary_name_server, true)?;
+                self.write_name(responsible_mailbox, true)?;
+                for value in [*serial, *refresh, *retry, *expire, *minimum] {
+                    self.write_u32(value)?;
+                }
+                Ok(())
+            }
+            ResourcePayload::Null(data) => self.write_bytes(data),
+            ResourcePayload::WellKnownService {
+                address,
+                protocol,
+                bitmap,
+            } => {
+                self.write_bytes(&address.octets())?;
+                self.write_u8(*protocol)?;
+                self.write_bytes(bitmap)
+            }
+            ResourcePayload::HostInformation {
+                cpu,
+                operating_system,
+            } => {
+                self.write_character_string(cpu)?;
+                self.write_character_string(operating_system)
+            }
+            ResourcePayload::MailboxInformation {
+                responsible_mailbox,
+                error_mailbox,
+            } => {
+                self.write_name(responsible_mailbox, true)?;
+                self.write_name(error_mailbox, true)
+            }
+            ResourcePayload::MailExchange {
+                preference,
+                exchange,
+            } => {
+                self.write_u16(*preference)?;
+                self.write_name(exchange, true)
+            }
+            ResourcePayload::TextStrings(strings) => {
+                for string in strings {
+                    self.write_character_string(string)?;
+                }
+                Ok(())
+            }
+            ResourcePayload::Service {
+                priority,
+                weight,
+                port,
+                target,
+            } => {
+                self.write_u16(*priority)?;
+                self.write_u16(*weight)?;
+                self.write_u16(*port)?;
+                // RFC 2782 forbids compressing the target
+                self.write_name(target, false)
+            }
+            ResourcePayload::NamingAuthorityPointer {
+                order,
+                preference,
+                flags,
+                services,
+                regular_expression,
+                replacement,
+            } => {
+                self.write_u16(*order)?;
+                self.write_u16(*preference)?;
+                self.write_character_string(flags)?;
+                self.write_character_string(services)?;
+                self.write_character_string(regular_expression)?;
+                // RFC 3403 forbids compressing the replacement
+                self.write_name(replacement, false)
+            }
+            // RFC 6672 forbids compressing the target
+            ResourcePayload::DelegationName(target) => self.write_name(target, false),
+            ResourcePayload::UniformResourceIdentifier {
+                priority,
+                weight,
+                target,
+            } => {
+                self.write_u16(*priority)?;
+                self.write_u16(*weight)?;
+                self.write_bytes(target)
+            }
+            ResourcePayload::DelegationSigner {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            }
+            | ResourcePayload::ChildDelegationSigner {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            } => {
+                self.write_u16(*key_tag)?;
+                self.write_u8(*algorithm)?;
+                self.write_u8(*digest_type)?;
+                self.write_bytes(digest)
+            }
+            ResourcePayload::DnsKey {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            }
+            | ResourcePayload::ChildDnsKey {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            } => {
+                self.write_u16(*flags)?;
+                self.write_u8(*protocol)?;
+                self.write_u8(*algorithm)?;
+                self.write_bytes(public_key)
+            }
+            ResourcePayload::ResourceSignature {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer_name,
+                signature,
+            } => {
+                self.write_u16(*type_covered)?;
+                self.write_u8(*algorithm)?;
+                self.write_u8(*labels)?;
+                self.write_u32(*original_ttl)?;
+                self.write_u32(*expiration)?;
+                self.write_u32(*inception)?;
+                self.write_u16(*key_tag)?;
+                // RFC 4034 forbids compressing the signer name
+                self.write_name(signer_name, false)?;
+                self.write_bytes(signature)
+            }
+            ResourcePayload::NextSecure {
+                next_domain_name,
+                types,
+            } => {
+                self.write_exact_name(next_domain_name)?;
+                let mut bitmap = Vec::new();
+                types.write(&mut bitmap);
+                self.write_bytes(&bitmap)
+            }
+            ResourcePayload::NextSecure3 {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                next_hashed_owner,
+                types,
+            } => {
+                self.write_u8(*hash_algorithm)?;
+                self.write_u8(*flags)?;
+                self.write_u16(*iterations)?;
+                self.write_character_string(salt)?;
+                self.write_character_string(next_hashed_owner)?;
+                let mut bitmap = Vec::new();
+                types.write(&mut bitmap);
+                self.write_bytes(&bitmap)
+            }
+            ResourcePayload::NextSecure3Parameters {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+            } => {
+                self.write_u8(*hash_algorithm)?;
+                self.write_u8(*flags)?;
+                self.write_u16(*iterations)?;
+                self.write_character_string(salt)
+            }
+            ResourcePayload::SshFingerprint {
+                algorithm,
+                fingerprint_type,
+                fingerprint,
+            } => {
+                self.write_u8(*algorithm)?;
+                self.write_u8(*fingerprint_type)?;
+                self.write_bytes(fingerprint)
+            }
+            ResourcePayload::TlsAssociation {
+                certificate_usage,
+                selector,
+                matching_type,
+                certificate_data,
+            }
+            | ResourcePayload::SmimeAssociation {
+                certificate_usage,
+                selector,
+                matching_type,
+                certificate_data,
+            } => {
+                self.write_u8(*certificate_usage)?;
+                self.write_u8(*selector)?;
+                self.write_u8(*matching_type)?;
+                self.write_bytes(certificate_data)
+            }
+            ResourcePayload::OpenPgpKey(key) => self.write_bytes(key),
+            ResourcePayload::CertificationAuthorityAuthorization { flags, tag, value } => {
+                self.write_u8(*flags)?;
+                self.write_character_string(tag)?;
+                self.write_bytes(value)
+            }
+            ResourcePayload::ServiceBinding(binding) | ResourcePayload::HttpsBinding(binding) => {
+                self.write_u16(binding.priority())?;
+                // RFC 9460 forbids compressing the target
+                self.write_exact_name(binding.target())?;
+                let mut value = Vec::new();
+                for parameter in binding.parameters() {
+                    value.clear();
+                    parameter.write_value(&mut value);
+                    if value.len() > u16::MAX as usize {
+                        return Err(Error::new(ErrorKind::WritePacketDataFailed));
+                    }
+                    self.write_u16(parameter.key())?;
+                    self.write_u16(value.len() as u16)?;
+                    self.write_bytes(&value)?;
+                }
+                Ok(())
+            }
+            ResourcePayload::TransactionSignature {
+                algorithm,
+                time_signed,
+                fudge,
+                mac,
+                original_id,
+                error,
+                other_data,
+            } => {
+                self.write_exact_name(algorithm)?;
+                self.write_bytes(&time_signed.to_be_bytes()[2..])?;
+                self.write_u16(*fudge)?;
+                self.write_u16(mac.len() as u16)?;
+                self.write_bytes(mac)?;
+                self.write_u16(*original_id)?;
+                self.write_u16(*error)?;
+                self.write_u16(other_data.len() as u16)?;
+                self.write_bytes(other_data)
+            }
+            ResourcePayload::Unknown { data, .. } => self.write_bytes(data),
+        }
+    }
+}
@@ -1 +1,336 @@
+use super::{DnsResponseBuilder, PacketWriter};
+use crate::dns::{
+    ExtendedError, Header, OperationCode, PacketType, Question, Resource, ResponseCode,
+    TsigSession, ValidationResult,
+};
+use crate::error::{Error, ErrorKind};
 
+impl<'a> DnsResponseBuilder<'a> {
+    /// Creates a response, the id must match the id of the query being answered
+    pub fn new(id: u16) -> DnsResponseBuilder<'a> {
+        let mut header = Header::new();
+        header.id = id;
+        header.packet_type = PacketType::Response;
+        DnsResponseBuilder {
+            header,
+            questions: Vec::new(),
+            answers: Vec::new(),
+            authority: Vec::new(),
+            additional: Vec::new(),
+            extended_error: None,
+        }
+    }
+
+    /// Creates a response carrying the header of another message, the counts are set when it is built
+    pub fn with_header(header: Header) -> DnsResponseBuilder<'a> {
+        DnsResponseBuilder {
+            header,
+            questions: Vec::new(),
+            answers: Vec::new(),
+            authority: Vec::new(),
+            additional: Vec::new(),
+            extended_error: None,
+        }
+    }
+
+    /// The response echoes the operation code of the request, ie NOTIFY
+    pub fn operation_code(&mut self, operation_code: OperationCode) -> &mut Self {
+        self.header.operation_code = operation_code;
+        self
+    }
+
+    pub fn authoritative(&mut self, authoritative: bool) -> &mut Self {
+        self.header.authorative = authoritative;
+        self
+    }
+
+    pub fn recursion_desired(&mut self, recursion_desired: bool) -> &mut Self {
+        self.header.recursion_desired = recursion_desired;
+        self
+    }
+
+    pub fn recursion_available(&mut self, recursion_available: bool) -> &mut Self {
+        self.header.recursion_available = recursion_available;
+        self
+    }
+
+    pub fn response_code(&mut self, response_code: ResponseCode) -> &mut Self {
+        self.header.response_code = response_code;
+        self
+    }
+
+    /// Explains a failure with an RFC 8914 extended error, this adds an OPT record to the response
+    pub fn extended_error(&mut self, extended_error: ExtendedError) -> &mut Self {
+        self.extended_error = Some(extended_error);
+        self
+    }
+
+    /// Sets the AD bit for secure data, bogus data is removed and answered with SERVFAIL as RFC 4035 requires
+    pub fn validation_result(&mut self, result: ValidationResult) -> &mut Self {
+        self.header.authentic_data = result == ValidationResult::Secure;
+        if let ValidationResult::Bogus(extended_error) = result {
+            self.answers.clear();
+            self.authority.clear();
+            self.additional.clear();
+            self.response_code(ResponseCode::SERVFAIL)
+                .extended_error(extended_error);
+        }
+        self
+    }
+
+    pub fn add_question(&mut self, question: Question<'a>) -> &mut Self {
+        self.questions.push(question);
+        self
+    }
+
+    pub fn add_answer(&mut self, answer: Resource<'a>) -> &mut Self {
+        self.answers.push(answer);
+        self
+    }
+
+    pub fn add_authority(&mut self, authority: Resource<'a>) -> &mut Self {
+        self.authority.push(authority);
+        self
+    }
+
+    pub fn add_additional(&mut self, additional: Resource<'a>) -> &mut Self {
+        self.additional.push(additional);
+        self
+    }
+
+    pub fn build_response(&mut self) -> Result<Vec<u8>, Error> {
+        let count = |total: usize| {
+            if total > u16::MAX as usize {
+                return Err(Error::new(ErrorKind::WritePacketDataFailed));
+            }
+            Ok(total as u16)
+        };
+        self.header.question_count = count(self.questions.len())?;
+        self.header.answer_count = count(self.answers.len())?;
+        self.header.authority_count = count(self.authority.len())?;
+        let options_count = self.extended_error.is_some() as usize;
+        self.header.additional_count = count(self.additional.len() + options_count)?;
+        let mut header_data = Vec::with_capacity(12);
+        self.header.write_header(&mut header_data)?;
+        let mut writer = PacketWriter::with_data(header_data);
+        for question in &self.questions {
+            writer.write_question(question)?;
+        }
+        let sections = [&self.answers, &self.authority, &self.additional];
+        for resource in sections.iter().flat_map(|section| section.iter()) {
+            writer.write_resource(resource)?;
+        }
+        if let Some(extended_error) = self.extended_error {
+            let mut options = Vec::new();
+            extended_error.write_option(&mut options);
+            writer.write_options(&options)?;
+        }
+        Ok(writer.into_inner())
+    }
+
+    /// Builds the response and appends a TSIG record, a request that failed verification is answered with NOTAUTH
+    pub fn build_signed_response(&mut self, session: &mut TsigSession) -> Result<Vec<u8>, Error> {
+        if session.error().is_some() {
+            self.response_code(ResponseCode::NOTAUTH);
+        }
+        let mut response = self.build_response()?;
+        session.sign(&mut response)?;
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{
+        zone::ZoneParser, DnsParser, DomainName, QuestionClass, QuestionType, ResourceClass,
+        ResourcePayload,
+    };
+    use std::borrow::Cow;
+
+    fn service_resource() -> Resource<'static> {
+        Resource::new(
+            DomainName::new(vec!["_sip", "_udp", "example", "com"]),
+            ResourceClass::Internet,
+            300,
+            ResourcePayload::Service {
+                priority: 10,
+                weight: 60,
+                port: 5060,
+                target: DomainName::new(vec!["example", "com"]),
+            },
+        )
+    }
+
+    #[test]
+    fn test_service_target_not_compressed() {
+        let question = Question::new(
+            DomainName::new(vec!["example", "com"]),
+            QuestionType::Service,
+            QuestionClass::Internet,
+        );
+        let mut builder = DnsResponseBuilder::new(7);
+        builder
+            .add_question(question)
+            .add_answer(service_resource());
+        let packet_data = builder.build_response().unwrap();
+        // The target must be written in full even though example.com was already written by the question
+        let target = b"\x07example\x03com\x00";
+        assert!(packet_data.ends_with(target));
+        let mut parser = DnsParser::new();
+        let packet = parser.parse_packet(&packet_data).unwrap();
+        assert_eq!(packet.answers[0], service_resource());
+    }
+
+    #[test]
+    fn test_unknown_type_round_trip() {
+        let question = Question::new(
+            DomainName::new(vec!["example", "com"]),
+            QuestionType::from(99),
+            QuestionClass::Internet,
+        );
+        let unknown = Resource::new(
+            DomainName::new(vec!["example", "com"]),
+            ResourceClass::Internet,
+            60,
+            ResourcePayload::Unknown {
+                resource_type: 99,
+                data: Cow::Borrowed(b"\x01\x02"),
+            },
+        );
+        let mut builder = DnsResponseBuilder::new(3);
+        builder.add_question(question).add_answer(unknown.clone());
+        let packet_data = builder.build_response().unwrap();
+        let mut parser = DnsParser::new();
+        let packet = parser.parse_packet(&packet_data).unwrap();
+        assert_eq!(u16::from(packet.questions[0].question_type), 99);
+        assert_eq!(packet.answers[0], unknown);
+        assert_eq!(u16::from(packet.answers[0].resource_type()), 99);
+    }
+
+    #[test]
+    fn test_naming_authority_round_trip() {
+        let naptr = Resource::new(
+            DomainName::new(vec!["example", "com"]),
+            ResourceClass::Internet,
+            60,
+            ResourcePayload::NamingAuthorityPointer {
+                order: 100,
+                preference: 10,
+                flags: Cow::Borrowed(b"S"),
+                services: Cow::Borrowed(b"SIP+D2U"),
+                regular_expression: Cow::Borrowed(b""),
+                replacement: DomainName::new(vec!["_sip", "_udp", "example", "com"]),
+            },
+        );
+        let uri = Resource::new(
+            DomainName::new(vec!["_ftp", "_tcp", "example", "com"]),
+            ResourceClass::Internet,
+            60,
+            ResourcePayload::UniformResourceIdentifier {
+                priority: 10,
+                weight: 1,
+                target: Cow::Borrowed(b"ftp://ftp1.example.com/public"),
+            },
+        );
+        let mut builder = DnsResponseBuilder::new(1);
+        builder.add_answer(naptr.clone()).add_answer(uri.clone());
+        let packet_data = builder.build_response().unwrap();
+        let mut parser = DnsParser::new();
+        let packet = parser.parse_packet(&packet_data).unwrap();
+        assert_eq!(packet.answers, vec![naptr, uri]);
+    }
+
+    #[test]
+    fn test_security_records_round_trip() {
+        let name = DomainName::new(vec!["example", "com"]);
+        let payloads = vec![
+            ResourcePayload::CertificationAuthorityAuthorization {
+                flags: 128,
+                tag: Cow::Borrowed(b"issue"),
+                value: Cow::Borrowed(b"ca.example.net"),
+            },
+            ResourcePayload::TlsAssociation {
+                certificate_usage: 3,
+                selector: 1,
+                matching_type: 1,
+                certificate_data: Cow::Borrowed(&[0x0c, 0x72, 0xac, 0x70]),
+            },
+            ResourcePayload::SmimeAssociation {
+                certificate_usage: 3,
+                selector: 0,
+                matching_type: 0,
+                certificate_data: Cow::Borrowed(&[0xab, 0xcd]),
+            },
+            ResourcePayload::SshFingerprint {
+                algorithm: 4,
+                fingerprint_type: 2,
+                fingerprint: Cow::Borrowed(&[0x12, 0x34]),
+            },
+            ResourcePayload::OpenPgpKey(Cow::Borrowed(&[1, 2, 3])),
+        ];
+        let resources: Vec<Resource> = payloads
+            .into_iter()
+            .map(|payload| Resource::new(name.clone(), ResourceClass::Internet, 60, payload))
+            .collect();
+        let mut builder = DnsResponseBuilder::new(9);
+        for resource in &resources {
+            builder.add_answer(resource.clone());
+        }
+        let packet_data = builder.build_response().unwrap();
+        let mut parser = DnsParser::new();
+        let packet = parser.parse_packet(&packet_data).unwrap();
+        assert_eq!(packet.answers, resources);
+    }
+
+    #[test]
+    fn test_dnssec_records_round_trip() {
+        let zone = concat!(
+            "$ORIGIN example.com.\n",
+            "$TTL 3600\n",
+            "@ DNSKEY 257 3 13 mdsswUyr3DPW132mOi8V9xESWE8jTo0dxCjjnopKl+GqJxpVXckHAeF+KkxLbxILfDLUT0rAK9iUzy1L53eKGQ==\n",
+            "@ CDNSKEY 0 3 0 AA==\n",
+            "@ RRSIG DNSKEY 13 2 3600 20210301000000 20210201000000 2371 example.com. AAECAwQFBgc=\n",
+            "@ NSEC www.example.com. NS SOA RRSIG NSEC DNSKEY\n",
+            "@ NSEC3 1 0 0 - 2T7B4G4VSA5SMI47K61MV5BV1A22BOJR A RRSIG\n",
+            "@ NSEC3PARAM 1 0 0 -\n",
+        );
+        let resources = ZoneParser::new(None)
+            .parse_str(zone, "signed.zone")
+            .unwrap();
+        let mut builder = DnsResponseBuilder::new(5);
+        for resource in &resources {
+            builder.add_answer(resource.clone());
+        }
+        let packet_data = builder.build_response().unwrap();
+        // The signer and next names are written in full even though example.com appears earlier
+        let signer = b"\x07example\x03com\x00\x00\x01\x02";
+        assert!(packet_data
+            .windows(signer.len())
+            .any(|window| window == signer));
+        let mut parser = DnsParser::new();
+        let packet = parser.parse_packet(&packet_data).unwrap();
+        assert_eq!(packet.answers, resources);
+    }
+
+    #[test]
+    fn test_validation_result() {
+        let mut builder = DnsResponseBuilder::new(3);
+        builder
+            .add_answer(service_resource())
+            .validation_result(ValidationResult::Secure);
+        let packet_data = builder.build_response().unwrap();
+        let mut parser = DnsParser::new();
+        let packet = parser.parse_packet(&packet_data).unwrap();
+        assert!(packet.header.authentic_data);
+        // Bogus answers are dropped and the reason is given in an extended error
+        builder.validation_result(ValidationResult::Bogus(ExtendedError::SignatureExpired));
+        let packet_data = builder.build_response().unwrap();
+        let packet = parser.parse_packet(&packet_data).unwrap();
+        assert!(!packet.header.authentic_data);
+        assert_eq!(packet.header.response_code, ResponseCode::SERVFAIL);
+        assert!(packet.answers.is_empty());
+        assert_eq!(packet.additional.len(), 1);
+        assert!(packet_data.ends_with(&[0x00, 0x0f, 0x00, 0x02, 0x00, 0x07]));
+    }
+}
@@ -0,0 +1,571 @@
+use super::{
+    dnssec, proxy::lock, validator, CachedRecordSet, CachedZone, DenialCache, DenialCacheHits,
+    DnsPacket, DomainName, QuestionType, RecordSet, Resource, ResourcePayload, ResourceType,
+    ResponseCode, ValidationResult,
+};
+use crate::helper::encode_base32_hex;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    ops::Bound,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// The most denial records kept for each zone, enough for the gaps a random subdomain flood walks through
+// without letting one zone take over the cache, the oldest are dropped first
+const MAXIMUM_RECORDS: usize = 1000;
+
+const START_AUTHORITY: u16 = ResourceType::StartAuthority.code();
+const NEXT_SECURE: u16 = ResourceType::NextSecure.code();
+const NEXT_SECURE_3: u16 = ResourceType::NextSecure3.code();
+const ALL: u16 = QuestionType::All.code();
+
+impl DenialCache {
+    pub fn new() -> DenialCache {
+        DenialCache {
+            zones: Mutex::new(HashMap::new()),
+            cache_time: None,
+            hits: Mutex::new(DenialCacheHits::default()),
+        }
+    }
+
+    pub fn hits(&self) -> DenialCacheHits {
+        *lock(&self.hits)
+    }
+
+    /// Keeps the signed NSEC and NSEC3 records of a response, along with the SOA of their zone
+    /// Nothing is kept unless the validator found the whole response to be secure
+    pub fn insert(&self, packet: &DnsPacket, result: ValidationResult) {
+        if result != ValidationResult::Secure {
+            return;
+        }
+        let now = self.now();
+        let answers = RecordSet::group(&packet.answers);
+        let authority = RecordSet::group(&packet.authority);
+        let mut zones = lock(&self.zones);
+        // RFC 2308 section 3, negative answers carry the SOA with a TTL no longer than its minimum field
+        for set in authority
+            .iter()
+            .filter(|set| set.resource_type == START_AUTHORITY)
+        {
+            let minimum = match set.records[0].payload() {
+                ResourcePayload::StartAuthority { minimum, .. } => *minimum,
+                _ => continue,
+            };
+            if let Some(start_authority) = CachedRecordSet::new(set, now, minimum) {
+                zones
+                    .entry(canonical_key(set.name))
+                    .or_insert_with(CachedZone::new)
+                    .start_authority = Some(start_authority);
+            }
+        }
+        for set in answers.iter().chain(&authority) {
+            if !matches!(set.resource_type, NEXT_SECURE | NEXT_SECURE_3) || !is_reusable(set) {
+                continue;
+            }
+            let zone = match set.signatures[0].payload() {
+                ResourcePayload::ResourceSignature { signer_name, .. } => signer_name,
+                _ => continue,
+            };
+            // RFC 9077, denials are kept no longer than the negative caching time of the SOA
+            let negative_ttl = zones
+                .get(&canonical_key(zone))
+                .and_then(|zone| zone.start_authority.as_ref())
+                .map(|start_authority| start_authority.remaining(now))
+                .filter(|remaining| *remaining > 0);
+            if let Some(denial) = negative_ttl.and_then(|ttl| CachedRecordSet::new(set, now, ttl)) {
+                zones
+                    .entry(canonical_key(zone))
+                    .or_insert_with(CachedZone::new)
+                    .insert(set, denial);
+            }
+        }
+    }
+
+    /// Synthesizes the authority section of an NXDOMAIN or NODATA answer from the cached records, returning None on a miss
+    pub fn lookup(
+        &self,
+        name: &DomainName,
+        question_type: u16,
+    ) -> Option<(ResponseCode, Vec<Resource<'static>>)> {
+        let synthesized = self.synthesize(name, question_type);
+        let mut hits = lock(&self.hits);
+        match &synthesized {
+            Some((ResponseCode::NXDOMAIN, _)) => hits.name_errors += 1,
+            Some(_) => hits.no_data += 1,
+            None => hits.misses += 1,
+        }
+        synthesized
+    }
+
+    /// Picks the records matching or covering the name and its ancestors in the closest zone, along with their wildcards
+    /// The records are copied out so names are hashed and denials proven without holding the lock
+    fn synthesize(
+        &self,
+        name: &DomainName,
+        question_type: u16,
+    ) -> Option<(ResponseCode, Vec<Resource<'static>>)> {
+        let now = self.now();
+        let key = canonical_key(name);
+        let labels = name.labels();
+        let (zone_length, start_authority, mut denials, hash_parameters) = {
+            let zones = lock(&self.zones);
+            let (zone_length, zone) = (0..=key.len())
+                .rev()
+                .find_map(|length| zones.get(&key[..length]).map(|zone| (length, zone)))?;
+            let start_authority = zone
+                .start_authority
+                .clone()
+                .filter(|start_authority| !start_authority.is_expired(now))?;
+            let mut denials = BTreeMap::new();
+            for length in zone_length..=key.len() {
+                let ancestor = key[..length].to_vec();
+                let mut wildcard = ancestor.clone();
+                wildcard.push(b"*".to_vec());
+                for candidate in [ancestor, wildcard] {
+                    if let Some((owner, denial)) = covering(&zone.next_secure, &candidate) {
+                        denials.insert(owner.clone(), denial.clone());
+                    }
+                }
+            }
+            (
+                zone_length,
+                start_authority,
+                denials,
+                zone.hash_parameters.clone(),
+            )
+        };
+        if let Some((hash_algorithm, iterations, salt)) = hash_parameters {
+            let zone_key = &key[..zone_length];
+            let hashed: Vec<Vec<Vec<u8>>> = (0..=labels.len() - zone_length)
+                .flat_map(|index| {
+                    let ancestor = DomainName::from(&labels[index..]);
+                    [validator::wildcard(&ancestor), ancestor]
+                })
+                .filter_map(|candidate| {
+                    dnssec::nsec3_hash(&candidate, hash_algorithm, iterations, &salt)
+                })
+                .map(|hash| {
+                    let mut hashed = zone_key.to_vec();
+                    hashed.push(encode_base32_hex(&hash).to_ascii_lowercase().into_bytes());
+                    hashed
+                })
+                .collect();
+            let zones = lock(&self.zones);
+            if let Some(zone) = zones.get(zone_key) {
+                for candidate in &hashed {
+                    if let Some((owner, denial)) = covering(&zone.next_secure_3, candidate) {
+                        denials.insert(owner.clone(), denial.clone());
+                    }
+                }
+            }
+        }
+        let denials: Vec<CachedRecordSet> = denials
+            .into_values()
+            .filter(|denial| !denial.is_expired(now))
+            .collect();
+        let records: Vec<&Resource> = denials
+            .iter()
+            .flat_map(|denial| denial.records.iter())
+            .collect();
+        let response_code = if validator::prove_name_error(name, &records)
+            == ValidationResult::Secure
+        {
+            ResponseCode::NXDOMAIN
+        } else if question_type != ALL
+            && validator::prove_no_data(name, question_type, &records) == ValidationResult::Secure
+        {
+            ResponseCode::NOERROR
+        } else {
+            return None;
+        };
+        let mut authority = start_authority.aged_records(now);
+        for denial in &denials {
+            authority.extend(denial.aged_records(now));
+        }
+        Some((response_code, authority))
+    }
+
+    fn now(&self) -> u32 {
+        self.cache_time.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs() as u32)
+        })
+    }
+}
+
+impl DenialCacheHits {
+    pub fn name_errors(&self) -> u64 {
+        self.name_errors
+    }
+
+    pub fn no_data(&self) -> u64 {
+        self.no_data
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+}
+
+impl CachedZone {
+    fn new() -> CachedZone {
+        CachedZone {
+            start_authority: None,
+            next_secure: BTreeMap::new(),
+            next_secure_3: BTreeMap::new(),
+            hash_parameters: None,
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Keeps a denial in place of the one with the same owner and type, dropping the oldest over the limit
+    fn insert(&mut self, set: &RecordSet, denial: CachedRecordSet) {
+        if let ResourcePayload::NextSecure3 {
+            hash_algorithm,
+            iterations,
+            salt,
+            ..
+        } = set.records[0].payload()
+        {
+            let parameters = (*hash_algorithm, *iterations, salt.to_vec());
+            if self.hash_parameters.as_ref() != Some(&parameters) {
+                self.next_secure_3.clear();
+                self.order
+                    .retain(|(resource_type, _)| *resource_type != NEXT_SECURE_3);
+                self.hash_parameters = Some(parameters);
+            }
+        }
+        let key = canonical_key(set.name);
+        if self
+            .denials_mut(set.resource_type)
+            .insert(key.clone(), denial)
+            .is_none()
+        {
+            self.order.push_back((set.resource_type, key));
+        }
+        if self.order.len() > MAXIMUM_RECORDS {
+            if let Some((resource_type, key)) = self.order.pop_front() {
+                self.denials_mut(resource_type).remove(&key);
+            }
+        }
+    }
+
+    fn denials_mut(&mut self, resource_type: u16) -> &mut BTreeMap<Vec<Vec<u8>>, CachedRecordSet> {
+        match resource_type {
+            NEXT_SECURE => &mut self.next_secure,
+            _ => &mut self.next_secure_3,
+        }
+    }
+}
+
+impl CachedRecordSet {
+    /// Copies a signed RRset, it expires with the first of its TTL, the maximum TTL and its signatures
+    fn new(set: &RecordSet, now: u32, maximum_ttl: u32) -> Option<CachedRecordSet> {
+        let signature_lifetime = set
+            .signatures
+            .iter()
+            .filter_map(|signature| match signature.payload() {
+                ResourcePayload::ResourceSignature { expiration, .. } => {
+                    Some(expiration.wrapping_sub(now) as i32)
+                }
+                _ => None,
+            })
+            .min()?;
+        let lifetime = set
+            .records
+            .iter()
+            .map(|record| record.time_to_live())
+            .chain([maximum_ttl, signature_lifetime.max(0) as u32])
+            .min()?;
+        if lifetime == 0 {
+            return None;
+        }
+        Some(CachedRecordSet {
+            records: set
+                .records
+                .iter()
+                .map(|record| (*record).clone().into_owned())
+                .collect(),
+            signatures: set
+                .signatures
+                .iter()
+                .map(|signature| (*signature).clone().into_owned())
+                .collect(),
+            expires: now.wrapping_add(lifetime),
+        })
+    }
+
+    fn remaining(&self, now: u32) -> u32 {
+        (self.expires.wrapping_sub(now) as i32).max(0) as u32
+    }
+
+    fn is_expired(&self, now: u32) -> bool {
+        self.remaining(now) == 0
+    }
+
+    /// The records and signatures with their TTLs counted down to the time left in the cache
+    fn aged_records(&self, now: u32) -> Vec<Resource<'static>> {
+        let remaining = self.remaining(now);
+        self.records
+            .iter()
+            .chain(&self.signatures)
+            .map(|record| {
+                let mut record = record.clone();
+                record.time_to_live = remaining;
+                record
+            })
+            .collect()
+    }
+}
+
+/// Returns true if the denial records can deny names other than their owner
+/// RFC 8198 section 5.1 and 5.2 rule out opt-out NSEC3 records, compact denials only ever cover a single name
+fn is_reusable(set: &RecordSet) -> bool {
+    let records: Vec<&Resource> = set.records.clone();
+    !set.signatures.is_empty()
+        && !validator::has_excessive_iterations(&records)
+        && set.records.iter().all(|record| match record.payload() {
+            ResourcePayload::NextSecure {
+                next_domain_name, ..
+            } => {
+                next_domain_name.len() != record.name().len() + 1
+                    || next_domain_name.labels()[0] != "\0"
+            }
+            _ => !validator::is_opt_out(record),
+        })
+}
+
+/// The labels of a name from the right in lower case, they sort in the canonical order of RFC 4034 section 6.1
+fn canonical_key(name: &DomainName) -> Vec<Vec<u8>> {
+    name.labels()
+        .iter()
+        .rev()
+        .map(|label| label.to_ascii_lowercase().into_bytes())
+        .collect()
+}
+
+/// The record at or before a key, which matches or covers it, the last record wraps around to the start of the chain
+fn covering<'c>(
+    denials: &'c BTreeMap<Vec<Vec<u8>>, CachedRecordSet>,
+    key: &[Vec<u8>],
+) -> Option<(&'c Vec<Vec<u8>>, &'c CachedRecordSet)> {
+    denials
+        .range::<[Vec<u8>], _>((Bound::Unbounded, Bound::Included(key)))
+        .next_back()
+        .or_else(|| denials.iter().next_back())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{
+        builders::DnsResponseBuilder, dnssec, tests::name, zone::ZoneParser, DenialOfExistence,
+        DnsParser, Question, QuestionClass, SigningKey, ZoneSigner,
+    };
+
+    const NOW: u32 = 1_700_000_000;
+
+    fn test_signer(denial: DenialOfExistence) -> ZoneSigner {
+        let key =
+            SigningKey::generate(name("example.com"), 257, dnssec::ECDSA_P256_SHA256).unwrap();
+        let records = ZoneParser::new(Some(name("example.com")))
+            .parse_str(
+                concat!(
+                    "$TTL 3600\n",
+                    "@ SOA ns hostmaster 1 7200 3600 1209600 300\n",
+                    "@ NS ns\n",
+                    "ns A 192.0.2.53\n",
+                    "mail A 192.0.2.25\n",
+                    "sub NS ns.example.net.\n",
+                    "www A 192.0.2.1\n",
+                ),
+                "example.com.zone",
+            )
+            .unwrap();
+        let mut signer = ZoneSigner::new(name("example.com"), vec![key], records, denial);
+        signer.signing_time = Some(NOW);
+        signer
+    }
+
+    /// Caches the denial the signer gives for a query
+    fn insert_denial(
+        cache: &DenialCache,
+        signer: &mut ZoneSigner,
+        question: &str,
+        question_type: QuestionType,
+    ) {
+        let (response_code, authority) = signer.deny(&name(question)).unwrap();
+        let mut builder = DnsResponseBuilder::new(1);
+        builder
+            .add_question(Question::new(
+                name(question),
+                question_type,
+                QuestionClass::Internet,
+            ))
+            .response_code(response_code);
+        for record in authority {
+            builder.add_authority(record);
+        }
+        let packet_data = builder.build_response().unwrap();
+        let packet = DnsParser::new().parse_packet(&packet_data).unwrap();
+        cache.insert(&packet, ValidationResult::Secure);
+    }
+
+    #[test]
+    fn test_next_secure_ranges() {
+        let mut signer = test_signer(DenialOfExistence::NextSecure);
+        let mut cache = DenialCache::new();
+        cache.cache_time = Some(NOW);
+        // The NSEC from the apex to mail also covers the wildcard at the apex
+        insert_denial(&cache, &mut signer, "b.example.com", QuestionType::Address);
+        let (response_code, authority) = cache
+            .lookup(&name("c.example.com"), QuestionType::Address.code())
+            .unwrap();
+        assert_eq!(response_code, ResponseCode::NXDOMAIN);
+        let types: Vec<ResourceType> = authority.iter().map(Resource::resource_type).collect();
+        assert_eq!(
+            types,
+            [
+                ResourceType::StartAuthority,
+                ResourceType::ResourceSignature,
+                ResourceType::NextSecure,
+                ResourceType::ResourceSignature,
+            ]
+        );
+        // Capped by the SOA minimum rather than the TTL of the zone
+        assert!(authority.iter().all(|record| record.time_to_live() == 300));
+        // Names after www are in a range that hasn't been seen
+        assert!(cache
+            .lookup(&name("x.example.com"), QuestionType::Address.code())
+            .is_none());
+        assert!(cache
+            .lookup(&name("www.example.com"), QuestionType::MailExchange.code())
+            .is_none());
+        insert_denial(
+            &cache,
+            &mut signer,
+            "www.example.com",
+            QuestionType::TextStrings,
+        );
+        let (response_code, _) = cache
+            .lookup(&name("www.example.com"), QuestionType::MailExchange.code())
+            .unwrap();
+        assert_eq!(response_code, ResponseCode::NOERROR);
+        // The type that does exist is not denied
+        assert!(cache
+            .lookup(&name("www.example.com"), QuestionType::Address.code())
+            .is_none());
+        cache.cache_time = Some(NOW + 100);
+        let (_, authority) = cache
+            .lookup(&name("d.example.com"), QuestionType::Address.code())
+            .unwrap();
+        assert!(authority.iter().all(|record| record.time_to_live() == 200));
+        cache.cache_time = Some(NOW + 300);
+        assert!(cache
+            .lookup(&name("d.example.com"), QuestionType::Address.code())
+            .is_none());
+        let hits = cache.hits();
+        assert_eq!(
+            (hits.name_errors(), hits.no_data(), hits.misses()),
+            (2, 1, 4)
+        );
+    }
+
+    #[test]
+    fn test_hashed_and_compact_ranges() {
+        let mut signer = test_signer(DenialOfExistence::NextSecure3 {
+            iterations: 0,
+            salt: Vec::new(),
+        });
+        let mut cache = DenialCache::new();
+        cache.cache_time = Some(NOW);
+        insert_denial(
+            &cache,
+            &mut signer,
+            "missing.example.com",
+            QuestionType::Address,
+        );
+        // The next closer name of a longer name is the name already denied
+        let (response_code, _) = cache
+            .lookup(
+                &name("deeper.missing.example.com"),
+                QuestionType::Address.code(),
+            )
+            .unwrap();
+        assert_eq!(response_code, ResponseCode::NXDOMAIN);
+        // Compact denials only speak for the name they were made for
+        let mut signer = test_signer(DenialOfExistence::Compact);
+        let mut cache = DenialCache::new();
+        cache.cache_time = Some(NOW);
+        insert_denial(
+            &cache,
+            &mut signer,
+            "missing.example.com",
+            QuestionType::Address,
+        );
+        assert!(cache
+            .lookup(&name("missing.example.com"), QuestionType::Address.code())
+            .is_none());
+    }
+
+    #[test]
+    fn test_delegations() {
+        let denials = [
+            DenialOfExistence::NextSecure,
+            DenialOfExistence::NextSecure3 {
+                iterations: 0,
+                salt: Vec::new(),
+            },
+        ];
+        for denial in denials {
+            let mut signer = test_signer(denial);
+            let mut cache = DenialCache::new();
+            cache.cache_time = Some(NOW);
+            insert_denial(
+                &cache,
+                &mut signer,
+                "sub.example.com",
+                QuestionType::DelegationSigner,
+            );
+            let (response_code, _) = cache
+                .lookup(
+                    &name("sub.example.com"),
+                    QuestionType::DelegationSigner.code(),
+                )
+                .unwrap();
+            assert_eq!(response_code, ResponseCode::NOERROR);
+            // The names below the delegation are in the child zone
+            assert!(cache
+                .lookup(&name("www.sub.example.com"), QuestionType::Address.code())
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn test_only_secure_responses() {
+        let mut signer = test_signer(DenialOfExistence::NextSecure);
+        let (response_code, authority) = signer.deny(&name("b.example.com")).unwrap();
+        let mut builder = DnsResponseBuilder::new(1);
+        builder
+            .add_question(Question::new(
+                name("b.example.com"),
+                QuestionType::Address,
+                QuestionClass::Internet,
+            ))
+            .response_code(response_code);
+        for record in authority {
+            builder.add_authority(record);
+        }
+        let packet_data = builder.build_response().unwrap();
+        let packet = DnsParser::new().parse_packet(&packet_data).unwrap();
+        let mut cache = DenialCache::new();
+        cache.cache_time = Some(NOW);
+        cache.insert(&packet, ValidationResult::Insecure);
+        cache.insert(&packet, ValidationResult::Indeterminate);
+        assert!(cache
+            .lookup(&name("b.example.com"), QuestionType::Address.code())
+            .is_none());
+    }
+}
@@ -0,0 +1,380 @@
+use super::{
+    builders::PacketWriter, DomainName, Resource, ResourcePayload, TypeBitmap,
+};
+use crate::error::{Error, ErrorKind};
+use ring::{digest, signature};
+use std::borrow::Cow;
+
+// Algorithm numbers from the IANA DNS security algorithm registry
+pub const RSA_SHA256: u8 = 8;
+pub const ECDSA_P256_SHA256: u8 = 13;
+pub const ECDSA_P384_SHA384: u8 = 14;
+pub const ED25519: u8 = 15;
+
+// Digest types used by DS records
+pub const DIGEST_SHA1: u8 = 1;
+pub const DIGEST_SHA256: u8 = 2;
+pub const DIGEST_SHA384: u8 = 4;
+
+// RFC 4034 section 2.1.1, only keys with the zone key flag can sign zone data
+pub const ZONE_KEY_FLAG: u16 = 0x0100;
+// RFC 4034 section 2.1.1, set on key signing keys
+pub const SECURE_ENTRY_POINT_FLAG: u16 = 0x0001;
+// RFC 5155 section 3.1.2.1
+pub const OPT_OUT_FLAG: u8 = 0x01;
+// The only hash defined for NSEC3 is SHA-1
+pub const NSEC3_SHA1: u8 = 1;
+// RFC 9824, listed by compact denial NSEC records for names that don't exist
+pub const NXNAME: u16 = 128;
+
+impl TypeBitmap {
+    pub fn new(mut types: Vec<u16>) -> TypeBitmap {
+        types.sort_unstable();
+        types.dedup();
+        TypeBitmap { types }
+    }
+
+    pub fn types(&self) -> &[u16] {
+        self.types.as_slice()
+    }
+
+    pub fn contains(&self, resource_type: u16) -> bool {
+        self.types.binary_search(&resource_type).is_ok()
+    }
+
+    /// Reads the window blocks described in RFC 4034 section 4.1.2, windows must be in ascending order
+    pub fn read(data: &[u8]) -> Result<TypeBitmap, Error> {
+        let malformed = || Error::new(ErrorKind::ReadPacketDataFailed);
+        let mut types = Vec::new();
+        let mut offset = 0;
+        let mut previous_window = None;
+        while offset < data.len() {
+            let header = data.get(offset..offset + 2).ok_or_else(malformed)?;
+            let (window, length) = (header[0], header[1] as usize);
+            if length == 0 || length > 32 || previous_window >= Some(window) {
+                return Err(malformed());
+            }
+            let bitmap = data
+                .get(offset + 2..offset + 2 + length)
+                .ok_or_else(malformed)?;
+            for (index, byte) in bitmap.iter().enumerate() {
+                for bit in 0..8 {
+                    if byte & (0b10000000 >> bit) != 0 {
+                        types.push((window as u16) << 8 | (index * 8 + bit) as u16);
+                    }
+                }
+            }
+            previous_window = Some(window);
+            offset += 2 + length;
+        }
+        Ok(TypeBitmap { types })
+    }
+
+    /// Appends the bitmap in wire format, windows without any types are left out
+    pub fn write(&self, data: &mut Vec<u8>) {
+        let mut types = self.types.iter().peekable();
+        while let Some(&first) = types.peek() {
+            let window = (first >> 8) as u8;
+            let mut bitmap = [0u8; 32];
+            let mut length = 0;
+            while let Some(&&resource_type) = types.peek() {
+                if (resource_type >> 8) as u8 != window {
+                    break;
+                }
+                let position = (resource_type & 0xff) as usize;
+                bitmap[position / 8] |= 0b10000000 >> (position % 8);
+                length = position / 8 + 1;
+                types.next();
+            }
+            data.push(window);
+            data.push(length as u8);
+            data.extend_from_slice(&bitmap[..length]);
+        }
+    }
+}
+
+impl ResourcePayload<'_> {
+    /// The key tag of a DNSKEY or CDNSKEY, used to match keys with signatures and DS records
+    pub fn key_tag(&self) -> Option<u16> {
+        match self {
+            ResourcePayload::DnsKey {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            }
+            | ResourcePayload::ChildDnsKey {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            } => Some(key_tag(*flags, *protocol, *algorithm, public_key)),
+            _ => None,
+        }
+    }
+}
+
+impl Resource<'_> {
+    /// The number of labels in the owner name as counted by RRSIG records, a leading wildcard label is not counted
+    pub fn signature_labels(&self) -> usize {
+        match self.resource_name.labels().first() {
+            Some(label) if label == "*" => self.resource_name.len() - 1,
+            _ => self.resource_name.len(),
+        }
+    }
+}
+
+pub fn is_supported_algorithm(algorithm: u8) -> bool {
+    matches!(
+        algorithm,
+        RSA_SHA256 | ECDSA_P256_SHA256 | ECDSA_P384_SHA384 | ED25519
+    )
+}
+
+pub fn is_supported_digest(digest_type: u8) -> bool {
+    matches!(digest_type, DIGEST_SHA1 | DIGEST_SHA256 | DIGEST_SHA384)
+}
+
+/// Builds the data an RRSIG signs as described in RFC 4034 section 3.1.8.1, the records must all belong to one RRset
+pub fn signed_data(signature: &ResourcePayload, records: &[&Resource]) -> Result<Vec<u8>, Error> {
+    let malformed = || Error::new(ErrorKind::WritePacketDataFailed);
+    let (type_covered, labels, original_ttl, signature_length) = match signature {
+        ResourcePayload::ResourceSignature {
+            type_covered,
+            labels,
+            original_ttl,
+            signature,
+            ..
+        } => (
+            *type_covered,
+            *labels as usize,
+            *original_ttl,
+            signature.len(),
+        ),
+        _ => return Err(malformed()),
+    };
+    let first = records.first().ok_or_else(malformed)?;
+    // Records expanded from a wildcard are signed with the wildcard as their owner
+    let owner = match first.signature_labels().checked_sub(labels) {
+        Some(0) => first.resource_name.clone(),
+        Some(_) => {
+            let owner_labels = first.resource_name.labels();
+            let mut wildcard = vec![Cow::Borrowed("*")];
+            wildcard.extend_from_slice(&owner_labels[owner_labels.len() - labels..]);
+            DomainName::Labels(wildcard)
+        }
+        None => return Err(malformed()),
+    };
+    let mut owner_data = PacketWriter::canonical();
+    owner_data.write_name(&owner, false)?;
+    owner_data.write_u16(type_covered)?;
+    owner_data.write_u16(u16::from(first.resource_class))?;
+    owner_data.write_u32(original_ttl)?;
+    let owner_data = owner_data.into_inner();
+    // The records are sorted by their canonical RDATA, duplicates are only signed once
+    let mut payloads = Vec::with_capacity(records.len());
+    for record in records {
+        let mut writer = PacketWriter::canonical();
+        writer.write_payload(&record.payload)?;
+        payloads.push(writer.into_inner());
+    }
+    payloads.sort_unstable();
+    payloads.dedup();
+    // The RRSIG RDATA comes first, without the signature itself
+    let mut writer = PacketWriter::canonical();
+    writer.write_payload(signature)?;
+    let mut data = writer.into_inner();
+    data.truncate(data.len() - signature_length);
+    for payload in payloads {
+        if payload.len() > u16::MAX as usize {
+            return Err(malformed());
+        }
+        data.extend_from_slice(&owner_data);
+        data.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        data.extend_from_slice(&payload);
+    }
+    Ok(data)
+}
+
+/// Verifies a signature with the public key of a DNSKEY, unsupported algorithms never verify
+pub fn verify_signature(algorithm: u8, public_key: &[u8], data: &[u8], signature: &[u8]) -> bool {
+    match algorithm {
+        RSA_SHA256 => {
+            // RFC 3110, the exponent length is one octet, or a zero octet followed by two octets
+            let (exponent_length, offset) = match public_key {
+                [0, high, low, ..] => (u16::from_be_bytes([*high, *low]) as usize, 3),
+                [length, ..] => (*length as usize, 1),
+                [] => return false,
+            };
+            if public_key.len() <= offset + exponent_length {
+                return false;
+            }
+            let components = signature::RsaPublicKeyComponents {
+                n: &public_key[offset + exponent_length..],
+                e: &public_key[offset..offset + exponent_length],
+            };
+            // Plenty of zones still sign with 1024 bit keys
+            components
+                .verify(
+                    &signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
+                    data,
+                    signature,
+                )
+                .is_ok()
+        }
+        ECDSA_P256_SHA256 | ECDSA_P384_SHA384 => {
+            // RFC 6605 keys are the bare coordinates, ring expects an uncompressed point
+            let mut point = Vec::with_capacity(public_key.len() + 1);
+            point.push(0x04);
+            point.extend_from_slice(public_key);
+            let verification = match algorithm {
+                ECDSA_P256_SHA256 => &signature::ECDSA_P256_SHA256_FIXED,
+                _ => &signature::ECDSA_P384_SHA384_FIXED,
+            };
+            signature::UnparsedPublicKey::new(verification, point)
+                .verify(data, signature)
+                .is_ok()
+        }
+        ED25519 => signature::UnparsedPublicKey::new(&signature::ED25519, public_key)
+            .verify(data, signature)
+            .is_ok(),
+        _ => false,
+    }
+}
+
+/// The digest a DS record holds for a DNSKEY, RFC 4034 section 5.1.4
+pub fn delegation_digest(
+    owner: &DomainName,
+    key: &ResourcePayload,
+    digest_type: u8,
+) -> Option<Vec<u8>> {
+    let algorithm = match digest_type {
+        DIGEST_SHA1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        DIGEST_SHA256 => &digest::SHA256,
+        DIGEST_SHA384 => &digest::SHA384,
+        _ => return None,
+    };
+    let mut writer = PacketWriter::canonical();
+    writer.write_name(owner, false).ok()?;
+    writer.write_payload(key).ok()?;
+    Some(
+        digest::digest(algorithm, &writer.into_inner())
+            .as_ref()
+            .to_vec(),
+    )
+}
+
+/// Hashes a name for NSEC3 as described in RFC 5155 section 5
+pub fn nsec3_hash(
+    name: &DomainName,
+    hash_algorithm: u8,
+    iterations: u16,
+    salt: &[u8],
+) -> Option<Vec<u8>> {
+    if hash_algorithm != NSEC3_SHA1 {
+        return None;
+    }
+    let mut writer = PacketWriter::canonical();
+    writer.write_name(name, false).ok()?;
+    let mut hash = writer.into_inner();
+    for _ in 0..=iterations {
+        let mut context = digest::Context::new(&digest::SHA1_FOR_LEGACY_USE_ONLY);
+        context.update(&hash);
+        context.update(salt);
+        hash = context.finish().as_ref().to_vec();
+    }
+    Some(hash)
+}
+
+/// Calculates a key tag as described in RFC 4034 appendix B
+pub fn key_tag(flags: u16, protocol: u8, algorithm: u8, public_key: &[u8]) -> u16 {
+    // RSA/MD5 keys use the most significant bits of the modulus instead
+    if algorithm == 1 {
+        return match public_key.len() {
+            length if length >= 3 => {
+                u16::from_be_bytes([public_key[length - 3], public_key[length - 2]])
+            }
+            _ => 0,
+        };
+    }
+    let header = [(flags >> 8) as u8, flags as u8, protocol, algorithm];
+    let mut accumulator: u32 = 0;
+    for (index, byte) in header.iter().chain(public_key.iter()).enumerate() {
+        if index % 2 == 0 {
+            accumulator += (*byte as u32) << 8;
+        } else {
+            accumulator += *byte as u32;
+        }
+    }
+    accumulator += (accumulator >> 16) & 0xffff;
+    (accumulator & 0xffff) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::{decode_base64, encode_base32_hex, encode_hex};
+
+    #[test]
+    fn test_type_bitmap_wire_format() {
+        // The NSEC example from RFC 4034 section 4.3, A MX RRSIG NSEC TYPE1234
+        let bitmap = TypeBitmap::new(vec![1234, 47, 46, 15, 1]);
+        let mut data = Vec::new();
+        bitmap.write(&mut data);
+        let mut expected = vec![0x00, 0x06, 0x40, 0x01, 0x00, 0x00, 0x00, 0x03];
+        expected.extend_from_slice(&[0x04, 0x1b]);
+        expected.extend_from_slice(&[0; 26]);
+        expected.push(0x20);
+        assert_eq!(data, expected);
+        assert_eq!(TypeBitmap::read(&data).unwrap(), bitmap);
+        assert!(bitmap.contains(1234));
+        assert!(!bitmap.contains(2));
+    }
+
+    #[test]
+    fn test_malformed_type_bitmap() {
+        // Windows must be in ascending order
+        assert!(TypeBitmap::read(&[0x01, 0x01, 0x80, 0x00, 0x01, 0x40]).is_err());
+        // Empty windows are not allowed
+        assert!(TypeBitmap::read(&[0x00, 0x00]).is_err());
+        // The bitmap runs past the end of the data
+        assert!(TypeBitmap::read(&[0x00, 0x02, 0x40]).is_err());
+    }
+
+    #[test]
+    fn test_delegation_digest() {
+        // The DS example from RFC 4034 section 5.4
+        let public_key = decode_base64(concat!(
+            "AQOeiiR0GOMYkDshWoSKz9XzfwJr1AYtsmx3TGkJaNXVbfi/2pHm822aJ5iI9BMzNXxeYCmZ",
+            "DRD99WYwYqUSdjMmmAphXdvxegXd/M5+X7OrzKBaMbCVdFLUUh6DhweJBjEVv5f2wwjM9Xzc",
+            "nOf+EPbtG9DMBmADjFDc2w/rljwvFw==",
+        ))
+        .unwrap();
+        let key = ResourcePayload::DnsKey {
+            flags: 256,
+            protocol: 3,
+            algorithm: 5,
+            public_key: Cow::Owned(public_key),
+        };
+        let owner = DomainName::new(vec!["dskey", "example", "com"]);
+        let digest = delegation_digest(&owner, &key, DIGEST_SHA1).unwrap();
+        assert_eq!(
+            encode_hex(&digest),
+            "2BB183AF5F22588179A53B0A98631FAD1A292118"
+        );
+        assert!(delegation_digest(&owner, &key, 3).is_none());
+    }
+
+    #[test]
+    fn test_nsec3_hash() {
+        // Hashes from the example zone in RFC 5155 appendix A
+        let salt = [0xaa, 0xbb, 0xcc, 0xdd];
+        for (labels, expected) in [
+            (vec!["example"], "0P9MHAVEQVM6T7VBL5LOP2U3T2RP3TOM"),
+            (vec!["a", "example"], "35MTHGPGCU1QG68FAB165KLNSNK3DPVL"),
+        ] {
+            let hash = nsec3_hash(&DomainName::new(labels), NSEC3_SHA1, 12, &salt).unwrap();
+            assert_eq!(encode_base32_hex(&hash), expected);
+        }
+    }
+}
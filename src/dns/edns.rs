@@ -0,0 +1,217 @@
+use super::ExtendedError;
+use std::{fmt::Display, time::Duration};
+
+// RFC 6891, the OPT pseudo record carries EDNS data in the additional section
+pub const OPTIONS_TYPE: u16 = 41;
+// The UDP payload size recommended by DNS flag day 2020, sent in the class field of the OPT record
+pub const PAYLOAD_SIZE: u16 = 1232;
+// RFC 8914
+pub const EXTENDED_ERROR_OPTION: u16 = 15;
+// RFC 7828, the idle timeout of a TCP connection in units of 100 milliseconds
+pub const KEEPALIVE_OPTION: u16 = 11;
+// RFC 3225, the DO bit in the flags of the OPT record asks for DNSSEC records
+const DNSSEC_OK: u16 = 0x8000;
+
+fn read_u16(message: &[u8], position: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([
+        *message.get(position)?,
+        *message.get(position + 1)?,
+    ]))
+}
+
+fn set_u16(message: &mut [u8], position: usize, value: u16) {
+    message[position..position + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+/// The position just past a name, compression pointers end the name
+fn skip_name(message: &[u8], mut position: usize) -> Option<usize> {
+    loop {
+        let length = *message.get(position)?;
+        match length & 0xC0 {
+            0xC0 => return Some(position + 2),
+            0 if length == 0 => return Some(position + 1),
+            0 => position += length as usize + 1,
+            _ => return None,
+        }
+    }
+}
+
+/// Finds the OPT record of a message without parsing it, returning the position of its RDLENGTH
+fn options_position(message: &[u8]) -> Option<usize> {
+    let records = (6..12)
+        .step_by(2)
+        .map(|position| read_u16(message, position).map(usize::from))
+        .sum::<Option<usize>>()?;
+    let mut position = 12;
+    for _ in 0..read_u16(message, 4)? {
+        position = skip_name(message, position)? + 4;
+    }
+    for _ in 0..records {
+        position = skip_name(message, position)?;
+        let length = read_u16(message, position + 8)? as usize;
+        if read_u16(message, position)? == OPTIONS_TYPE {
+            return Some(position + 8).filter(|_| position + 10 + length <= message.len());
+        }
+        position += 10 + length;
+    }
+    None
+}
+
+/// The OPT record of a message as it is on the wire, from its root name to the end of its options
+pub fn options_record(message: &[u8]) -> Option<&[u8]> {
+    let length_position = options_position(message)?;
+    let length = read_u16(message, length_position)? as usize;
+    message
+        .get(length_position - 9..length_position + 2 + length)
+        .filter(|record| record[0] == 0)
+}
+
+/// Copies the OPT record of one message to the end of another, which has to have been written without one
+pub fn copy_options_record(from: &[u8], to: &mut Vec<u8>) {
+    if let Some(options) = options_record(from) {
+        let count = read_u16(to, 10).unwrap_or(0).saturating_add(1);
+        set_u16(to, 10, count);
+        to.extend_from_slice(options);
+    }
+}
+
+/// The largest UDP message the sender of a message accepts, RFC 6891 section 6.2.5
+/// Without an OPT record this is the 512 bytes of RFC 1035, smaller sizes are treated as 512 as well
+pub fn payload_size(message: &[u8]) -> usize {
+    options_position(message)
+        .and_then(|length_position| read_u16(message, length_position - 6))
+        .map_or(512, |size| (size as usize).max(512))
+}
+
+/// Removes an option from the OPT record of a message, returning whether it was there
+/// Records after the OPT record must not be compressed against each other, which holds for the TSIG that may follow it
+pub fn remove_option(message: &mut Vec<u8>, code: u16) -> bool {
+    let length_position = match options_position(message) {
+        Some(length_position) => length_position,
+        None => return false,
+    };
+    let length = read_u16(message, length_position).unwrap_or(0);
+    let end = length_position + 2 + length as usize;
+    let mut position = length_position + 2;
+    while let (Some(option), Some(option_length)) = (
+        read_u16(&message[..end], position),
+        read_u16(&message[..end], position + 2),
+    ) {
+        let next = position + 4 + option_length as usize;
+        if next > end {
+            return false;
+        }
+        if option == code {
+            message.drain(position..next);
+            set_u16(message, length_position, length - (next - position) as u16);
+            return true;
+        }
+        position = next;
+    }
+    false
+}
+
+/// Tells the client how long we keep an idle connection open, replacing any keepalive option already in the message
+/// A message without an OPT record gets one
+pub fn add_keepalive(message: &mut Vec<u8>, idle_timeout: Duration) {
+    remove_option(message, KEEPALIVE_OPTION);
+    let units = (idle_timeout.as_millis() / 100).min(u16::MAX as u128) as u16;
+    let mut option = Vec::with_capacity(6);
+    option.extend_from_slice(&KEEPALIVE_OPTION.to_be_bytes());
+    option.extend_from_slice(&2u16.to_be_bytes());
+    option.extend_from_slice(&units.to_be_bytes());
+    add_option(message, &option);
+}
+
+/// Explains a failure to the client with an RFC 8914 extended error, a message without an OPT record gets one
+pub fn add_extended_error(message: &mut Vec<u8>, extended_error: ExtendedError) {
+    let mut option = Vec::with_capacity(6);
+    extended_error.write_option(&mut option);
+    add_option(message, &option);
+}
+
+/// Adds an option in wire format to the end of the OPT record, or to a new OPT record
+fn add_option(message: &mut Vec<u8>, option: &[u8]) {
+    match options_position(message) {
+        Some(length_position) => {
+            let length = read_u16(message, length_position).unwrap_or(0);
+            let end = length_position + 2 + length as usize;
+            message.splice(end..end, option.iter().copied());
+            set_u16(message, length_position, length + option.len() as u16);
+        }
+        None => {
+            append_options_record(message, option);
+        }
+    }
+}
+
+/// Whether the sender of a message asked for DNSSEC records with the DO bit
+pub fn dnssec_ok(message: &[u8]) -> bool {
+    options_position(message)
+        .and_then(|length_position| read_u16(message, length_position - 2))
+        .is_some_and(|flags| flags & DNSSEC_OK != 0)
+}
+
+/// Asks for DNSSEC records by setting the DO bit, a message without an OPT record gets one
+pub fn set_dnssec_ok(message: &mut Vec<u8>) {
+    if options_position(message).is_none() && !append_options_record(message, &[]) {
+        return;
+    }
+    if let Some(length_position) = options_position(message) {
+        let flags = read_u16(message, length_position - 2).unwrap_or(0);
+        set_u16(message, length_position - 2, flags | DNSSEC_OK);
+    }
+}
+
+/// Clears the DO bit so that an answer mirrors a query that didn't ask for DNSSEC records, RFC 3225 section 3
+pub fn clear_dnssec_ok(message: &mut [u8]) {
+    if let Some(length_position) = options_position(message) {
+        let flags = read_u16(message, length_position - 2).unwrap_or(0);
+        set_u16(message, length_position - 2, flags & !DNSSEC_OK);
+    }
+}
+
+/// Adds an OPT record holding the options to the end of a message, returning false when the additional section is full
+fn append_options_record(message: &mut Vec<u8>, options: &[u8]) -> bool {
+    let additional_count = match read_u16(message, 10) {
+        Some(count) if count < u16::MAX => count,
+        _ => return false,
+    };
+    message.push(0);
+    message.extend_from_slice(&OPTIONS_TYPE.to_be_bytes());
+    message.extend_from_slice(&PAYLOAD_SIZE.to_be_bytes());
+    message.extend_from_slice(&0u32.to_be_bytes());
+    message.extend_from_slice(&(options.len() as u16).to_be_bytes());
+    message.extend_from_slice(options);
+    set_u16(message, 10, additional_count + 1);
+    true
+}
+
+impl ExtendedError {
+    pub fn code(&self) -> u16 {
+        *self as u16
+    }
+
+    /// Appends the option in wire format, the extra text is left empty
+    pub fn write_option(&self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&EXTENDED_ERROR_OPTION.to_be_bytes());
+        data.extend_from_slice(&2u16.to_be_bytes());
+        data.extend_from_slice(&self.code().to_be_bytes());
+    }
+}
+
+impl Display for ExtendedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtendedError::UnsupportedDnsKeyAlgorithm => write!(f, "Unsupported DNSKEY Algorithm"),
+            ExtendedError::DnssecIndeterminate => write!(f, "DNSSEC Indeterminate"),
+            ExtendedError::DnssecBogus => write!(f, "DNSSEC Bogus"),
+            ExtendedError::SignatureExpired => write!(f, "Signature Expired"),
+            ExtendedError::SignatureNotYetValid => write!(f, "Signature Not Yet Valid"),
+            ExtendedError::DnsKeyMissing => write!(f, "DNSKEY Missing"),
+            ExtendedError::RrsigsMissing => write!(f, "RRSIGs Missing"),
+            ExtendedError::NoZoneKeyBitSet => write!(f, "No Zone Key Bit Set"),
+            ExtendedError::NsecMissing => write!(f, "NSEC Missing"),
+        }
+    }
+}
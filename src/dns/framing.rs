@@ -0,0 +1,35 @@
+use crate::error::{Error, ErrorKind};
+use std::io::{ErrorKind as IoErrorKind, Read, Write};
+
+/// Reads one message from a TCP stream, where each message is preceded by its length in two bytes, RFC 1035 section 4.2.2
+/// Returns None when the other side closed the connection between messages
+pub fn read_message<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>, Error> {
+    let mut length = [0u8; 2];
+    match reader.read_exact(&mut length[..1]) {
+        Ok(()) => (),
+        Err(error) if error.kind() == IoErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(Error::new(ErrorKind::ConnectionFailed(error.to_string()))),
+    }
+    let mut message = Vec::new();
+    reader
+        .read_exact(&mut length[1..])
+        .and_then(|_| {
+            message.resize(u16::from_be_bytes(length) as usize, 0);
+            reader.read_exact(&mut message)
+        })
+        .map_err(|error| Error::new(ErrorKind::ConnectionFailed(error.to_string())))?;
+    Ok(Some(message))
+}
+
+/// Writes a message with its length prefix, in a single write so that small messages aren't split over two segments
+pub fn write_message<W: Write>(writer: &mut W, message: &[u8]) -> Result<(), Error> {
+    if message.len() > u16::MAX as usize {
+        return Err(Error::new(ErrorKind::ExceededPacketSize));
+    }
+    let mut framed = Vec::with_capacity(message.len() + 2);
+    framed.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    framed.extend_from_slice(message);
+    writer
+        .write_all(&framed)
+        .map_err(|error| Error::new(ErrorKind::ConnectionFailed(error.to_string())))
+}
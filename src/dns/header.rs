@@ -1,6 +1,9 @@
 use super::{Error, ErrorKind, Header, OperationCode, PacketType, ResponseCode};
-use byteorder::{NetworkEndian, ReadBytesExt};
-use std::{fmt::Display, io::Cursor};
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use std::{
+    fmt::Display,
+    io::{Cursor, Write},
+};
 
 impl Header {
     pub fn new() -> Header {
@@ -8,6 +11,8 @@ impl Header {
             id: 0,
             recursion_available: false,
             recursion_desired: false,
+            authentic_data: false,
+            checking_disabled: false,
             authorative: false,
             operation_code: OperationCode::StandardQuery,
             packet_type: PacketType::Query,
@@ -19,6 +24,34 @@ impl Header {
             truncated: false,
         }
     }
+
+    /// Writes the header in wire format, the counts must already be set
+    pub fn write_header<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let mut bitmask = u16::from(self.packet_type) << 15;
+        bitmask |= u16::from(self.operation_code) << 11;
+        bitmask |= (self.authorative as u16) << 10;
+        bitmask |= (self.truncated as u16) << 9;
+        bitmask |= (self.recursion_desired as u16) << 8;
+        bitmask |= (self.recursion_available as u16) << 7;
+        // Z is reserved and always written as zero
+        bitmask |= (self.authentic_data as u16) << 5;
+        bitmask |= (self.checking_disabled as u16) << 4;
+        // Extended codes above 15 only exist in the TSIG error field
+        bitmask |= u16::from(self.response_code) & 0x000F;
+        for value in [
+            self.id,
+            bitmask,
+            self.question_count,
+            self.answer_count,
+            self.authority_count,
+            self.additional_count,
+        ] {
+            writer
+                .write_u16::<NetworkEndian>(value)
+                .map_err(|_| Error::new(ErrorKind::WritePacketDataFailed))?;
+        }
+        Ok(())
+    }
 }
 
 impl Display for Header {
@@ -93,6 +126,8 @@ impl From<u8> for OperationCode {
             0 => OperationCode::StandardQuery,
             1 => OperationCode::InverseQuery,
             2 => OperationCode::ServerStatus,
+            4 => OperationCode::Notify,
+            5 => OperationCode::Update,
             _ => OperationCode::Unknown,
         }
     }
@@ -104,6 +139,8 @@ impl From<OperationCode> for u16 {
             OperationCode::StandardQuery => 0,
             OperationCode::InverseQuery => 1,
             OperationCode::ServerStatus => 2,
+            OperationCode::Notify => 4,
+            OperationCode::Update => 5,
             OperationCode::Unknown => unreachable!("An unknown operation code has no constant"),
         }
     }
@@ -119,6 +156,15 @@ impl From<u8> for ResponseCode {
             3 => ResponseCode::NXDOMAIN,
             4 => ResponseCode::NOTIMP,
             5 => ResponseCode::REFUSED,
+            6 => ResponseCode::YXDOMAIN,
+            7 => ResponseCode::YXRRSET,
+            8 => ResponseCode::NXRRSET,
+            9 => ResponseCode::NOTAUTH,
+            10 => ResponseCode::NOTZONE,
+            16 => ResponseCode::BADSIG,
+            17 => ResponseCode::BADKEY,
+            18 => ResponseCode::BADTIME,
+            22 => ResponseCode::BADTRUNC,
             0 => ResponseCode::NOERROR,
             _ => ResponseCode::UNKNOWN,
         }
@@ -133,6 +179,15 @@ impl From<ResponseCode> for u16 {
             ResponseCode::NXDOMAIN => 3,
             ResponseCode::NOTIMP => 4,
             ResponseCode::REFUSED => 5,
+            ResponseCode::YXDOMAIN => 6,
+            ResponseCode::YXRRSET => 7,
+            ResponseCode::NXRRSET => 8,
+            ResponseCode::NOTAUTH => 9,
+            ResponseCode::NOTZONE => 10,
+            ResponseCode::BADSIG => 16,
+            ResponseCode::BADKEY => 17,
+            ResponseCode::BADTIME => 18,
+            ResponseCode::BADTRUNC => 22,
             ResponseCode::NOERROR => 0,
             ResponseCode::UNKNOWN => {
                 unreachable!("An unknown response code can't be used in a DNS Query or response")
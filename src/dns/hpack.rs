@@ -0,0 +1,681 @@
+use super::HeaderDecoder;
+use crate::error::{Error, ErrorKind};
+
+/// The largest dynamic table we let a peer use, the HTTP/2 default for SETTINGS_HEADER_TABLE_SIZE
+pub const MAX_TABLE_SIZE: usize = 4096;
+
+/// The largest header list we decode, as SETTINGS_MAX_HEADER_LIST_SIZE counts it, no bigger than a request we read
+pub const MAX_LIST_SIZE: usize = 65_535;
+
+/// Entries 1 to 61 of the header table, RFC 7541 appendix A
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// The code of each octet and of EOS with its length in bits, RFC 7541 appendix B
+const HUFFMAN_CODES: [(u32, u8); 257] = [
+    (0x1ff8, 13),
+    (0x7fffd8, 23),
+    (0xfffffe2, 28),
+    (0xfffffe3, 28),
+    (0xfffffe4, 28),
+    (0xfffffe5, 28),
+    (0xfffffe6, 28),
+    (0xfffffe7, 28),
+    (0xfffffe8, 28),
+    (0xffffea, 24),
+    (0x3ffffffc, 30),
+    (0xfffffe9, 28),
+    (0xfffffea, 28),
+    (0x3ffffffd, 30),
+    (0xfffffeb, 28),
+    (0xfffffec, 28),
+    (0xfffffed, 28),
+    (0xfffffee, 28),
+    (0xfffffef, 28),
+    (0xffffff0, 28),
+    (0xffffff1, 28),
+    (0xffffff2, 28),
+    (0x3ffffffe, 30),
+    (0xffffff3, 28),
+    (0xffffff4, 28),
+    (0xffffff5, 28),
+    (0xffffff6, 28),
+    (0xffffff7, 28),
+    (0xffffff8, 28),
+    (0xffffff9, 28),
+    (0xffffffa, 28),
+    (0xffffffb, 28),
+    (0x14, 6),
+    (0x3f8, 10),
+    (0x3f9, 10),
+    (0xffa, 12),
+    (0x1ff9, 13),
+    (0x15, 6),
+    (0xf8, 8),
+    (0x7fa, 11),
+    (0x3fa, 10),
+    (0x3fb, 10),
+    (0xf9, 8),
+    (0x7fb, 11),
+    (0xfa, 8),
+    (0x16, 6),
+    (0x17, 6),
+    (0x18, 6),
+    (0x0, 5),
+    (0x1, 5),
+    (0x2, 5),
+    (0x19, 6),
+    (0x1a, 6),
+    (0x1b, 6),
+    (0x1c, 6),
+    (0x1d, 6),
+    (0x1e, 6),
+    (0x1f, 6),
+    (0x5c, 7),
+    (0xfb, 8),
+    (0x7ffc, 15),
+    (0x20, 6),
+    (0xffb, 12),
+    (0x3fc, 10),
+    (0x1ffa, 13),
+    (0x21, 6),
+    (0x5d, 7),
+    (0x5e, 7),
+    (0x5f, 7),
+    (0x60, 7),
+    (0x61, 7),
+    (0x62, 7),
+    (0x63, 7),
+    (0x64, 7),
+    (0x65, 7),
+    (0x66, 7),
+    (0x67, 7),
+    (0x68, 7),
+    (0x69, 7),
+    (0x6a, 7),
+    (0x6b, 7),
+    (0x6c, 7),
+    (0x6d, 7),
+    (0x6e, 7),
+    (0x6f, 7),
+    (0x70, 7),
+    (0x71, 7),
+    (0x72, 7),
+    (0xfc, 8),
+    (0x73, 7),
+    (0xfd, 8),
+    (0x1ffb, 13),
+    (0x7fff0, 19),
+    (0x1ffc, 13),
+    (0x3ffc, 14),
+    (0x22, 6),
+    (0x7ffd, 15),
+    (0x3, 5),
+    (0x23, 6),
+    (0x4, 5),
+    (0x24, 6),
+    (0x5, 5),
+    (0x25, 6),
+    (0x26, 6),
+    (0x27, 6),
+    (0x6, 5),
+    (0x74, 7),
+    (0x75, 7),
+    (0x28, 6),
+    (0x29, 6),
+    (0x2a, 6),
+    (0x7, 5),
+    (0x2b, 6),
+    (0x76, 7),
+    (0x2c, 6),
+    (0x8, 5),
+    (0x9, 5),
+    (0x2d, 6),
+    (0x77, 7),
+    (0x78, 7),
+    (0x79, 7),
+    (0x7a, 7),
+    (0x7b, 7),
+    (0x7ffe, 15),
+    (0x7fc, 11),
+    (0x3ffd, 14),
+    (0x1ffd, 13),
+    (0xffffffc, 28),
+    (0xfffe6, 20),
+    (0x3fffd2, 22),
+    (0xfffe7, 20),
+    (0xfffe8, 20),
+    (0x3fffd3, 22),
+    (0x3fffd4, 22),
+    (0x3fffd5, 22),
+    (0x7fffd9, 23),
+    (0x3fffd6, 22),
+    (0x7fffda, 23),
+    (0x7fffdb, 23),
+    (0x7fffdc, 23),
+    (0x7fffdd, 23),
+    (0x7fffde, 23),
+    (0xffffeb, 24),
+    (0x7fffdf, 23),
+    (0xffffec, 24),
+    (0xffffed, 24),
+    (0x3fffd7, 22),
+    (0x7fffe0, 23),
+    (0xffffee, 24),
+    (0x7fffe1, 23),
+    (0x7fffe2, 23),
+    (0x7fffe3, 23),
+    (0x7fffe4, 23),
+    (0x1fffdc, 21),
+    (0x3fffd8, 22),
+    (0x7fffe5, 23),
+    (0x3fffd9, 22),
+    (0x7fffe6, 23),
+    (0x7fffe7, 23),
+    (0xffffef, 24),
+    (0x3fffda, 22),
+    (0x1fffdd, 21),
+    (0xfffe9, 20),
+    (0x3fffdb, 22),
+    (0x3fffdc, 22),
+    (0x7fffe8, 23),
+    (0x7fffe9, 23),
+    (0x1fffde, 21),
+    (0x7fffea, 23),
+    (0x3fffdd, 22),
+    (0x3fffde, 22),
+    (0xfffff0, 24),
+    (0x1fffdf, 21),
+    (0x3fffdf, 22),
+    (0x7fffeb, 23),
+    (0x7fffec, 23),
+    (0x1fffe0, 21),
+    (0x1fffe1, 21),
+    (0x3fffe0, 22),
+    (0x1fffe2, 21),
+    (0x7fffed, 23),
+    (0x3fffe1, 22),
+    (0x7fffee, 23),
+    (0x7fffef, 23),
+    (0xfffea, 20),
+    (0x3fffe2, 22),
+    (0x3fffe3, 22),
+    (0x3fffe4, 22),
+    (0x7ffff0, 23),
+    (0x3fffe5, 22),
+    (0x3fffe6, 22),
+    (0x7ffff1, 23),
+    (0x3ffffe0, 26),
+    (0x3ffffe1, 26),
+    (0xfffeb, 20),
+    (0x7fff1, 19),
+    (0x3fffe7, 22),
+    (0x7ffff2, 23),
+    (0x3fffe8, 22),
+    (0x1ffffec, 25),
+    (0x3ffffe2, 26),
+    (0x3ffffe3, 26),
+    (0x3ffffe4, 26),
+    (0x7ffffde, 27),
+    (0x7ffffdf, 27),
+    (0x3ffffe5, 26),
+    (0xfffff1, 24),
+    (0x1ffffed, 25),
+    (0x7fff2, 19),
+    (0x1fffe3, 21),
+    (0x3ffffe6, 26),
+    (0x7ffffe0, 27),
+    (0x7ffffe1, 27),
+    (0x3ffffe7, 26),
+    (0x7ffffe2, 27),
+    (0xfffff2, 24),
+    (0x1fffe4, 21),
+    (0x1fffe5, 21),
+    (0x3ffffe8, 26),
+    (0x3ffffe9, 26),
+    (0xffffffd, 28),
+    (0x7ffffe3, 27),
+    (0x7ffffe4, 27),
+    (0x7ffffe5, 27),
+    (0xfffec, 20),
+    (0xfffff3, 24),
+    (0xfffed, 20),
+    (0x1fffe6, 21),
+    (0x3fffe9, 22),
+    (0x1fffe7, 21),
+    (0x1fffe8, 21),
+    (0x7ffff3, 23),
+    (0x3fffea, 22),
+    (0x3fffeb, 22),
+    (0x1ffffee, 25),
+    (0x1ffffef, 25),
+    (0xfffff4, 24),
+    (0xfffff5, 24),
+    (0x3ffffea, 26),
+    (0x7ffff4, 23),
+    (0x3ffffeb, 26),
+    (0x7ffffe6, 27),
+    (0x3ffffec, 26),
+    (0x3ffffed, 26),
+    (0x7ffffe7, 27),
+    (0x7ffffe8, 27),
+    (0x7ffffe9, 27),
+    (0x7ffffea, 27),
+    (0x7ffffeb, 27),
+    (0xffffffe, 28),
+    (0x7ffffec, 27),
+    (0x7ffffed, 27),
+    (0x7ffffee, 27),
+    (0x7ffffef, 27),
+    (0x7fffff0, 27),
+    (0x3ffffee, 26),
+    (0x3fffffff, 30),
+];
+
+/// Marks a child in the Huffman tree as a symbol rather than another node
+const LEAF: u16 = 0x8000;
+const EOS: u16 = LEAF | 256;
+
+/// Walks each code from the root to build the tree the decoder follows bit by bit, node 0 is the root
+/// The codes are complete, so 257 symbols take 256 nodes and no child is left empty
+const fn huffman_tree() -> [[u16; 2]; 256] {
+    let mut tree = [[0u16; 2]; 256];
+    let mut nodes = 1;
+    let mut symbol = 0;
+    while symbol < HUFFMAN_CODES.len() {
+        let (code, length) = HUFFMAN_CODES[symbol];
+        let mut node = 0;
+        let mut bit = length - 1;
+        while bit > 0 {
+            let branch = (code >> bit & 1) as usize;
+            if tree[node][branch] == 0 {
+                tree[node][branch] = nodes;
+                nodes += 1;
+            }
+            node = tree[node][branch] as usize;
+            bit -= 1;
+        }
+        tree[node][(code & 1) as usize] = LEAF | symbol as u16;
+        symbol += 1;
+    }
+    tree
+}
+
+const HUFFMAN_TREE: [[u16; 2]; 256] = huffman_tree();
+
+fn compression_error(reason: &str) -> Error {
+    Error::new(ErrorKind::HttpFailed(format!(
+        "compression error, {}",
+        reason
+    )))
+}
+
+/// Reads an integer that starts in the low bits of an octet, RFC 7541 section 5.1
+fn read_integer(block: &[u8], position: &mut usize, prefix: u32) -> Result<usize, Error> {
+    let truncated = || compression_error("the header block ends in an integer");
+    let maximum = (1 << prefix) - 1;
+    let first = *block.get(*position).ok_or_else(truncated)? as usize & maximum;
+    *position += 1;
+    if first < maximum {
+        return Ok(first);
+    }
+    let mut value = maximum;
+    for shift in (0..28).step_by(7) {
+        let byte = *block.get(*position).ok_or_else(truncated)?;
+        *position += 1;
+        value += ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(compression_error("an integer is too large"))
+}
+
+fn write_integer(block: &mut Vec<u8>, flags: u8, prefix: u32, value: usize) {
+    let maximum = (1 << prefix) - 1;
+    if value < maximum {
+        block.push(flags | value as u8);
+        return;
+    }
+    block.push(flags | maximum as u8);
+    let mut remaining = value - maximum;
+    while remaining >= 0x80 {
+        block.push(remaining as u8 | 0x80);
+        remaining >>= 7;
+    }
+    block.push(remaining as u8);
+}
+
+/// Decodes a Huffman coded string by following the tree one bit at a time
+fn decode_huffman(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut decoded = Vec::with_capacity(data.len() * 8 / 5);
+    // The node reached, and the bits read since the last symbol
+    let (mut node, mut code, mut length) = (0usize, 0u32, 0u8);
+    for byte in data {
+        for shift in (0..8).rev() {
+            let bit = *byte >> shift & 1;
+            code = code << 1 | bit as u32;
+            length += 1;
+            match HUFFMAN_TREE[node][bit as usize] {
+                EOS => return Err(compression_error("a string contains EOS")),
+                child if child & LEAF != 0 => {
+                    decoded.push(child as u8);
+                    node = 0;
+                    code = 0;
+                    length = 0;
+                }
+                child => node = child as usize,
+            }
+        }
+    }
+    // Padding is the start of EOS, which is all ones, and shorter than an octet
+    if length > 7 || code != (1 << length) - 1 {
+        return Err(compression_error("invalid Huffman padding"));
+    }
+    Ok(decoded)
+}
+
+/// Reads a string literal, Huffman coded or not, RFC 7541 section 5.2
+fn read_string(block: &[u8], position: &mut usize) -> Result<String, Error> {
+    let huffman = block.get(*position).is_some_and(|byte| byte & 0x80 != 0);
+    let length = read_integer(block, position, 7)?;
+    let data = block
+        .get(*position..*position + length)
+        .ok_or_else(|| compression_error("the header block ends in a string"))?;
+    *position += length;
+    let data = match huffman {
+        true => decode_huffman(data)?,
+        false => data.to_vec(),
+    };
+    String::from_utf8(data).map_err(|_| compression_error("a header is not UTF-8"))
+}
+
+/// Writes a string literal as it is, Huffman coding our few short headers isn't worth it
+fn write_string(block: &mut Vec<u8>, string: &str) {
+    write_integer(block, 0, 7, string.len());
+    block.extend_from_slice(string.as_bytes());
+}
+
+/// Encodes headers without touching the dynamic table, so the peer's table never needs tracking
+/// Headers in the static table are indexed, others are literals that may use a static name
+pub fn encode_headers(headers: &[(&str, &str)]) -> Vec<u8> {
+    let mut block = Vec::new();
+    for (name, value) in headers {
+        let index = |found: Option<usize>| found.map(|index| index + 1);
+        if let Some(index) = index(
+            STATIC_TABLE
+                .iter()
+                .position(|entry| entry == &(*name, *value)),
+        ) {
+            write_integer(&mut block, 0x80, 7, index);
+            continue;
+        }
+        match index(STATIC_TABLE.iter().position(|entry| entry.0 == *name)) {
+            Some(index) => write_integer(&mut block, 0, 4, index),
+            None => {
+                block.push(0);
+                write_string(&mut block, name);
+            }
+        }
+        write_string(&mut block, value);
+    }
+    block
+}
+
+/// The space an entry takes in the dynamic table, RFC 7541 section 4.1
+fn entry_size(entry: &(String, String)) -> usize {
+    entry.0.len() + entry.1.len() + 32
+}
+
+impl HeaderDecoder {
+    pub fn new() -> HeaderDecoder {
+        HeaderDecoder {
+            dynamic_table: Vec::new(),
+            size: 0,
+            max_size: MAX_TABLE_SIZE,
+        }
+    }
+
+    /// The entry at an index, the dynamic table follows the static table with its newest entry first
+    fn entry(&self, index: usize) -> Result<(String, String), Error> {
+        match index {
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((String::from(name), String::from(value)))
+            }
+            _ => self
+                .dynamic_table
+                .get(index.wrapping_sub(62))
+                .cloned()
+                .ok_or_else(|| compression_error("an index past the end of the table")),
+        }
+    }
+
+    /// Evicts the oldest entries until the table fits in the given size
+    fn evict(&mut self, max_size: usize) {
+        while self.size > max_size {
+            match self.dynamic_table.pop() {
+                Some(entry) => self.size -= entry_size(&entry),
+                None => break,
+            }
+        }
+    }
+
+    /// Adds an entry, an entry larger than the whole table empties it, RFC 7541 section 4.4
+    fn insert(&mut self, entry: (String, String)) {
+        let size = entry_size(&entry);
+        self.evict(self.max_size.saturating_sub(size));
+        if size <= self.max_size {
+            self.size += size;
+            self.dynamic_table.insert(0, entry);
+        }
+    }
+
+    /// Decodes a complete header block into names and values, in order
+    /// A list larger than MAX_LIST_SIZE fails as soon as it grows past it, however small the block
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<(String, String)>, Error> {
+        let mut headers = Vec::new();
+        let mut list_size = 0;
+        let mut position = 0;
+        while position < block.len() {
+            let first = block[position];
+            if first & 0xE0 == 0x20 {
+                let max_size = read_integer(block, &mut position, 5)?;
+                if max_size > MAX_TABLE_SIZE {
+                    return Err(compression_error("the table grows past our limit"));
+                }
+                self.max_size = max_size;
+                self.evict(max_size);
+                continue;
+            }
+            let header = match first & 0x80 {
+                0 => {
+                    // Literals with incremental indexing have a six bit index, those without or never indexed four bits
+                    let indexed = first & 0xC0 == 0x40;
+                    let index = read_integer(block, &mut position, if indexed { 6 } else { 4 })?;
+                    let name = match index {
+                        0 => read_string(block, &mut position)?,
+                        _ => self.entry(index)?.0,
+                    };
+                    let header = (name, read_string(block, &mut position)?);
+                    if indexed {
+                        self.insert(header.clone());
+                    }
+                    header
+                }
+                _ => self.entry(read_integer(block, &mut position, 7)?)?,
+            };
+            // Indexing one large entry over and over is a small block but a huge list
+            list_size += entry_size(&header);
+            if list_size > MAX_LIST_SIZE {
+                return Err(compression_error("the header list grows past our limit"));
+            }
+            headers.push(header);
+        }
+        Ok(headers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::decode_hex;
+
+    fn header(name: &str, value: &str) -> (String, String) {
+        (String::from(name), String::from(value))
+    }
+
+    #[test]
+    fn test_integers() {
+        // RFC 7541 appendix C.1
+        let mut block = Vec::new();
+        write_integer(&mut block, 0, 5, 10);
+        write_integer(&mut block, 0, 5, 1337);
+        write_integer(&mut block, 0, 8, 42);
+        assert_eq!(block, vec![0x0A, 0x1F, 0x9A, 0x0A, 0x2A]);
+        let mut position = 0;
+        assert_eq!(read_integer(&block, &mut position, 5).unwrap(), 10);
+        assert_eq!(read_integer(&block, &mut position, 5).unwrap(), 1337);
+        assert_eq!(read_integer(&block, &mut position, 8).unwrap(), 42);
+        assert!(read_integer(&[0x1F, 0xFF], &mut 0, 5).is_err());
+    }
+
+    #[test]
+    fn test_huffman_requests() {
+        // RFC 7541 appendix C.4, the second request refers to the table the first one filled
+        let mut decoder = HeaderDecoder::new();
+        let first = decode_hex("828684418CF1E3C2E5F23A6BA0AB90F4FF").unwrap();
+        assert_eq!(
+            decoder.decode(&first).unwrap(),
+            vec![
+                header(":method", "GET"),
+                header(":scheme", "http"),
+                header(":path", "/"),
+                header(":authority", "www.example.com"),
+            ]
+        );
+        assert_eq!(decoder.size, 57);
+        let second = decode_hex("828684BE5886A8EB10649CBF").unwrap();
+        assert_eq!(
+            decoder.decode(&second).unwrap()[3..],
+            [
+                header(":authority", "www.example.com"),
+                header("cache-control", "no-cache"),
+            ]
+        );
+        assert_eq!(decoder.size, 110);
+
+        // Padding that isn't the start of EOS
+        assert!(decode_huffman(&[
+            0xF1, 0xE3, 0xC2, 0xE5, 0xF2, 0x3A, 0x6B, 0xA0, 0xAB, 0x90, 0xF4, 0x00
+        ])
+        .is_err());
+        assert!(decoder.decode(&[0xC0]).is_err());
+
+        // Every code on its own, padded with ones to a whole octet
+        for (symbol, (code, length)) in HUFFMAN_CODES[..256].iter().enumerate() {
+            let padding = (8 - length % 8) % 8;
+            let padded = (*code as u64) << padding | ((1 << padding) - 1);
+            let octets = padded.to_be_bytes();
+            let data = &octets[8 - (*length + padding) as usize / 8..];
+            assert_eq!(decode_huffman(data).unwrap(), vec![symbol as u8]);
+        }
+        assert!(decode_huffman(&[0xFF, 0xFF, 0xFF, 0xFF]).is_err());
+    }
+
+    #[test]
+    fn test_header_list_size() {
+        // One large entry in the dynamic table, then indexed until the list is too large
+        let mut block = vec![0x40];
+        write_string(&mut block, "x-large");
+        write_string(&mut block, &"a".repeat(4000));
+        let mut decoder = HeaderDecoder::new();
+        assert_eq!(decoder.decode(&block).unwrap()[0].1.len(), 4000);
+        let repeated = vec![0xBE; MAX_LIST_SIZE / 4039];
+        assert_eq!(decoder.decode(&repeated).unwrap().len(), repeated.len());
+        block.extend_from_slice(&repeated);
+        assert!(decoder.decode(&block).is_err());
+    }
+
+    #[test]
+    fn test_encode_headers() {
+        let headers = [
+            (":status", "200"),
+            ("content-type", "application/dns-message"),
+            ("x-served-by", "pp"),
+        ];
+        let block = encode_headers(&headers);
+        assert_eq!(block[0], 0x88);
+        let mut decoder = HeaderDecoder::new();
+        let decoded = decoder.decode(&block).unwrap();
+        assert_eq!(
+            decoded,
+            headers
+                .iter()
+                .map(|(name, value)| header(name, value))
+                .collect::<Vec<_>>()
+        );
+        assert!(decoder.dynamic_table.is_empty());
+    }
+}
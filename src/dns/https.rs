@@ -0,0 +1,1096 @@
+use super::{
+    builders::PacketWriter,
+    edns, hpack,
+    proxy::{is_timeout, lock},
+    DnsParser, DomainName, Frame, Header, HeaderDecoder, HttpRequest, HttpsConnection,
+    HttpsExchange, HttpsReader, PatientReader, Question, QuestionClass, QuestionType, Resource,
+    ResourcePayload, ResourceType, SendWindows, StreamReader, StreamWriter, TcpConnection,
+};
+use crate::error::{Error, ErrorKind};
+use crate::helper::decode_base64;
+use std::{
+    collections::HashMap,
+    io::{ErrorKind as IoErrorKind, Read, Write},
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+/// The ALPN protocol IDs clients pick between, RFC 8484 section 5.2 recommends HTTP/2
+pub const H2: &[u8] = b"h2";
+pub const HTTP1: &[u8] = b"http/1.1";
+
+/// What an HTTP/2 client starts the connection with, RFC 9113 section 3.4
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const PATH: &str = "/dns-query";
+const DNS_MESSAGE: &str = "application/dns-message";
+const DNS_JSON: &str = "application/dns-json";
+
+/// A request body or header section larger than any DNS message is refused
+const MAXIMUM_REQUEST_SIZE: usize = 65_535;
+
+/// The default frame size, which we never raise, RFC 9113 section 6.5.2
+const MAXIMUM_FRAME_SIZE: usize = 16_384;
+
+/// The window each side starts with, RFC 9113 section 6.9.2
+const INITIAL_WINDOW: i64 = 65_535;
+const MAXIMUM_WINDOW: i64 = 0x7FFF_FFFF;
+
+const DATA: u8 = 0;
+const HEADERS: u8 = 1;
+const RST_STREAM: u8 = 3;
+const SETTINGS: u8 = 4;
+const PUSH_PROMISE: u8 = 5;
+const PING: u8 = 6;
+const GOAWAY: u8 = 7;
+const WINDOW_UPDATE: u8 = 8;
+const CONTINUATION: u8 = 9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+
+const MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const INITIAL_WINDOW_SIZE: u16 = 0x4;
+const MAX_FRAME_SIZE: u16 = 0x5;
+const MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+const NO_ERROR: u32 = 0x0;
+const PROTOCOL_ERROR: u32 = 0x1;
+const REFUSED_STREAM: u32 = 0x7;
+const CANCEL: u32 = 0x8;
+const COMPRESSION_ERROR: u32 = 0x9;
+
+fn http_error(reason: &str) -> Error {
+    Error::new(ErrorKind::HttpFailed(String::from(reason)))
+}
+
+fn connection_error(error: std::io::Error) -> Error {
+    Error::new(ErrorKind::ConnectionFailed(error.to_string()))
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        415 => "Unsupported Media Type",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Writes a frame in a single write so that the frames of different streams never interleave
+pub fn write_frame<W: Write>(
+    writer: &mut W,
+    frame_type: u8,
+    flags: u8,
+    stream: u32,
+    payload: &[u8],
+) -> Result<(), Error> {
+    let mut frame = Vec::with_capacity(payload.len() + 9);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    frame.push(frame_type);
+    frame.push(flags);
+    frame.extend_from_slice(&(stream & 0x7FFF_FFFF).to_be_bytes());
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame).map_err(connection_error)
+}
+
+/// Reads one frame, returns None when the client closed the connection between frames
+pub fn read_frame<R: Read>(reader: &mut R) -> Result<Option<Frame>, Error> {
+    let mut header = [0u8; 9];
+    match reader.read_exact(&mut header[..1]) {
+        Ok(()) => (),
+        Err(error) if error.kind() == IoErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(connection_error(error)),
+    }
+    reader
+        .read_exact(&mut header[1..])
+        .map_err(connection_error)?;
+    let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+    if length > MAXIMUM_FRAME_SIZE {
+        return Err(http_error("a frame is larger than the maximum frame size"));
+    }
+    let mut payload = vec![0u8; length];
+    reader.read_exact(&mut payload).map_err(connection_error)?;
+    Ok(Some(Frame {
+        frame_type: header[3],
+        flags: header[4],
+        stream: u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7FFF_FFFF,
+        payload,
+    }))
+}
+
+/// Decodes %XX escapes in a query parameter
+fn percent_decode(text: &str) -> Option<String> {
+    let mut decoded = Vec::with_capacity(text.len());
+    let mut bytes = text.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            _ => decoded.push(byte),
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Decodes base64url without padding, RFC 8484 section 4.1
+fn decode_base64_url(text: &str) -> Option<Vec<u8>> {
+    if text.contains(['+', '/', '=']) {
+        return None;
+    }
+    let mut standard = text.replace('-', "+").replace('_', "/");
+    while !standard.len().is_multiple_of(4) {
+        standard.push('=');
+    }
+    decode_base64(&standard)
+}
+
+/// The query a JSON API request asks, with recursion desired and an ID of 0
+fn json_query(name: &str, question_type: &str, checking_disabled: bool) -> Result<Vec<u8>, Error> {
+    let question_type = match question_type.parse::<u16>() {
+        Ok(code) => QuestionType::from(code),
+        Err(_) => ResourceType::from_mnemonic(&question_type.to_ascii_uppercase())
+            .map(|resource_type| QuestionType::from(u16::from(resource_type)))
+            .ok_or_else(|| http_error("an unknown record type"))?,
+    };
+    let mut header = Header::new();
+    header.recursion_desired = true;
+    header.checking_disabled = checking_disabled;
+    header.question_count = 1;
+    let mut message = Vec::with_capacity(512);
+    header.write_header(&mut message)?;
+    let question = Question {
+        domain_name: DomainName::new(name.split('.').filter(|label| !label.is_empty()).collect()),
+        question_type,
+        question_class: QuestionClass::Internet,
+    };
+    let mut writer = PacketWriter::with_data(message);
+    writer.write_question(&question)?;
+    Ok(writer.into_inner())
+}
+
+/// How long an answer may be cached, the lowest TTL of its records, RFC 8484 section 5.1
+/// A negative answer lasts no longer than the minimum of its SOA record, RFC 2308 section 5
+pub fn max_age(answer: &[u8]) -> u32 {
+    let packet = match DnsParser::new().parse_packet(answer) {
+        Ok(packet) => packet,
+        Err(_) => return 0,
+    };
+    let records = packet
+        .answers
+        .iter()
+        .chain(&packet.authority)
+        .chain(&packet.additional);
+    records
+        .filter(|record| record.payload.type_code() != edns::OPTIONS_TYPE)
+        .map(|record| match record.payload {
+            ResourcePayload::StartAuthority { minimum, .. } => record.time_to_live.min(minimum),
+            _ => record.time_to_live,
+        })
+        .min()
+        .unwrap_or(0)
+}
+
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for letter in text.chars() {
+        match letter {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            letter if (letter as u32) < 0x20 => {
+                quoted.push_str(&format!("\\u{:04x}", letter as u32))
+            }
+            letter => quoted.push(letter),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn json_records(records: &[Resource]) -> String {
+    let records = records
+        .iter()
+        .filter(|record| record.payload.type_code() != edns::OPTIONS_TYPE)
+        .map(|record| {
+            format!(
+                "{{\"name\":{},\"type\":{},\"TTL\":{},\"data\":{}}}",
+                json_string(&record.resource_name.to_string()),
+                record.payload.type_code(),
+                record.time_to_live,
+                json_string(&record.payload.to_string())
+            )
+        })
+        .collect::<Vec<_>>();
+    records.join(",")
+}
+
+/// An answer in the JSON format curl users know from public resolvers, records are in presentation format
+pub fn json_answer(answer: &[u8]) -> Result<String, Error> {
+    let packet = DnsParser::new().parse_packet(answer)?;
+    let header = &packet.header;
+    let mut json = format!(
+        "{{\"Status\":{},\"TC\":{},\"RD\":{},\"RA\":{},\"AD\":{},\"CD\":{}",
+        u16::from(header.response_code),
+        header.truncated,
+        header.recursion_desired,
+        header.recursion_available,
+        header.authentic_data,
+        header.checking_disabled
+    );
+    let questions = packet
+        .questions
+        .iter()
+        .map(|question| {
+            format!(
+                "{{\"name\":{},\"type\":{}}}",
+                json_string(&question.domain_name.to_string()),
+                question.question_type.code()
+            )
+        })
+        .collect::<Vec<_>>();
+    json.push_str(&format!(",\"Question\":[{}]", questions.join(",")));
+    let sections = [
+        ("Answer", &packet.answers),
+        ("Authority", &packet.authority),
+        ("Additional", &packet.additional),
+    ];
+    for (section, records) in sections.iter() {
+        let records = json_records(records);
+        if !records.is_empty() {
+            json.push_str(&format!(",\"{}\":[{}]", section, records));
+        }
+    }
+    json.push('}');
+    Ok(json)
+}
+
+impl HttpRequest {
+    /// The first header with this name, names are compared in lower case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The DNS query a request carries and whether the answer is wanted as JSON, or the status to refuse it with
+    /// GET takes a base64url query in dns, or a name, type and cd for the JSON API, and POST takes the query as the body
+    pub fn query(&self) -> Result<(Vec<u8>, bool), u16> {
+        let target = self.header(":path").unwrap_or("");
+        let (path, parameters) = match target.split_once('?') {
+            Some((path, parameters)) => (path, parameters),
+            None => (target, ""),
+        };
+        if path != PATH {
+            return Err(404);
+        }
+        let (query, json) = match self.header(":method") {
+            Some("GET") => {
+                let mut values = HashMap::new();
+                for parameter in parameters
+                    .split('&')
+                    .filter(|parameter| !parameter.is_empty())
+                {
+                    let (name, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+                    values.insert(name, percent_decode(value).ok_or(400u16)?);
+                }
+                match (values.get("dns"), values.get("name")) {
+                    (Some(query), _) => (decode_base64_url(query).ok_or(400u16)?, false),
+                    (None, Some(name)) => {
+                        let question_type = values.get("type").map_or("A", String::as_str);
+                        let checking_disabled =
+                            matches!(values.get("cd").map(String::as_str), Some("1" | "true"));
+                        let query = json_query(name, question_type, checking_disabled)
+                            .map_err(|_| 400u16)?;
+                        (query, true)
+                    }
+                    (None, None) => return Err(400),
+                }
+            }
+            Some("POST") => {
+                let content_type = self.header("content-type").unwrap_or("");
+                let media_type = content_type.split(';').next().unwrap_or("").trim();
+                if !media_type.eq_ignore_ascii_case(DNS_MESSAGE) {
+                    return Err(415);
+                }
+                (self.body.clone(), false)
+            }
+            _ => return Err(405),
+        };
+        if query.len() < 12 {
+            return Err(400);
+        }
+        Ok((query, json))
+    }
+}
+
+impl HttpsConnection {
+    pub fn new(stream: StreamWriter, http2: bool, timeout: Duration) -> HttpsConnection {
+        HttpsConnection {
+            connection: TcpConnection::new(stream),
+            http2,
+            windows: Mutex::new(SendWindows {
+                connection: INITIAL_WINDOW,
+                streams: HashMap::new(),
+                initial: INITIAL_WINDOW,
+                max_frame_size: MAXIMUM_FRAME_SIZE,
+            }),
+            window_opened: Condvar::new(),
+            timeout,
+        }
+    }
+
+    fn send_frame(
+        &self,
+        frame_type: u8,
+        flags: u8,
+        stream: u32,
+        payload: &[u8],
+    ) -> Result<(), Error> {
+        write_frame(
+            &mut *lock(&self.connection.stream),
+            frame_type,
+            flags,
+            stream,
+            payload,
+        )
+    }
+
+    /// Tells the client the connection is closing and the last stream we read, RFC 9113 section 6.8
+    fn go_away(&self, last_stream: u32, error_code: u32) -> Result<(), Error> {
+        let mut payload = last_stream.to_be_bytes().to_vec();
+        payload.extend_from_slice(&error_code.to_be_bytes());
+        self.send_frame(GOAWAY, 0, 0, &payload)
+    }
+
+    /// Takes up to the wanted size out of the send windows, waiting for the client to open them
+    /// Returns None once the stream has been reset
+    fn reserve(&self, stream: u32, wanted: usize) -> Result<Option<usize>, Error> {
+        let started = Instant::now();
+        let mut windows = lock(&self.windows);
+        loop {
+            let stream_window = match windows.streams.get(&stream) {
+                Some(window) => *window,
+                None => return Ok(None),
+            };
+            let available = windows.connection.min(stream_window);
+            if available > 0 {
+                let size = wanted.min(available as usize).min(windows.max_frame_size);
+                windows.connection -= size as i64;
+                if let Some(window) = windows.streams.get_mut(&stream) {
+                    *window -= size as i64;
+                }
+                return Ok(Some(size));
+            }
+            if started.elapsed() >= self.timeout {
+                return Err(http_error(
+                    "the client never opened its flow control window",
+                ));
+            }
+            windows = self
+                .window_opened
+                .wait_timeout(windows, self.timeout)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+    }
+
+    /// Sends a response with these headers and a content length, over HTTP/2 on the stream it answers
+    pub fn respond(
+        &self,
+        stream: u32,
+        status: u16,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<(), Error> {
+        let length = body.len().to_string();
+        if !self.http2 {
+            let mut response = format!("HTTP/1.1 {} {}\r\n", status, reason_phrase(status));
+            for (name, value) in headers {
+                response.push_str(&format!("{}: {}\r\n", name, value));
+            }
+            response.push_str(&format!("content-length: {}\r\n\r\n", length));
+            let mut response = response.into_bytes();
+            response.extend_from_slice(body);
+            return lock(&self.connection.stream)
+                .write_all(&response)
+                .map_err(connection_error);
+        }
+        // A stream the client reset is not answered
+        if !lock(&self.windows).streams.contains_key(&stream) {
+            return Ok(());
+        }
+        let status = status.to_string();
+        let mut fields = vec![(":status", status.as_str())];
+        fields.extend_from_slice(headers);
+        fields.push(("content-length", &length));
+        let flags = match body.is_empty() {
+            true => END_HEADERS | END_STREAM,
+            false => END_HEADERS,
+        };
+        self.send_frame(HEADERS, flags, stream, &hpack::encode_headers(&fields))?;
+        let mut sent = 0;
+        while sent < body.len() {
+            let size = match self.reserve(stream, body.len() - sent)? {
+                Some(size) => size,
+                None => return Ok(()),
+            };
+            let end = sent + size;
+            let flags = if end == body.len() { END_STREAM } else { 0 };
+            self.send_frame(DATA, flags, stream, &body[sent..end])?;
+            sent = end;
+        }
+        lock(&self.windows).streams.remove(&stream);
+        Ok(())
+    }
+
+    /// Refuses a request with a status and its reason as the body
+    pub fn reject(&self, stream: u32, status: u16) -> Result<(), Error> {
+        let mut headers = vec![("content-type", "text/plain")];
+        if status == 405 {
+            headers.push(("allow", "GET, POST"));
+        }
+        self.respond(stream, status, &headers, reason_phrase(status).as_bytes())
+    }
+
+    /// Sends an answer as it was asked for, cacheable for as long as its records last
+    pub fn answer(&self, exchange: HttpsExchange, answer: &[u8]) -> Result<(), Error> {
+        let cache_control = format!("max-age={}", max_age(answer));
+        let (content_type, body) = match exchange.json {
+            true => match json_answer(answer) {
+                Ok(json) => (DNS_JSON, json.into_bytes()),
+                Err(error) => {
+                    self.reject(exchange.stream, 500)?;
+                    return Err(error);
+                }
+            },
+            false => (DNS_MESSAGE, answer.to_vec()),
+        };
+        let headers = [
+            ("content-type", content_type),
+            ("cache-control", cache_control.as_str()),
+        ];
+        self.respond(exchange.stream, 200, &headers, &body)
+    }
+}
+
+impl Read for PatientReader<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            match self.reader.read(buffer) {
+                Err(error) if is_timeout(&error) && Instant::now() < self.deadline => continue,
+                result => return result,
+            }
+        }
+    }
+}
+
+impl HttpsReader {
+    /// Starts reading requests, over HTTP/2 the client's preface is read and our settings are sent first
+    /// A request or frame that has started arriving has until the timeout to arrive in full
+    pub fn new(
+        mut reader: StreamReader,
+        connection: Arc<HttpsConnection>,
+        max_streams: usize,
+        timeout: Duration,
+    ) -> Result<HttpsReader, Error> {
+        if connection.http2 {
+            let mut preface = [0u8; PREFACE.len()];
+            PatientReader {
+                reader: &mut reader,
+                deadline: Instant::now() + timeout,
+            }
+            .read_exact(&mut preface)
+            .map_err(connection_error)?;
+            if preface != PREFACE {
+                return Err(http_error(
+                    "the client didn't start with the HTTP/2 preface",
+                ));
+            }
+            let mut settings = MAX_CONCURRENT_STREAMS.to_be_bytes().to_vec();
+            settings.extend_from_slice(&(max_streams.min(u32::MAX as usize) as u32).to_be_bytes());
+            settings.extend_from_slice(&MAX_HEADER_LIST_SIZE.to_be_bytes());
+            settings.extend_from_slice(&(hpack::MAX_LIST_SIZE as u32).to_be_bytes());
+            connection.send_frame(SETTINGS, 0, 0, &settings)?;
+        }
+        Ok(HttpsReader {
+            reader,
+            connection,
+            buffered: Vec::new(),
+            streams: HashMap::new(),
+            continuing: None,
+            decoder: HeaderDecoder::new(),
+            last_stream: 0,
+            closed: false,
+            max_streams,
+            timeout,
+        })
+    }
+
+    /// Waits for more of a request to arrive, like StreamReader::wait
+    pub fn wait(&mut self) -> std::io::Result<bool> {
+        match self.buffered.is_empty() {
+            true => self.reader.wait(),
+            false => Ok(true),
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Lets an HTTP/2 client know no more requests are read, RFC 9113 section 6.8
+    pub fn close(&mut self) {
+        if self.connection.http2 && !self.closed {
+            let _ = self.connection.go_away(self.last_stream, NO_ERROR);
+        }
+        self.closed = true;
+    }
+
+    /// Reads until a request is complete, over HTTP/2 a single frame is read and None is returned while no request is complete
+    /// The connection is closed on errors, over HTTP/2 with a GOAWAY
+    pub fn read(&mut self) -> Result<Option<(u32, HttpRequest)>, Error> {
+        if !self.connection.http2 {
+            let request = self.read_request();
+            if request.is_err() {
+                self.closed = true;
+            }
+            return request.map(|request| request.map(|request| (0, request)));
+        }
+        let deadline = Instant::now() + self.timeout;
+        let received = self.expire().and_then(|_| {
+            read_frame(&mut PatientReader {
+                reader: &mut self.reader,
+                deadline,
+            })
+        });
+        let received = match received {
+            Ok(Some(frame)) => self.receive(frame),
+            Ok(None) => {
+                self.closed = true;
+                return Ok(None);
+            }
+            Err(error) => Err(error),
+        };
+        if received.is_err() && !self.closed {
+            self.fail(PROTOCOL_ERROR);
+        }
+        received
+    }
+
+    /// Closes the connection on an error, the GOAWAY says which, RFC 9113 section 5.4.1
+    fn fail(&mut self, error_code: u32) {
+        self.closed = true;
+        let _ = self.connection.go_away(self.last_stream, error_code);
+    }
+
+    /// Reads one HTTP/1.1 request, the body has to come with a Content-Length
+    fn read_request(&mut self) -> Result<Option<HttpRequest>, Error> {
+        let deadline = Instant::now() + self.timeout;
+        let end = loop {
+            if let Some(end) = self
+                .buffered
+                .windows(4)
+                .position(|window| window == b"\r\n\r\n")
+            {
+                break end;
+            }
+            if self.buffered.len() > MAXIMUM_REQUEST_SIZE {
+                return Err(http_error("the request header is too large"));
+            }
+            if !self.fill(deadline)? {
+                return Ok(None);
+            }
+        };
+        let head = String::from_utf8(self.buffered[..end].to_vec())
+            .map_err(|_| http_error("the request header is not UTF-8"))?;
+        let mut lines = head.split("\r\n");
+        let request_line = lines.next().unwrap_or("").split(' ').collect::<Vec<_>>();
+        let (method, target, version) = match request_line.as_slice() {
+            [method, target, version] => (*method, *target, *version),
+            _ => return Err(http_error("a malformed request line")),
+        };
+        let mut headers = vec![
+            (String::from(":method"), String::from(method)),
+            (String::from(":path"), String::from(target)),
+        ];
+        for line in lines {
+            let (name, value) = match line.split_once(':') {
+                Some((name, value)) if !name.is_empty() && !name.ends_with(' ') => (name, value),
+                _ => return Err(http_error("a malformed header line")),
+            };
+            headers.push((name.to_ascii_lowercase(), String::from(value.trim())));
+        }
+        let mut request = HttpRequest {
+            headers,
+            body: Vec::new(),
+        };
+        match version {
+            "HTTP/1.1" => (),
+            "HTTP/1.0" => self.closed = true,
+            _ => return Err(http_error("an unsupported HTTP version")),
+        }
+        if request
+            .header("connection")
+            .is_some_and(|value| value.eq_ignore_ascii_case("close"))
+        {
+            self.closed = true;
+        }
+        if request.header("transfer-encoding").is_some() {
+            return Err(http_error("only bodies with a content length are read"));
+        }
+        let length = match request.header("content-length") {
+            Some(length) => length
+                .parse::<usize>()
+                .ok()
+                .filter(|length| *length <= MAXIMUM_REQUEST_SIZE)
+                .ok_or_else(|| http_error("an invalid content length"))?,
+            None => 0,
+        };
+        let start = end + 4;
+        let continuing = request
+            .header("expect")
+            .is_some_and(|value| value.eq_ignore_ascii_case("100-continue"));
+        if continuing && length > 0 && self.buffered.len() == start {
+            lock(&self.connection.connection.stream)
+                .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+                .map_err(connection_error)?;
+        }
+        while self.buffered.len() < start + length {
+            if !self.fill(deadline)? {
+                return Err(http_error(
+                    "the client closed the connection in a request body",
+                ));
+            }
+        }
+        request.body = self.buffered[start..start + length].to_vec();
+        self.buffered.drain(..start + length);
+        Ok(Some(request))
+    }
+
+    /// Reads more of an HTTP/1.1 request, returns false once the client has closed
+    /// A client may pause between reads, but the request has to arrive by the deadline
+    fn fill(&mut self, deadline: Instant) -> Result<bool, Error> {
+        let mut buffer = [0u8; 4096];
+        let mut reader = PatientReader {
+            reader: &mut self.reader,
+            deadline,
+        };
+        let size = reader.read(&mut buffer).map_err(connection_error)?;
+        if size == 0 {
+            self.closed = true;
+            return Ok(false);
+        }
+        self.buffered.extend_from_slice(&buffer[..size]);
+        Ok(true)
+    }
+
+    /// Handles a frame, returns a request once a stream has all of it
+    fn receive(&mut self, frame: Frame) -> Result<Option<(u32, HttpRequest)>, Error> {
+        if let Some((stream, _, _)) = &self.continuing {
+            if frame.frame_type != CONTINUATION || frame.stream != *stream {
+                return Err(http_error("a header block was interrupted"));
+            }
+        }
+        match frame.frame_type {
+            DATA => self.receive_data(frame),
+            HEADERS => {
+                let mut fragment = unpad(&frame)?;
+                if frame.flags & PRIORITY != 0 {
+                    fragment = fragment
+                        .get(5..)
+                        .ok_or_else(|| http_error("a HEADERS frame is too short"))?;
+                }
+                let block = fragment.to_vec();
+                let end_stream = frame.flags & END_STREAM != 0;
+                match frame.flags & END_HEADERS {
+                    0 => {
+                        self.continuing = Some((frame.stream, block, end_stream));
+                        Ok(None)
+                    }
+                    _ => self.receive_headers(frame.stream, &block, end_stream),
+                }
+            }
+            CONTINUATION => {
+                let (stream, mut block, end_stream) = self
+                    .continuing
+                    .take()
+                    .ok_or_else(|| http_error("a CONTINUATION frame without a header block"))?;
+                block.extend_from_slice(&frame.payload);
+                if block.len() > MAXIMUM_REQUEST_SIZE {
+                    return Err(http_error("a header block is too large"));
+                }
+                match frame.flags & END_HEADERS {
+                    0 => {
+                        self.continuing = Some((stream, block, end_stream));
+                        Ok(None)
+                    }
+                    _ => self.receive_headers(stream, &block, end_stream),
+                }
+            }
+            RST_STREAM => {
+                self.forget(frame.stream);
+                Ok(None)
+            }
+            SETTINGS => {
+                self.receive_settings(frame)?;
+                Ok(None)
+            }
+            PING if frame.flags & ACK == 0 => {
+                if frame.payload.len() != 8 {
+                    return Err(http_error("a PING frame is not eight octets"));
+                }
+                self.connection.send_frame(PING, ACK, 0, &frame.payload)?;
+                Ok(None)
+            }
+            GOAWAY => {
+                self.closed = true;
+                Ok(None)
+            }
+            WINDOW_UPDATE => {
+                self.receive_window_update(frame)?;
+                Ok(None)
+            }
+            PUSH_PROMISE => Err(http_error("a client can't push")),
+            // PRIORITY, PING acknowledgements and unknown frames
+            _ => Ok(None),
+        }
+    }
+
+    fn receive_data(&mut self, frame: Frame) -> Result<Option<(u32, HttpRequest)>, Error> {
+        if frame.stream == 0 {
+            return Err(http_error("a DATA frame on the connection"));
+        }
+        // Data counts against the connection window, padding included, and is given back straight away
+        if !frame.payload.is_empty() {
+            let increment = frame.payload.len() as u32;
+            self.connection
+                .send_frame(WINDOW_UPDATE, 0, 0, &increment.to_be_bytes())?;
+        }
+        let data = unpad(&frame)?;
+        let request = match self.streams.get_mut(&frame.stream) {
+            Some((request, _)) => request,
+            // The stream was reset or has already ended
+            None => return Ok(None),
+        };
+        // The stream window is never opened past the first 65535 octets, which is enough for any query
+        if request.body.len() + data.len() > MAXIMUM_REQUEST_SIZE {
+            return Err(http_error("a request body is too large"));
+        }
+        request.body.extend_from_slice(data);
+        if frame.flags & END_STREAM == 0 {
+            return Ok(None);
+        }
+        Ok(self
+            .streams
+            .remove(&frame.stream)
+            .map(|(request, _)| (frame.stream, request)))
+    }
+
+    /// Decodes a complete header block, which opens a stream or ends one with trailers
+    fn receive_headers(
+        &mut self,
+        stream: u32,
+        block: &[u8],
+        end_stream: bool,
+    ) -> Result<Option<(u32, HttpRequest)>, Error> {
+        // Every block goes through the decoder so that its table stays in step with the client's
+        // A block that fails leaves the tables out of step, so the connection can't go on
+        let headers = match self.decoder.decode(block) {
+            Ok(headers) => headers,
+            Err(error) => {
+                self.fail(COMPRESSION_ERROR);
+                return Err(error);
+            }
+        };
+        if let Some((request, _)) = self.streams.remove(&stream) {
+            if !end_stream {
+                return Err(http_error("trailers that don't end the stream"));
+            }
+            return Ok(Some((stream, request)));
+        }
+        if stream.is_multiple_of(2) || stream <= self.last_stream {
+            return Err(http_error("a client opened a stream it can't"));
+        }
+        self.last_stream = stream;
+        {
+            let mut windows = lock(&self.connection.windows);
+            // Streams past the limit we advertised are refused, the client may ask again, RFC 9113 section 5.1.2
+            if windows.streams.len() >= self.max_streams {
+                drop(windows);
+                return self.reset(stream, REFUSED_STREAM).map(|_| None);
+            }
+            let initial = windows.initial;
+            windows.streams.insert(stream, initial);
+        }
+        let request = HttpRequest {
+            headers,
+            body: Vec::new(),
+        };
+        if end_stream {
+            return Ok(Some((stream, request)));
+        }
+        let deadline = Instant::now() + self.timeout;
+        self.streams.insert(stream, (request, deadline));
+        Ok(None)
+    }
+
+    /// Forgets a stream the client reset or we did
+    fn forget(&mut self, stream: u32) {
+        self.streams.remove(&stream);
+        lock(&self.connection.windows).streams.remove(&stream);
+    }
+
+    /// Ends a stream early with RST_STREAM, RFC 9113 section 6.4
+    fn reset(&mut self, stream: u32, error_code: u32) -> Result<(), Error> {
+        self.forget(stream);
+        self.connection
+            .send_frame(RST_STREAM, 0, stream, &error_code.to_be_bytes())
+    }
+
+    /// Resets the streams whose requests haven't arrived in time, however often their frames come
+    fn expire(&mut self) -> Result<(), Error> {
+        let now = Instant::now();
+        let expired = self
+            .streams
+            .iter()
+            .filter(|(_, (_, deadline))| *deadline <= now)
+            .map(|(stream, _)| *stream)
+            .collect::<Vec<_>>();
+        for stream in expired {
+            self.reset(stream, CANCEL)?;
+        }
+        Ok(())
+    }
+
+    fn receive_settings(&mut self, frame: Frame) -> Result<(), Error> {
+        if frame.flags & ACK != 0 {
+            return Ok(());
+        }
+        if frame.stream != 0 || !frame.payload.len().is_multiple_of(6) {
+            return Err(http_error("a malformed SETTINGS frame"));
+        }
+        {
+            let mut windows = lock(&self.connection.windows);
+            for setting in frame.payload.chunks(6) {
+                let identifier = u16::from_be_bytes([setting[0], setting[1]]);
+                let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+                match identifier {
+                    INITIAL_WINDOW_SIZE => {
+                        let value = value as i64;
+                        if value > MAXIMUM_WINDOW {
+                            return Err(http_error("an initial window that is too large"));
+                        }
+                        // Open streams change by the difference, RFC 9113 section 6.9.2
+                        let difference = value - windows.initial;
+                        for window in windows.streams.values_mut() {
+                            *window += difference;
+                        }
+                        windows.initial = value;
+                    }
+                    MAX_FRAME_SIZE => {
+                        if !(16_384..=16_777_215).contains(&value) {
+                            return Err(http_error("an invalid maximum frame size"));
+                        }
+                        // Our frames stay within the default so that writes stay small
+                        windows.max_frame_size = (value as usize).min(MAXIMUM_FRAME_SIZE);
+                    }
+                    // We never add to the client's dynamic table, so its size doesn't matter
+                    _ => (),
+                }
+            }
+        }
+        self.connection.window_opened.notify_all();
+        self.connection.send_frame(SETTINGS, ACK, 0, &[])
+    }
+
+    fn receive_window_update(&mut self, frame: Frame) -> Result<(), Error> {
+        if frame.payload.len() != 4 {
+            return Err(http_error("a WINDOW_UPDATE frame is not four octets"));
+        }
+        let increment = u32::from_be_bytes([
+            frame.payload[0],
+            frame.payload[1],
+            frame.payload[2],
+            frame.payload[3],
+        ]) & 0x7FFF_FFFF;
+        if increment == 0 {
+            return Err(http_error("a window was opened by nothing"));
+        }
+        {
+            let mut windows = lock(&self.connection.windows);
+            let window = match frame.stream {
+                0 => &mut windows.connection,
+                stream => match windows.streams.get_mut(&stream) {
+                    Some(window) => window,
+                    None => return Ok(()),
+                },
+            };
+            *window += increment as i64;
+            if *window > MAXIMUM_WINDOW {
+                return Err(http_error("a window was opened too far"));
+            }
+        }
+        self.connection.window_opened.notify_all();
+        Ok(())
+    }
+}
+
+/// The payload of a frame without its padding, RFC 9113 section 6.1
+fn unpad(frame: &Frame) -> Result<&[u8], Error> {
+    if frame.flags & PADDED == 0 {
+        return Ok(&frame.payload);
+    }
+    let padding = *frame
+        .payload
+        .first()
+        .ok_or_else(|| http_error("a padded frame without a pad length"))?
+        as usize;
+    if padding >= frame.payload.len() {
+        return Err(http_error("more padding than payload"));
+    }
+    Ok(&frame.payload[1..frame.payload.len() - padding])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{builders::PacketWriter, ResourceClass};
+    use std::net::Ipv4Addr;
+
+    fn request(method: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> HttpRequest {
+        let mut fields = vec![
+            (String::from(":method"), String::from(method)),
+            (String::from(":path"), String::from(path)),
+        ];
+        fields.extend(
+            headers
+                .iter()
+                .map(|(name, value)| (String::from(*name), String::from(*value))),
+        );
+        HttpRequest {
+            headers: fields,
+            body: body.to_vec(),
+        }
+    }
+
+    fn answer(ttls: &[u32]) -> Vec<u8> {
+        let mut header = Header::new();
+        header.packet_type = crate::dns::PacketType::Response;
+        header.recursion_desired = true;
+        header.recursion_available = true;
+        header.question_count = 1;
+        header.answer_count = ttls.len() as u16;
+        let mut message = Vec::new();
+        header.write_header(&mut message).unwrap();
+        let question = Question {
+            domain_name: DomainName::new(vec!["www", "example", "com"]),
+            question_type: QuestionType::Address,
+            question_class: QuestionClass::Internet,
+        };
+        let records = ttls
+            .iter()
+            .map(|ttl| Resource {
+                resource_name: DomainName::new(vec!["www", "example", "com"]),
+                resource_class: ResourceClass::Internet,
+                time_to_live: *ttl,
+                payload: ResourcePayload::Address(Ipv4Addr::new(192, 0, 2, 1)),
+            })
+            .collect::<Vec<_>>();
+        let mut writer = PacketWriter::with_data(message);
+        writer.write_question(&question).unwrap();
+        for record in records.iter() {
+            writer.write_resource(record).unwrap();
+        }
+        writer.into_inner()
+    }
+
+    #[test]
+    fn test_request_queries() {
+        // RFC 8484 section 4.1.1, a query for www.example.com with ID 0
+        let encoded = "AAABAAABAAAAAAAAA3d3dwdleGFtcGxlA2NvbQAAAQAB";
+        let (query, json) = request("GET", &format!("/dns-query?dns={}", encoded), &[], &[])
+            .query()
+            .unwrap();
+        assert!(!json);
+        assert_eq!(query.len(), 33);
+        assert_eq!(&query[..4], &[0, 0, 1, 0]);
+
+        let post = request(
+            "POST",
+            "/dns-query",
+            &[("content-type", "application/dns-message")],
+            &query,
+        );
+        assert_eq!(post.query(), Ok((query.clone(), false)));
+
+        let (json_query, json) =
+            request("GET", "/dns-query?name=www.example.com.&type=a", &[], &[])
+                .query()
+                .unwrap();
+        assert!(json);
+        assert_eq!(json_query, query);
+        let (aaaa, _) = request(
+            "GET",
+            "/dns-query?name=www.example.com&type=28&cd=1",
+            &[],
+            &[],
+        )
+        .query()
+        .unwrap();
+        assert_eq!(&aaaa[aaaa.len() - 4..], &[0, 28, 0, 1]);
+        assert_eq!(aaaa[3] & 0x10, 0x10);
+
+        assert_eq!(
+            request("GET", "/resolve?dns=AAAB", &[], &[]).query(),
+            Err(404)
+        );
+        assert_eq!(
+            request("GET", "/dns-query?dns=AAAB%3D", &[], &[]).query(),
+            Err(400)
+        );
+        assert_eq!(request("GET", "/dns-query", &[], &[]).query(), Err(400));
+        assert_eq!(
+            request("GET", "/dns-query?name=x&type=BOGUS", &[], &[]).query(),
+            Err(400)
+        );
+        assert_eq!(request("POST", "/dns-query", &[], &query).query(), Err(415));
+        assert_eq!(request("PUT", "/dns-query", &[], &query).query(), Err(405));
+    }
+
+    #[test]
+    fn test_max_age() {
+        assert_eq!(max_age(&answer(&[300, 60, 3600])), 60);
+        assert_eq!(max_age(&answer(&[])), 0);
+    }
+
+    #[test]
+    fn test_json_answer() {
+        let json = json_answer(&answer(&[300])).unwrap();
+        assert_eq!(
+            json,
+            "{\"Status\":0,\"TC\":false,\"RD\":true,\"RA\":true,\"AD\":false,\"CD\":false,\
+             \"Question\":[{\"name\":\"www.example.com.\",\"type\":1}],\
+             \"Answer\":[{\"name\":\"www.example.com.\",\"type\":1,\"TTL\":300,\"data\":\"192.0.2.1\"}]}"
+        );
+        // A question type we have no name for is given by its number
+        let mut unknown = answer(&[]);
+        let type_position = unknown.len() - 4;
+        unknown[type_position..type_position + 2].copy_from_slice(&99u16.to_be_bytes());
+        let json = json_answer(&unknown).unwrap();
+        assert!(json.contains("\"Question\":[{\"name\":\"www.example.com.\",\"type\":99}]"));
+        assert_eq!(
+            json_string("a \"quoted\"\\\n"),
+            "\"a \\\"quoted\\\"\\\\\\u000a\""
+        );
+    }
+}
@@ -2,20 +2,45 @@ use self::builders::{DomainNameBuilder, DomainNamePointer};
 
 use super::error::{Error, ErrorKind};
 use std::{
-    collections::{HashMap, HashSet},
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     convert::TryFrom,
     fmt::{write, Display},
     io::Cursor,
-    sync::atomic,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    path::PathBuf,
+    sync::{atomic, Arc, Condvar, Mutex},
+    time::{Duration, Instant},
 };
 
+mod authority;
 mod builders;
+mod denial_cache;
+mod dnssec;
+mod edns;
+mod framing;
 mod header;
+mod hpack;
+mod https;
+mod notify;
 mod packet;
 mod parser;
+mod presentation;
+mod proxy;
 mod question;
 mod raw;
 mod resource;
+mod sanitizer;
+mod service_binding;
+mod signer;
+mod spoofing;
+mod tls;
+mod transfer;
+mod tsig;
+mod update;
+mod upstream;
+mod validator;
+mod zone;
 
 pub struct RawPacket {
     data: [u8; 512],
@@ -32,13 +57,15 @@ pub struct DnsParser {
 }
 
 #[derive(Debug, Copy, Clone)]
+#[repr(u16)]
 pub enum QuestionClass {
     Internet = 1,
     CSNet = 2, // Obsolete
     Chaos = 3,
     Hesiod = 4,
     Any = 255,
-    Unknown,
+    // A class we have no name for keeps its number so that it can be written back
+    Unknown(u16),
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -49,9 +76,21 @@ pub enum ResponseCode {
     NXDOMAIN = 3,
     NOTIMP = 4,
     REFUSED = 5,
+    // RFC 2136, failed UPDATE prerequisites and updates outside the zone
+    YXDOMAIN = 6,
+    YXRRSET = 7,
+    NXRRSET = 8,
+    NOTAUTH = 9,
+    NOTZONE = 10,
+    // Extended codes that only fit in the error field of a TSIG record
+    BADSIG = 16,
+    BADKEY = 17,
+    BADTIME = 18,
+    BADTRUNC = 22,
     UNKNOWN,
 }
 #[derive(Debug, Copy, Clone)]
+#[repr(u16)]
 pub enum QuestionType {
     Address = 1,
     NameServer = 2,
@@ -69,23 +108,51 @@ pub enum QuestionType {
     MailboxInformation = 14,
     MailExchange = 15,
     TextStrings = 16,
+    Ipv6Address = 28,
+    Service = 33,
+    NamingAuthorityPointer = 35,
+    DelegationName = 39,
+    DelegationSigner = 43,
+    SshFingerprint = 44,
+    ResourceSignature = 46,
+    NextSecure = 47,
+    DnsKey = 48,
+    NextSecure3 = 50,
+    NextSecure3Parameters = 51,
+    TlsAssociation = 52,
+    SmimeAssociation = 53,
+    ChildDelegationSigner = 59,
+    ChildDnsKey = 60,
+    OpenPgpKey = 61,
+    ServiceBinding = 64,
+    HttpsBinding = 65,
+    TransactionSignature = 250,
+    IncrementalTransfer = 251,
     TransferZone = 252,
     MailboxRelated = 253,
     MailAgent = 254, // Obsolete
     All = 255,       // All available records
-    Unknown,
+    UniformResourceIdentifier = 256,
+    CertificationAuthorityAuthorization = 257,
+    // RFC 3597, a type we have no name for keeps its code so that it can be written back
+    Unknown(u16),
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u16)]
 pub enum ResourceClass {
     Internet = 1,
     CSNet = 2, // Obsolete
     Chaos = 3,
     Hesiod = 4,
-    Unknown,
+    None = 254, // Only used by UPDATE to delete a single record
+    Any = 255,  // Only used by meta records such as TSIG and by UPDATE
+    // RFC 3597, a class we have no name for keeps its number so that it can be written back
+    Unknown(u16),
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u16)]
 pub enum ResourceType {
     Address = 1,
     NameServer = 2,
@@ -103,13 +170,37 @@ pub enum ResourceType {
     MailboxInformation = 14,
     MailExchange = 15,
     TextStrings = 16,
-    Unknown,
+    Ipv6Address = 28,
+    Service = 33,
+    NamingAuthorityPointer = 35,
+    DelegationName = 39,
+    DelegationSigner = 43,
+    SshFingerprint = 44,
+    ResourceSignature = 46,
+    NextSecure = 47,
+    DnsKey = 48,
+    NextSecure3 = 50,
+    NextSecure3Parameters = 51,
+    TlsAssociation = 52,
+    SmimeAssociation = 53,
+    ChildDelegationSigner = 59,
+    ChildDnsKey = 60,
+    OpenPgpKey = 61,
+    ServiceBinding = 64,
+    HttpsBinding = 65,
+    TransactionSignature = 250,
+    UniformResourceIdentifier = 256,
+    CertificationAuthorityAuthorization = 257,
+    // RFC 3597, a type we have no name for keeps its code so that it can be written back
+    Unknown(u16),
 }
 #[derive(Debug, Copy, Clone)]
 pub enum OperationCode {
     StandardQuery = 0,
     InverseQuery = 1,
     ServerStatus = 2,
+    Notify = 4,
+    Update = 5,
     Unknown,
 }
 
@@ -164,18 +255,88 @@ pub struct Question<'a> {
 #[derive(Hash, Eq, PartialEq, Clone, Debug)]
 pub enum DomainName<'a> {
     // Owned str references, ie an uncompressed domain name from the packet, and the basis for the other three types of domain labels
-    Labels(Vec<&'a str>),
+    // Labels parsed from a zone file or built at runtime are owned rather than borrowed from a packet
+    Labels(Vec<Cow<'a, str>>),
     // Partial slices to the above Vec in a different DomainName, of the form [1..], [2..], [3..]
-    LabelVariation(&'a [&'a str]),
+    LabelVariation(&'a [Cow<'a, str>]),
 }
 
 impl<'a> DomainName<'a> {
-    pub fn new(labels: Vec<&'a str>) -> DomainName<'a> {
+    pub fn new<L: Into<Cow<'a, str>>>(labels: Vec<L>) -> DomainName<'a> {
         // TODO: We can calculate the offset of each label when we create the domain name
-        let labels = DomainName::Labels(labels);
+        let labels = DomainName::Labels(labels.into_iter().map(Into::into).collect());
         labels
     }
 
+    /// The root domain name, ie a name with no labels
+    pub fn root() -> DomainName<'static> {
+        DomainName::Labels(Vec::new())
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Copies any borrowed labels so the domain name no longer depends on the packet it was read from
+    pub fn into_owned(self) -> DomainName<'static> {
+        let labels = self
+            .labels()
+            .iter()
+            .map(|label| Cow::Owned(label.to_string()))
+            .collect();
+        DomainName::Labels(labels)
+    }
+
+    /// Appends the labels of origin to the end of this name, used to make a relative name absolute
+    pub fn append(&self, origin: &DomainName<'a>) -> DomainName<'a> {
+        let mut labels = self.labels().to_vec();
+        labels.extend_from_slice(origin.labels());
+        DomainName::Labels(labels)
+    }
+
+    /// Compares two domain names ignoring the case of ASCII letters as required by RFC 4343
+    pub fn eq_ignore_case(&self, other: &DomainName) -> bool {
+        self.len() == other.len()
+            && self
+                .labels()
+                .iter()
+                .zip(other.labels())
+                .all(|(label, other_label)| label.eq_ignore_ascii_case(other_label))
+    }
+
+    /// Returns true if this name is equal to or below the given ancestor
+    pub fn is_subdomain_of(&self, ancestor: &DomainName) -> bool {
+        if ancestor.len() > self.len() {
+            return false;
+        }
+        self.labels()
+            .iter()
+            .rev()
+            .zip(ancestor.labels().iter().rev())
+            .all(|(label, ancestor_label)| label.eq_ignore_ascii_case(ancestor_label))
+    }
+
+    /// Orders names as described in RFC 4034, labels are compared from the right as lower case bytes
+    pub fn canonical_cmp(&self, other: &DomainName) -> std::cmp::Ordering {
+        let lower_case = |label: &str| label.to_ascii_lowercase().into_bytes();
+        for (label, other_label) in self.labels().iter().rev().zip(other.labels().iter().rev()) {
+            match lower_case(label).cmp(&lower_case(other_label)) {
+                std::cmp::Ordering::Equal => continue,
+                ordering => return ordering,
+            }
+        }
+        self.len().cmp(&other.len())
+    }
+
+    /// The length of the name in wire format, including the length octets and the terminating root label
+    pub fn wire_length(&self) -> usize {
+        self.labels()
+            .iter()
+            .map(|label| label.len() + 1)
+            .sum::<usize>()
+            + 1
+    }
+
     pub fn len(&self) -> usize {
         match self {
             DomainName::Labels(labels) => labels.len(),
@@ -183,7 +344,7 @@ impl<'a> DomainName<'a> {
         }
     }
 
-    pub fn labels(&'a self) -> &'a [&'a str] {
+    pub fn labels(&self) -> &[Cow<'a, str>] {
         match self {
             DomainName::Labels(labels) => labels.as_slice(),
             DomainName::LabelVariation(labels) => labels,
@@ -279,38 +440,12 @@ impl<'a> DomainName<'a> {
     }
 }
 
-impl<'a> From<&'a [&'a str]> for DomainName<'a> {
-    fn from(labels: &'a [&'a str]) -> Self {
+impl<'a> From<&'a [Cow<'a, str>]> for DomainName<'a> {
+    fn from(labels: &'a [Cow<'a, str>]) -> Self {
         DomainName::LabelVariation(labels)
     }
 }
 
-impl<'a> Display for DomainName<'a> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        fn display_labels(
-            f: &mut std::fmt::Formatter<'_>,
-            labels_to_display: &[&str],
-        ) -> std::fmt::Result {
-            if let Some((last_label, remaining_labels)) = labels_to_display.split_last() {
-                for label in remaining_labels {
-                    write!(f, "{}.", label)?;
-                }
-                writeln!(f, "{}", last_label)?;
-            }
-            Ok(())
-        };
-        match self {
-            DomainName::Labels(labels) => {
-                display_labels(f, labels)?;
-            }
-            DomainName::LabelVariation(labels) => {
-                display_labels(f, labels)?;
-            }
-        }
-        Ok(())
-    }
-}
-
 /// Contains all the possible domain names that can be created with the labels in this packet
 /// Keyed by packet byte position
 pub struct PreviousNames<'a> {
@@ -358,33 +493,845 @@ pub struct Header {
     truncated: bool,
     recursion_desired: bool,
     recursion_available: bool,
-    // z was reserved by now used in sec dns
+    // RFC 4035, the data was validated by a security aware resolver
+    authentic_data: bool,
+    // RFC 4035, the client asked for validation to be skipped
+    checking_disabled: bool,
     response_code: ResponseCode,
     question_count: u16,
     answer_count: u16,
     authority_count: u16,
     additional_count: u16,
 }
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ResourcePayload<'a> {
-    Address(&'a [u8]),
-    CanonicalName(&'a str),
+    Address(Ipv4Addr),
+    NameServer(DomainName<'a>),
+    MailDestination(DomainName<'a>),
+    MailForwarder(DomainName<'a>),
+    CanonicalName(DomainName<'a>),
+    StartAuthority {
+        primary_name_server: DomainName<'a>,
+        responsible_mailbox: DomainName<'a>,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    MailBox(DomainName<'a>),
+    MailGroup(DomainName<'a>),
+    MailRename(DomainName<'a>),
+    Null(Cow<'a, [u8]>),
+    WellKnownService {
+        address: Ipv4Addr,
+        protocol: u8,
+        bitmap: Cow<'a, [u8]>,
+    },
+    DomainName(DomainName<'a>),
+    HostInformation {
+        cpu: Cow<'a, [u8]>,
+        operating_system: Cow<'a, [u8]>,
+    },
+    MailboxInformation {
+        responsible_mailbox: DomainName<'a>,
+        error_mailbox: DomainName<'a>,
+    },
+    MailExchange {
+        preference: u16,
+        exchange: DomainName<'a>,
+    },
+    TextStrings(Vec<Cow<'a, [u8]>>),
+    Ipv6Address(Ipv6Addr),
+    // RFC 2782, the target is never compressed
+    Service {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: DomainName<'a>,
+    },
+    // RFC 3403, the replacement is never compressed
+    NamingAuthorityPointer {
+        order: u16,
+        preference: u16,
+        flags: Cow<'a, [u8]>,
+        services: Cow<'a, [u8]>,
+        regular_expression: Cow<'a, [u8]>,
+        replacement: DomainName<'a>,
+    },
+    // RFC 6672, redirects every name below the owner, the target is never compressed
+    DelegationName(DomainName<'a>),
+    // RFC 4034, CDS from RFC 7344 uses the same format
+    DelegationSigner {
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        digest: Cow<'a, [u8]>,
+    },
+    // RFC 4255
+    SshFingerprint {
+        algorithm: u8,
+        fingerprint_type: u8,
+        fingerprint: Cow<'a, [u8]>,
+    },
+    // RFC 4034, the signer name is never compressed
+    ResourceSignature {
+        type_covered: u16,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        // Seconds since the epoch using serial number arithmetic
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer_name: DomainName<'a>,
+        signature: Cow<'a, [u8]>,
+    },
+    // RFC 4034, the next name is never compressed
+    NextSecure {
+        next_domain_name: DomainName<'a>,
+        types: TypeBitmap,
+    },
+    // RFC 4034, CDNSKEY from RFC 7344 uses the same format
+    DnsKey {
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        public_key: Cow<'a, [u8]>,
+    },
+    // RFC 5155
+    NextSecure3 {
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Cow<'a, [u8]>,
+        next_hashed_owner: Cow<'a, [u8]>,
+        types: TypeBitmap,
+    },
+    NextSecure3Parameters {
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Cow<'a, [u8]>,
+    },
+    // RFC 6698, SMIMEA from RFC 8162 uses the same format
+    TlsAssociation {
+        certificate_usage: u8,
+        selector: u8,
+        matching_type: u8,
+        certificate_data: Cow<'a, [u8]>,
+    },
+    SmimeAssociation {
+        certificate_usage: u8,
+        selector: u8,
+        matching_type: u8,
+        certificate_data: Cow<'a, [u8]>,
+    },
+    ChildDelegationSigner {
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        digest: Cow<'a, [u8]>,
+    },
+    ChildDnsKey {
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        public_key: Cow<'a, [u8]>,
+    },
+    // RFC 7929, a transferable public key
+    OpenPgpKey(Cow<'a, [u8]>),
+    // RFC 7553
+    UniformResourceIdentifier {
+        priority: u16,
+        weight: u16,
+        target: Cow<'a, [u8]>,
+    },
+    // RFC 8659, the tag is usually issue, issuewild or iodef
+    CertificationAuthorityAuthorization {
+        flags: u8,
+        tag: Cow<'a, [u8]>,
+        value: Cow<'a, [u8]>,
+    },
+    // RFC 9460, SVCB and HTTPS share the same format
+    ServiceBinding(ServiceBinding<'a>),
+    HttpsBinding(ServiceBinding<'a>),
+    // RFC 8945, always the last additional record and its names are never compressed
+    TransactionSignature {
+        algorithm: DomainName<'a>,
+        // Seconds since the epoch, only the low 48 bits are sent
+        time_signed: u64,
+        fudge: u16,
+        mac: Cow<'a, [u8]>,
+        original_id: u16,
+        error: u16,
+        other_data: Cow<'a, [u8]>,
+    },
+    // A resource type we don't understand, the data is kept so that it can still be forwarded or written out
+    Unknown {
+        resource_type: u16,
+        data: Cow<'a, [u8]>,
+    },
 }
 
-impl Display for ResourcePayload<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ResourcePayload::Address(address) => writeln!(
-                f,
-                "Address: {}.{}.{}.{}",
-                address[0], address[1], address[2], address[3]
-            )?,
-            ResourcePayload::CanonicalName(canonical_name) => {
-                writeln!(f, "Canonical Name: {}", canonical_name)?
-            }
-        }
-        Ok(())
-    }
+/// The types that exist at a name as listed by NSEC and NSEC3, kept sorted without duplicates
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TypeBitmap {
+    types: Vec<u16>,
+}
+
+/// The contents of SVCB and HTTPS resources, a priority of zero means the resource is an alias for the target
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceBinding<'a> {
+    priority: u16,
+    target: DomainName<'a>,
+    // Always kept in ascending order of key as required on the wire
+    parameters: Vec<ServiceParameter<'a>>,
+}
+
+/// A SvcParam, values for keys we don't understand are kept as is
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServiceParameter<'a> {
+    Mandatory(Vec<u16>),
+    Alpn(Vec<Cow<'a, [u8]>>),
+    NoDefaultAlpn,
+    Port(u16),
+    Ipv4Hint(Vec<Ipv4Addr>),
+    EncryptedClientHello(Cow<'a, [u8]>),
+    Ipv6Hint(Vec<Ipv6Addr>),
+    Unknown { key: u16, value: Cow<'a, [u8]> },
+}
+
+/// Formats a value in the presentation format shared by zone files and dig style output
+/// Names are written relative to the origin when one is given
+pub struct Presentation<'p, T> {
+    value: &'p T,
+    origin: Option<&'p DomainName<'p>>,
+}
+
+/// Extended DNS errors from RFC 8914, sent in the OPT record to explain why a query failed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExtendedError {
+    UnsupportedDnsKeyAlgorithm = 1,
+    DnssecIndeterminate = 5,
+    DnssecBogus = 6,
+    SignatureExpired = 7,
+    SignatureNotYetValid = 8,
+    DnsKeyMissing = 9,
+    RrsigsMissing = 10,
+    NoZoneKeyBitSet = 11,
+    NsecMissing = 12,
+}
+
+/// The security status of a response, as defined in RFC 4033 section 5
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ValidationResult {
+    // Every record was proven by a chain of trust from a trust anchor
+    Secure,
+    // The records belong to a zone that was proven to be unsigned
+    Insecure,
+    // The records should have been signed but could not be validated
+    Bogus(ExtendedError),
+    // No trust anchor covers the records
+    Indeterminate,
+}
+
+/// DS and DNSKEY records that are trusted without being validated, every chain of trust starts at one of these
+pub struct TrustAnchors {
+    anchors: Vec<Resource<'static>>,
+}
+
+/// Validates responses against the trust anchors, keys proven on the way are remembered for later responses
+pub struct Validator {
+    trust_anchors: TrustAnchors,
+    // The DNSKEY records proven to be secure for each zone
+    trusted_keys: Vec<(DomainName<'static>, Vec<ResourcePayload<'static>>)>,
+    // The DS records proven to be secure for each delegated zone
+    trusted_delegations: Vec<(DomainName<'static>, Vec<ResourcePayload<'static>>)>,
+    // Zones proven to be unsigned by a secure denial of their DS records
+    insecure_zones: Vec<DomainName<'static>>,
+    // Signatures are checked against this time instead of the current time when set
+    validation_time: Option<u32>,
+}
+
+/// Validated NSEC and NSEC3 records, used to deny other names in the same ranges without asking upstream as described in RFC 8198
+pub struct DenialCache {
+    // Keyed by the canonical labels of the zone name, the lock is not held while names are hashed
+    zones: Mutex<HashMap<Vec<Vec<u8>>, CachedZone>>,
+    // Records expire against this time instead of the current time when set
+    cache_time: Option<u32>,
+    hits: Mutex<DenialCacheHits>,
+}
+
+/// How often the denial cache could answer a query
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct DenialCacheHits {
+    name_errors: u64,
+    no_data: u64,
+    misses: u64,
+}
+
+/// The denial records cached for a zone, along with the SOA that synthesized answers carry
+struct CachedZone {
+    start_authority: Option<CachedRecordSet>,
+    // NSEC records keyed by the canonical labels of their owner, so the record covering a name is the one before it
+    next_secure: BTreeMap<Vec<Vec<u8>>, CachedRecordSet>,
+    // NSEC3 records keyed the same way, the hashed owner labels sort in the order of the hashes
+    next_secure_3: BTreeMap<Vec<Vec<u8>>, CachedRecordSet>,
+    // The hash algorithm, iterations and salt of the NSEC3 records, a new set of parameters replaces the old records
+    hash_parameters: Option<(u8, u16, Vec<u8>)>,
+    // The type and key of each record in the order they were cached, the oldest are dropped first
+    order: VecDeque<(u16, Vec<Vec<u8>>)>,
+}
+
+/// A validated RRset and its signatures, kept until the earliest of the TTLs and signature expirations
+#[derive(Clone)]
+struct CachedRecordSet {
+    records: Vec<Resource<'static>>,
+    signatures: Vec<Resource<'static>>,
+    expires: u32,
+}
+
+/// A private key that signs the data of a zone, along with the DNSKEY fields published for it
+pub struct SigningKey {
+    zone: DomainName<'static>,
+    // Keys with the secure entry point flag sign the DNSKEY RRset, the rest sign everything else
+    flags: u16,
+    algorithm: u8,
+    key_pair: SigningKeyPair,
+    // The PKCS#8 document the key pair was read from, kept so a generated key can be written out
+    pkcs8: Vec<u8>,
+}
+
+/// The key pairs ring can sign with
+enum SigningKeyPair {
+    Rsa(ring::signature::RsaKeyPair),
+    Ecdsa(ring::signature::EcdsaKeyPair),
+    Ed25519(ring::signature::Ed25519KeyPair),
+}
+
+/// How a signed zone proves that a name or type does not exist
+#[derive(Debug, Clone, PartialEq)]
+pub enum DenialOfExistence {
+    // RFC 4034, a chain of NSEC records linking every name in the zone
+    NextSecure,
+    // RFC 5155, a chain of NSEC3 records linking the hashes of every name in the zone
+    NextSecure3 { iterations: u16, salt: Vec<u8> },
+    // RFC 9824, a single NSEC record covering only the query name, NXDOMAIN is answered as NODATA
+    Compact,
+}
+
+/// Signs answers from a locally served zone as they are sent, signatures are cached until they need refreshing
+pub struct ZoneSigner {
+    zone: DomainName<'static>,
+    keys: Vec<SigningKey>,
+    // The zone data, including the DNSKEY records of the keys
+    records: Vec<Resource<'static>>,
+    // The names in the zone in canonical order with the types at each, built again when the records change
+    owners: Vec<(DomainName<'static>, Vec<u16>)>,
+    // The NSEC3 chain as hashed owners in order with the types at each, empty unless the zone is denied with NSEC3
+    hashed_owners: Vec<(Vec<u8>, Vec<u16>)>,
+    denial: DenialOfExistence,
+    // How long new signatures are valid for, in seconds
+    signature_lifetime: u32,
+    // Keyed by the owner name in canonical wire format and the type of the signed RRset
+    signature_cache: HashMap<(Vec<u8>, u16), CachedSignatures>,
+    // Signatures are made for this time instead of the current time when set
+    signing_time: Option<u32>,
+}
+
+/// The signatures made for an RRset, kept with the records so changed data is signed again
+struct CachedSignatures {
+    records: Vec<Resource<'static>>,
+    signatures: Vec<Resource<'static>>,
+    expiration: u32,
+}
+
+/// The records sharing an owner, class and type, along with the signatures covering them
+struct RecordSet<'r, 'a> {
+    name: &'r DomainName<'a>,
+    resource_type: u16,
+    records: Vec<&'r Resource<'a>>,
+    signatures: Vec<&'r Resource<'a>>,
+}
+
+/// The HMAC algorithms a TSIG key can use, RFC 8945 section 6
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TsigAlgorithm {
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+}
+
+/// A secret shared with another server, used to sign messages with TSIG
+#[derive(Clone)]
+pub struct TsigKey {
+    name: DomainName<'static>,
+    algorithm: TsigAlgorithm,
+    secret: Vec<u8>,
+}
+
+/// The TSIG keys shared with other servers, found by the key name in a signed message
+#[derive(Clone, Default)]
+pub struct Keyring {
+    keys: Vec<TsigKey>,
+}
+
+/// Signs and verifies the messages of one transaction, ie a request and its responses, each MAC covers the one before it
+pub struct TsigSession {
+    key: TsigKey,
+    // The MAC of the last signed message, the next one is chained to it
+    previous_mac: Option<Vec<u8>>,
+    // Messages after the request and first response only cover the timers, RFC 8945 section 5.3.1
+    signed_messages: usize,
+    // Unsigned messages of a TCP sequence received since the last signed one
+    unsigned_messages: Vec<u8>,
+    unsigned_count: usize,
+    // The signing time of the last verified message, a BADTIME response is signed with it
+    peer_time: Option<u64>,
+    // Why the request failed verification, reported in the TSIG of the response
+    error: Option<ResponseCode>,
+    // Messages are signed and checked for this time instead of the current time when set
+    signing_time: Option<u64>,
+}
+
+/// The records removed and added to move a zone from one SOA to the next, as sent in an IXFR, RFC 1995
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZoneDiff {
+    from: Resource<'static>,
+    removed: Vec<Resource<'static>>,
+    to: Resource<'static>,
+    added: Vec<Resource<'static>>,
+}
+
+/// A zone served to secondaries, with the diffs between recent serials kept for IXFR
+pub struct PrimaryZone {
+    zone: DomainName<'static>,
+    records: Vec<Resource<'static>>,
+    // Oldest first, the last diff ends at the current SOA
+    history: Vec<ZoneDiff>,
+    // Sent a NOTIFY when the zone changes, signed with the key when there is one
+    secondaries: Vec<(SocketAddr, Option<TsigKey>)>,
+    update_policy: UpdatePolicy,
+    // Every change to the zone is appended here and replayed when the zone is loaded again
+    journal: Option<PathBuf>,
+}
+
+/// Who may change a primary zone with UPDATE messages, RFC 2136
+#[derive(Clone)]
+pub enum UpdatePolicy {
+    Refused,
+    Anyone,
+    // The UPDATE must be signed with one of the keys in the keyring
+    Signed(Keyring),
+}
+
+/// Where the proxy listens and forwards to, read from the command line
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProxyConfig {
+    listen: SocketAddr,
+    // Each upstream with its weight, in the order the failover strategy tries them
+    upstreams: Vec<(SocketAddr, u32)>,
+    // The upstreams above that are reached over DNS over TLS instead of UDP, RFC 7858
+    tls_upstreams: HashMap<SocketAddr, TlsUpstream>,
+    strategy: UpstreamStrategy,
+    health_check: HealthCheck,
+    // DNS over TLS clients are accepted here when it is set, RFC 7858 section 3.1 suggests port 853
+    tls_listen: Option<SocketAddr>,
+    // DNS over HTTPS clients are accepted here when it is set, with the same certificate, RFC 8484
+    https_listen: Option<SocketAddr>,
+    // The PEM certificate chain and private key DNS over TLS and DNS over HTTPS clients are answered with
+    tls_certificate: Option<(PathBuf, PathBuf)>,
+    // Whether the case of query names is randomized and has to come back the same in answers
+    randomize_case: bool,
+    sanitizer: Sanitizer,
+    // Queries the upstream hasn't answered within this time are forgotten
+    timeout: Duration,
+    // TCP connections without a query or an answer for this long are closed, RFC 7766 section 6.2.3
+    idle_timeout: Duration,
+    // Connections beyond this are closed as soon as they are accepted
+    max_connections: usize,
+    // Queries a single connection can have waiting on the upstream before we stop reading from it
+    max_pipelined: usize,
+    // Whether failures and removed records on the paths every query takes are logged
+    verbose: bool,
+    // A zone file of DS and DNSKEY records, answers are validated against them when it is set
+    trust_anchors: Option<PathBuf>,
+    // Zones loaded from these files are answered authoritatively and served over AXFR and IXFR
+    primary_zones: Vec<(DomainName<'static>, PathBuf)>,
+    // Zones transferred from these primaries and answered authoritatively once we hold a copy
+    secondary_zones: Vec<(DomainName<'static>, SocketAddr)>,
+    // Clients allowed to transfer the zones we hold
+    allow_transfer: Vec<IpAddr>,
+    // Transfers of our zones must be signed with a key from the keyring file, the named key signs our own
+    // transfer requests and NOTIFY messages and is the only one accepted in a NOTIFY
+    transfer_keys: Option<(PathBuf, DomainName<'static>)>,
+    // Secondaries sent a NOTIFY when one of the primary zones changes, they may transfer the zones too
+    notify: Vec<SocketAddr>,
+    // Primary zones changed by UPDATE, by anyone or only with a key from the keyring file, RFC 2136
+    updates: Vec<(DomainName<'static>, Option<PathBuf>)>,
+    // PKCS#8 PEM keys primary zones are signed with as they are answered, for clients that set DO, and their DNSKEY flags
+    signing_keys: Vec<(DomainName<'static>, PathBuf, u16)>,
+    // The DNSKEY algorithm number of every signing key
+    signing_algorithm: u8,
+    // Seconds signatures are valid for, the signer's default when not given
+    signature_lifetime: Option<u32>,
+    denial: DenialOfExistence,
+}
+
+/// Forwards queries from clients to an upstream resolver and relays the answers back, over UDP, TCP, TLS and HTTPS
+/// Each query is sent upstream with a random ID of our own from a socket of its own, RFC 5452
+pub struct Proxy {
+    socket: UdpSocket,
+    listener: TcpListener,
+    // Accepts DNS over TLS clients, whose queries are handled like those over TCP
+    tls_listener: Option<(TcpListener, Arc<rustls::ServerConfig>)>,
+    // Accepts DNS over HTTPS clients with the same certificate
+    https_listener: Option<(TcpListener, Arc<rustls::ServerConfig>)>,
+    upstreams: Vec<Upstream>,
+    strategy: UpstreamStrategy,
+    // Counts queries for the round robin strategy
+    next_upstream: atomic::AtomicUsize,
+    health_check: HealthCheck,
+    randomize_case: bool,
+    sanitizer: Sanitizer,
+    timeout: Duration,
+    idle_timeout: Duration,
+    max_connections: usize,
+    max_pipelined: usize,
+    verbose: bool,
+    // Validates answers for clients that didn't set CD, the keys it has proven are kept for later answers
+    validator: Option<Mutex<Validator>>,
+    // The NSEC and NSEC3 records of secure answers, names and types they deny are answered without asking upstream
+    denial_cache: DenialCache,
+    connections: atomic::AtomicUsize,
+    // Queries over UDP waiting on a socket and thread of their own
+    waiting: atomic::AtomicUsize,
+    pending: Mutex<PendingQueries>,
+    // The zones we answer for authoritatively, also served over AXFR and IXFR to the clients it allows
+    transfers: Mutex<TransferServer>,
+    // Refreshed in the background, each is copied into the transfer server after a transfer
+    secondaries: Mutex<Vec<SecondaryZone>>,
+    // Signs answers from the primary zones that have keys, kept in step with the zones as they are updated
+    signers: Vec<Mutex<ZoneSigner>>,
+}
+
+/// How the proxy picks the upstream each query is forwarded to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpstreamStrategy {
+    // The first upstream that isn't failing, in the order they were given
+    Failover,
+    RoundRobin,
+    // Picked at random, an upstream with twice the weight gets twice the queries
+    Weighted,
+    // The upstream with the lowest smoothed round trip time
+    LowestLatency,
+}
+
+/// The probe sent to each upstream and how many results in a row decide whether it is up or down
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthCheck {
+    name: DomainName<'static>,
+    question_type: ResourceType,
+    interval: Duration,
+    // A probe not answered within this time has failed
+    timeout: Duration,
+    // Probes or client queries in a row that fail before an upstream is marked down
+    down_after: u32,
+    // Probes in a row that succeed before a down upstream is marked up again
+    up_after: u32,
+}
+
+/// How a DNS over TLS upstream is authenticated, RFC 7858 section 4 and RFC 8310
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsUpstream {
+    // Sent in SNI and checked against the certificate when it is checked against certificate authorities
+    name: String,
+    // A PEM file of the certificate authorities the upstream's certificate has to chain to
+    certificate_authorities: Option<PathBuf>,
+    // SHA-256 digests of SubjectPublicKeyInfo, the certificate's has to be one of them, RFC 7858 section 4.2
+    pins: Vec<[u8; 32]>,
+}
+
+/// Checks a DNS over TLS upstream's certificate against certificate authorities, pins or both
+#[derive(Debug)]
+struct PinnedVerifier {
+    // Checks the chain and the name, only the pins are checked when there are no certificate authorities
+    authorities: Option<Arc<rustls::client::WebPkiServerVerifier>>,
+    pins: Vec<[u8; 32]>,
+    provider: Arc<rustls::crypto::CryptoProvider>,
+}
+
+/// Whether an upstream is sent client queries, a down upstream only gets probes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpstreamState {
+    Up,
+    Down,
+}
+
+/// An upstream resolver and what we have measured of it
+pub struct Upstream {
+    address: SocketAddr,
+    weight: u32,
+    // Set for a DNS over TLS upstream, every query is then sent over the streams below
+    tls: Option<(Arc<rustls::ClientConfig>, rustls::pki_types::ServerName<'static>)>,
+    // Truncated answers are asked for again over these, every query to a DNS over TLS upstream goes over them
+    streams: Mutex<Vec<Arc<UpstreamStream>>>,
+    health: Mutex<UpstreamHealth>,
+}
+
+struct UpstreamHealth {
+    // Smoothed round trip time as in RFC 6298, None until the upstream has answered
+    srtt: Option<Duration>,
+    // When the SRTT was last measured, it counts for less the longer ago that was
+    measured: Instant,
+    state: UpstreamState,
+    // Probes or queries in a row the upstream hasn't answered in time
+    failures: u32,
+    // Probes in a row the upstream has answered
+    successes: u32,
+}
+
+/// The queries waiting on the upstream, keyed by the ID they were forwarded with
+struct PendingQueries {
+    queries: HashMap<u16, PendingQuery>,
+    last_expiry: Instant,
+}
+
+struct PendingQuery {
+    requester: Requester,
+    // The index of the upstream the query was sent to, only its answer is accepted
+    upstream: usize,
+    // The ID the client used, restored in the answer
+    id: u16,
+    forwarded: Instant,
+    // Whether the client asked for our idle timeout with the RFC 7828 keepalive option
+    keepalive: bool,
+    // The query as it was forwarded, sent again over TCP when the answer is truncated
+    query: Vec<u8>,
+    // The question section as the client sent it, put back in the answer in case its case was randomized
+    question: Vec<u8>,
+    // Whether the query was sent again over TCP, a late answer over UDP is then ignored
+    over_tcp: bool,
+    // The largest answer a UDP client accepts, anything larger is truncated
+    payload_size: usize,
+    // Whether the client sent an OPT record and set DO itself, the DNSSEC records we asked for are hidden otherwise
+    options: bool,
+    dnssec_ok: bool,
+}
+
+/// Where the answer to a query goes back to
+enum Requester {
+    Udp(SocketAddr),
+    Tcp(Arc<TcpConnection>),
+    // The answer goes back as the HTTP response to a DNS over HTTPS request
+    Https(Arc<HttpsConnection>, HttpsExchange),
+}
+
+/// The sending half of a client connection, answers are written as they arrive so they can be out of order
+struct TcpConnection {
+    stream: Mutex<StreamWriter>,
+    // The number of queries waiting on the upstream, and when the last of them was answered
+    in_flight: Mutex<(usize, Instant)>,
+    released: Condvar,
+}
+
+/// A persistent connection to an upstream, queries are pipelined over it and answered in any order
+struct UpstreamStream {
+    stream: Mutex<StreamWriter>,
+    // Queries sent and not yet answered, new connections are opened when the existing ones are busy
+    in_flight: atomic::AtomicUsize,
+    // Set once the upstream closes the connection or it fails
+    closed: atomic::AtomicBool,
+}
+
+/// The sending half of a DNS over HTTPS connection, RFC 8484
+struct HttpsConnection {
+    // Writes the responses and counts the requests waiting on the upstream
+    connection: TcpConnection,
+    // HTTP/2 streams are answered in any order, HTTP/1.1 requests one at a time in the order they came in
+    http2: bool,
+    // What the client lets us send before it opens its windows again, RFC 9113 section 6.9
+    windows: Mutex<SendWindows>,
+    window_opened: Condvar,
+    // A response that still can't be sent after this long is given up on
+    timeout: Duration,
+}
+
+/// The HTTP/2 flow control windows responses are sent within
+struct SendWindows {
+    connection: i64,
+    // Only streams still waiting on their response are listed, a stream the client reset is dropped
+    streams: HashMap<u32, i64>,
+    initial: i64,
+    // The largest frame payload the client accepts
+    max_frame_size: usize,
+}
+
+/// How the answer to a DNS over HTTPS request is sent back
+#[derive(Debug, Clone, Copy)]
+struct HttpsExchange {
+    // The HTTP/2 stream the request came on, 0 over HTTP/1.1
+    stream: u32,
+    // Whether the answer is wanted as application/dns-json rather than application/dns-message
+    json: bool,
+}
+
+/// An HTTP request, over HTTP/1.1 the method and target are kept as the :method and :path pseudo headers too
+struct HttpRequest {
+    // Names are in lower case
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+/// Reads DNS over HTTPS requests off a connection, over HTTP/1.1 or HTTP/2
+struct HttpsReader {
+    reader: StreamReader,
+    connection: Arc<HttpsConnection>,
+    // Read past the end of the last HTTP/1.1 request
+    buffered: Vec<u8>,
+    // HTTP/2 requests whose bodies are still arriving, by stream, and when each has to be complete
+    streams: HashMap<u32, (HttpRequest, Instant)>,
+    // The stream whose header block goes on in CONTINUATION frames, the block so far and whether it ends the request
+    continuing: Option<(u32, Vec<u8>, bool)>,
+    decoder: HeaderDecoder,
+    // The highest stream the client has opened, new streams have to be higher
+    last_stream: u32,
+    // Set once the client closed the connection or has asked for it to be closed
+    closed: bool,
+    // The streams open at once that we advertised, streams being answered count too
+    max_streams: usize,
+    // How long a request or frame may take to arrive once it has started
+    timeout: Duration,
+}
+
+/// Reads from a socket that times out every poll interval, waiting out those timeouts until a deadline
+struct PatientReader<'r> {
+    reader: &'r mut StreamReader,
+    deadline: Instant,
+}
+
+/// An HTTP/2 frame, RFC 9113 section 4.1
+struct Frame {
+    frame_type: u8,
+    flags: u8,
+    stream: u32,
+    payload: Vec<u8>,
+}
+
+/// Decodes HTTP/2 header blocks, the dynamic table lasts as long as the connection, RFC 7541
+struct HeaderDecoder {
+    // Newest entry first
+    dynamic_table: Vec<(String, String)>,
+    // Each entry counts its name and value and 32 octets more
+    size: usize,
+    max_size: usize,
+}
+
+/// The reading half of a connection, over plain TCP or TLS
+pub enum StreamReader {
+    Tcp(TcpStream),
+    Tls(TlsReader),
+}
+
+/// The writing half of a connection, over plain TCP or TLS
+pub enum StreamWriter {
+    Tcp(TcpStream),
+    Tls(TlsWriter),
+}
+
+/// Reads records off the socket and decrypts them, the session is shared with the writing half
+pub struct TlsReader {
+    session: Arc<Mutex<rustls::Connection>>,
+    socket: TcpStream,
+}
+
+/// Encrypts and writes, the reading half can't process records while a write holds the session
+pub struct TlsWriter {
+    session: Arc<Mutex<rustls::Connection>>,
+    socket: TcpStream,
+}
+
+/// Strips the records of an upstream answer that the upstream has no say over, RFC 5452 section 6
+/// Answers are sanitized before they are cached or forwarded
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sanitizer {
+    // Every record has to be at or below this zone, the root when forwarding to a recursive resolver
+    bailiwick: DomainName<'static>,
+}
+
+/// A record the sanitizer removed from an answer
+#[derive(Debug, Clone, PartialEq)]
+pub struct Removal {
+    section: Section,
+    name: DomainName<'static>,
+    type_code: u16,
+    reason: RemovalReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Section {
+    Answer,
+    Authority,
+    Additional,
+}
+
+/// Why the sanitizer removed a record
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RemovalReason {
+    // The owner is outside the bailiwick of the upstream
+    OutOfBailiwick,
+    // An answer the chain of CNAMEs and DNAMEs from the question doesn't lead to
+    OffChain,
+    // Authority or additional data that nothing else in the answer refers to
+    Unrelated,
+}
+
+/// What a primary zone answers to a question, RFC 1034 section 4.3.2
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZoneAnswer {
+    response_code: ResponseCode,
+    // Referrals are not authoritative, the data belongs to the child zone
+    authoritative: bool,
+    answers: Vec<Resource<'static>>,
+    authority: Vec<Resource<'static>>,
+    additional: Vec<Resource<'static>>,
+}
+
+/// Serves AXFR and IXFR of local zones to the clients allowed to transfer them
+pub struct TransferServer {
+    zones: Vec<PrimaryZone>,
+    allowed: Vec<IpAddr>,
+    // When set, requests must be signed with one of these keys
+    keyring: Option<Keyring>,
+}
+
+/// A zone pulled from a primary and kept up to date with the refresh, retry and expire timers of its SOA
+#[derive(Clone)]
+pub struct SecondaryZone {
+    zone: DomainName<'static>,
+    primary: SocketAddr,
+    // Transfer requests are signed with this key and the responses must be too
+    key: Option<TsigKey>,
+    // Empty until the first transfer succeeds
+    records: Vec<Resource<'static>>,
+    last_success: Option<u32>,
+    last_failure: Option<u32>,
+    // A NOTIFY from the primary makes a refresh due straight away
+    notified: bool,
+    // Timers are checked against this time instead of the current time when set
+    transfer_time: Option<u32>,
+}
+
+/// What a refresh of a secondary zone did
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TransferOutcome {
+    // The primary has the same serial as us
+    UpToDate,
+    // Diffs were applied to the records we had
+    Incremental,
+    // The whole zone was transferred
+    Full,
 }
 
 pub struct DnsPacket<'a> {
@@ -395,10 +1342,11 @@ pub struct DnsPacket<'a> {
     additional: Vec<Resource<'a>>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Resource<'a> {
     // The contents of a resource is based on its class and its type.
     resource_name: DomainName<'a>,
+    resource_class: ResourceClass,
     time_to_live: u32,
     payload: ResourcePayload<'a>,
 }
@@ -407,6 +1355,16 @@ pub struct Resource<'a> {
 mod tests {
     use super::*;
 
+    /// Parses a name written with dots, shared by the tests of the other modules
+    pub(crate) fn name(text: &str) -> DomainName<'static> {
+        DomainName::new(
+            text.split('.')
+                .filter(|label| !label.is_empty())
+                .map(String::from)
+                .collect(),
+        )
+    }
+
     #[test]
     fn test_has_suitable_pointer() {
         // create a domain name that is look to see if it can use a pointer
@@ -437,13 +1395,19 @@ mod tests {
         let original_domain_name = DomainName::new(vec!["dev", "break", "com"]);
         let original_domain_name2 = DomainName::new(vec!["spi", "google", "com"]);
         let previous_name = DomainNameBuilder::new(&original_domain_name, 12);
-        let previous_name2 = DomainNameBuilder::new(&original_domain_name2, 12+15);
+        let previous_name2 = DomainNameBuilder::new(&original_domain_name2, 12 + 15);
         let list_of_names = vec![previous_name, previous_name2];
         let labels = vec!["box", "spi", "google", "com"];
         let google = DomainName::new(labels);
         let res = google.has_suitable_pointer(list_of_names.as_slice());
-        let expected = vec!["box"];
-        assert_eq!(res, Some(DomainNamePointer::LabelsThenPointer(expected.as_slice(), 27)));
+        let expected = vec![Cow::from("box")];
+        assert_eq!(
+            res,
+            Some(DomainNamePointer::LabelsThenPointer(
+                expected.as_slice(),
+                27
+            ))
+        );
         println!("Result: {:?}", res);
     }
 
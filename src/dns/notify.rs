@@ -0,0 +1,433 @@
+use super::{
+    builders::{DnsResponseBuilder, PacketWriter},
+    spoofing,
+    transfer::serial_is_newer,
+    DnsPacket, DnsParser, DomainName, Header, Keyring, OperationCode, PacketType, PrimaryZone,
+    Question, QuestionClass, QuestionType, Resource, ResourcePayload, ResponseCode, SecondaryZone,
+    TransferServer, TsigKey, TsigSession,
+};
+use crate::error::{Error, ErrorKind};
+use std::{
+    io::ErrorKind as IoErrorKind,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    thread,
+    time::Duration,
+};
+
+/// How long to wait for a secondary to acknowledge a NOTIFY before sending it again
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(2);
+/// A NOTIFY is sent again until it is acknowledged or this many have gone unanswered, RFC 1996 section 3.6
+const NOTIFY_ATTEMPTS: usize = 5;
+
+/// Answers a NOTIFY from the primary of one of our zones, RFC 1996 section 3.7
+/// The zone becomes due for a refresh unless the NOTIFY carries a serial that isn't newer than ours
+pub fn answer_notify(
+    zones: &mut [SecondaryZone],
+    request: &[u8],
+    peer: IpAddr,
+) -> Result<Vec<u8>, Error> {
+    let packet = DnsParser::new().parse_packet(request)?;
+    let position = packet.questions.first().and_then(|question| {
+        zones
+            .iter()
+            .position(|zone| zone.zone.eq_ignore_case(&question.domain_name))
+    });
+    // Only the key of the zone is accepted, anything else is answered with BADKEY
+    let mut keyring = Keyring::new();
+    if let Some(key) = position.and_then(|position| zones[position].key.as_ref()) {
+        keyring.add(key.clone());
+    }
+    let mut session = keyring.session(&packet);
+    if let Some(session) = &mut session {
+        let _ = session.verify(request, &packet);
+    }
+    let response_code = match notify_zone(zones, position, &packet, peer, &session) {
+        Ok(zone) => {
+            let notified_serial = packet
+                .answers
+                .iter()
+                .find_map(|record| match record.payload {
+                    ResourcePayload::StartAuthority { serial, .. } => Some(serial),
+                    _ => None,
+                });
+            let current = match (notified_serial, zone.serial()) {
+                (Some(notified), Some(serial)) => !serial_is_newer(notified, serial),
+                _ => false,
+            };
+            if !current {
+                zone.notified = true;
+            }
+            ResponseCode::NOERROR
+        }
+        Err(response_code) => response_code,
+    };
+    let mut builder = DnsResponseBuilder::new(packet.header.id);
+    builder
+        .operation_code(OperationCode::Notify)
+        .authoritative(response_code == ResponseCode::NOERROR)
+        .response_code(response_code);
+    if let Some(question) = packet.questions.first() {
+        builder.add_question(question.clone());
+    }
+    match &mut session {
+        Some(session) => builder.build_signed_response(session),
+        None => builder.build_response(),
+    }
+}
+
+/// The zone a NOTIFY is for, or the response code it is refused with
+fn notify_zone<'z>(
+    zones: &'z mut [SecondaryZone],
+    position: Option<usize>,
+    packet: &DnsPacket,
+    peer: IpAddr,
+    session: &Option<TsigSession>,
+) -> Result<&'z mut SecondaryZone, ResponseCode> {
+    let is_notify = matches!(packet.header.operation_code, OperationCode::Notify);
+    match packet.questions.as_slice() {
+        [question]
+            if is_notify && matches!(question.question_type, QuestionType::StartAuthority) => {}
+        _ => return Err(ResponseCode::FORMERR),
+    }
+    if session
+        .as_ref()
+        .is_some_and(|session| session.error().is_some())
+    {
+        return Err(ResponseCode::NOTAUTH);
+    }
+    let zone = &mut zones[position.ok_or(ResponseCode::NOTAUTH)?];
+    // Only the primary we transfer from may tell us about changes
+    if (zone.key.is_some() && session.is_none()) || zone.primary.ip() != peer {
+        return Err(ResponseCode::REFUSED);
+    }
+    Ok(zone)
+}
+
+/// A NOTIFY for the zone holding its current SOA in the answer section, RFC 1996 section 3.7
+fn notify_message(
+    id: u16,
+    zone: &DomainName<'static>,
+    start_authority: &Resource<'static>,
+) -> Result<Vec<u8>, Error> {
+    let mut header = Header::new();
+    header.id = id;
+    header.operation_code = OperationCode::Notify;
+    header.authorative = true;
+    header.question_count = 1;
+    header.answer_count = 1;
+    let mut message = Vec::with_capacity(512);
+    header.write_header(&mut message)?;
+    let question = Question {
+        domain_name: zone.clone(),
+        question_type: QuestionType::StartAuthority,
+        question_class: QuestionClass::Internet,
+    };
+    let mut writer = PacketWriter::with_data(message);
+    writer.write_question(&question)?;
+    writer.write_resource(start_authority)?;
+    Ok(writer.into_inner())
+}
+
+/// Sends a NOTIFY to one secondary over UDP, waiting for it to be acknowledged
+fn notify_secondary(
+    zone: &DomainName<'static>,
+    start_authority: &Resource<'static>,
+    secondary: SocketAddr,
+    key: Option<&TsigKey>,
+) -> Result<(), Error> {
+    let connection_failed = |error: std::io::Error| {
+        Error::new(ErrorKind::ConnectionFailed(format!(
+            "{}: {}",
+            secondary, error
+        )))
+    };
+    let local: IpAddr = match secondary {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = UdpSocket::bind((local, 0)).map_err(connection_failed)?;
+    socket
+        .connect(secondary)
+        .and_then(|_| socket.set_read_timeout(Some(NOTIFY_TIMEOUT)))
+        .map_err(connection_failed)?;
+    let id = spoofing::random_id()?;
+    let mut request = notify_message(id, zone, start_authority)?;
+    let mut session = key.cloned().map(TsigSession::new);
+    if let Some(session) = &mut session {
+        session.sign(&mut request)?;
+    }
+    let mut buffer = [0u8; 4096];
+    for _ in 0..NOTIFY_ATTEMPTS {
+        socket.send(&request).map_err(connection_failed)?;
+        let size = match socket.recv(&mut buffer) {
+            Ok(size) => size,
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    IoErrorKind::WouldBlock | IoErrorKind::TimedOut
+                ) =>
+            {
+                continue
+            }
+            Err(error) => return Err(connection_failed(error)),
+        };
+        let response = &buffer[..size];
+        // Anything that isn't an answer to this NOTIFY is ignored, it may be a late answer to an earlier one
+        let packet = match DnsParser::new().parse_packet(response) {
+            Ok(packet) if packet.header.id == id => packet,
+            _ => continue,
+        };
+        let is_response = matches!(packet.header.packet_type, PacketType::Response)
+            && matches!(packet.header.operation_code, OperationCode::Notify);
+        if !is_response {
+            continue;
+        }
+        if let Some(session) = &mut session {
+            session.verify(response, &packet)?;
+        }
+        if packet.header.response_code != ResponseCode::NOERROR {
+            return Err(Error::new(ErrorKind::NotifyFailed(format!(
+                "{} answered {:?}",
+                secondary, packet.header.response_code
+            ))));
+        }
+        return Ok(());
+    }
+    Err(Error::new(ErrorKind::NotifyFailed(format!(
+        "{} didn't answer after {} attempts",
+        secondary, NOTIFY_ATTEMPTS
+    ))))
+}
+
+impl PrimaryZone {
+    /// Sends a NOTIFY to the secondary whenever the zone changes, signed when there is a key
+    pub fn add_secondary(&mut self, secondary: SocketAddr, key: Option<TsigKey>) -> &mut Self {
+        self.secondaries.push((secondary, key));
+        self
+    }
+
+    /// Tells every secondary about the current serial, the secondaries are notified in parallel
+    pub fn send_notify(&self) -> Vec<(SocketAddr, Result<(), Error>)> {
+        let start_authority = self.start_authority();
+        thread::scope(|scope| {
+            let pending: Vec<_> = self
+                .secondaries
+                .iter()
+                .map(|(secondary, key)| {
+                    let notify = scope.spawn(move || {
+                        notify_secondary(&self.zone, start_authority, *secondary, key.as_ref())
+                    });
+                    (*secondary, notify)
+                })
+                .collect();
+            pending
+                .into_iter()
+                .map(|(secondary, notify)| {
+                    let result = notify.join().unwrap_or_else(|_| {
+                        Err(Error::new(ErrorKind::NotifyFailed(format!(
+                            "notifying {} panicked",
+                            secondary
+                        ))))
+                    });
+                    (secondary, result)
+                })
+                .collect()
+        })
+    }
+}
+
+impl TransferServer {
+    /// Replaces the records of a zone and notifies its secondaries in the background
+    pub fn update_zone(
+        &mut self,
+        zone: &DomainName,
+        records: Vec<Resource<'static>>,
+    ) -> Result<(), Error> {
+        let primary = self.zone_mut(zone).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidZone(format!(
+                "{} isn't a primary zone",
+                zone
+            )))
+        })?;
+        primary.update(records)?;
+        if primary.secondaries.is_empty() {
+            return Ok(());
+        }
+        let mut notifier = PrimaryZone::new(primary.zone.clone(), primary.records.clone())?;
+        notifier.secondaries = primary.secondaries.clone();
+        thread::spawn(move || {
+            for (secondary, result) in notifier.send_notify() {
+                if let Err(error) = result {
+                    println!(
+                        "Failed to notify {} of {}: {}",
+                        secondary, notifier.zone, error
+                    );
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{tests::name, zone::ZoneParser, ResourceClass, TsigAlgorithm};
+
+    fn example_zone() -> PrimaryZone {
+        let records = ZoneParser::new(None)
+            .parse_file("zones/example.com.zone")
+            .unwrap();
+        PrimaryZone::new(name("example.com"), records).unwrap()
+    }
+
+    fn transfer_key() -> TsigKey {
+        Keyring::from_file("testdata/keys/transfer.example.com.key")
+            .unwrap()
+            .get(&name("transfer.example.com"))
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn test_answer_notify() {
+        let primary = example_zone();
+        let request = notify_message(0x1234, primary.zone(), primary.start_authority()).unwrap();
+        let localhost: IpAddr = Ipv4Addr::LOCALHOST.into();
+        let mut zones = vec![SecondaryZone::new(
+            name("example.com"),
+            SocketAddr::new(localhost, 53),
+        )];
+        zones[0].transfer_time = Some(1_700_000_000);
+        zones[0].last_success = Some(1_700_000_000 - 10);
+        zones[0].records = primary.records().to_vec();
+        let answer = |zones: &mut [SecondaryZone], request: &[u8], peer: IpAddr| {
+            let response = answer_notify(zones, request, peer).unwrap();
+            let packet = DnsParser::new().parse_packet(&response).unwrap();
+            assert_eq!(packet.header.id, 0x1234);
+            assert!(matches!(
+                packet.header.operation_code,
+                OperationCode::Notify
+            ));
+            packet.header.response_code
+        };
+
+        // Our copy already holds the serial, nothing to do
+        assert_eq!(
+            answer(&mut zones, &request, localhost),
+            ResponseCode::NOERROR
+        );
+        assert!(!zones[0].notified);
+        let mut newer = primary.records().to_vec();
+        for record in &mut newer {
+            if let ResourcePayload::StartAuthority { serial, .. } = &mut record.payload {
+                *serial += 1;
+            }
+        }
+        let mut updated = example_zone();
+        updated.update(newer).unwrap();
+        let request = notify_message(0x1234, updated.zone(), updated.start_authority()).unwrap();
+        assert_eq!(
+            answer(&mut zones, &request, "192.0.2.1".parse().unwrap()),
+            ResponseCode::REFUSED
+        );
+        assert!(!zones[0].notified);
+        assert_eq!(
+            answer(&mut zones, &request, localhost),
+            ResponseCode::NOERROR
+        );
+        assert_eq!(zones[0].next_refresh(), 1_700_000_000);
+
+        let mut other = primary.start_authority().clone();
+        other.resource_name = name("example.org");
+        let request = notify_message(0x1234, &name("example.org"), &other).unwrap();
+        assert_eq!(
+            answer(&mut zones, &request, localhost),
+            ResponseCode::NOTAUTH
+        );
+
+        // Once the zone has a key the NOTIFY has to be signed with it
+        zones[0].key(transfer_key());
+        let mut request =
+            notify_message(0x1234, primary.zone(), primary.start_authority()).unwrap();
+        assert_eq!(
+            answer(&mut zones, &request, localhost),
+            ResponseCode::REFUSED
+        );
+        let wrong_key = TsigKey::new(
+            name("transfer.example.com"),
+            TsigAlgorithm::HmacSha256,
+            vec![7; 32],
+        );
+        let mut signed = request.clone();
+        TsigSession::new(wrong_key).sign(&mut signed).unwrap();
+        assert_eq!(
+            answer(&mut zones, &signed, localhost),
+            ResponseCode::NOTAUTH
+        );
+        TsigSession::new(transfer_key()).sign(&mut request).unwrap();
+        assert_eq!(
+            answer(&mut zones, &request, localhost),
+            ResponseCode::NOERROR
+        );
+    }
+
+    #[test]
+    fn test_notify_secondaries() {
+        let secondary = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = secondary.local_addr().unwrap();
+        let mut primary = example_zone();
+        primary.add_secondary(address, Some(transfer_key()));
+        let mut zones = vec![SecondaryZone::new(
+            name("example.com"),
+            "127.0.0.1:53".parse().unwrap(),
+        )];
+        zones[0].key(transfer_key());
+
+        thread::scope(|scope| {
+            let server = scope.spawn(|| {
+                let mut buffer = [0u8; 4096];
+                for _ in 0..2 {
+                    let (size, peer) = secondary.recv_from(&mut buffer).unwrap();
+                    let response = answer_notify(&mut zones, &buffer[..size], peer.ip()).unwrap();
+                    secondary.send_to(&response, peer).unwrap();
+                    // The primary of the zone moves away so the second NOTIFY is refused
+                    zones[0].primary = "192.0.2.1:53".parse().unwrap();
+                }
+                zones[0].notified
+            });
+            let results = primary.send_notify();
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].0, address);
+            assert!(results[0].1.is_ok());
+            let results = primary.send_notify();
+            assert!(matches!(
+                results[0].1.as_ref().map_err(|error| error.kind()),
+                Err(ErrorKind::NotifyFailed(_))
+            ));
+            assert!(server.join().unwrap());
+        });
+
+        // Zones without secondaries are updated without sending anything
+        let mut server = TransferServer::new();
+        server.add_zone(example_zone());
+        let mut records = example_zone().records().to_vec();
+        for record in &mut records {
+            if let ResourcePayload::StartAuthority { serial, .. } = &mut record.payload {
+                *serial += 1;
+            }
+        }
+        records.push(Resource::new(
+            name("new.example.com"),
+            ResourceClass::Internet,
+            300,
+            ResourcePayload::Address("192.0.2.99".parse().unwrap()),
+        ));
+        server.update_zone(&name("example.com"), records).unwrap();
+        assert!(server
+            .zone(&name("example.com"))
+            .unwrap()
+            .diffs_since(example_zone().serial())
+            .is_some());
+    }
+}
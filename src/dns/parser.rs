@@ -1,10 +1,14 @@
 use super::{
     DnsPacket, DnsParser, DomainName, Error, ErrorKind, Header, OperationCode, PacketType,
     PreviousNames, Question, QuestionClass, QuestionType, Resource, ResourceClass, ResourcePayload,
-    ResourceType, ResponseCode,
+    ResourceType, ResponseCode, ServiceBinding, TsigSession, TypeBitmap,
 };
 use byteorder::{NetworkEndian, ReadBytesExt};
-use std::io::Cursor;
+use std::{
+    borrow::Cow,
+    io::Cursor,
+    net::{Ipv4Addr, Ipv6Addr},
+};
 
 impl DnsParser {
     pub fn new() -> DnsParser {
@@ -32,7 +36,7 @@ impl DnsParser {
             answers.push(answer);
         }
 
-        for _ in 0..additional_count {
+        for _ in 0..authority_count {
             let authority = self.read_answer(packet_data, &mut previous_names)?;
             authorities.push(authority);
         }
@@ -45,6 +49,17 @@ impl DnsParser {
         Ok(packet)
     }
 
+    /// Parses a packet and verifies its TSIG record against the session key
+    pub fn parse_signed_packet<'a>(
+        &mut self,
+        packet_data: &'a [u8],
+        session: &mut TsigSession,
+    ) -> Result<DnsPacket<'a>, Error> {
+        let packet = self.parse_packet(packet_data)?;
+        session.verify(packet_data, &packet)?;
+        Ok(packet)
+    }
+
     pub fn read_header(&mut self, packet_data: &[u8]) -> Result<Header, Error> {
         // Read the header of a DNS packet, requires a cursor for any type that can be turned into a reference to a u8 slice
         let mut reader = Cursor::new(&packet_data[0..12]);
@@ -68,8 +83,12 @@ impl DnsParser {
         let recursion_desired = Self::get_bit_position(7, 1, &bitmask) == 1;
         // Recursion Available 1 bit field
         let recursion_available = Self::get_bit_position(8, 1, &bitmask) == 1;
-        // Z Ignore for now 3 bit field
-        let z = Self::get_bit_position(9, 3, &bitmask);
+        // Z Ignore for now 1 bit field
+        let z = Self::get_bit_position(9, 1, &bitmask);
+        // Authentic Data 1 bit field
+        let authentic_data = Self::get_bit_position(10, 1, &bitmask) == 1;
+        // Checking Disabled 1 bit field
+        let checking_disabled = Self::get_bit_position(11, 1, &bitmask) == 1;
         // Response Code 4 bit field
         let response_code = ResponseCode::from(Self::get_bit_position(12, 4, &bitmask));
         // --
@@ -96,6 +115,8 @@ impl DnsParser {
             truncated: truncation,
             recursion_available,
             recursion_desired,
+            authentic_data,
+            checking_disabled,
             operation_code: op_code,
             response_code: response_code,
             packet_type: query_response,
@@ -331,20 +352,24 @@ impl DnsParser {
             .map_err(|err| Error::new(ErrorKind::ReadPacketDataFailed))?;
         // ReadPacketDataFailed
         self.position += 2;
-        let payload = if rs == ResourceClass::Internet {
-            match rt {
-                ResourceType::Address => {
-                    let address = &packet_data[self.position..self.position + 4];
-                    println!("Address: {:?}", address);
-                    ResourcePayload::Address(address)
+        // RFC 2136, UPDATE matches whole RRsets with an empty RDATA and class ANY or NONE
+        let payload = match rs {
+            ResourceClass::Any | ResourceClass::None if resource_length == 0 => {
+                ResourcePayload::Unknown {
+                    resource_type,
+                    data: Cow::Borrowed(&[]),
                 }
-                _ => return Err(Error::new(ErrorKind::ReadPacketDataFailed)),
             }
-        } else {
-            return Err(Error::new(ErrorKind::ReadPacketDataFailed));
+            _ => self.read_payload(
+                packet_data,
+                resource_type,
+                resource_length as usize,
+                domain_labels,
+            )?,
         };
         let resource = Resource {
             resource_name: domain_name,
+            resource_class: rs,
             time_to_live: ttl,
             payload,
         };
@@ -352,6 +377,414 @@ impl DnsParser {
         Ok(resource)
     }
 
+    /// Reads the RDATA of a resource starting at the current position, the position is left at the end of the RDATA
+    pub fn read_payload<'a>(
+        &mut self,
+        packet_data: &'a [u8],
+        resource_type: u16,
+        resource_length: usize,
+        domain_labels: &mut PreviousNames<'a>,
+    ) -> Result<ResourcePayload<'a>, Error> {
+        let payload_end = self.position + resource_length;
+        if payload_end > packet_data.len() {
+            return Err(Error::new(ErrorKind::ReadPacketDataFailed));
+        }
+        let data = &packet_data[self.position..payload_end];
+        let payload = match ResourceType::from(resource_type) {
+            ResourceType::Address if data.len() == 4 => {
+                ResourcePayload::Address(Ipv4Addr::new(data[0], data[1], data[2], data[3]))
+            }
+            ResourceType::Ipv6Address if data.len() == 16 => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(data);
+                ResourcePayload::Ipv6Address(Ipv6Addr::from(octets))
+            }
+            ResourceType::Address | ResourceType::Ipv6Address => {
+                return Err(Error::new(ErrorKind::ReadPacketDataFailed))
+            }
+            ResourceType::NameServer => {
+                ResourcePayload::NameServer(self.read_domain_name(packet_data, domain_labels)?)
+            }
+            ResourceType::MailDestination => {
+                ResourcePayload::MailDestination(self.read_domain_name(packet_data, domain_labels)?)
+            }
+            ResourceType::MailForwarder => {
+                ResourcePayload::MailForwarder(self.read_domain_name(packet_data, domain_labels)?)
+            }
+            ResourceType::CanonicalName => {
+                ResourcePayload::CanonicalName(self.read_domain_name(packet_data, domain_labels)?)
+            }
+            ResourceType::MailBox => {
+                ResourcePayload::MailBox(self.read_domain_name(packet_data, domain_labels)?)
+            }
+            ResourceType::MailGroup => {
+                ResourcePayload::MailGroup(self.read_domain_name(packet_data, domain_labels)?)
+            }
+            ResourceType::MailRename => {
+                ResourcePayload::MailRename(self.read_domain_name(packet_data, domain_labels)?)
+            }
+            ResourceType::DomainName => {
+                ResourcePayload::DomainName(self.read_domain_name(packet_data, domain_labels)?)
+            }
+            ResourceType::StartAuthority => {
+                let primary_name_server = self.read_domain_name(packet_data, domain_labels)?;
+                let responsible_mailbox = self.read_domain_name(packet_data, domain_labels)?;
+                let mut reader = Cursor::new(Self::remaining_payload(
+                    packet_data,
+                    self.position,
+                    payload_end,
+                )?);
+                let mut read_u32 = || {
+                    reader
+                        .read_u32::<NetworkEndian>()
+                        .map_err(|_| Error::new(ErrorKind::ReadPacketDataFailed))
+                };
+                ResourcePayload::StartAuthority {
+                    primary_name_server,
+                    responsible_mailbox,
+                    serial: read_u32()?,
+                    refresh: read_u32()?,
+                    retry: read_u32()?,
+                    expire: read_u32()?,
+                    minimum: read_u32()?,
+                }
+            }
+            ResourceType::Null => ResourcePayload::Null(Cow::Borrowed(data)),
+            ResourceType::WellKnownService if data.len() >= 5 => {
+                ResourcePayload::WellKnownService {
+                    address: Ipv4Addr::new(data[0], data[1], data[2], data[3]),
+                    protocol: data[4],
+                    bitmap: Cow::Borrowed(&data[5..]),
+                }
+            }
+            ResourceType::WellKnownService => {
+                return Err(Error::new(ErrorKind::ReadPacketDataFailed))
+            }
+            ResourceType::HostInformation => {
+                let mut offset = 0;
+                let cpu = Self::read_character_string(data, &mut offset)?;
+                let operating_system = Self::read_character_string(data, &mut offset)?;
+                ResourcePayload::HostInformation {
+                    cpu: Cow::Borrowed(cpu),
+                    operating_system: Cow::Borrowed(operating_system),
+                }
+            }
+            ResourceType::MailboxInformation => ResourcePayload::MailboxInformation {
+                responsible_mailbox: self.read_domain_name(packet_data, domain_labels)?,
+                error_mailbox: self.read_domain_name(packet_data, domain_labels)?,
+            },
+            ResourceType::MailExchange => {
+                let preference = Cursor::new(data)
+                    .read_u16::<NetworkEndian>()
+                    .map_err(|_| Error::new(ErrorKind::ReadPacketDataFailed))?;
+                self.position += 2;
+                ResourcePayload::MailExchange {
+                    preference,
+                    exchange: self.read_domain_name(packet_data, domain_labels)?,
+                }
+            }
+            ResourceType::TextStrings => {
+                let mut offset = 0;
+                let mut strings = Vec::new();
+                while offset < data.len() {
+                    let string = Self::read_character_string(data, &mut offset)?;
+                    strings.push(Cow::Borrowed(string));
+                }
+                ResourcePayload::TextStrings(strings)
+            }
+            ResourceType::Service => {
+                let mut reader = Cursor::new(data);
+                let mut read_u16 = || {
+                    reader
+                        .read_u16::<NetworkEndian>()
+                        .map_err(|_| Error::new(ErrorKind::ReadPacketDataFailed))
+                };
+                let (priority, weight, port) = (read_u16()?, read_u16()?, read_u16()?);
+                self.position += 6;
+                ResourcePayload::Service {
+                    priority,
+                    weight,
+                    port,
+                    target: self.read_domain_name(packet_data, domain_labels)?,
+                }
+            }
+            ResourceType::NamingAuthorityPointer => {
+                let mut reader = Cursor::new(data);
+                let mut read_u16 = || {
+                    reader
+                        .read_u16::<NetworkEndian>()
+                        .map_err(|_| Error::new(ErrorKind::ReadPacketDataFailed))
+                };
+                let (order, preference) = (read_u16()?, read_u16()?);
+                let mut offset = 4;
+                let flags = Self::read_character_string(data, &mut offset)?;
+                let services = Self::read_character_string(data, &mut offset)?;
+                let regular_expression = Self::read_character_string(data, &mut offset)?;
+                self.position += offset;
+                ResourcePayload::NamingAuthorityPointer {
+                    order,
+                    preference,
+                    flags: Cow::Borrowed(flags),
+                    services: Cow::Borrowed(services),
+                    regular_expression: Cow::Borrowed(regular_expression),
+                    replacement: self.read_domain_name(packet_data, domain_labels)?,
+                }
+            }
+            ResourceType::DelegationName => {
+                ResourcePayload::DelegationName(self.read_domain_name(packet_data, domain_labels)?)
+            }
+            ResourceType::UniformResourceIdentifier if data.len() >= 4 => {
+                ResourcePayload::UniformResourceIdentifier {
+                    priority: u16::from_be_bytes([data[0], data[1]]),
+                    weight: u16::from_be_bytes([data[2], data[3]]),
+                    target: Cow::Borrowed(&data[4..]),
+                }
+            }
+            ResourceType::UniformResourceIdentifier => {
+                return Err(Error::new(ErrorKind::ReadPacketDataFailed))
+            }
+            ResourceType::DelegationSigner | ResourceType::ChildDelegationSigner
+                if data.len() >= 4 =>
+            {
+                let key_tag = u16::from_be_bytes([data[0], data[1]]);
+                let (algorithm, digest_type) = (data[2], data[3]);
+                let digest = Cow::Borrowed(&data[4..]);
+                match ResourceType::from(resource_type) {
+                    ResourceType::DelegationSigner => ResourcePayload::DelegationSigner {
+                        key_tag,
+                        algorithm,
+                        digest_type,
+                        digest,
+                    },
+                    _ => ResourcePayload::ChildDelegationSigner {
+                        key_tag,
+                        algorithm,
+                        digest_type,
+                        digest,
+                    },
+                }
+            }
+            ResourceType::DnsKey | ResourceType::ChildDnsKey if data.len() >= 4 => {
+                let flags = u16::from_be_bytes([data[0], data[1]]);
+                let (protocol, algorithm) = (data[2], data[3]);
+                let public_key = Cow::Borrowed(&data[4..]);
+                match ResourceType::from(resource_type) {
+                    ResourceType::DnsKey => ResourcePayload::DnsKey {
+                        flags,
+                        protocol,
+                        algorithm,
+                        public_key,
+                    },
+                    _ => ResourcePayload::ChildDnsKey {
+                        flags,
+                        protocol,
+                        algorithm,
+                        public_key,
+                    },
+                }
+            }
+            ResourceType::ResourceSignature if data.len() >= 18 => {
+                let mut reader = Cursor::new(data);
+                let malformed = |_| Error::new(ErrorKind::ReadPacketDataFailed);
+                let type_covered = reader.read_u16::<NetworkEndian>().map_err(malformed)?;
+                let algorithm = reader.read_u8().map_err(malformed)?;
+                let labels = reader.read_u8().map_err(malformed)?;
+                let original_ttl = reader.read_u32::<NetworkEndian>().map_err(malformed)?;
+                let expiration = reader.read_u32::<NetworkEndian>().map_err(malformed)?;
+                let inception = reader.read_u32::<NetworkEndian>().map_err(malformed)?;
+                let key_tag = reader.read_u16::<NetworkEndian>().map_err(malformed)?;
+                self.position += 18;
+                let signer_name = self.read_domain_name(packet_data, domain_labels)?;
+                let signature = Self::remaining_payload(packet_data, self.position, payload_end)?;
+                ResourcePayload::ResourceSignature {
+                    type_covered,
+                    algorithm,
+                    labels,
+                    original_ttl,
+                    expiration,
+                    inception,
+                    key_tag,
+                    signer_name,
+                    signature: Cow::Borrowed(signature),
+                }
+            }
+            ResourceType::NextSecure => {
+                let next_domain_name = self.read_domain_name(packet_data, domain_labels)?;
+                let types = TypeBitmap::read(Self::remaining_payload(
+                    packet_data,
+                    self.position,
+                    payload_end,
+                )?)?;
+                ResourcePayload::NextSecure {
+                    next_domain_name,
+                    types,
+                }
+            }
+            ResourceType::NextSecure3 | ResourceType::NextSecure3Parameters if data.len() >= 5 => {
+                let (hash_algorithm, flags) = (data[0], data[1]);
+                let iterations = u16::from_be_bytes([data[2], data[3]]);
+                let mut offset = 4;
+                let salt = Cow::Borrowed(Self::read_character_string(data, &mut offset)?);
+                match ResourceType::from(resource_type) {
+                    ResourceType::NextSecure3 => {
+                        let next_hashed_owner = Self::read_character_string(data, &mut offset)?;
+                        if next_hashed_owner.is_empty() {
+                            return Err(Error::new(ErrorKind::ReadPacketDataFailed));
+                        }
+                        ResourcePayload::NextSecure3 {
+                            hash_algorithm,
+                            flags,
+                            iterations,
+                            salt,
+                            next_hashed_owner: Cow::Borrowed(next_hashed_owner),
+                            types: TypeBitmap::read(&data[offset..])?,
+                        }
+                    }
+                    _ => ResourcePayload::NextSecure3Parameters {
+                        hash_algorithm,
+                        flags,
+                        iterations,
+                        salt,
+                    },
+                }
+            }
+            ResourceType::DelegationSigner
+            | ResourceType::ChildDelegationSigner
+            | ResourceType::DnsKey
+            | ResourceType::ChildDnsKey
+            | ResourceType::ResourceSignature
+            | ResourceType::NextSecure3
+            | ResourceType::NextSecure3Parameters => {
+                return Err(Error::new(ErrorKind::ReadPacketDataFailed))
+            }
+            ResourceType::SshFingerprint if data.len() >= 2 => ResourcePayload::SshFingerprint {
+                algorithm: data[0],
+                fingerprint_type: data[1],
+                fingerprint: Cow::Borrowed(&data[2..]),
+            },
+            ResourceType::TlsAssociation if data.len() >= 3 => ResourcePayload::TlsAssociation {
+                certificate_usage: data[0],
+                selector: data[1],
+                matching_type: data[2],
+                certificate_data: Cow::Borrowed(&data[3..]),
+            },
+            ResourceType::SmimeAssociation if data.len() >= 3 => {
+                ResourcePayload::SmimeAssociation {
+                    certificate_usage: data[0],
+                    selector: data[1],
+                    matching_type: data[2],
+                    certificate_data: Cow::Borrowed(&data[3..]),
+                }
+            }
+            ResourceType::SshFingerprint
+            | ResourceType::TlsAssociation
+            | ResourceType::SmimeAssociation => {
+                return Err(Error::new(ErrorKind::ReadPacketDataFailed))
+            }
+            ResourceType::OpenPgpKey => ResourcePayload::OpenPgpKey(Cow::Borrowed(data)),
+            ResourceType::CertificationAuthorityAuthorization => {
+                let mut offset = 1;
+                let flags = *data
+                    .first()
+                    .ok_or_else(|| Error::new(ErrorKind::ReadPacketDataFailed))?;
+                let tag = Self::read_character_string(data, &mut offset)?;
+                if tag.is_empty() {
+                    return Err(Error::new(ErrorKind::ReadPacketDataFailed));
+                }
+                ResourcePayload::CertificationAuthorityAuthorization {
+                    flags,
+                    tag: Cow::Borrowed(tag),
+                    value: Cow::Borrowed(&data[offset..]),
+                }
+            }
+            ResourceType::ServiceBinding | ResourceType::HttpsBinding if data.len() >= 2 => {
+                let priority = u16::from_be_bytes([data[0], data[1]]);
+                self.position += 2;
+                let target = self.read_domain_name(packet_data, domain_labels)?;
+                let parameters = ServiceBinding::read_parameters(Self::remaining_payload(
+                    packet_data,
+                    self.position,
+                    payload_end,
+                )?)?;
+                let binding = ServiceBinding::new(priority, target, parameters);
+                match ResourceType::from(resource_type) {
+                    ResourceType::ServiceBinding => ResourcePayload::ServiceBinding(binding),
+                    _ => ResourcePayload::HttpsBinding(binding),
+                }
+            }
+            ResourceType::ServiceBinding | ResourceType::HttpsBinding => {
+                return Err(Error::new(ErrorKind::ReadPacketDataFailed))
+            }
+            ResourceType::TransactionSignature => {
+                let algorithm = self.read_domain_name(packet_data, domain_labels)?;
+                let fixed = Self::remaining_payload(packet_data, self.position, payload_end)?;
+                if fixed.len() < 10 {
+                    return Err(Error::new(ErrorKind::ReadPacketDataFailed));
+                }
+                let mut time_signed = [0u8; 8];
+                time_signed[2..].copy_from_slice(&fixed[..6]);
+                let fudge = u16::from_be_bytes([fixed[6], fixed[7]]);
+                let mac_length = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+                let mac = fixed
+                    .get(10..10 + mac_length)
+                    .ok_or_else(|| Error::new(ErrorKind::ReadPacketDataFailed))?;
+                let rest = &fixed[10 + mac_length..];
+                if rest.len() < 6 {
+                    return Err(Error::new(ErrorKind::ReadPacketDataFailed));
+                }
+                let other_length = u16::from_be_bytes([rest[4], rest[5]]) as usize;
+                if rest.len() != 6 + other_length {
+                    return Err(Error::new(ErrorKind::ReadPacketDataFailed));
+                }
+                ResourcePayload::TransactionSignature {
+                    algorithm,
+                    time_signed: u64::from_be_bytes(time_signed),
+                    fudge,
+                    mac: Cow::Borrowed(mac),
+                    original_id: u16::from_be_bytes([rest[0], rest[1]]),
+                    error: u16::from_be_bytes([rest[2], rest[3]]),
+                    other_data: Cow::Borrowed(&rest[6..]),
+                }
+            }
+            ResourceType::Unknown(_) => ResourcePayload::Unknown {
+                resource_type,
+                data: Cow::Borrowed(data),
+            },
+        };
+        // Domain names inside the payload must not run past the end of the RDATA
+        if self.position > payload_end {
+            return Err(Error::new(ErrorKind::ReadPacketDataFailed));
+        }
+        self.position = payload_end;
+        Ok(payload)
+    }
+
+    /// Reads a length prefixed character string, offset is moved past the string
+    pub fn read_character_string<'a>(
+        data: &'a [u8],
+        offset: &mut usize,
+    ) -> Result<&'a [u8], Error> {
+        let length = *data
+            .get(*offset)
+            .ok_or_else(|| Error::new(ErrorKind::ReadPacketDataFailed))?
+            as usize;
+        let string = data
+            .get(*offset + 1..*offset + 1 + length)
+            .ok_or_else(|| Error::new(ErrorKind::ReadPacketDataFailed))?;
+        *offset += 1 + length;
+        Ok(string)
+    }
+
+    fn remaining_payload(
+        packet_data: &[u8],
+        position: usize,
+        payload_end: usize,
+    ) -> Result<&[u8], Error> {
+        packet_data
+            .get(position..payload_end)
+            .ok_or_else(|| Error::new(ErrorKind::ReadPacketDataFailed))
+    }
+
     #[inline]
     pub fn get_bit_position(position: u8, bit_length: u8, source: &u16) -> u8 {
         // 1 becomes 1
@@ -0,0 +1,492 @@
+use super::{
+    DomainName, Presentation, Resource, ResourceClass, ResourcePayload, ResourceType,
+    ServiceBinding, ServiceParameter, TypeBitmap,
+};
+use crate::helper::{encode_base32_hex, encode_base64, encode_hex, format_timestamp};
+use std::{
+    borrow::Cow,
+    fmt::{Display, Formatter, Result},
+};
+
+impl<'p, T> Presentation<'p, T> {
+    pub fn new(value: &'p T, origin: Option<&'p DomainName<'p>>) -> Presentation<'p, T> {
+        Presentation { value, origin }
+    }
+}
+
+/// Writes a label escaping the characters that have a special meaning in zone files
+fn write_label(f: &mut Formatter<'_>, label: &str) -> Result {
+    for byte in label.bytes() {
+        match byte {
+            b'.' | b';' | b'(' | b')' | b'"' | b'\\' | b'@' | b'$' => {
+                write!(f, "\\{}", byte as char)?
+            }
+            0x21..=0x7e => write!(f, "{}", byte as char)?,
+            _ => write!(f, "\\{:03}", byte)?,
+        }
+    }
+    Ok(())
+}
+
+fn write_labels(f: &mut Formatter<'_>, labels: &[Cow<str>]) -> Result {
+    if let Some((last_label, remaining_labels)) = labels.split_last() {
+        for label in remaining_labels {
+            write_label(f, label)?;
+            write!(f, ".")?;
+        }
+        write_label(f, last_label)?;
+    }
+    Ok(())
+}
+
+/// Writes a character string in quotes so that empty strings and strings with spaces survive
+pub fn write_character_string(f: &mut Formatter<'_>, string: &[u8]) -> Result {
+    write!(f, "\"")?;
+    for byte in string {
+        match byte {
+            b'"' | b'\\' => write!(f, "\\{}", *byte as char)?,
+            0x20..=0x7e => write!(f, "{}", *byte as char)?,
+            _ => write!(f, "\\{:03}", byte)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Writes data using the RFC 3597 generic format, ie \# <length> <hex data>
+pub fn write_generic(f: &mut Formatter<'_>, data: &[u8]) -> Result {
+    if data.is_empty() {
+        return write!(f, "\\# 0");
+    }
+    write!(f, "\\# {} {}", data.len(), encode_hex(data))
+}
+
+/// The mnemonic of a resource type, falling back to the RFC 3597 TYPE form for unknown types
+pub fn type_mnemonic(type_code: u16) -> Cow<'static, str> {
+    match ResourceType::from(type_code) {
+        ResourceType::Unknown(_) => Cow::Owned(format!("TYPE{}", type_code)),
+        resource_type => Cow::Borrowed(resource_type.mnemonic()),
+    }
+}
+
+/// The mnemonic of a resource class, falling back to the RFC 3597 CLASS form for unknown classes
+pub fn class_mnemonic(resource_class: ResourceClass) -> Cow<'static, str> {
+    match resource_class {
+        ResourceClass::Unknown(class) => Cow::Owned(format!("CLASS{}", class)),
+        resource_class => Cow::Borrowed(resource_class.mnemonic()),
+    }
+}
+
+/// Writes each type in a bitmap preceded by a space
+fn write_types(f: &mut Formatter<'_>, types: &TypeBitmap) -> Result {
+    for resource_type in types.types() {
+        write!(f, " {}", type_mnemonic(*resource_type))?;
+    }
+    Ok(())
+}
+
+/// Writes an NSEC3 salt as hexadecimal, an empty salt is written as -
+fn write_salt(f: &mut Formatter<'_>, salt: &[u8]) -> Result {
+    if salt.is_empty() {
+        return write!(f, "-");
+    }
+    write!(f, "{}", encode_hex(salt))
+}
+
+impl Display for Presentation<'_, DomainName<'_>> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let name = self.value;
+        if let Some(origin) = self.origin.filter(|origin| !origin.is_root()) {
+            if name.eq_ignore_case(origin) {
+                return write!(f, "@");
+            }
+            if name.is_subdomain_of(origin) {
+                return write_labels(f, &name.labels()[..name.len() - origin.len()]);
+            }
+        }
+        if name.is_root() {
+            return write!(f, ".");
+        }
+        write_labels(f, name.labels())?;
+        write!(f, ".")
+    }
+}
+
+impl Display for DomainName<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        Presentation::new(self, None).fmt(f)
+    }
+}
+
+impl Display for Presentation<'_, ResourcePayload<'_>> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let origin = self.origin;
+        let name = |name| Presentation::new(name, origin);
+        match self.value {
+            ResourcePayload::Address(address) => write!(f, "{}", address),
+            ResourcePayload::Ipv6Address(address) => write!(f, "{}", address),
+            ResourcePayload::NameServer(target)
+            | ResourcePayload::MailDestination(target)
+            | ResourcePayload::MailForwarder(target)
+            | ResourcePayload::CanonicalName(target)
+            | ResourcePayload::DelegationName(target)
+            | ResourcePayload::MailBox(target)
+            | ResourcePayload::MailGroup(target)
+            | ResourcePayload::MailRename(target)
+            | ResourcePayload::DomainName(target) => write!(f, "{}", name(target)),
+            ResourcePayload::StartAuthority {
+                primary_name_server,
+                responsible_mailbox,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                name(primary_name_server),
+                name(responsible_mailbox),
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum
+            ),
+            ResourcePayload::Null(data) => write_generic(f, data),
+            ResourcePayload::WellKnownService {
+                address,
+                protocol,
+                bitmap,
+            } => {
+                write!(f, "{} {}", address, protocol)?;
+                for (index, byte) in bitmap.iter().enumerate() {
+                    for bit in 0..8 {
+                        if byte & (0b10000000 >> bit) != 0 {
+                            write!(f, " {}", index * 8 + bit)?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            ResourcePayload::HostInformation {
+                cpu,
+                operating_system,
+            } => {
+                write_character_string(f, cpu)?;
+                write!(f, " ")?;
+                write_character_string(f, operating_system)
+            }
+            ResourcePayload::MailboxInformation {
+                responsible_mailbox,
+                error_mailbox,
+            } => write!(f, "{} {}", name(responsible_mailbox), name(error_mailbox)),
+            ResourcePayload::MailExchange {
+                preference,
+                exchange,
+            } => write!(f, "{} {}", preference, name(exchange)),
+            ResourcePayload::TextStrings(strings) => {
+                if let Some((first, remaining)) = strings.split_first() {
+                    write_character_string(f, first)?;
+                    for string in remaining {
+                        write!(f, " ")?;
+                        write_character_string(f, string)?;
+                    }
+                }
+                Ok(())
+            }
+            ResourcePayload::Service {
+                priority,
+                weight,
+                port,
+                target,
+            } => write!(f, "{} {} {} {}", priority, weight, port, name(target)),
+            ResourcePayload::NamingAuthorityPointer {
+                order,
+                preference,
+                flags,
+                services,
+                regular_expression,
+                replacement,
+            } => {
+                write!(f, "{} {} ", order, preference)?;
+                write_character_string(f, flags)?;
+                write!(f, " ")?;
+                write_character_string(f, services)?;
+                write!(f, " ")?;
+                write_character_string(f, regular_expression)?;
+                write!(f, " {}", name(replacement))
+            }
+            ResourcePayload::UniformResourceIdentifier {
+                priority,
+                weight,
+                target,
+            } => {
+                write!(f, "{} {} ", priority, weight)?;
+                write_character_string(f, target)
+            }
+            ResourcePayload::DelegationSigner {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            }
+            | ResourcePayload::ChildDelegationSigner {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            } => write!(
+                f,
+                "{} {} {} {}",
+                key_tag,
+                algorithm,
+                digest_type,
+                encode_hex(digest)
+            ),
+            ResourcePayload::DnsKey {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            }
+            | ResourcePayload::ChildDnsKey {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            } => write!(
+                f,
+                "{} {} {} {}",
+                flags,
+                protocol,
+                algorithm,
+                encode_base64(public_key)
+            ),
+            ResourcePayload::ResourceSignature {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer_name,
+                signature,
+            } => write!(
+                f,
+                "{} {} {} {} {} {} {} {} {}",
+                type_mnemonic(*type_covered),
+                algorithm,
+                labels,
+                original_ttl,
+                format_timestamp(*expiration),
+                format_timestamp(*inception),
+                key_tag,
+                name(signer_name),
+                encode_base64(signature)
+            ),
+            ResourcePayload::NextSecure {
+                next_domain_name,
+                types,
+            } => {
+                write!(f, "{}", name(next_domain_name))?;
+                write_types(f, types)
+            }
+            ResourcePayload::NextSecure3 {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                next_hashed_owner,
+                types,
+            } => {
+                write!(f, "{} {} {} ", hash_algorithm, flags, iterations)?;
+                write_salt(f, salt)?;
+                write!(f, " {}", encode_base32_hex(next_hashed_owner))?;
+                write_types(f, types)
+            }
+            ResourcePayload::NextSecure3Parameters {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+            } => {
+                write!(f, "{} {} {} ", hash_algorithm, flags, iterations)?;
+                write_salt(f, salt)
+            }
+            ResourcePayload::SshFingerprint {
+                algorithm,
+                fingerprint_type,
+                fingerprint,
+            } => write!(
+                f,
+                "{} {} {}",
+                algorithm,
+                fingerprint_type,
+                encode_hex(fingerprint)
+            ),
+            ResourcePayload::TlsAssociation {
+                certificate_usage,
+                selector,
+                matching_type,
+                certificate_data,
+            }
+            | ResourcePayload::SmimeAssociation {
+                certificate_usage,
+                selector,
+                matching_type,
+                certificate_data,
+            } => write!(
+                f,
+                "{} {} {} {}",
+                certificate_usage,
+                selector,
+                matching_type,
+                encode_hex(certificate_data)
+            ),
+            ResourcePayload::OpenPgpKey(key) => write!(f, "{}", encode_base64(key)),
+            ResourcePayload::CertificationAuthorityAuthorization { flags, tag, value } => {
+                write!(f, "{} {} ", flags, String::from_utf8_lossy(tag))?;
+                write_character_string(f, value)
+            }
+            ResourcePayload::ServiceBinding(binding) | ResourcePayload::HttpsBinding(binding) => {
+                Presentation::new(binding, origin).fmt(f)
+            }
+            // RFC 8945 gives no presentation format, this follows the dig output
+            ResourcePayload::TransactionSignature {
+                algorithm,
+                time_signed,
+                fudge,
+                mac,
+                original_id,
+                error,
+                other_data,
+            } => {
+                write!(
+                    f,
+                    "{} {} {} {} {} {} {} {}",
+                    name(algorithm),
+                    time_signed,
+                    fudge,
+                    mac.len(),
+                    encode_base64(mac),
+                    original_id,
+                    error,
+                    other_data.len()
+                )?;
+                if !other_data.is_empty() {
+                    write!(f, " {}", encode_base64(other_data))?;
+                }
+                Ok(())
+            }
+            ResourcePayload::Unknown { data, .. } => write_generic(f, data),
+        }
+    }
+}
+
+impl Display for Presentation<'_, ServiceBinding<'_>> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let binding = self.value;
+        write!(
+            f,
+            "{} {}",
+            binding.priority(),
+            Presentation::new(binding.target(), self.origin)
+        )?;
+        for parameter in binding.parameters() {
+            write!(f, " {}", parameter)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes items separated by commas, only used for items that can't contain a comma themselves
+fn write_value_list<'i, I, T>(f: &mut Formatter<'_>, items: I) -> Result
+where
+    I: IntoIterator<Item = &'i T>,
+    T: Display + 'i,
+{
+    let mut separator = "";
+    for item in items {
+        write!(f, "{}{}", separator, item)?;
+        separator = ",";
+    }
+    Ok(())
+}
+
+impl Display for ServiceParameter<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}", ServiceParameter::key_mnemonic(self.key()))?;
+        match self {
+            ServiceParameter::Mandatory(keys) => {
+                let keys: Vec<Cow<str>> = keys
+                    .iter()
+                    .map(|key| ServiceParameter::key_mnemonic(*key))
+                    .collect();
+                write!(f, "=")?;
+                write_value_list(f, &keys)
+            }
+            ServiceParameter::Alpn(protocols) => {
+                let mut list = Vec::new();
+                for protocol in protocols {
+                    if !list.is_empty() {
+                        list.push(b',');
+                    }
+                    for byte in protocol.iter() {
+                        if *byte == b',' || *byte == b'\\' {
+                            list.push(b'\\');
+                        }
+                        list.push(*byte);
+                    }
+                }
+                write!(f, "=")?;
+                write_character_string(f, &list)
+            }
+            ServiceParameter::NoDefaultAlpn => Ok(()),
+            ServiceParameter::Port(port) => write!(f, "={}", port),
+            ServiceParameter::Ipv4Hint(addresses) => {
+                write!(f, "=")?;
+                write_value_list(f, addresses)
+            }
+            ServiceParameter::EncryptedClientHello(config) => {
+                write!(f, "={}", encode_base64(config))
+            }
+            ServiceParameter::Ipv6Hint(addresses) => {
+                write!(f, "=")?;
+                write_value_list(f, addresses)
+            }
+            ServiceParameter::Unknown { value, .. } if value.is_empty() => Ok(()),
+            ServiceParameter::Unknown { value, .. } => {
+                write!(f, "=")?;
+                write_character_string(f, value)
+            }
+        }
+    }
+}
+
+impl Display for ResourcePayload<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        Presentation::new(self, None).fmt(f)
+    }
+}
+
+impl Display for Presentation<'_, Resource<'_>> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let resource = self.value;
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}",
+            Presentation::new(&resource.resource_name, self.origin),
+            resource.time_to_live,
+            class_mnemonic(resource.resource_class),
+            type_mnemonic(resource.payload.type_code()),
+            Presentation::new(&resource.payload, self.origin)
+        )
+    }
+}
+
+impl Display for Resource<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        Presentation::new(self, None).fmt(f)
+    }
+}
@@ -0,0 +1,3842 @@
+use super::{
+    builders::{DnsResponseBuilder, PacketWriter},
+    dnssec, edns, framing, https, notify, spoofing, tls,
+    zone::{ZoneParser, ZoneWriter},
+    DenialCache, DenialCacheHits, DenialOfExistence, DnsPacket, DnsParser, DomainName,
+    ExtendedError, Header, HealthCheck, HttpRequest, HttpsConnection, HttpsExchange, HttpsReader,
+    Keyring, OperationCode, PacketType, PendingQueries, PendingQuery, PrimaryZone, Proxy,
+    ProxyConfig, Question, QuestionClass, QuestionType, Requester, Resource, ResourceType,
+    ResponseCode, Sanitizer, SecondaryZone, SigningKey, StreamReader, StreamWriter, TcpConnection,
+    TlsUpstream, TransferOutcome, TransferServer, TrustAnchors, UpdatePolicy, Upstream,
+    UpstreamState, UpstreamStrategy, UpstreamStream, ValidationResult, Validator, ZoneSigner,
+};
+use crate::error::{Error, ErrorKind};
+use crate::helper::decode_base64;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fmt,
+    io::ErrorKind as IoErrorKind,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, SyncSender, TrySendError},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread::{self, Scope},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// How often loops waiting on a socket check whether the proxy has stopped
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Connections kept open to each upstream for truncated answers or DNS over TLS, a new one is only opened when the others are busy
+const UPSTREAM_STREAMS: usize = 4;
+
+/// The most queries over UDP waiting on their upstream at once, each has a thread and an answer buffer of its own
+const MAXIMUM_WAITING_QUERIES: usize = 512;
+
+/// Large enough for any UDP answer, including EDNS answers over the usual 1232 byte limit
+const MAXIMUM_DATAGRAM_SIZE: usize = 65_535;
+
+/// The CD bit in the fourth byte of the header, a client sets it to get answers that weren't validated
+const CHECKING_DISABLED: u8 = 0x10;
+
+/// Zone keys that are also secure entry points, they sign the whole zone when there is no zone signing key
+const KEY_SIGNING_KEY_FLAGS: u16 = dnssec::ZONE_KEY_FLAG | dnssec::SECURE_ENTRY_POINT_FLAG;
+
+/// How long the DS records written for generated keys are cached by the parent zone
+const DELEGATION_TIME_TO_LIVE: u32 = 3600;
+
+/// How many NOTIFY and UPDATE messages over UDP wait for the worker that answers them, more are dropped
+const ZONE_MESSAGE_BACKLOG: usize = 16;
+
+/// How many zones above the one that signed an answer are looked up on the way to a trust anchor
+const MAXIMUM_KEY_DEPTH: usize = 16;
+
+fn socket_error(address: SocketAddr) -> impl Fn(std::io::Error) -> Error {
+    move |error| {
+        Error::new(ErrorKind::ConnectionFailed(format!(
+            "{}: {}",
+            address, error
+        )))
+    }
+}
+
+pub fn is_timeout(error: &std::io::Error) -> bool {
+    matches!(
+        error.kind(),
+        IoErrorKind::WouldBlock | IoErrorKind::TimedOut
+    )
+}
+
+/// The state behind our locks stays consistent when a thread panics, so a poisoned lock is still used
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Reads a name written with dots, a trailing dot is allowed
+fn parse_name(text: &str) -> DomainName<'static> {
+    let labels = text.split('.').filter(|label| !label.is_empty());
+    DomainName::new(labels.map(String::from).collect())
+}
+
+/// Reads a zone's signing key, generating one when the file doesn't exist yet
+/// The DS record of a generated key is written to FILE.ds for the parent zone, or to use as a trust anchor
+fn signing_key(
+    zone: &DomainName<'static>,
+    file: &Path,
+    flags: u16,
+    algorithm: u8,
+) -> Result<SigningKey, Error> {
+    if file.exists() {
+        return SigningKey::from_file(zone.clone(), flags, algorithm, file);
+    }
+    let key = SigningKey::generate(zone.clone(), flags, algorithm)?;
+    key.write_file(file)?;
+    if let Some(delegation) = key.delegation(dnssec::DIGEST_SHA256, DELEGATION_TIME_TO_LIVE) {
+        let mut delegation_file = file.as_os_str().to_owned();
+        delegation_file.push(".ds");
+        ZoneWriter::new(Some(key.zone().clone()))
+            .default_ttl(DELEGATION_TIME_TO_LIVE)
+            .write_file(&[delegation], delegation_file)?;
+    }
+    Ok(key)
+}
+
+/// A query for the DS or DNSKEY records of a zone that asks for their signatures
+fn key_query(zone: &DomainName, question_type: u16) -> Result<Vec<u8>, Error> {
+    let mut header = Header::new();
+    header.id = spoofing::random_id()?;
+    header.recursion_desired = true;
+    header.question_count = 1;
+    let mut message = Vec::with_capacity(512);
+    header.write_header(&mut message)?;
+    let question = Question::new(
+        zone.clone(),
+        QuestionType::from(question_type),
+        QuestionClass::Internet,
+    );
+    let mut writer = PacketWriter::with_data(message);
+    writer.write_question(&question)?;
+    let mut query = writer.into_inner();
+    edns::set_dnssec_ok(&mut query);
+    Ok(query)
+}
+
+/// Answers a query with only a response code, echoing its questions
+/// The questions are copied rather than parsed and written again, so that types we don't know are echoed as they were
+fn error_response(query: &[u8], response_code: ResponseCode) -> Result<Vec<u8>, Error> {
+    let questions = spoofing::question_section(query)
+        .ok_or_else(|| Error::new(ErrorKind::ReadPacketDataFailed))?;
+    let questions = &query[questions];
+    let mut response = Vec::with_capacity(12 + questions.len());
+    response.extend_from_slice(&query[..4]);
+    // QR is set and the operation code and RD are kept, every other flag is cleared
+    response[2] = 0x80 | (query[2] & 0x79);
+    response[3] = u16::from(response_code) as u8 & 0x0F;
+    response.extend_from_slice(&query[4..6]);
+    response.extend_from_slice(&[0; 6]);
+    response.extend_from_slice(questions);
+    Ok(response)
+}
+
+/// Cuts an answer down to its header and questions with TC set, so that a UDP client asks again over TCP
+fn truncate(answer: &[u8]) -> Result<Vec<u8>, Error> {
+    let questions = spoofing::question_section(answer)
+        .ok_or_else(|| Error::new(ErrorKind::ReadPacketDataFailed))?;
+    let questions = &answer[questions];
+    let mut truncated = Vec::with_capacity(12 + questions.len());
+    truncated.extend_from_slice(&answer[..6]);
+    truncated[2] |= 0x02;
+    truncated.extend_from_slice(&[0; 6]);
+    truncated.extend_from_slice(questions);
+    Ok(truncated)
+}
+
+/// Takes the DNSSEC records out of an answer to a client that didn't set DO, RFC 4035 section 3.2.1
+/// Records of the type that was asked for stay, the OPT record is kept with DO cleared only when the client sent one
+fn hide_dnssec(answer: &[u8], options: bool) -> Result<Vec<u8>, Error> {
+    let packet = DnsParser::new().parse_packet(answer)?;
+    let asked = packet
+        .questions
+        .first()
+        .map(|question| question.question_type.code());
+    let shown = |record: &Resource| {
+        let type_code = record.payload.type_code();
+        let dnssec = matches!(
+            ResourceType::from(type_code),
+            ResourceType::ResourceSignature | ResourceType::NextSecure | ResourceType::NextSecure3
+        );
+        type_code != edns::OPTIONS_TYPE && (!dnssec || Some(type_code) == asked)
+    };
+    let mut builder = DnsResponseBuilder::with_header(packet.header);
+    for question in packet.questions {
+        builder.add_question(question);
+    }
+    for answer in packet.answers.into_iter().filter(shown) {
+        builder.add_answer(answer);
+    }
+    for authority in packet.authority.into_iter().filter(shown) {
+        builder.add_authority(authority);
+    }
+    for additional in packet.additional.into_iter().filter(shown) {
+        builder.add_additional(additional);
+    }
+    let mut hidden = builder.build_response()?;
+    if options {
+        edns::copy_options_record(answer, &mut hidden);
+        edns::clear_dnssec_ok(&mut hidden);
+    }
+    Ok(hidden)
+}
+
+impl ProxyConfig {
+    /// Listens on every address on port 53 and forwards to Cloudflare
+    pub fn new() -> ProxyConfig {
+        ProxyConfig {
+            listen: SocketAddr::from(([0, 0, 0, 0], 53)),
+            upstreams: vec![(SocketAddr::from(([1, 1, 1, 1], 53)), 1)],
+            tls_upstreams: HashMap::new(),
+            strategy: UpstreamStrategy::Failover,
+            health_check: HealthCheck::new(),
+            tls_listen: None,
+            https_listen: None,
+            tls_certificate: None,
+            randomize_case: false,
+            sanitizer: Sanitizer::new(DomainName::root()),
+            timeout: Duration::from_secs(5),
+            idle_timeout: Duration::from_secs(10),
+            max_connections: 150,
+            max_pipelined: 100,
+            verbose: false,
+            trust_anchors: None,
+            primary_zones: Vec::new(),
+            secondary_zones: Vec::new(),
+            allow_transfer: Vec::new(),
+            transfer_keys: None,
+            notify: Vec::new(),
+            updates: Vec::new(),
+            signing_keys: Vec::new(),
+            signing_algorithm: dnssec::ECDSA_P256_SHA256,
+            signature_lifetime: None,
+            denial: DenialOfExistence::NextSecure,
+        }
+    }
+
+    /// Reads the options listed in main's usage, anything not given keeps its default
+    /// Options that pair a file or address with a name separate the two with a #, ie --primary-zone FILE#NAME
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<ProxyConfig, Error> {
+        let invalid = |reason: String| Error::new(ErrorKind::InvalidConfiguration(reason));
+        let mut config = ProxyConfig::new();
+        let mut upstreams = Vec::new();
+        let mut tls_upstreams = HashMap::new();
+        let (mut certificate, mut private_key) = (None, None);
+        let (mut certificate_authorities, mut pins) = (None, Vec::new());
+        let mut args = args.into_iter();
+        while let Some(option) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| invalid(format!("{} needs a value", option)))?;
+            let address = |value: &str| {
+                value
+                    .parse::<SocketAddr>()
+                    .map_err(|_| invalid(format!("{} is not an address and port", value)))
+            };
+            let count = |value: &str| {
+                value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|count| *count > 0)
+                    .ok_or_else(|| invalid(format!("{} is not a positive number", value)))
+            };
+            let boolean = |value: &str| {
+                value
+                    .parse::<bool>()
+                    .map_err(|_| invalid(format!("{} is neither true nor false", value)))
+            };
+            let seconds = || count(&value).map(|seconds| Duration::from_secs(seconds as u64));
+            let times = || {
+                count(&value).and_then(|times| {
+                    u32::try_from(times).map_err(|_| invalid(format!("{} is too many", times)))
+                })
+            };
+            match option.as_str() {
+                "--listen" => config.listen = address(&value)?,
+                "--upstream" | "--tls-upstream" => {
+                    let (upstream, weight) = match value.rsplit_once(',') {
+                        Some((upstream, weight)) => (upstream, count(weight)?),
+                        None => (value.as_str(), 1),
+                    };
+                    let weight = u32::try_from(weight)
+                        .map_err(|_| invalid(format!("{} is too large a weight", weight)))?;
+                    let upstream = match (option.as_str(), upstream.split_once('#')) {
+                        ("--tls-upstream", Some((upstream, name))) => {
+                            let upstream = address(upstream)?;
+                            tls_upstreams.insert(upstream, TlsUpstream::new(name));
+                            upstream
+                        }
+                        ("--tls-upstream", None) => {
+                            return Err(invalid(format!("{} has no name after a #", value)))
+                        }
+                        _ => address(upstream)?,
+                    };
+                    upstreams.push((upstream, weight));
+                }
+                "--tls-listen" => config.tls_listen = Some(address(&value)?),
+                "--https-listen" => config.https_listen = Some(address(&value)?),
+                "--tls-certificate" => certificate = Some(PathBuf::from(&value)),
+                "--tls-key" => private_key = Some(PathBuf::from(&value)),
+                "--tls-ca" => certificate_authorities = Some(PathBuf::from(&value)),
+                "--tls-pin" => {
+                    let pin = decode_base64(&value)
+                        .and_then(|pin| <[u8; 32]>::try_from(pin.as_slice()).ok())
+                        .ok_or_else(|| invalid(format!("{} is not a SHA-256 pin", value)))?;
+                    pins.push(pin);
+                }
+                "--strategy" => {
+                    config.strategy = UpstreamStrategy::from_name(&value)
+                        .ok_or_else(|| invalid(format!("unknown strategy {}", value)))?
+                }
+                "--probe-name" => {
+                    config.health_check.name(parse_name(&value));
+                }
+                "--probe-type" => {
+                    let question_type = ResourceType::from_mnemonic(&value)
+                        .ok_or_else(|| invalid(format!("unknown record type {}", value)))?;
+                    config.health_check.question_type(question_type);
+                }
+                "--probe-interval" => {
+                    config.health_check.interval(seconds()?);
+                }
+                "--probe-timeout" => {
+                    config.health_check.timeout(seconds()?);
+                }
+                "--down-after" => {
+                    config.health_check.down_after(times()?);
+                }
+                "--up-after" => {
+                    config.health_check.up_after(times()?);
+                }
+                "--bailiwick" => config.sanitizer = Sanitizer::new(parse_name(&value)),
+                "--randomize-case" => config.randomize_case = boolean(&value)?,
+                "--verbose" => config.verbose = boolean(&value)?,
+                "--trust-anchors" => config.trust_anchors = Some(PathBuf::from(&value)),
+                "--primary-zone" | "--secondary-zone" => {
+                    let (location, zone) = value
+                        .split_once('#')
+                        .ok_or_else(|| invalid(format!("{} has no zone after a #", value)))?;
+                    let zone = parse_name(zone);
+                    match option.as_str() {
+                        "--primary-zone" => {
+                            config.primary_zones.push((zone, PathBuf::from(location)))
+                        }
+                        _ => config.secondary_zones.push((zone, address(location)?)),
+                    }
+                }
+                "--notify" => config.notify.push(address(&value)?),
+                "--transfer-keys" => {
+                    let (file, key) = value
+                        .split_once('#')
+                        .ok_or_else(|| invalid(format!("{} has no key name after a #", value)))?;
+                    let key = parse_name(key);
+                    config.transfer_keys = Some((PathBuf::from(file), key));
+                }
+                "--allow-update" => config.updates.push((parse_name(&value), None)),
+                "--signing-key" | "--zone-signing-key" => {
+                    let (file, zone) = value
+                        .split_once('#')
+                        .ok_or_else(|| invalid(format!("{} has no zone after a #", value)))?;
+                    let zone = parse_name(zone);
+                    let flags = match option.as_str() {
+                        "--signing-key" => KEY_SIGNING_KEY_FLAGS,
+                        _ => dnssec::ZONE_KEY_FLAG,
+                    };
+                    config.signing_keys.push((zone, PathBuf::from(file), flags));
+                }
+                "--signing-algorithm" => {
+                    config.signing_algorithm = value
+                        .parse::<u8>()
+                        .map_err(|_| invalid(format!("{} is not an algorithm number", value)))?
+                }
+                "--signature-lifetime" => {
+                    let seconds = count(&value)?;
+                    let seconds = u32::try_from(seconds)
+                        .map_err(|_| invalid(format!("{} seconds is too long", seconds)))?;
+                    config.signature_lifetime = Some(seconds);
+                }
+                "--denial" => {
+                    config.denial = match value.as_str() {
+                        "nsec" => DenialOfExistence::NextSecure,
+                        // RFC 9276 section 3.1, no extra iterations and no salt
+                        "nsec3" => DenialOfExistence::NextSecure3 {
+                            iterations: 0,
+                            salt: Vec::new(),
+                        },
+                        "compact" => DenialOfExistence::Compact,
+                        _ => return Err(invalid(format!("unknown denial {}", value))),
+                    }
+                }
+                "--update-keys" => {
+                    let (file, zone) = value
+                        .split_once('#')
+                        .ok_or_else(|| invalid(format!("{} has no zone after a #", value)))?;
+                    let zone = parse_name(zone);
+                    config.updates.push((zone, Some(PathBuf::from(file))));
+                }
+                "--allow-transfer" => {
+                    let address = value
+                        .parse::<IpAddr>()
+                        .map_err(|_| invalid(format!("{} is not an address", value)))?;
+                    config.allow_transfer.push(address);
+                }
+                "--timeout" => config.timeout = seconds()?,
+                "--idle-timeout" => config.idle_timeout = seconds()?,
+                "--max-connections" => config.max_connections = count(&value)?,
+                "--max-pipelined" => config.max_pipelined = count(&value)?,
+                _ => return Err(invalid(format!("unknown option {}", option))),
+            }
+        }
+        if !upstreams.is_empty() {
+            config.upstreams = upstreams;
+        }
+        for upstream in tls_upstreams.values_mut() {
+            if let Some(file) = &certificate_authorities {
+                upstream.certificate_authorities(file.clone());
+            }
+            for pin in &pins {
+                upstream.pin(*pin);
+            }
+        }
+        config.tls_upstreams = tls_upstreams;
+        config.tls_certificate = match (certificate, private_key) {
+            (Some(certificate), Some(private_key)) => Some((certificate, private_key)),
+            (None, None) => None,
+            _ => {
+                return Err(invalid(String::from(
+                    "--tls-certificate and --tls-key go together",
+                )))
+            }
+        };
+        if config.tls_listen.is_some() && config.tls_certificate.is_none() {
+            return Err(invalid(String::from(
+                "--tls-listen needs --tls-certificate and --tls-key",
+            )));
+        }
+        if config.https_listen.is_some() && config.tls_certificate.is_none() {
+            return Err(invalid(String::from(
+                "--https-listen needs --tls-certificate and --tls-key",
+            )));
+        }
+        Ok(config)
+    }
+
+    pub fn listen(&mut self, listen: SocketAddr) -> &mut Self {
+        self.listen = listen;
+        self
+    }
+
+    /// Replaces the upstreams with this one
+    pub fn upstream(&mut self, upstream: SocketAddr) -> &mut Self {
+        self.upstreams = vec![(upstream, 1)];
+        self.tls_upstreams.clear();
+        self
+    }
+
+    pub fn add_upstream(&mut self, upstream: SocketAddr, weight: u32) -> &mut Self {
+        self.upstreams.push((upstream, weight));
+        self
+    }
+
+    /// Adds an upstream that every query is sent to over DNS over TLS, RFC 7858
+    pub fn add_tls_upstream(
+        &mut self,
+        upstream: SocketAddr,
+        weight: u32,
+        tls: TlsUpstream,
+    ) -> &mut Self {
+        self.upstreams.push((upstream, weight));
+        self.tls_upstreams.insert(upstream, tls);
+        self
+    }
+
+    /// Accepts DNS over TLS clients here, they are answered with the TLS certificate
+    pub fn tls_listen(&mut self, tls_listen: SocketAddr) -> &mut Self {
+        self.tls_listen = Some(tls_listen);
+        self
+    }
+
+    /// Accepts DNS over HTTPS clients here, they are answered with the TLS certificate
+    pub fn https_listen(&mut self, https_listen: SocketAddr) -> &mut Self {
+        self.https_listen = Some(https_listen);
+        self
+    }
+
+    /// Accepts DNS over TLS and DNS over HTTPS clients with this PEM certificate chain and private key
+    pub fn tls_certificate(&mut self, certificate: PathBuf, private_key: PathBuf) -> &mut Self {
+        self.tls_certificate = Some((certificate, private_key));
+        self
+    }
+
+    pub fn strategy(&mut self, strategy: UpstreamStrategy) -> &mut Self {
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn health_check(&mut self, health_check: HealthCheck) -> &mut Self {
+        self.health_check = health_check;
+        self
+    }
+
+    /// Strips out of bailiwick and unrelated records from answers before they are forwarded
+    pub fn sanitizer(&mut self, sanitizer: Sanitizer) -> &mut Self {
+        self.sanitizer = sanitizer;
+        self
+    }
+
+    /// Randomizes the case of query names, draft-vixie-dnsext-dns0x20
+    /// Upstreams that don't echo the name exactly as it was asked then can't be used
+    pub fn randomize_case(&mut self, randomize_case: bool) -> &mut Self {
+        self.randomize_case = randomize_case;
+        self
+    }
+
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn idle_timeout(&mut self, idle_timeout: Duration) -> &mut Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    pub fn max_connections(&mut self, max_connections: usize) -> &mut Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    pub fn max_pipelined(&mut self, max_pipelined: usize) -> &mut Self {
+        self.max_pipelined = max_pipelined;
+        self
+    }
+
+    /// Logs failures and removed records for single queries, which are otherwise dropped quietly
+    pub fn verbose(&mut self, verbose: bool) -> &mut Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Validates answers against the DS and DNSKEY records in a zone file, secure answers get the AD bit
+    /// and bogus ones become SERVFAIL, the file is read when the proxy is bound
+    pub fn trust_anchors(&mut self, file: PathBuf) -> &mut Self {
+        self.trust_anchors = Some(file);
+        self
+    }
+
+    /// Answers for the zone from a zone file and serves it over AXFR and IXFR
+    pub fn primary_zone(&mut self, zone: DomainName<'static>, file: PathBuf) -> &mut Self {
+        self.primary_zones.push((zone, file));
+        self
+    }
+
+    /// Transfers the zone from its primary and answers for it once it has been transferred
+    pub fn secondary_zone(&mut self, zone: DomainName<'static>, primary: SocketAddr) -> &mut Self {
+        self.secondary_zones.push((zone, primary));
+        self
+    }
+
+    /// Transfers of our zones must be signed with a key from the keyring file
+    /// The named key signs our own transfer requests and NOTIFY messages
+    pub fn transfer_keys(&mut self, keyring: PathBuf, key: DomainName<'static>) -> &mut Self {
+        self.transfer_keys = Some((keyring, key));
+        self
+    }
+
+    /// Sends a NOTIFY to the secondary when a primary zone changes and allows it to transfer the zones
+    pub fn notify(&mut self, secondary: SocketAddr) -> &mut Self {
+        self.notify.push(secondary);
+        self
+    }
+
+    /// Lets a primary zone be changed with UPDATE, only when signed with a key from the keyring file if there is one
+    pub fn allow_update(
+        &mut self,
+        zone: DomainName<'static>,
+        keyring: Option<PathBuf>,
+    ) -> &mut Self {
+        self.updates.push((zone, keyring));
+        self
+    }
+
+    /// Signs a primary zone with the key as it is answered, the flags tell key signing keys from zone signing keys
+    pub fn signing_key(
+        &mut self,
+        zone: DomainName<'static>,
+        file: PathBuf,
+        flags: u16,
+    ) -> &mut Self {
+        self.signing_keys.push((zone, file, flags));
+        self
+    }
+
+    pub fn signing_algorithm(&mut self, algorithm: u8) -> &mut Self {
+        self.signing_algorithm = algorithm;
+        self
+    }
+
+    pub fn signature_lifetime(&mut self, seconds: u32) -> &mut Self {
+        self.signature_lifetime = Some(seconds);
+        self
+    }
+
+    /// How signed zones prove that a name or type doesn't exist
+    pub fn denial(&mut self, denial: DenialOfExistence) -> &mut Self {
+        self.denial = denial;
+        self
+    }
+
+    /// Allows a client to transfer the zones we hold, nobody is allowed by default
+    pub fn allow_transfer(&mut self, address: IpAddr) -> &mut Self {
+        self.allow_transfer.push(address);
+        self
+    }
+}
+
+impl PendingQueries {
+    fn new() -> PendingQueries {
+        PendingQueries {
+            queries: HashMap::new(),
+            last_expiry: Instant::now(),
+        }
+    }
+
+    /// Forgets the queries the upstreams haven't answered in time, at most once per timeout
+    /// Returns the upstream of each query that was forgotten
+    fn expire(&mut self, timeout: Duration) -> Vec<usize> {
+        let mut failed = Vec::new();
+        if self.last_expiry.elapsed() < timeout {
+            return failed;
+        }
+        self.queries.retain(|_, pending| {
+            let waiting = pending.forwarded.elapsed() < timeout;
+            if !waiting {
+                pending.requester.release();
+                failed.push(pending.upstream);
+            }
+            waiting
+        });
+        self.last_expiry = Instant::now();
+        failed
+    }
+
+    /// Picks a random ID that no other pending query uses, the query is given back when every ID is taken
+    fn insert(&mut self, query: PendingQuery) -> Result<u16, PendingQuery> {
+        if self.queries.len() > u16::MAX as usize {
+            return Err(query);
+        }
+        let id = loop {
+            match spoofing::random_id() {
+                Ok(id) if self.queries.contains_key(&id) => continue,
+                Ok(id) => break id,
+                Err(_) => return Err(query),
+            }
+        };
+        self.queries.insert(id, query);
+        Ok(id)
+    }
+}
+
+impl PendingQuery {
+    /// The query as the client sent it, with its own ID and the case of its question
+    fn asked(&self) -> Vec<u8> {
+        let mut asked = self.query.clone();
+        asked[12..12 + self.question.len()].copy_from_slice(&self.question);
+        asked
+    }
+}
+
+impl Requester {
+    /// Lets a connection read another query once one of its queries is answered or forgotten
+    fn release(&self) {
+        match self {
+            Requester::Udp(_) => (),
+            Requester::Tcp(connection) => connection.release(),
+            Requester::Https(connection, _) => connection.connection.release(),
+        }
+    }
+}
+
+impl TcpConnection {
+    pub fn new(stream: StreamWriter) -> TcpConnection {
+        TcpConnection {
+            stream: Mutex::new(stream),
+            in_flight: Mutex::new((0, Instant::now())),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Takes a slot for a query unless the connection already has the limit in flight
+    /// Waits a while for a slot to be released before giving up
+    fn acquire(&self, limit: usize) -> bool {
+        let mut in_flight = lock(&self.in_flight);
+        if in_flight.0 >= limit {
+            in_flight = self
+                .released
+                .wait_timeout(in_flight, POLL_INTERVAL)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+        if in_flight.0 >= limit {
+            return false;
+        }
+        in_flight.0 += 1;
+        true
+    }
+
+    fn release(&self) {
+        let mut in_flight = lock(&self.in_flight);
+        *in_flight = (in_flight.0.saturating_sub(1), Instant::now());
+        self.released.notify_one();
+    }
+
+    /// Whether nothing is in flight and nothing has been answered for the given time
+    fn is_idle(&self, idle_timeout: Duration) -> bool {
+        let in_flight = lock(&self.in_flight);
+        in_flight.0 == 0 && in_flight.1.elapsed() >= idle_timeout
+    }
+
+    fn send(&self, message: &[u8]) -> Result<(), Error> {
+        framing::write_message(&mut *lock(&self.stream), message)
+    }
+}
+
+impl UpstreamStream {
+    /// Connects over TLS to a DNS over TLS upstream and over plain TCP to any other
+    fn connect(
+        upstream: &Upstream,
+        timeout: Duration,
+    ) -> Result<(UpstreamStream, StreamReader), Error> {
+        let (reader, stream) = upstream.connect(timeout)?;
+        reader
+            .set_read_timeout(Some(POLL_INTERVAL))
+            .map_err(socket_error(upstream.address))?;
+        let connection = UpstreamStream {
+            stream: Mutex::new(stream),
+            in_flight: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+        };
+        Ok((connection, reader))
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    fn send(&self, query: &[u8]) -> Result<(), Error> {
+        let sent = framing::write_message(&mut *lock(&self.stream), query);
+        if sent.is_ok() {
+            self.in_flight.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.closed.store(true, Ordering::Relaxed);
+        }
+        sent
+    }
+
+    fn answered(&self) {
+        let _ = self
+            .in_flight
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                count.checked_sub(1)
+            });
+    }
+}
+
+impl Proxy {
+    /// Binds the UDP socket and TCP listener for clients, and the DNS over TLS and DNS over HTTPS listeners
+    /// when there is a certificate
+    pub fn bind(config: &ProxyConfig) -> Result<Proxy, Error> {
+        if config.upstreams.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidConfiguration(String::from(
+                "there are no upstreams",
+            ))));
+        }
+        let socket = UdpSocket::bind(config.listen).map_err(socket_error(config.listen))?;
+        // When binding to port 0 the listener takes the port the UDP socket got
+        let listen = socket.local_addr().map_err(socket_error(config.listen))?;
+        let listener = TcpListener::bind(listen).map_err(socket_error(listen))?;
+        let tls_listener = |listen: Option<SocketAddr>, protocols: &[&[u8]]| match (
+            listen,
+            &config.tls_certificate,
+        ) {
+            (Some(listen), Some((certificate, private_key))) => {
+                let tls_config = tls::server_config(certificate, private_key, protocols)?;
+                let tls_listener = TcpListener::bind(listen).map_err(socket_error(listen))?;
+                Ok(Some((tls_listener, tls_config)))
+            }
+            _ => Ok(None),
+        };
+        let https_listener = tls_listener(config.https_listen, &[https::H2, https::HTTP1])?;
+        let tls_listener = tls_listener(config.tls_listen, &[tls::ALPN])?;
+        let upstreams = config
+            .upstreams
+            .iter()
+            .map(
+                |(address, weight)| match config.tls_upstreams.get(address) {
+                    Some(tls) => Upstream::over_tls(*address, *weight, tls),
+                    None => Ok(Upstream::new(*address, *weight)),
+                },
+            )
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut transfers = TransferServer::new();
+        let mut transfer_key = None;
+        if let Some((file, name)) = &config.transfer_keys {
+            let keyring = Keyring::from_file(file)?;
+            let key = keyring.get(name).cloned().ok_or_else(|| {
+                Error::new(ErrorKind::InvalidConfiguration(format!(
+                    "{} has no key {}",
+                    file.display(),
+                    name
+                )))
+            })?;
+            transfers.keyring(keyring);
+            transfer_key = Some(key);
+        }
+        let mut signers = Vec::new();
+        for (zone, file) in &config.primary_zones {
+            let mut records = ZoneParser::new(Some(zone.clone())).parse_file(file)?;
+            let keys = config
+                .signing_keys
+                .iter()
+                .filter(|(signed, _, _)| signed.eq_ignore_case(zone))
+                .map(|(_, file, flags)| signing_key(zone, file, *flags, config.signing_algorithm))
+                .collect::<Result<Vec<_>, _>>()?;
+            // The signer adds the DNSKEY records, which the zone answers and transfers like any other
+            let mut signer = None;
+            if !keys.is_empty() {
+                let mut zone_signer =
+                    ZoneSigner::new(zone.clone(), keys, records, config.denial.clone());
+                if let Some(seconds) = config.signature_lifetime {
+                    zone_signer.signature_lifetime(seconds);
+                }
+                records = zone_signer.records().to_vec();
+                signer = Some(zone_signer);
+            }
+            let mut primary = PrimaryZone::new(zone.clone(), records)?;
+            for secondary in &config.notify {
+                primary.add_secondary(*secondary, transfer_key.clone());
+            }
+            let update = config
+                .updates
+                .iter()
+                .find(|(updated, _)| updated.eq_ignore_case(zone));
+            if let Some((_, keyring)) = update {
+                primary.update_policy(match keyring {
+                    Some(file) => UpdatePolicy::Signed(Keyring::from_file(file)?),
+                    None => UpdatePolicy::Anyone,
+                });
+                let mut journal = file.clone().into_os_string();
+                journal.push(".jnl");
+                primary.journal(journal)?;
+            }
+            if let Some(mut signer) = signer {
+                signer.update(primary.records().to_vec());
+                signers.push(Mutex::new(signer));
+            }
+            transfers.add_zone(primary);
+        }
+        let zones = config
+            .updates
+            .iter()
+            .map(|(zone, _)| zone)
+            .chain(config.signing_keys.iter().map(|(zone, _, _)| zone));
+        for zone in zones {
+            if transfers.zone(zone).is_none() {
+                return Err(Error::new(ErrorKind::InvalidConfiguration(format!(
+                    "{} takes updates or signing keys but isn't a primary zone",
+                    zone
+                ))));
+            }
+        }
+        let secondaries = config.notify.iter().map(|secondary| secondary.ip());
+        for address in config.allow_transfer.iter().copied().chain(secondaries) {
+            transfers.allow(address);
+        }
+        let secondaries = config
+            .secondary_zones
+            .iter()
+            .map(|(zone, primary)| {
+                let mut secondary = SecondaryZone::new(zone.clone(), *primary);
+                if let Some(key) = &transfer_key {
+                    secondary.key(key.clone());
+                }
+                secondary
+            })
+            .collect();
+        let validator = match &config.trust_anchors {
+            Some(file) => Some(Mutex::new(Validator::new(TrustAnchors::from_file(file)?))),
+            None => None,
+        };
+        Ok(Proxy {
+            socket,
+            listener,
+            tls_listener,
+            https_listener,
+            upstreams,
+            strategy: config.strategy,
+            next_upstream: AtomicUsize::new(0),
+            health_check: config.health_check.clone(),
+            randomize_case: config.randomize_case,
+            sanitizer: config.sanitizer.clone(),
+            timeout: config.timeout,
+            idle_timeout: config.idle_timeout,
+            max_connections: config.max_connections,
+            max_pipelined: config.max_pipelined,
+            verbose: config.verbose,
+            validator,
+            denial_cache: DenialCache::new(),
+            connections: AtomicUsize::new(0),
+            waiting: AtomicUsize::new(0),
+            pending: Mutex::new(PendingQueries::new()),
+            transfers: Mutex::new(transfers),
+            secondaries: Mutex::new(secondaries),
+            signers,
+        })
+    }
+
+    /// The address clients send queries to, useful when binding to port 0
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.socket
+            .local_addr()
+            .map_err(|error| Error::new(ErrorKind::ConnectionFailed(error.to_string())))
+    }
+
+    /// The address DNS over TLS clients connect to, None without a certificate
+    pub fn tls_local_addr(&self) -> Option<SocketAddr> {
+        let (listener, _) = self.tls_listener.as_ref()?;
+        listener.local_addr().ok()
+    }
+
+    /// The address DNS over HTTPS clients connect to, None without a certificate
+    pub fn https_local_addr(&self) -> Option<SocketAddr> {
+        let (listener, _) = self.https_listener.as_ref()?;
+        listener.local_addr().ok()
+    }
+
+    /// How often queries were answered from the denial cache
+    pub fn denial_cache_hits(&self) -> DenialCacheHits {
+        self.denial_cache.hits()
+    }
+
+    /// Forwards queries and relays answers until the UDP socket fails
+    pub fn run(&self) -> Result<(), Error> {
+        let stopped = &AtomicBool::new(false);
+        thread::scope(|scope| {
+            for index in 0..self.upstreams.len() {
+                scope.spawn(move || self.check_health(index, stopped));
+            }
+            if !lock(&self.secondaries).is_empty() {
+                scope.spawn(move || self.refresh_zones(stopped));
+            }
+            scope.spawn(move || {
+                self.accept_connections(&self.listener, None, false, scope, stopped)
+            });
+            if let Some((listener, tls_config)) = &self.tls_listener {
+                scope.spawn(move || {
+                    self.accept_connections(listener, Some(tls_config), false, scope, stopped)
+                });
+            }
+            if let Some((listener, tls_config)) = &self.https_listener {
+                scope.spawn(move || {
+                    self.accept_connections(listener, Some(tls_config), true, scope, stopped)
+                });
+            }
+            // An UPDATE changes and signs a zone, which mustn't hold up other clients
+            let (zone_messages, received) =
+                mpsc::sync_channel::<(OperationCode, Vec<u8>, SocketAddr)>(ZONE_MESSAGE_BACKLOG);
+            scope.spawn(move || {
+                for (operation_code, message, client) in received {
+                    self.answer_zone_datagram(operation_code, &message, client);
+                }
+            });
+            let forwarded = self.forward_datagrams(&zone_messages, scope, stopped);
+            drop(zone_messages);
+            stopped.store(true, Ordering::Relaxed);
+            // Wakes the listeners up so that they see we stopped
+            let listeners = self
+                .tls_listener
+                .iter()
+                .chain(&self.https_listener)
+                .map(|(listener, _)| listener);
+            for listener in std::iter::once(&self.listener).chain(listeners) {
+                if let Ok(mut listen) = listener.local_addr() {
+                    if listen.ip().is_unspecified() {
+                        listen.set_ip(match listen.ip() {
+                            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                        });
+                    }
+                    let _ = TcpStream::connect(listen);
+                }
+            }
+            forwarded
+        })
+    }
+
+    fn forward_datagrams<'s>(
+        &'s self,
+        zone_messages: &SyncSender<(OperationCode, Vec<u8>, SocketAddr)>,
+        scope: &'s Scope<'s, '_>,
+        stopped: &'s AtomicBool,
+    ) -> Result<(), Error> {
+        let mut buffer = vec![0u8; MAXIMUM_DATAGRAM_SIZE];
+        let listen = self.local_addr()?;
+        loop {
+            let (size, client) = self
+                .socket
+                .recv_from(&mut buffer)
+                .map_err(socket_error(listen))?;
+            if let Err(error) = self.forward_datagram(
+                buffer[..size].to_vec(),
+                client,
+                zone_messages,
+                scope,
+                stopped,
+            ) {
+                self.log(format_args!(
+                    "Failed to forward a query from {}: {}",
+                    client, error
+                ));
+            }
+        }
+    }
+
+    /// Datagrams that aren't queries are dropped
+    /// NOTIFY and UPDATE messages from peers that may send them are queued for the zone worker, the rest are refused here
+    fn forward_datagram<'s>(
+        &'s self,
+        mut datagram: Vec<u8>,
+        client: SocketAddr,
+        zone_messages: &SyncSender<(OperationCode, Vec<u8>, SocketAddr)>,
+        scope: &'s Scope<'s, '_>,
+        stopped: &'s AtomicBool,
+    ) -> Result<(), Error> {
+        let packet = DnsParser::new().parse_packet(&datagram)?;
+        if !matches!(packet.header.packet_type, PacketType::Query) {
+            return Ok(());
+        }
+        if self.is_zone_message(&packet) {
+            let operation_code = packet.header.operation_code;
+            if !self.accepts_zone_message(&packet, client.ip()) {
+                self.answer_zone_datagram(operation_code, &datagram, client);
+                return Ok(());
+            }
+            return match zone_messages.try_send((operation_code, datagram, client)) {
+                // The peer sends it again when it isn't answered
+                Err(TrySendError::Full(_)) => Err(Error::new(ErrorKind::ConnectionFailed(
+                    String::from("too many NOTIFY and UPDATE messages are waiting"),
+                ))),
+                _ => Ok(()),
+            };
+        }
+        // RFC 7828 section 3.2.1, keepalive only means something over TCP
+        edns::remove_option(&mut datagram, edns::KEEPALIVE_OPTION);
+        self.forward(datagram, Requester::Udp(client), false, scope, stopped)
+    }
+
+    /// Connections over TCP, TLS and HTTPS share the limit on connections
+    fn accept_connections<'s>(
+        &'s self,
+        listener: &TcpListener,
+        tls_config: Option<&'s Arc<rustls::ServerConfig>>,
+        https: bool,
+        scope: &'s Scope<'s, '_>,
+        stopped: &'s AtomicBool,
+    ) {
+        for stream in listener.incoming() {
+            if stopped.load(Ordering::Relaxed) {
+                return;
+            }
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    self.log(format_args!("Failed to accept a connection: {}", error));
+                    continue;
+                }
+            };
+            // Dropping the stream closes connections over the limit straight away
+            if self.connections.fetch_add(1, Ordering::Relaxed) >= self.max_connections {
+                self.connections.fetch_sub(1, Ordering::Relaxed);
+                continue;
+            }
+            scope.spawn(move || {
+                if let Err(error) = self.serve_connection(stream, tls_config, https, scope, stopped)
+                {
+                    self.log(format_args!("Closed a connection: {}", error));
+                }
+                self.connections.fetch_sub(1, Ordering::Relaxed);
+            });
+        }
+    }
+
+    /// Reads queries from a connection until the client closes it or it has been idle too long, RFC 7766
+    /// A DNS over TLS client has until the idle timeout to finish the handshake, RFC 7858 section 3.4
+    /// DNS over HTTPS connections are handed on once the handshake is done
+    fn serve_connection<'s>(
+        &'s self,
+        stream: TcpStream,
+        tls_config: Option<&Arc<rustls::ServerConfig>>,
+        https: bool,
+        scope: &'s Scope<'s, '_>,
+        stopped: &'s AtomicBool,
+    ) -> Result<(), Error> {
+        let peer = stream
+            .peer_addr()
+            .map_err(|error| Error::new(ErrorKind::ConnectionFailed(error.to_string())))?;
+        stream
+            .set_read_timeout(Some(self.idle_timeout))
+            .and_then(|_| stream.set_write_timeout(Some(self.idle_timeout)))
+            .and_then(|_| stream.set_nodelay(true))
+            .map_err(socket_error(peer))?;
+        let (mut reader, writer) = match tls_config {
+            Some(tls_config) => tls::accept(stream, tls_config.clone())?,
+            None => {
+                let writer = stream.try_clone().map_err(socket_error(peer))?;
+                (StreamReader::Tcp(stream), StreamWriter::Tcp(writer))
+            }
+        };
+        reader
+            .set_read_timeout(Some(POLL_INTERVAL.min(self.idle_timeout)))
+            .map_err(socket_error(peer))?;
+        if https {
+            return self.serve_https(reader, writer, peer, scope, stopped);
+        }
+        let connection = Arc::new(TcpConnection::new(writer));
+        let mut last_query = Instant::now();
+        while !stopped.load(Ordering::Relaxed) {
+            match reader.wait() {
+                Ok(false) => break,
+                Ok(true) => (),
+                Err(error) if is_timeout(&error) => {
+                    if last_query.elapsed() >= self.idle_timeout
+                        && connection.is_idle(self.idle_timeout)
+                    {
+                        break;
+                    }
+                    continue;
+                }
+                Err(error) => return Err(socket_error(peer)(error)),
+            }
+            // Once a message starts arriving it has to arrive without stalling
+            let message = match framing::read_message(&mut reader)? {
+                Some(message) => message,
+                None => break,
+            };
+            last_query = Instant::now();
+            if let Err(error) =
+                self.forward_stream_query(message, &connection, peer, scope, stopped)
+            {
+                self.log(format_args!(
+                    "Failed to forward a query from {}: {}",
+                    peer, error
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads DNS over HTTPS requests until the client closes the connection or it has been idle too long
+    /// HTTP/1.1 requests are answered one at a time in order, HTTP/2 streams up to the pipelining limit at once
+    fn serve_https<'s>(
+        &'s self,
+        reader: StreamReader,
+        writer: StreamWriter,
+        peer: SocketAddr,
+        scope: &'s Scope<'s, '_>,
+        stopped: &'s AtomicBool,
+    ) -> Result<(), Error> {
+        let http2 = reader.alpn_protocol().as_deref() == Some(https::H2);
+        let connection = Arc::new(HttpsConnection::new(writer, http2, self.timeout));
+        let mut requests = HttpsReader::new(
+            reader,
+            connection.clone(),
+            self.max_pipelined,
+            self.idle_timeout,
+        )?;
+        let limit = if http2 { self.max_pipelined } else { 1 };
+        let mut last_request = Instant::now();
+        while !stopped.load(Ordering::Relaxed) && !requests.is_closed() {
+            match requests.wait() {
+                Ok(false) => break,
+                Ok(true) => (),
+                Err(error) if is_timeout(&error) => {
+                    if last_request.elapsed() >= self.idle_timeout
+                        && connection.connection.is_idle(self.idle_timeout)
+                    {
+                        break;
+                    }
+                    continue;
+                }
+                Err(error) => return Err(socket_error(peer)(error)),
+            }
+            last_request = Instant::now();
+            let (stream, request) = match requests.read()? {
+                Some(request) => request,
+                None => continue,
+            };
+            if let Err(error) =
+                self.forward_https_request(request, stream, &connection, limit, scope, stopped)
+            {
+                self.log(format_args!(
+                    "Failed to forward a query from {}: {}",
+                    peer, error
+                ));
+            }
+        }
+        requests.close();
+        Ok(())
+    }
+
+    /// Forwards the query in a DNS over HTTPS request, requests without one are refused with an HTTP status
+    fn forward_https_request<'s>(
+        &'s self,
+        request: HttpRequest,
+        stream: u32,
+        connection: &Arc<HttpsConnection>,
+        limit: usize,
+        scope: &'s Scope<'s, '_>,
+        stopped: &'s AtomicBool,
+    ) -> Result<(), Error> {
+        while !connection.connection.acquire(limit) {
+            if stopped.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            self.expire();
+        }
+        let reject = |status| {
+            let rejected = connection.reject(stream, status);
+            connection.connection.release();
+            rejected
+        };
+        let (mut query, json) = match request.query() {
+            Ok(query) => query,
+            Err(status) => return reject(status),
+        };
+        let transfer = match DnsParser::new().parse_packet(&query) {
+            Ok(packet) if matches!(packet.header.packet_type, PacketType::Query) => {
+                packet.questions.iter().any(|question| {
+                    matches!(
+                        question.question_type,
+                        QuestionType::TransferZone | QuestionType::IncrementalTransfer
+                    )
+                })
+            }
+            _ => return reject(400),
+        };
+        let requester = Requester::Https(connection.clone(), HttpsExchange { stream, json });
+        // A transfer can't be answered in a single response
+        if transfer {
+            return self.refuse(&requester, &query, ResponseCode::REFUSED);
+        }
+        // RFC 8484 section 5.3, connection options like keepalive are left to HTTP
+        edns::remove_option(&mut query, edns::KEEPALIVE_OPTION);
+        self.forward(query, requester, false, scope, stopped)
+    }
+
+    /// Forwards a query read from a connection, transfers are answered here instead
+    fn forward_stream_query<'s>(
+        &'s self,
+        mut message: Vec<u8>,
+        connection: &Arc<TcpConnection>,
+        peer: SocketAddr,
+        scope: &'s Scope<'s, '_>,
+        stopped: &'s AtomicBool,
+    ) -> Result<(), Error> {
+        let packet = DnsParser::new().parse_packet(&message)?;
+        if !matches!(packet.header.packet_type, PacketType::Query) {
+            return Ok(());
+        }
+        let transfer = packet.questions.iter().any(|question| {
+            matches!(
+                question.question_type,
+                QuestionType::TransferZone | QuestionType::IncrementalTransfer
+            )
+        });
+        if self.is_zone_message(&packet) {
+            return connection.send(&self.answer_zone_message(
+                packet.header.operation_code,
+                &message,
+                peer.ip(),
+            )?);
+        }
+        if transfer {
+            let responses = lock(&self.transfers).respond(&message, peer.ip())?;
+            for response in responses {
+                connection.send(&response)?;
+            }
+            return Ok(());
+        }
+        // Stops reading from the connection until the upstream answers some of its queries
+        while !connection.acquire(self.max_pipelined) {
+            if stopped.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            self.expire();
+        }
+        let keepalive = edns::remove_option(&mut message, edns::KEEPALIVE_OPTION);
+        self.forward(
+            message,
+            Requester::Tcp(connection.clone()),
+            keepalive,
+            scope,
+            stopped,
+        )
+    }
+
+    /// Forgets queries that weren't answered in time, which counts against their upstreams
+    fn expire(&self) {
+        let failed = lock(&self.pending).expire(self.timeout);
+        for index in failed {
+            let changed = self.upstreams[index].failed(self.timeout, &self.health_check);
+            self.log_state(index, changed);
+        }
+    }
+
+    /// Logs an upstream going down or coming back up
+    fn log_state(&self, index: usize, changed: Option<UpstreamState>) {
+        if let Some(state) = changed {
+            self.log(format_args!(
+                "Upstream {} is {}",
+                self.upstreams[index].address, state
+            ));
+        }
+    }
+
+    /// Probes an upstream every interval until the proxy stops, the first probe goes out after one interval
+    fn check_health(&self, index: usize, stopped: &AtomicBool) {
+        let upstream = &self.upstreams[index];
+        let mut started = Instant::now();
+        while !stopped.load(Ordering::Relaxed) {
+            let remaining = self.health_check.interval.saturating_sub(started.elapsed());
+            if !remaining.is_zero() {
+                thread::sleep(remaining.min(POLL_INTERVAL));
+                continue;
+            }
+            started = Instant::now();
+            let probed = self.health_check.probe(upstream);
+            let changed = upstream.probed(&probed, &self.health_check);
+            self.log_state(index, changed);
+        }
+    }
+
+    /// Transfers each secondary zone when its SOA timers say so until the proxy stops
+    /// A zone is answered from once it has been transferred and forwarded again once it expires
+    fn refresh_zones(&self, stopped: &AtomicBool) {
+        while !stopped.load(Ordering::Relaxed) {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs() as u32);
+            let zones = lock(&self.secondaries).len();
+            for index in 0..zones {
+                // The transfer works on a copy, a NOTIFY mustn't wait on the lock until it is done
+                let due = {
+                    let mut secondaries = lock(&self.secondaries);
+                    let zone = &mut secondaries[index];
+                    match zone.next_refresh() <= now {
+                        true => {
+                            let copy = zone.clone();
+                            zone.notified = false;
+                            Some(copy)
+                        }
+                        false => None,
+                    }
+                };
+                if let Some(mut zone) = due {
+                    match zone.refresh() {
+                        Ok(TransferOutcome::UpToDate) => {}
+                        Ok(_) => {
+                            if let Err(error) = self.serve_zone(&zone) {
+                                self.log(format_args!(
+                                    "Failed to serve {}: {}",
+                                    zone.zone(),
+                                    error
+                                ));
+                            }
+                        }
+                        Err(error) => self.log(format_args!(
+                            "Failed to transfer {} from {}: {}",
+                            zone.zone(),
+                            zone.primary(),
+                            error
+                        )),
+                    }
+                    // A NOTIFY that came in during the transfer makes the zone due again
+                    let mut secondaries = lock(&self.secondaries);
+                    zone.notified = secondaries[index].notified;
+                    secondaries[index] = zone;
+                }
+                let secondaries = lock(&self.secondaries);
+                if secondaries[index].is_expired() {
+                    lock(&self.transfers).remove_zone(secondaries[index].zone());
+                }
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Whether a message is for our zones rather than a query to forward
+    fn is_zone_message(&self, packet: &DnsPacket) -> bool {
+        matches!(
+            packet.header.operation_code,
+            OperationCode::Notify | OperationCode::Update
+        )
+    }
+
+    /// Whether a NOTIFY comes from the primary of a secondary zone, or an UPDATE is for a primary zone that takes updates
+    /// The TSIG is checked when the message is answered
+    fn accepts_zone_message(&self, packet: &DnsPacket, peer: IpAddr) -> bool {
+        let zone = match packet.questions.first() {
+            Some(question) => &question.domain_name,
+            None => return false,
+        };
+        match packet.header.operation_code {
+            OperationCode::Update => lock(&self.transfers)
+                .zone(zone)
+                .is_some_and(|primary| !matches!(primary.update_policy, UpdatePolicy::Refused)),
+            _ => lock(&self.secondaries).iter().any(|secondary| {
+                secondary.zone().eq_ignore_case(zone) && secondary.primary().ip() == peer
+            }),
+        }
+    }
+
+    fn answer_zone_datagram(
+        &self,
+        operation_code: OperationCode,
+        message: &[u8],
+        client: SocketAddr,
+    ) {
+        let answered = self
+            .answer_zone_message(operation_code, message, client.ip())
+            .and_then(|answer| self.reply(&Requester::Udp(client), &answer));
+        if let Err(error) = answered {
+            self.log(format_args!(
+                "Failed to answer a message from {}: {}",
+                client, error
+            ));
+        }
+    }
+
+    /// Answers a NOTIFY from the primary of a secondary zone, which makes the zone due for a refresh,
+    /// or applies an UPDATE to a primary zone, whose secondaries are then notified
+    fn answer_zone_message(
+        &self,
+        operation_code: OperationCode,
+        message: &[u8],
+        peer: IpAddr,
+    ) -> Result<Vec<u8>, Error> {
+        match operation_code {
+            OperationCode::Update => {
+                let mut transfers = lock(&self.transfers);
+                let answer = transfers.answer_update(message);
+                for signer in &self.signers {
+                    let mut signer = lock(signer);
+                    if let Some(primary) = transfers.zone(signer.zone()) {
+                        let records = primary.records().to_vec();
+                        signer.update(records);
+                    }
+                }
+                answer
+            }
+            _ => notify::answer_notify(&mut lock(&self.secondaries), message, peer),
+        }
+    }
+
+    /// Copies the records of a secondary zone into the transfer server, keeping the change for IXFR when it can
+    fn serve_zone(&self, zone: &SecondaryZone) -> Result<(), Error> {
+        let mut transfers = lock(&self.transfers);
+        let updated = transfers
+            .zone_mut(zone.zone())
+            .map(|primary| primary.update(zone.records().to_vec()));
+        if !matches!(updated, Some(Ok(()))) {
+            transfers.add_zone(PrimaryZone::new(
+                zone.zone().clone(),
+                zone.records().to_vec(),
+            )?);
+        }
+        Ok(())
+    }
+
+    /// Sends a query to an upstream picked by the strategy, under a random ID of our own and from a socket of its own
+    /// or over a connection to a DNS over TLS upstream
+    fn forward<'s>(
+        &'s self,
+        mut query: Vec<u8>,
+        requester: Requester,
+        keepalive: bool,
+        scope: &'s Scope<'s, '_>,
+        stopped: &'s AtomicBool,
+    ) -> Result<(), Error> {
+        self.expire();
+        if self.answer_from_zones(&query, &requester, keepalive)?
+            || self.answer_from_denials(&query, &requester, keepalive)?
+        {
+            return Ok(());
+        }
+        let turn = self.next_upstream.fetch_add(1, Ordering::Relaxed);
+        let index = match self.strategy.choose(&self.upstreams, turn) {
+            Some(index) => index,
+            // Every upstream is down, only probes go out until one is back up
+            None => return self.refuse(&requester, &query, ResponseCode::SERVFAIL),
+        };
+        let upstream = &self.upstreams[index];
+        // Answers are checked against the question section, which has to be written plainly
+        let question = match spoofing::question_section(&query) {
+            Some(section) => query[section].to_vec(),
+            None => return self.refuse(&requester, &query, ResponseCode::FORMERR),
+        };
+        // A DNS over TLS upstream is only ever asked over its connections, never in the clear
+        let socket = match upstream.is_tls() {
+            true => None,
+            false => match upstream.socket() {
+                Ok(socket) => Some(socket),
+                Err(error) => {
+                    self.refuse(&requester, &query, ResponseCode::SERVFAIL)?;
+                    return Err(error);
+                }
+            },
+        };
+        if self.randomize_case {
+            if let Err(error) = spoofing::randomize_case(&mut query) {
+                self.refuse(&requester, &query, ResponseCode::SERVFAIL)?;
+                return Err(error);
+            }
+        }
+        let payload_size = edns::payload_size(&query);
+        let (options, dnssec_ok) = (
+            edns::options_record(&query).is_some(),
+            edns::dnssec_ok(&query),
+        );
+        // Signatures only come back when they are asked for
+        if self.validator.is_some() {
+            edns::set_dnssec_ok(&mut query);
+        }
+        let pending = PendingQuery {
+            requester,
+            upstream: index,
+            id: u16::from_be_bytes([query[0], query[1]]),
+            forwarded: Instant::now(),
+            keepalive,
+            query: query.clone(),
+            question,
+            over_tcp: socket.is_none(),
+            payload_size,
+            options,
+            dnssec_ok,
+        };
+        let id = match lock(&self.pending).insert(pending) {
+            Ok(id) => id,
+            // Too many queries are waiting on the upstream
+            Err(pending) => return self.refuse(&pending.requester, &query, ResponseCode::SERVFAIL),
+        };
+        query[..2].copy_from_slice(&id.to_be_bytes());
+        let socket = match socket {
+            Some(socket) => socket,
+            None => {
+                if let Err(error) = self.send_over_stream(index, &query, scope, stopped) {
+                    if let Some(pending) = lock(&self.pending).queries.remove(&id) {
+                        self.refuse(&pending.requester, &pending.asked(), ResponseCode::SERVFAIL)?;
+                    }
+                    return Err(error);
+                }
+                return Ok(());
+            }
+        };
+        // Over the limit the client is told to try again rather than starting yet another thread
+        if self.waiting.fetch_add(1, Ordering::Relaxed) >= MAXIMUM_WAITING_QUERIES {
+            self.waiting.fetch_sub(1, Ordering::Relaxed);
+            if let Some(pending) = lock(&self.pending).queries.remove(&id) {
+                self.refuse(&pending.requester, &pending.asked(), ResponseCode::SERVFAIL)?;
+            }
+            return Ok(());
+        }
+        let sent = socket
+            .set_read_timeout(Some(POLL_INTERVAL.min(self.timeout)))
+            .and_then(|_| socket.send(&query));
+        if let Err(error) = sent {
+            self.waiting.fetch_sub(1, Ordering::Relaxed);
+            return Err(socket_error(upstream.address)(error));
+        }
+        scope.spawn(move || {
+            self.await_answer(index, id, socket, scope, stopped);
+            self.waiting.fetch_sub(1, Ordering::Relaxed);
+        });
+        Ok(())
+    }
+
+    /// Logs what went wrong with a single query or connection, or how the denial cache answered it, only when the proxy is verbose
+    fn log(&self, message: fmt::Arguments) {
+        if self.verbose {
+            eprintln!("{}", message);
+        }
+    }
+
+    /// Answers a query that isn't forwarded with an error
+    fn refuse(
+        &self,
+        requester: &Requester,
+        query: &[u8],
+        response_code: ResponseCode,
+    ) -> Result<(), Error> {
+        let replied = error_response(query, response_code)
+            .and_then(|response| self.reply(requester, &response));
+        requester.release();
+        replied
+    }
+
+    fn reply(&self, requester: &Requester, message: &[u8]) -> Result<(), Error> {
+        match requester {
+            Requester::Udp(client) => self
+                .socket
+                .send_to(message, client)
+                .map(|_| ())
+                .map_err(socket_error(*client)),
+            Requester::Tcp(connection) => connection.send(message),
+            Requester::Https(connection, exchange) => connection.answer(*exchange, message),
+        }
+    }
+
+    /// Waits for the answer to a query on the socket it was sent from, a truncated answer is asked for again over TCP
+    /// Datagrams with another ID or question are ignored rather than ending the wait, RFC 5452 section 9.1
+    fn await_answer<'s>(
+        &'s self,
+        index: usize,
+        id: u16,
+        socket: UdpSocket,
+        scope: &'s Scope<'s, '_>,
+        stopped: &'s AtomicBool,
+    ) {
+        let upstream = &self.upstreams[index];
+        let started = Instant::now();
+        let mut buffer = vec![0u8; MAXIMUM_DATAGRAM_SIZE];
+        while !stopped.load(Ordering::Relaxed) && started.elapsed() < self.timeout {
+            let (size, from) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(error) if is_timeout(&error) => continue,
+                Err(error) => {
+                    // An ICMP error, nothing listens on the upstream's port and the query is left to time out
+                    self.log(format_args!(
+                        "Failed to read from {}: {}",
+                        upstream.address, error
+                    ));
+                    return;
+                }
+            };
+            if from != upstream.address || size < 12 {
+                continue;
+            }
+            let answer = &buffer[..size];
+            let header = match DnsParser::new().read_header(answer) {
+                Ok(header) => header,
+                Err(_) => continue,
+            };
+            if header.id != id || !matches!(header.packet_type, PacketType::Response) {
+                continue;
+            }
+            if header.truncated && self.retry_over_tcp(index, answer, scope, stopped) {
+                return;
+            }
+            if self.deliver(answer.to_vec(), index, false) {
+                return;
+            }
+        }
+    }
+
+    /// Sends a query again over a connection to its upstream, returning whether it was sent
+    fn retry_over_tcp<'s>(
+        &'s self,
+        index: usize,
+        answer: &[u8],
+        scope: &'s Scope<'s, '_>,
+        stopped: &'s AtomicBool,
+    ) -> bool {
+        let id = u16::from_be_bytes([answer[0], answer[1]]);
+        let mut query = match lock(&self.pending).queries.get_mut(&id) {
+            Some(pending)
+                if pending.upstream == index
+                    && !pending.over_tcp
+                    && spoofing::same_question(&pending.query, answer, self.randomize_case) =>
+            {
+                pending.over_tcp = true;
+                pending.query.clone()
+            }
+            _ => return false,
+        };
+        query[..2].copy_from_slice(&id.to_be_bytes());
+        match self.send_over_stream(index, &query, scope, stopped) {
+            Ok(()) => return true,
+            Err(error) => self.log(format_args!(
+                "Failed to ask {} again over TCP: {}",
+                self.upstreams[index].address, error
+            )),
+        }
+        // The truncated answer is better than none
+        if let Some(pending) = lock(&self.pending).queries.get_mut(&id) {
+            pending.over_tcp = false;
+        }
+        false
+    }
+
+    /// Sends a query over a connection to its upstream
+    fn send_over_stream<'s>(
+        &'s self,
+        index: usize,
+        query: &[u8],
+        scope: &'s Scope<'s, '_>,
+        stopped: &'s AtomicBool,
+    ) -> Result<(), Error> {
+        let send = || {
+            self.upstream_stream(index, scope, stopped)
+                .and_then(|connection| connection.send(query))
+        };
+        // A connection can fail between queries without us noticing, so a second one is tried
+        send().or_else(|_| send())
+    }
+
+    /// The least busy open connection to an upstream, a new one is opened when they are all busy
+    fn upstream_stream<'s>(
+        &'s self,
+        index: usize,
+        scope: &'s Scope<'s, '_>,
+        stopped: &'s AtomicBool,
+    ) -> Result<Arc<UpstreamStream>, Error> {
+        let upstream = &self.upstreams[index];
+        let mut streams = lock(&upstream.streams);
+        streams.retain(|connection| !connection.is_closed());
+        let least_busy = streams
+            .iter()
+            .min_by_key(|connection| connection.in_flight.load(Ordering::Relaxed))
+            .cloned();
+        match least_busy {
+            Some(connection)
+                if connection.in_flight.load(Ordering::Relaxed) < self.max_pipelined
+                    || streams.len() >= UPSTREAM_STREAMS =>
+            {
+                Ok(connection)
+            }
+            _ => {
+                let (connection, reader) = UpstreamStream::connect(upstream, self.timeout)?;
+                let connection = Arc::new(connection);
+                let reading = connection.clone();
+                scope.spawn(move || self.read_upstream_stream(index, reader, &reading, stopped));
+                streams.push(connection.clone());
+                Ok(connection)
+            }
+        }
+    }
+
+    /// Reads answers from a connection to an upstream until it is closed
+    fn read_upstream_stream(
+        &self,
+        index: usize,
+        mut reader: StreamReader,
+        connection: &UpstreamStream,
+        stopped: &AtomicBool,
+    ) {
+        while !stopped.load(Ordering::Relaxed) && !connection.is_closed() {
+            match reader.wait() {
+                Ok(false) => break,
+                Ok(true) => (),
+                Err(error) if is_timeout(&error) => continue,
+                Err(_) => break,
+            }
+            match framing::read_message(&mut reader) {
+                Ok(Some(answer)) if answer.len() >= 12 => {
+                    connection.answered();
+                    self.deliver(answer, index, true);
+                }
+                Ok(Some(_)) => (),
+                Ok(None) | Err(_) => break,
+            }
+        }
+        // Queries still waiting on this connection are forgotten once they time out
+        connection.closed.store(true, Ordering::Relaxed);
+    }
+
+    /// Strips what the upstream has no say over from an answer, an answer that can't be checked becomes SERVFAIL
+    fn sanitize(
+        &self,
+        answer: Vec<u8>,
+        query: &PendingQuery,
+        upstream: SocketAddr,
+    ) -> Result<Vec<u8>, Error> {
+        match self.sanitizer.sanitize_message(&answer) {
+            Ok((sanitized, removals)) => {
+                for removal in removals {
+                    self.log(format_args!(
+                        "Removed {} from an answer of {}",
+                        removal, upstream
+                    ));
+                }
+                Ok(sanitized.unwrap_or(answer))
+            }
+            Err(error) => {
+                self.log(format_args!(
+                    "Failed to sanitize an answer from {}: {}",
+                    upstream, error
+                ));
+                error_response(&query.asked(), ResponseCode::SERVFAIL)
+            }
+        }
+    }
+
+    /// Validates an answer when there are trust anchors and the client didn't set CD, RFC 4035 section 3.2.2
+    /// The keys the answer is signed with are looked up first, only answers with NOERROR or NXDOMAIN can be checked
+    fn validate(
+        &self,
+        answer: Vec<u8>,
+        query: &PendingQuery,
+        index: usize,
+    ) -> Result<Vec<u8>, Error> {
+        let validator = match &self.validator {
+            Some(validator) => validator,
+            None => return Ok(answer),
+        };
+        let response_code = ResponseCode::from(answer[3] & 0x0F);
+        if query.query[3] & CHECKING_DISABLED != 0
+            || !matches!(
+                response_code,
+                ResponseCode::NOERROR | ResponseCode::NXDOMAIN
+            )
+        {
+            return Ok(answer);
+        }
+        match self.check_answer(validator, &answer, index) {
+            Ok(validated) => Ok(validated),
+            // An answer that can't be checked is neither secure nor bogus, RFC 8914 section 4.6
+            Err(error) => {
+                self.log(format_args!(
+                    "Failed to validate an answer from {}: {}",
+                    self.upstreams[index].address, error
+                ));
+                let mut response = error_response(&query.asked(), ResponseCode::SERVFAIL)?;
+                edns::add_extended_error(&mut response, ExtendedError::DnssecIndeterminate);
+                Ok(response)
+            }
+        }
+    }
+
+    /// Validates an answer, caching the denials of a secure one
+    fn check_answer(
+        &self,
+        validator: &Mutex<Validator>,
+        answer: &[u8],
+        index: usize,
+    ) -> Result<Vec<u8>, Error> {
+        let missing = lock(validator).missing_keys(&DnsParser::new().parse_packet(answer)?);
+        for zone in missing {
+            self.learn_keys(validator, &zone, index, MAXIMUM_KEY_DEPTH);
+        }
+        let (validated, result) = lock(validator).validate_message(answer)?;
+        match result {
+            ValidationResult::Secure => self
+                .denial_cache
+                .insert(&DnsParser::new().parse_packet(&validated)?, result),
+            ValidationResult::Bogus(extended_error) => self.log(format_args!(
+                "An answer from {} is bogus: {}",
+                self.upstreams[index].address, extended_error
+            )),
+            _ => {}
+        }
+        Ok(validated)
+    }
+
+    /// Answers NXDOMAIN or NODATA from the denial cache when its records prove the answer, RFC 8198 section 5
+    /// Returns whether the query was answered, only queries that would have been validated are
+    fn answer_from_denials(
+        &self,
+        query: &[u8],
+        requester: &Requester,
+        keepalive: bool,
+    ) -> Result<bool, Error> {
+        if self.validator.is_none() || query[3] & CHECKING_DISABLED != 0 {
+            return Ok(false);
+        }
+        let packet = match DnsParser::new().parse_packet(query) {
+            Ok(packet) if packet.questions.len() == 1 => packet,
+            _ => return Ok(false),
+        };
+        let question = &packet.questions[0];
+        let (response_code, authority) = match self
+            .denial_cache
+            .lookup(&question.domain_name, question.question_type.code())
+        {
+            Some(denial) => denial,
+            None => return Ok(false),
+        };
+        let hits = self.denial_cache_hits();
+        self.log(format_args!(
+            "{} answered from validated denials, {} NXDOMAIN, {} NODATA and {} misses so far",
+            question.domain_name,
+            hits.name_errors(),
+            hits.no_data(),
+            hits.misses()
+        ));
+        let mut builder = DnsResponseBuilder::new(packet.header.id);
+        builder
+            .add_question(question.clone())
+            .recursion_desired(packet.header.recursion_desired)
+            .recursion_available(true)
+            .response_code(response_code)
+            .validation_result(ValidationResult::Secure);
+        for record in authority {
+            builder.add_authority(record);
+        }
+        self.answer_directly(builder.build_response(), query, requester, keepalive)
+    }
+
+    /// Answers a query for a name in one of our zones authoritatively instead of forwarding it
+    /// Returns whether the query was answered
+    fn answer_from_zones(
+        &self,
+        query: &[u8],
+        requester: &Requester,
+        keepalive: bool,
+    ) -> Result<bool, Error> {
+        let (zone, answer) = {
+            let transfers = lock(&self.transfers);
+            let zone = DnsParser::new()
+                .parse_packet(query)
+                .ok()
+                .and_then(|packet| packet.questions.into_iter().next())
+                .and_then(|question| transfers.zone_for(&question.domain_name))
+                .map(|primary| primary.zone().clone());
+            match zone {
+                Some(zone) => (zone, transfers.answer_query(query)),
+                None => return Ok(false),
+            }
+        };
+        let signer = self
+            .signers
+            .iter()
+            .find(|signer| lock(signer).zone().eq_ignore_case(&zone));
+        let answer = match signer {
+            Some(signer) if edns::dnssec_ok(query) => {
+                answer.and_then(|answer| lock(signer).sign_response(&answer))
+            }
+            _ => answer,
+        };
+        self.answer_directly(answer, query, requester, keepalive)
+    }
+
+    /// Sends an answer the proxy made itself rather than got from an upstream, truncated if it is too big for UDP
+    fn answer_directly(
+        &self,
+        answer: Result<Vec<u8>, Error>,
+        query: &[u8],
+        requester: &Requester,
+        keepalive: bool,
+    ) -> Result<bool, Error> {
+        let replied = answer
+            .and_then(|mut answer| {
+                if keepalive {
+                    edns::add_keepalive(&mut answer, self.idle_timeout);
+                }
+                match requester {
+                    Requester::Udp(_) if answer.len() > edns::payload_size(query) => {
+                        truncate(&answer)
+                    }
+                    _ => Ok(answer),
+                }
+            })
+            .and_then(|answer| self.reply(requester, &answer));
+        requester.release();
+        replied.map(|_| true)
+    }
+
+    /// Asks the upstream for the DS and DNSKEY records of a zone, the keys of the parent that signed the DS records first
+    fn learn_keys(
+        &self,
+        validator: &Mutex<Validator>,
+        zone: &DomainName,
+        index: usize,
+        depth: usize,
+    ) {
+        let upstream = &self.upstreams[index];
+        // A zone is asked for its DS records and then for its DNSKEY records
+        for _ in 0..2 {
+            let question_type = match lock(validator).next_key_query(zone) {
+                Some(question_type) if depth > 0 => question_type,
+                _ => return,
+            };
+            let answer = match key_query(zone, question_type)
+                .and_then(|query| upstream.exchange(&query, self.timeout))
+            {
+                Ok(answer) => answer,
+                Err(error) => {
+                    self.log(format_args!(
+                        "Failed to look up the keys of {}: {}",
+                        zone, error
+                    ));
+                    return;
+                }
+            };
+            let packet = match DnsParser::new().parse_packet(&answer) {
+                Ok(packet) => packet,
+                Err(error) => {
+                    self.log(format_args!(
+                        "Failed to look up the keys of {}: {}",
+                        zone, error
+                    ));
+                    return;
+                }
+            };
+            let parents = lock(validator).missing_keys(&packet);
+            for parent in parents.iter().filter(|parent| !parent.eq_ignore_case(zone)) {
+                self.learn_keys(validator, parent, index, depth - 1);
+            }
+            lock(validator).validate(&packet);
+        }
+    }
+
+    /// Sends an answer to the client that asked, with the client's ID and question put back
+    /// Only an answer from the upstream the query was sent to, over the transport it was last sent over,
+    /// that echoes the question is accepted, returns whether the answer was accepted
+    fn deliver(&self, mut answer: Vec<u8>, index: usize, over_tcp: bool) -> bool {
+        let id = u16::from_be_bytes([answer[0], answer[1]]);
+        let query = {
+            let mut pending = lock(&self.pending);
+            match pending.queries.get(&id) {
+                Some(query)
+                    if query.upstream == index
+                        && query.over_tcp == over_tcp
+                        && spoofing::same_question(&query.query, &answer, self.randomize_case) =>
+                {
+                    pending.queries.remove(&id)
+                }
+                _ => None,
+            }
+        };
+        let query = match query {
+            Some(query) => query,
+            None => return false,
+        };
+        let upstream = &self.upstreams[index];
+        upstream.answered(query.forwarded.elapsed());
+        answer[..2].copy_from_slice(&query.id.to_be_bytes());
+        if let Some(section) = spoofing::question_section(&answer) {
+            answer[section].copy_from_slice(&query.question);
+        }
+        answer = match self
+            .sanitize(answer, &query, upstream.address)
+            .and_then(|answer| self.validate(answer, &query, index))
+            .and_then(
+                |answer| match self.validator.is_some() && !query.dnssec_ok {
+                    true => hide_dnssec(&answer, query.options),
+                    false => Ok(answer),
+                },
+            ) {
+            Ok(answer) => answer,
+            Err(error) => {
+                self.log(format_args!("Failed to answer a query: {}", error));
+                if let Err(error) =
+                    self.refuse(&query.requester, &query.asked(), ResponseCode::SERVFAIL)
+                {
+                    self.log(format_args!("Failed to answer a query: {}", error));
+                }
+                return true;
+            }
+        };
+        if query.keepalive {
+            edns::add_keepalive(&mut answer, self.idle_timeout);
+        }
+        if matches!(query.requester, Requester::Udp(_)) && answer.len() > query.payload_size {
+            answer = match truncate(&answer) {
+                Ok(truncated) => truncated,
+                Err(error) => {
+                    self.log(format_args!(
+                        "Failed to truncate an answer from {}: {}",
+                        upstream.address, error
+                    ));
+                    query.requester.release();
+                    return true;
+                }
+            };
+        }
+        if let Err(error) = self.reply(&query.requester, &answer) {
+            self.log(format_args!("Failed to answer a query: {}", error));
+        }
+        query.requester.release();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{
+        builders::{DnsResponseBuilder, PacketWriter},
+        dnssec, hpack,
+        zone::{ZoneParser, ZoneWriter},
+        DenialOfExistence, DomainName, Header, HeaderDecoder, Question, QuestionClass, Resource,
+        ResourceClass, ResourcePayload, SigningKey, ZoneSigner,
+    };
+    use crate::helper::encode_base64;
+    use std::{
+        borrow::Cow,
+        collections::HashSet,
+        io::{Read, Write},
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    fn query(id: u16, domain: &str, options: Option<&[u8]>) -> Vec<u8> {
+        let mut header = Header::new();
+        header.id = id;
+        header.recursion_desired = true;
+        header.question_count = 1;
+        header.additional_count = options.is_some() as u16;
+        let mut message = Vec::new();
+        header.write_header(&mut message).unwrap();
+        let question = Question {
+            domain_name: DomainName::new(domain.split('.').collect()),
+            question_type: QuestionType::Address,
+            question_class: QuestionClass::Internet,
+        };
+        let mut writer = PacketWriter::with_data(message);
+        writer.write_question(&question).unwrap();
+        if let Some(options) = options {
+            writer.write_options(options).unwrap();
+        }
+        writer.into_inner()
+    }
+
+    fn answer_address(answer: &[u8]) -> ResourcePayload<'static> {
+        let packet = DnsParser::new().parse_packet(answer).unwrap();
+        packet.answers[0].payload.clone().into_owned()
+    }
+
+    /// Answers queries with an address made from the length of their first label, in reverse order
+    fn stub_upstream(socket: &UdpSocket, queries: usize) -> HashSet<u16> {
+        let mut received = Vec::new();
+        let mut buffer = [0u8; 512];
+        for _ in 0..queries {
+            let (size, peer) = socket.recv_from(&mut buffer).unwrap();
+            received.push((buffer[..size].to_vec(), peer));
+        }
+        let mut ids = HashSet::new();
+        for (query, peer) in received.iter().rev() {
+            ids.insert(u16::from_be_bytes([query[0], query[1]]));
+            socket.send_to(&stub_answer(query), peer).unwrap();
+        }
+        ids
+    }
+
+    /// Answers with 192.0.2.N where N is the length of the first label
+    fn stub_answer(query: &[u8]) -> Vec<u8> {
+        let packet = DnsParser::new().parse_packet(query).unwrap();
+        let question = packet.questions[0].clone();
+        let length = question.domain_name.labels()[0].len() as u8;
+        let mut builder = DnsResponseBuilder::new(packet.header.id);
+        builder.add_answer(Resource::new(
+            question.domain_name.clone(),
+            ResourceClass::Internet,
+            60,
+            ResourcePayload::Address([192, 0, 2, length].into()),
+        ));
+        builder.add_question(question);
+        builder.build_response().unwrap()
+    }
+
+    fn start_proxy(config: &mut ProxyConfig) -> (SocketAddr, UdpSocket) {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        config
+            .listen("127.0.0.1:0".parse().unwrap())
+            .upstream(upstream.local_addr().unwrap());
+        let proxy = Proxy::bind(config).unwrap();
+        let address = proxy.local_addr().unwrap();
+        thread::spawn(move || proxy.run());
+        (address, upstream)
+    }
+
+    #[test]
+    fn test_config_from_args() {
+        let args = |text: &str| {
+            text.split_whitespace()
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+        let config = ProxyConfig::from_args(args(
+            "--listen 127.0.0.1:5353 --upstream [::1]:53 --upstream 192.0.2.1:53,3 --strategy weighted \
+             --timeout 2 --idle-timeout 30 --max-connections 10 --max-pipelined 5 --verbose true",
+        ))
+        .unwrap();
+        assert_eq!(config.listen, "127.0.0.1:5353".parse().unwrap());
+        assert_eq!(
+            config.upstreams,
+            vec![
+                ("[::1]:53".parse().unwrap(), 1),
+                ("192.0.2.1:53".parse().unwrap(), 3)
+            ]
+        );
+        assert_eq!(config.strategy, UpstreamStrategy::Weighted);
+        assert_eq!(config.timeout, Duration::from_secs(2));
+        assert_eq!(config.idle_timeout, Duration::from_secs(30));
+        assert_eq!((config.max_connections, config.max_pipelined), (10, 5));
+        assert!(config.verbose);
+        let config = ProxyConfig::from_args(args(
+            "--probe-name example.com. --probe-type soa --probe-interval 10 --probe-timeout 1 \
+             --down-after 5 --up-after 4 --randomize-case true --bailiwick example.com \
+             --tls-listen 127.0.0.1:8853 --tls-certificate server.pem --tls-key server.key \
+             --tls-upstream 192.0.2.2:853#dns.example.com,2 --tls-ca ca.pem \
+             --tls-pin YMmNj85cTjqPhcL34fe36XRtfNDsOxIWmYI64VHaDAs= --https-listen [::1]:8443 \
+             --trust-anchors anchors.zone --primary-zone zones/example.com.zone#example.com \
+             --secondary-zone 192.0.2.53:53#example.net --allow-transfer 192.0.2.54 \
+             --notify 192.0.2.55:53 --transfer-keys transfer.key#transfer.example.com \
+             --allow-update example.com \
+             --update-keys update.key#example.org --signing-key example.com.pem#example.com \
+             --zone-signing-key example.com.zsk.pem#example.com --signing-algorithm 15 \
+             --signature-lifetime 86400 --denial nsec3",
+        ))
+        .unwrap();
+        let mut health_check = HealthCheck::new();
+        health_check
+            .name(DomainName::new(vec!["example", "com"]))
+            .question_type(ResourceType::StartAuthority)
+            .interval(Duration::from_secs(10))
+            .timeout(Duration::from_secs(1))
+            .down_after(5)
+            .up_after(4);
+        assert_eq!(config.health_check, health_check);
+        assert!(config.randomize_case);
+        assert_eq!(config.tls_listen, Some("127.0.0.1:8853".parse().unwrap()));
+        assert_eq!(config.https_listen, Some("[::1]:8443".parse().unwrap()));
+        assert_eq!(config.trust_anchors, Some(PathBuf::from("anchors.zone")));
+        assert_eq!(
+            config.primary_zones,
+            vec![(
+                DomainName::new(vec!["example", "com"]),
+                PathBuf::from("zones/example.com.zone")
+            )]
+        );
+        assert_eq!(
+            config.secondary_zones,
+            vec![(
+                DomainName::new(vec!["example", "net"]),
+                "192.0.2.53:53".parse().unwrap()
+            )]
+        );
+        assert_eq!(config.notify, vec!["192.0.2.55:53".parse().unwrap()]);
+        assert_eq!(
+            config.transfer_keys,
+            Some((
+                PathBuf::from("transfer.key"),
+                DomainName::new(vec!["transfer", "example", "com"])
+            ))
+        );
+        assert_eq!(
+            config.updates,
+            vec![
+                (DomainName::new(vec!["example", "com"]), None),
+                (
+                    DomainName::new(vec!["example", "org"]),
+                    Some(PathBuf::from("update.key"))
+                )
+            ]
+        );
+        assert_eq!(
+            config.signing_keys,
+            vec![
+                (
+                    DomainName::new(vec!["example", "com"]),
+                    PathBuf::from("example.com.pem"),
+                    KEY_SIGNING_KEY_FLAGS
+                ),
+                (
+                    DomainName::new(vec!["example", "com"]),
+                    PathBuf::from("example.com.zsk.pem"),
+                    dnssec::ZONE_KEY_FLAG
+                )
+            ]
+        );
+        assert_eq!(config.signature_lifetime, Some(86_400));
+        assert_eq!(config.signing_algorithm, dnssec::ED25519);
+        assert_eq!(
+            config.denial,
+            DenialOfExistence::NextSecure3 {
+                iterations: 0,
+                salt: Vec::new()
+            }
+        );
+        assert_eq!(
+            config.allow_transfer,
+            vec!["192.0.2.54".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(
+            config.tls_certificate,
+            Some((PathBuf::from("server.pem"), PathBuf::from("server.key")))
+        );
+        let dot_upstream: SocketAddr = "192.0.2.2:853".parse().unwrap();
+        assert_eq!(config.upstreams, vec![(dot_upstream, 2)]);
+        let pin = decode_base64("YMmNj85cTjqPhcL34fe36XRtfNDsOxIWmYI64VHaDAs=").unwrap();
+        let mut tls_upstream = TlsUpstream::new("dns.example.com");
+        tls_upstream
+            .certificate_authorities(PathBuf::from("ca.pem"))
+            .pin(<[u8; 32]>::try_from(pin.as_slice()).unwrap());
+        assert_eq!(config.tls_upstreams.get(&dot_upstream), Some(&tls_upstream));
+        assert_eq!(
+            config.sanitizer,
+            Sanitizer::new(DomainName::new(vec!["example", "com"]))
+        );
+        assert_eq!(
+            ProxyConfig::from_args(Vec::new()).unwrap(),
+            ProxyConfig::new()
+        );
+        for invalid in [
+            "--listen",
+            "--listen nowhere",
+            "--timeout 0",
+            "--max-connections many",
+            "--upstream 192.0.2.1:53,0",
+            "--strategy fastest",
+            "--probe-type nothing",
+            "--randomize-case maybe",
+            "--down-after 0",
+            "--tls-certificate server.pem",
+            "--tls-upstream 192.0.2.2:853",
+            "--tls-pin YMmNj85c",
+            "--tls-listen 127.0.0.1:853",
+            "--https-listen 127.0.0.1:443",
+            "--verbose yes",
+            "--primary-zone example.com.zone",
+            "--secondary-zone example.net#example.net",
+            "--allow-transfer 192.0.2.54:53",
+            "--update-keys update.key",
+            "--transfer-keys transfer.key",
+            "--signing-key example.com.pem",
+            "--signing-algorithm ed25519",
+            "--signature-lifetime 4294967296",
+            "--denial nsec5",
+        ]
+        .iter()
+        {
+            assert!(matches!(
+                ProxyConfig::from_args(args(invalid)).map_err(|error| error.kind().clone()),
+                Err(ErrorKind::InvalidConfiguration(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_udp_proxy() {
+        let (address, upstream) = start_proxy(&mut ProxyConfig::new());
+
+        // Both clients use the same ID, the upstream must see two different ones
+        let clients = [
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+        ];
+        for client in &clients {
+            client
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+        }
+        clients[0]
+            .send_to(&query(0x1111, "a.example.com", None), address)
+            .unwrap();
+        clients[1]
+            .send_to(&query(0x1111, "abc.example.com", None), address)
+            .unwrap();
+        let ids = stub_upstream(&upstream, 2);
+        assert_eq!(ids.len(), 2);
+
+        for (client, expected) in clients.iter().zip([1u8, 3].iter()) {
+            let mut buffer = [0u8; 512];
+            let size = client.recv(&mut buffer).unwrap();
+            assert_eq!(&buffer[..2], &[0x11, 0x11]);
+            assert_eq!(
+                answer_address(&buffer[..size]),
+                ResourcePayload::Address([192, 0, 2, *expected].into())
+            );
+        }
+    }
+
+    #[test]
+    fn test_waiting_limit() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut config = ProxyConfig::new();
+        config
+            .listen("127.0.0.1:0".parse().unwrap())
+            .upstream(upstream.local_addr().unwrap());
+        let proxy = Arc::new(Proxy::bind(&config).unwrap());
+        let address = proxy.local_addr().unwrap();
+        let running = proxy.clone();
+        thread::spawn(move || running.run());
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut buffer = [0u8; 512];
+
+        // Queries over the limit are answered straight away without reaching the upstream
+        proxy
+            .waiting
+            .store(MAXIMUM_WAITING_QUERIES, Ordering::Relaxed);
+        client
+            .send_to(&query(1, "example.com", None), address)
+            .unwrap();
+        let size = client.recv(&mut buffer).unwrap();
+        let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+        assert_eq!(packet.header.response_code, ResponseCode::SERVFAIL);
+        proxy.waiting.store(0, Ordering::Relaxed);
+        client
+            .send_to(&query(2, "example.com", None), address)
+            .unwrap();
+        assert_eq!(stub_upstream(&upstream, 1).len(), 1);
+        let size = client.recv(&mut buffer).unwrap();
+        let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+        assert_eq!(packet.header.id, 2);
+        assert_eq!(packet.header.response_code, ResponseCode::NOERROR);
+    }
+
+    #[test]
+    fn test_spoofed_answers() {
+        let mut config = ProxyConfig::new();
+        config.randomize_case(true);
+        let (address, upstream) = start_proxy(&mut config);
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let asked = query(7, "abcdefghijklmnop.example.com", None);
+        client.send_to(&asked, address).unwrap();
+        client
+            .send_to(&query(8, "abcdefghijklmnop.example.com", None), address)
+            .unwrap();
+        let mut buffer = [0u8; 512];
+        let (size, peer) = upstream.recv_from(&mut buffer).unwrap();
+        let forwarded = buffer[..size].to_vec();
+        let (_, other_peer) = upstream.recv_from(&mut buffer).unwrap();
+        assert_ne!(peer.port(), other_peer.port());
+        // Twenty six letters all keep their case only once in millions of tries
+        assert!(forwarded[12..].eq_ignore_ascii_case(&asked[12..]));
+        assert_ne!(&forwarded[12..], &asked[12..]);
+
+        // Forged answers carry another address, none of them may reach the client
+        let forged = |mut answer: Vec<u8>| {
+            let length = answer.len();
+            answer[length - 4..].copy_from_slice(&[203, 0, 113, 66]);
+            answer
+        };
+        let spoofer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        spoofer
+            .send_to(&forged(stub_answer(&forwarded)), peer)
+            .unwrap();
+        let mut wrong_id = forged(stub_answer(&forwarded));
+        wrong_id[1] ^= 1;
+        upstream.send_to(&wrong_id, peer).unwrap();
+        let mut lowercase = forwarded.clone();
+        lowercase[12..].make_ascii_lowercase();
+        upstream
+            .send_to(&forged(stub_answer(&lowercase)), peer)
+            .unwrap();
+        upstream.send_to(&stub_answer(&forwarded), peer).unwrap();
+
+        // The client gets its own ID and question back
+        let size = client.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..2], &[0, 7]);
+        assert_eq!(&buffer[12..asked.len()], &asked[12..]);
+        assert_eq!(
+            answer_address(&buffer[..size]),
+            ResourcePayload::Address([192, 0, 2, 16].into())
+        );
+    }
+
+    #[test]
+    fn test_sanitized_answers() {
+        let mut config = ProxyConfig::new();
+        config.sanitizer(Sanitizer::new(DomainName::new(vec!["example", "com"])));
+        let (address, upstream) = start_proxy(&mut config);
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client
+            .send_to(&query(9, "a.example.com", None), address)
+            .unwrap();
+
+        // The upstream slips in an address for a name it has no say over
+        let mut buffer = [0u8; 512];
+        let (size, peer) = upstream.recv_from(&mut buffer).unwrap();
+        let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+        let mut builder = DnsResponseBuilder::new(packet.header.id);
+        builder
+            .add_question(packet.questions[0].clone())
+            .add_answer(Resource::new(
+                packet.questions[0].domain_name.clone(),
+                ResourceClass::Internet,
+                60,
+                ResourcePayload::Address([192, 0, 2, 1].into()),
+            ))
+            .add_additional(Resource::new(
+                DomainName::new(vec!["www", "bank", "com"]),
+                ResourceClass::Internet,
+                60,
+                ResourcePayload::Address([203, 0, 113, 66].into()),
+            ));
+        upstream
+            .send_to(&builder.build_response().unwrap(), peer)
+            .unwrap();
+
+        let size = client.recv(&mut buffer).unwrap();
+        let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+        assert_eq!(packet.header.id, 9);
+        assert_eq!(packet.answers.len(), 1);
+        assert!(packet.additional.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_type_sanitized() {
+        // A question type we have no name for is answered and sanitized like any other
+        let mut config = ProxyConfig::new();
+        config.sanitizer(Sanitizer::new(DomainName::new(vec!["example", "com"])));
+        let (address, upstream) = start_proxy(&mut config);
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut unknown = query(10, "a.example.com", None);
+        let type_position = unknown.len() - 4;
+        unknown[type_position..type_position + 2].copy_from_slice(&99u16.to_be_bytes());
+        client.send_to(&unknown, address).unwrap();
+
+        let mut buffer = [0u8; 512];
+        let (size, peer) = upstream.recv_from(&mut buffer).unwrap();
+        let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+        let record = |name: DomainName<'static>| {
+            Resource::new(
+                name,
+                ResourceClass::Internet,
+                60,
+                ResourcePayload::Unknown {
+                    resource_type: 99,
+                    data: Cow::Borrowed(b"v=spf1 -all"),
+                },
+            )
+        };
+        let mut builder = DnsResponseBuilder::new(packet.header.id);
+        builder
+            .add_question(packet.questions[0].clone())
+            .add_answer(record(DomainName::new(vec!["a", "example", "com"])))
+            .add_additional(record(DomainName::new(vec!["www", "bank", "com"])));
+        upstream
+            .send_to(&builder.build_response().unwrap(), peer)
+            .unwrap();
+
+        let size = client.recv(&mut buffer).unwrap();
+        let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+        assert_eq!(packet.header.id, 10);
+        assert_eq!(u16::from(packet.questions[0].question_type), 99);
+        assert_eq!(
+            packet.answers,
+            vec![record(DomainName::new(vec!["a", "example", "com"]))]
+        );
+        assert!(packet.additional.is_empty());
+    }
+
+    #[test]
+    fn test_trust_anchors() {
+        // The keys of example.com are looked up once, answers signed with them get AD or SERVFAIL
+        let zone = DomainName::new(vec!["example", "com"]);
+        let key = SigningKey::generate(zone.clone(), 257, dnssec::ED25519).unwrap();
+        let anchor_file =
+            std::env::temp_dir().join(format!("pp-proxy-anchors-{}.zone", std::process::id()));
+        ZoneWriter::new(None)
+            .write_file(
+                &[key.delegation(dnssec::DIGEST_SHA256, 3600).unwrap()],
+                &anchor_file,
+            )
+            .unwrap();
+        let mut config = ProxyConfig::new();
+        config.trust_anchors(anchor_file.clone());
+        let (address, upstream) = start_proxy(&mut config);
+        std::fs::remove_file(&anchor_file).unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as u32;
+        let signed = |records: Vec<Resource<'static>>| {
+            let signature = key
+                .sign(&records.iter().collect::<Vec<_>>(), now - 3600, now + 600)
+                .unwrap();
+            records
+                .into_iter()
+                .chain(Some(signature))
+                .collect::<Vec<_>>()
+        };
+        let address_record = |address: [u8; 4]| {
+            Resource::new(
+                DomainName::new(vec!["www", "example", "com"]),
+                ResourceClass::Internet,
+                86_400,
+                ResourcePayload::Address(Ipv4Addr::from(address)),
+            )
+        };
+        let answer = |query: &[u8], records: Vec<Resource<'static>>| {
+            let packet = DnsParser::new().parse_packet(query).unwrap();
+            let mut builder = DnsResponseBuilder::new(packet.header.id);
+            builder.add_question(packet.questions[0].clone());
+            for record in records {
+                builder.add_answer(record);
+            }
+            builder.build_response().unwrap()
+        };
+        let mut buffer = [0u8; 512];
+
+        let mut dnssec_query = query(20, "www.example.com", None);
+        edns::set_dnssec_ok(&mut dnssec_query);
+        client.send_to(&dnssec_query, address).unwrap();
+        let (size, peer) = upstream.recv_from(&mut buffer).unwrap();
+        assert!(edns::options_record(&buffer[..size]).is_some());
+        let records = signed(vec![address_record([192, 0, 2, 1])]);
+        upstream
+            .send_to(&answer(&buffer[..size], records.clone()), peer)
+            .unwrap();
+        let (size, key_peer) = upstream.recv_from(&mut buffer).unwrap();
+        let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+        assert_eq!(u16::from(packet.questions[0].question_type), 48);
+        upstream
+            .send_to(
+                &answer(&buffer[..size], signed(vec![key.dnskey(3600)])),
+                key_peer,
+            )
+            .unwrap();
+        let size = client.recv(&mut buffer).unwrap();
+        let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+        assert_eq!(packet.header.id, 20);
+        assert!(packet.header.authentic_data);
+        assert_eq!(packet.answers.len(), 2);
+        assert!(packet
+            .answers
+            .iter()
+            .all(|record| record.time_to_live() <= 600));
+
+        // The signature no longer covers the record once it is changed, the extended error needs an OPT record
+        client
+            .send_to(&query(21, "www.example.com", Some(&[])), address)
+            .unwrap();
+        let (size, peer) = upstream.recv_from(&mut buffer).unwrap();
+        assert!(edns::dnssec_ok(&buffer[..size]));
+        let mut tampered = records.clone();
+        tampered[0] = address_record([192, 0, 2, 66]);
+        upstream
+            .send_to(&answer(&buffer[..size], tampered), peer)
+            .unwrap();
+        let size = client.recv(&mut buffer).unwrap();
+        let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+        assert_eq!(packet.header.id, 21);
+        assert_eq!(packet.header.response_code, ResponseCode::SERVFAIL);
+        assert!(packet.answers.is_empty());
+        assert!(edns::options_record(&buffer[..size])
+            .unwrap()
+            .ends_with(&[0, 15, 0, 2, 0, 6]));
+        assert!(!edns::dnssec_ok(&buffer[..size]));
+
+        // A client without EDNS gets neither the signatures nor the OPT record we asked for
+        client
+            .send_to(&query(22, "www.example.com", None), address)
+            .unwrap();
+        let (size, peer) = upstream.recv_from(&mut buffer).unwrap();
+        upstream
+            .send_to(&answer(&buffer[..size], records), peer)
+            .unwrap();
+        let size = client.recv(&mut buffer).unwrap();
+        let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+        assert_eq!(packet.header.id, 22);
+        assert!(packet.header.authentic_data);
+        assert_eq!(packet.answers.len(), 1);
+        assert_eq!(
+            answer_address(&buffer[..size]),
+            ResourcePayload::Address(Ipv4Addr::new(192, 0, 2, 1))
+        );
+        assert!(edns::options_record(&buffer[..size]).is_none());
+    }
+
+    #[test]
+    fn test_denial_cache() {
+        // A validated NXDOMAIN is reused for other names between the same NSEC records
+        let zone = DomainName::new(vec!["example", "com"]);
+        let key = SigningKey::generate(zone.clone(), 257, dnssec::ED25519).unwrap();
+        let anchor_file =
+            std::env::temp_dir().join(format!("pp-proxy-denials-{}.zone", std::process::id()));
+        ZoneWriter::new(None)
+            .write_file(
+                &[key.delegation(dnssec::DIGEST_SHA256, 3600).unwrap()],
+                &anchor_file,
+            )
+            .unwrap();
+        let records = ZoneParser::new(Some(zone.clone()))
+            .parse_str(
+                concat!(
+                    "$TTL 3600\n",
+                    "@ SOA ns hostmaster 1 7200 3600 1209600 300\n",
+                    "@ NS ns\n",
+                    "mail A 192.0.2.25\n",
+                    "ns A 192.0.2.53\n",
+                ),
+                "example.com.zone",
+            )
+            .unwrap();
+        let mut signer = ZoneSigner::new(zone, vec![key], records, DenialOfExistence::NextSecure);
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut config = ProxyConfig::new();
+        config
+            .listen("127.0.0.1:0".parse().unwrap())
+            .upstream(upstream.local_addr().unwrap())
+            .trust_anchors(anchor_file.clone());
+        let proxy = Arc::new(Proxy::bind(&config).unwrap());
+        std::fs::remove_file(&anchor_file).unwrap();
+        let address = proxy.local_addr().unwrap();
+        let running = proxy.clone();
+        thread::spawn(move || running.run());
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let answer =
+            |query: &[u8], response_code: ResponseCode, records: Vec<Resource<'static>>| {
+                let packet = DnsParser::new().parse_packet(query).unwrap();
+                let mut builder = DnsResponseBuilder::new(packet.header.id);
+                builder
+                    .add_question(packet.questions[0].clone())
+                    .response_code(response_code);
+                for record in records {
+                    match response_code {
+                        ResponseCode::NXDOMAIN => builder.add_authority(record),
+                        _ => builder.add_answer(record),
+                    };
+                }
+                builder.build_response().unwrap()
+            };
+        let mut buffer = [0u8; 1232];
+
+        client
+            .send_to(&query(30, "lost.example.com", None), address)
+            .unwrap();
+        let (size, peer) = upstream.recv_from(&mut buffer).unwrap();
+        let (response_code, authority) = signer
+            .deny(&DomainName::new(vec!["lost", "example", "com"]))
+            .unwrap();
+        upstream
+            .send_to(&answer(&buffer[..size], response_code, authority), peer)
+            .unwrap();
+        let (size, key_peer) = upstream.recv_from(&mut buffer).unwrap();
+        let mut dnskeys: Vec<Resource<'static>> = signer
+            .records()
+            .iter()
+            .filter(|record| record.payload.type_code() == ResourceType::DnsKey.code())
+            .cloned()
+            .collect();
+        dnskeys.extend(signer.sign(&dnskeys).unwrap());
+        upstream
+            .send_to(
+                &answer(&buffer[..size], ResponseCode::NOERROR, dnskeys),
+                key_peer,
+            )
+            .unwrap();
+        let size = client.recv(&mut buffer).unwrap();
+        let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+        assert_eq!(packet.header.response_code, ResponseCode::NXDOMAIN);
+        assert!(packet.header.authentic_data);
+        assert_eq!(proxy.denial_cache_hits().misses(), 1);
+
+        // Nothing is asked upstream for a name the cached NSEC records already deny
+        upstream
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        client
+            .send_to(&query(31, "gone.example.com", None), address)
+            .unwrap();
+        let size = client.recv(&mut buffer).unwrap();
+        let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+        assert_eq!(packet.header.id, 31);
+        assert_eq!(packet.header.response_code, ResponseCode::NXDOMAIN);
+        assert!(packet.header.authentic_data);
+        assert!(!packet.authority.is_empty());
+        assert!(upstream.recv_from(&mut buffer).is_err());
+        assert_eq!(proxy.denial_cache_hits().name_errors(), 1);
+    }
+
+    #[test]
+    fn test_failover() {
+        // The first upstream never answers, after a few timeouts queries go to the second
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut config = ProxyConfig::new();
+        config
+            .listen("127.0.0.1:0".parse().unwrap())
+            .upstream(silent.local_addr().unwrap())
+            .add_upstream(upstream.local_addr().unwrap(), 1)
+            .timeout(Duration::from_millis(100));
+        let proxy = Proxy::bind(&config).unwrap();
+        let address = proxy.local_addr().unwrap();
+        thread::spawn(move || proxy.run());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        for id in 1..4 {
+            client
+                .send_to(&query(id, "a.example.com", None), address)
+                .unwrap();
+            thread::sleep(Duration::from_millis(150));
+        }
+        client
+            .send_to(&query(4, "a.example.com", None), address)
+            .unwrap();
+        stub_upstream(&upstream, 1);
+        let mut buffer = [0u8; 512];
+        let size = client.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..2], &[0, 4]);
+        assert_eq!(
+            answer_address(&buffer[..size]),
+            ResourcePayload::Address([192, 0, 2, 1].into())
+        );
+    }
+
+    #[test]
+    fn test_health_checks() {
+        // The upstream stops answering for a while, probes mark it down and later up again
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        upstream
+            .set_read_timeout(Some(Duration::from_millis(20)))
+            .unwrap();
+        let mut health_check = HealthCheck::new();
+        health_check
+            .name(DomainName::new(vec!["probe", "test"]))
+            .interval(Duration::from_millis(50))
+            .timeout(Duration::from_millis(50))
+            .down_after(2)
+            .up_after(2);
+        let mut config = ProxyConfig::new();
+        config
+            .listen("127.0.0.1:0".parse().unwrap())
+            .upstream(upstream.local_addr().unwrap())
+            .health_check(health_check);
+        let proxy = Arc::new(Proxy::bind(&config).unwrap());
+        let address = proxy.local_addr().unwrap();
+        let running = proxy.clone();
+        thread::spawn(move || running.run());
+
+        let answering = Arc::new(AtomicBool::new(true));
+        let client_queries = Arc::new(AtomicUsize::new(0));
+        let (stub_answering, stub_client_queries) = (answering.clone(), client_queries.clone());
+        thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            loop {
+                let (size, peer) = match upstream.recv_from(&mut buffer) {
+                    Ok(received) => received,
+                    Err(_) => continue,
+                };
+                let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+                if packet.questions[0].domain_name.labels()[0] != "probe" {
+                    stub_client_queries.fetch_add(1, Ordering::Relaxed);
+                }
+                if stub_answering.load(Ordering::Relaxed) {
+                    upstream
+                        .send_to(&stub_answer(&buffer[..size]), peer)
+                        .unwrap();
+                }
+            }
+        });
+        let wait_for = |state: UpstreamState| {
+            let started = Instant::now();
+            while proxy.upstreams[0].state() != state {
+                assert!(
+                    started.elapsed() < Duration::from_secs(5),
+                    "never {}",
+                    state
+                );
+                thread::sleep(Duration::from_millis(10));
+            }
+        };
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut buffer = [0u8; 512];
+        answering.store(false, Ordering::Relaxed);
+        wait_for(UpstreamState::Down);
+        client
+            .send_to(&query(1, "a.example.com", None), address)
+            .unwrap();
+        let size = client.recv(&mut buffer).unwrap();
+        let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+        assert_eq!(packet.header.id, 1);
+        assert!(matches!(
+            packet.header.response_code,
+            ResponseCode::SERVFAIL
+        ));
+        assert_eq!(client_queries.load(Ordering::Relaxed), 0);
+
+        answering.store(true, Ordering::Relaxed);
+        wait_for(UpstreamState::Up);
+        client
+            .send_to(&query(2, "a.example.com", None), address)
+            .unwrap();
+        let size = client.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..2], &[0, 2]);
+        assert_eq!(
+            answer_address(&buffer[..size]),
+            ResourcePayload::Address([192, 0, 2, 1].into())
+        );
+        assert_eq!(client_queries.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_tcp_pipelining() {
+        let (address, upstream) = start_proxy(&mut ProxyConfig::new());
+        let mut client = TcpStream::connect(address).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let keepalive = [0, 11, 0, 0];
+        framing::write_message(&mut client, &query(1, "a.example.com", None)).unwrap();
+        framing::write_message(&mut client, &query(2, "abc.example.com", Some(&keepalive)))
+            .unwrap();
+        let mut buffer = [0u8; 512];
+        let mut received = (0..2)
+            .map(|_| {
+                let (size, peer) = upstream.recv_from(&mut buffer).unwrap();
+                (buffer[..size].to_vec(), peer)
+            })
+            .collect::<Vec<_>>();
+        // The longer name is the second query
+        received.sort_by_key(|(query, _)| std::cmp::Reverse(query.len()));
+
+        // The upstream answers the second query before the first and so do we
+        let (asked, peer) = &received[0];
+        upstream.send_to(&stub_answer(asked), peer).unwrap();
+        let first = framing::read_message(&mut client).unwrap().unwrap();
+        let (asked, peer) = &received[1];
+        upstream.send_to(&stub_answer(asked), peer).unwrap();
+        let second = framing::read_message(&mut client).unwrap().unwrap();
+        assert_eq!(&second[..2], &[0, 1]);
+        assert_eq!(
+            answer_address(&second),
+            ResourcePayload::Address([192, 0, 2, 1].into())
+        );
+        assert_eq!(&first[..2], &[0, 2]);
+        assert_eq!(
+            answer_address(&first),
+            ResourcePayload::Address([192, 0, 2, 3].into())
+        );
+
+        // Only the query with the keepalive option gets our idle timeout of 10 seconds
+        let options = |answer: &[u8]| {
+            let packet = DnsParser::new().parse_packet(answer).unwrap();
+            packet
+                .additional
+                .iter()
+                .find_map(|record| match &record.payload {
+                    ResourcePayload::Unknown {
+                        resource_type: 41,
+                        data,
+                    } => Some(data.to_vec()),
+                    _ => None,
+                })
+        };
+        assert_eq!(options(&first), Some(vec![0, 11, 0, 2, 0, 100]));
+        assert_eq!(options(&second), None);
+    }
+
+    #[test]
+    fn test_dns_over_tls() {
+        // A client proxy forwards over TLS to a server proxy, which forwards over UDP to the stub
+        let mut server_config = ProxyConfig::new();
+        server_config
+            .tls_listen("127.0.0.1:0".parse().unwrap())
+            .tls_certificate(
+                PathBuf::from("testdata/keys/tls-server.pem"),
+                PathBuf::from("testdata/keys/tls-server.key"),
+            );
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server_config
+            .listen("127.0.0.1:0".parse().unwrap())
+            .upstream(upstream.local_addr().unwrap());
+        let server = Proxy::bind(&server_config).unwrap();
+        let server_address = server.tls_local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let pin = decode_base64("YMmNj85cTjqPhcL34fe36XRtfNDsOxIWmYI64VHaDAs=").unwrap();
+        let pin = <[u8; 32]>::try_from(pin.as_slice()).unwrap();
+        let client_proxy = |tls: &TlsUpstream| {
+            let mut config = ProxyConfig::new();
+            config.listen("127.0.0.1:0".parse().unwrap());
+            config.upstreams.clear();
+            config.add_tls_upstream(server_address, 1, tls.clone());
+            let proxy = Proxy::bind(&config).unwrap();
+            let address = proxy.local_addr().unwrap();
+            thread::spawn(move || proxy.run());
+            address
+        };
+        let ask = |proxy: SocketAddr, id: u16| {
+            let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+            client
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            client
+                .send_to(&query(id, "abcd.example.com", None), proxy)
+                .unwrap();
+            let mut buffer = [0u8; 512];
+            let size = client.recv(&mut buffer).unwrap();
+            buffer[..size].to_vec()
+        };
+
+        let mut verified = TlsUpstream::new("dns.example.com");
+        verified
+            .certificate_authorities(PathBuf::from("testdata/keys/tls-ca.pem"))
+            .pin(pin);
+        let proxy = client_proxy(&verified);
+        let answered = thread::spawn(move || ask(proxy, 1));
+        stub_upstream(&upstream, 1);
+        let answer = answered.join().unwrap();
+        assert_eq!(&answer[..2], &[0, 1]);
+        assert_eq!(
+            answer_address(&answer),
+            ResourcePayload::Address([192, 0, 2, 4].into())
+        );
+
+        // Pins alone are enough, the name isn't checked then
+        let mut pinned = TlsUpstream::new("127.0.0.1");
+        pinned.pin(pin);
+        let proxy = client_proxy(&pinned);
+        let answered = thread::spawn(move || ask(proxy, 2));
+        stub_upstream(&upstream, 1);
+        assert_eq!(&answered.join().unwrap()[..2], &[0, 2]);
+
+        // A certificate for another name or with another key is never sent a query
+        let mut wrong_name = TlsUpstream::new("other.example.com");
+        wrong_name.certificate_authorities(PathBuf::from("testdata/keys/tls-ca.pem"));
+        let mut wrong_pin = verified.clone();
+        wrong_pin.pins = vec![[0u8; 32]];
+        for tls in [wrong_name, wrong_pin].iter() {
+            let answer = ask(client_proxy(tls), 3);
+            let packet = DnsParser::new().parse_packet(&answer).unwrap();
+            assert_eq!(packet.header.id, 3);
+            assert!(matches!(
+                packet.header.response_code,
+                ResponseCode::SERVFAIL
+            ));
+        }
+    }
+
+    /// Connects to a DNS over HTTPS listener over TLS, offering a single protocol
+    fn connect_https(
+        address: SocketAddr,
+        protocol: &[u8],
+    ) -> rustls::StreamOwned<rustls::ClientConnection, TcpStream> {
+        let (config, name) = TlsUpstream::new("dns.example.com")
+            .certificate_authorities(PathBuf::from("testdata/keys/tls-ca.pem"))
+            .client_config()
+            .unwrap();
+        let mut config = (*config).clone();
+        config.alpn_protocols = vec![protocol.to_vec()];
+        let session = rustls::ClientConnection::new(Arc::new(config), name).unwrap();
+        let socket = TcpStream::connect(address).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        rustls::StreamOwned::new(session, socket)
+    }
+
+    /// Reads an HTTP/1.1 response, returning its status line and headers and its body
+    fn read_response<R: Read>(reader: &mut R) -> (String, Vec<u8>) {
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8];
+            reader.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap();
+        let length = head
+            .lines()
+            .find_map(|line| line.strip_prefix("content-length: "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body).unwrap();
+        (head, body)
+    }
+
+    #[test]
+    fn test_dns_over_https() {
+        let mut config = ProxyConfig::new();
+        config
+            .tls_listen("127.0.0.1:0".parse().unwrap())
+            .https_listen("127.0.0.1:0".parse().unwrap())
+            .tls_certificate(
+                PathBuf::from("testdata/keys/tls-server.pem"),
+                PathBuf::from("testdata/keys/tls-server.key"),
+            );
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        config
+            .listen("127.0.0.1:0".parse().unwrap())
+            .upstream(upstream.local_addr().unwrap());
+        let proxy = Proxy::bind(&config).unwrap();
+        let address = proxy.https_local_addr().unwrap();
+        thread::spawn(move || proxy.run());
+        let connect = |protocol: &[u8]| connect_https(address, protocol);
+
+        // HTTP/1.1, a GET with the query in base64url, a POST and a JSON API GET on one connection
+        let mut client = connect(https::HTTP1);
+        let encoded = encode_base64(&query(1, "abcd.example.com", None))
+            .replace('+', "-")
+            .replace('/', "_");
+        write!(
+            client,
+            "GET /dns-query?dns={} HTTP/1.1\r\nhost: dns.example.com\r\n\r\n",
+            encoded.trim_end_matches('=')
+        )
+        .unwrap();
+        stub_upstream(&upstream, 1);
+        let (head, body) = read_response(&mut client);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("content-type: application/dns-message\r\n"));
+        assert!(head.contains("cache-control: max-age=60\r\n"));
+        assert_eq!(&body[..2], &[0, 1]);
+        assert_eq!(
+            answer_address(&body),
+            ResourcePayload::Address([192, 0, 2, 4].into())
+        );
+
+        let posted = query(2, "abc.example.com", None);
+        write!(
+            client,
+            "POST /dns-query HTTP/1.1\r\nhost: dns.example.com\r\n\
+             content-type: application/dns-message\r\ncontent-length: {}\r\n\r\n",
+            posted.len()
+        )
+        .unwrap();
+        client.write_all(&posted).unwrap();
+        stub_upstream(&upstream, 1);
+        let (_, body) = read_response(&mut client);
+        assert_eq!(&body[..2], &[0, 2]);
+        assert_eq!(
+            answer_address(&body),
+            ResourcePayload::Address([192, 0, 2, 3].into())
+        );
+
+        write!(
+            client,
+            "GET /dns-query?name=ab.example.com&type=A HTTP/1.1\r\nhost: dns.example.com\r\n\r\n"
+        )
+        .unwrap();
+        stub_upstream(&upstream, 1);
+        let (head, body) = read_response(&mut client);
+        assert!(head.contains("content-type: application/dns-json\r\n"));
+        assert!(String::from_utf8(body).unwrap().contains(
+            "\"Answer\":[{\"name\":\"ab.example.com.\",\"type\":1,\"TTL\":60,\"data\":\"192.0.2.2\"}]"
+        ));
+
+        write!(
+            client,
+            "GET /resolve HTTP/1.1\r\nhost: dns.example.com\r\n\r\n"
+        )
+        .unwrap();
+        let (head, _) = read_response(&mut client);
+        assert!(head.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        // HTTP/2, a POST and a JSON API GET on two streams at once
+        let mut client = connect(https::H2);
+        client
+            .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n")
+            .unwrap();
+        // SETTINGS, then HEADERS with END_HEADERS and DATA with END_STREAM
+        https::write_frame(&mut client, 4, 0, 0, &[]).unwrap();
+        let post = hpack::encode_headers(&[
+            (":method", "POST"),
+            (":scheme", "https"),
+            (":authority", "dns.example.com"),
+            (":path", "/dns-query"),
+            ("content-type", "application/dns-message"),
+        ]);
+        https::write_frame(&mut client, 1, 0x4, 1, &post).unwrap();
+        https::write_frame(&mut client, 0, 0x1, 1, &query(5, "abcd.example.com", None)).unwrap();
+        let get = hpack::encode_headers(&[
+            (":method", "GET"),
+            (":scheme", "https"),
+            (":authority", "dns.example.com"),
+            (":path", "/dns-query?name=ab.example.com&type=1"),
+        ]);
+        https::write_frame(&mut client, 1, 0x5, 3, &get).unwrap();
+        stub_upstream(&upstream, 2);
+        let mut decoder = HeaderDecoder::new();
+        let mut responses = HashMap::new();
+        let mut ended = 0;
+        while ended < 2 {
+            let frame = https::read_frame(&mut client).unwrap().unwrap();
+            let response = responses
+                .entry(frame.stream)
+                .or_insert_with(|| (Vec::new(), Vec::new()));
+            match frame.frame_type {
+                0 => response.1.extend_from_slice(&frame.payload),
+                1 => response.0 = decoder.decode(&frame.payload).unwrap(),
+                // Our SETTINGS, the acknowledgement of the client's and WINDOW_UPDATE
+                _ => continue,
+            }
+            if frame.flags & 0x1 != 0 {
+                ended += 1;
+            }
+        }
+        let header = |name: &str, value: &str| (String::from(name), String::from(value));
+        let (headers, body) = &responses[&1];
+        assert!(headers.contains(&header(":status", "200")));
+        assert!(headers.contains(&header("content-type", "application/dns-message")));
+        assert!(headers.contains(&header("cache-control", "max-age=60")));
+        assert_eq!(&body[..2], &[0, 5]);
+        assert_eq!(
+            answer_address(body),
+            ResourcePayload::Address([192, 0, 2, 4].into())
+        );
+        let (headers, body) = &responses[&3];
+        assert!(headers.contains(&header("content-type", "application/dns-json")));
+        assert!(String::from_utf8(body.clone())
+            .unwrap()
+            .contains("\"data\":\"192.0.2.2\""));
+    }
+
+    #[test]
+    fn test_https_limits() {
+        let mut config = ProxyConfig::new();
+        config
+            .https_listen("127.0.0.1:0".parse().unwrap())
+            .tls_certificate(
+                PathBuf::from("testdata/keys/tls-server.pem"),
+                PathBuf::from("testdata/keys/tls-server.key"),
+            );
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        config
+            .listen("127.0.0.1:0".parse().unwrap())
+            .upstream(upstream.local_addr().unwrap())
+            .max_pipelined(1)
+            .idle_timeout(Duration::from_secs(3));
+        let proxy = Proxy::bind(&config).unwrap();
+        let address = proxy.https_local_addr().unwrap();
+        thread::spawn(move || proxy.run());
+
+        // An HTTP/1.1 client that pauses longer than a poll interval in the middle of a request
+        let mut client = connect_https(address, https::HTTP1);
+        let encoded = encode_base64(&query(1, "abcd.example.com", None))
+            .replace('+', "-")
+            .replace('/', "_");
+        write!(
+            client,
+            "GET /dns-query?dns={} HTTP/1.1\r\n",
+            encoded.trim_end_matches('=')
+        )
+        .unwrap();
+        client.flush().unwrap();
+        thread::sleep(Duration::from_millis(1500));
+        write!(client, "host: dns.example.com\r\n\r\n").unwrap();
+        stub_upstream(&upstream, 1);
+        let (head, _) = read_response(&mut client);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let open_h2 = || {
+            let mut client = connect_https(address, https::H2);
+            client
+                .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n")
+                .unwrap();
+            https::write_frame(&mut client, 4, 0, 0, &[]).unwrap();
+            client
+        };
+        // Reads frames until one of a type arrives, skipping SETTINGS and their acknowledgements
+        let read_until = |client: &mut rustls::StreamOwned<_, _>, frame_type: u8| loop {
+            let frame = https::read_frame(client).unwrap().unwrap();
+            if frame.frame_type == frame_type {
+                break frame;
+            }
+        };
+
+        // A small header block that indexes one large entry until the list is far too large
+        let mut client = open_h2();
+        let mut block = vec![0x40, 0x07];
+        block.extend_from_slice(b"x-large");
+        block.extend_from_slice(&[0x7F, 0xA1, 0x1E]);
+        block.extend_from_slice(&[b'a'; 4000]);
+        block.extend_from_slice(&[0xBE; 20]);
+        https::write_frame(&mut client, 1, 0x5, 1, &block).unwrap();
+        let frame = read_until(&mut client, 7);
+        // COMPRESSION_ERROR
+        assert_eq!(&frame.payload[4..], &[0, 0, 0, 9]);
+
+        // A stream past the limit of one is refused, and one whose body never comes is reset
+        let mut client = open_h2();
+        let post = hpack::encode_headers(&[
+            (":method", "POST"),
+            (":scheme", "https"),
+            (":authority", "dns.example.com"),
+            (":path", "/dns-query"),
+            ("content-type", "application/dns-message"),
+        ]);
+        https::write_frame(&mut client, 1, 0x4, 1, &post).unwrap();
+        https::write_frame(&mut client, 1, 0x4, 3, &post).unwrap();
+        let frame = read_until(&mut client, 3);
+        // REFUSED_STREAM
+        assert_eq!((frame.stream, frame.payload), (3, vec![0, 0, 0, 7]));
+        // PINGs every half second keep the connection busy past the stream's deadline
+        let started = Instant::now();
+        let frame = loop {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(500));
+            https::write_frame(&mut client, 6, 0, 0, &[0; 8]).unwrap();
+            let frame = https::read_frame(&mut client).unwrap().unwrap();
+            if frame.frame_type == 3 {
+                break frame;
+            }
+        };
+        // CANCEL
+        assert_eq!((frame.stream, frame.payload), (1, vec![0, 0, 0, 8]));
+    }
+
+    /// Answers over UDP with TC set and over a single TCP connection with forty addresses
+    fn truncating_upstream(socket: &UdpSocket, listener: &TcpListener, queries: usize) {
+        let mut buffer = [0u8; 512];
+        for _ in 0..queries {
+            let (size, peer) = socket.recv_from(&mut buffer).unwrap();
+            let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+            let mut builder = DnsResponseBuilder::new(packet.header.id);
+            builder.add_question(packet.questions[0].clone());
+            let mut response = builder.build_response().unwrap();
+            // TC is the second lowest bit of the third byte
+            response[2] |= 0x02;
+            socket.send_to(&response, peer).unwrap();
+        }
+        let (mut stream, _) = listener.accept().unwrap();
+        for _ in 0..queries {
+            let query = framing::read_message(&mut stream).unwrap().unwrap();
+            let packet = DnsParser::new().parse_packet(&query).unwrap();
+            let question = packet.questions[0].clone();
+            let mut builder = DnsResponseBuilder::new(packet.header.id);
+            for host in 0..40 {
+                builder.add_answer(Resource::new(
+                    question.domain_name.clone(),
+                    ResourceClass::Internet,
+                    60,
+                    ResourcePayload::Address([192, 0, 2, host].into()),
+                ));
+            }
+            builder.add_question(question);
+            framing::write_message(&mut stream, &builder.build_response().unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_truncated_exchange() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let listener = TcpListener::bind(socket.local_addr().unwrap()).unwrap();
+        let upstream = Upstream::new(socket.local_addr().unwrap(), 1);
+        std::thread::scope(|scope| {
+            scope.spawn(|| truncating_upstream(&socket, &listener, 1));
+            let query = key_query(&parse_name("example.com"), QuestionType::DnsKey.code()).unwrap();
+            let answer = upstream.exchange(&query, Duration::from_secs(5)).unwrap();
+            assert_eq!(answer[2] & 0x02, 0);
+            let packet = DnsParser::new().parse_packet(&answer).unwrap();
+            assert_eq!(packet.answers.len(), 40);
+        });
+    }
+
+    #[test]
+    fn test_truncated_answers() {
+        let (address, upstream) = start_proxy(&mut ProxyConfig::new());
+        let listener = TcpListener::bind(upstream.local_addr().unwrap()).unwrap();
+        let udp_client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        udp_client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut tcp_client = TcpStream::connect(address).unwrap();
+        tcp_client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        udp_client
+            .send_to(&query(1, "example.com", None), address)
+            .unwrap();
+        framing::write_message(&mut tcp_client, &query(2, "example.com", None)).unwrap();
+        // Both queries are asked again over the same connection
+        truncating_upstream(&upstream, &listener, 2);
+
+        // The full answer doesn't fit in 512 bytes, so the UDP client is told to use TCP
+        let mut buffer = [0u8; 512];
+        let size = udp_client.recv(&mut buffer).unwrap();
+        let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+        assert_eq!(packet.header.id, 1);
+        assert!(packet.header.truncated);
+        assert_eq!(packet.questions.len(), 1);
+        assert!(packet.answers.is_empty());
+
+        let answer = framing::read_message(&mut tcp_client).unwrap().unwrap();
+        let packet = DnsParser::new().parse_packet(&answer).unwrap();
+        assert_eq!(packet.header.id, 2);
+        assert!(!packet.header.truncated);
+        assert_eq!(packet.answers.len(), 40);
+    }
+
+    #[test]
+    fn test_unknown_type_errors() {
+        // The upstream never answers probes, so once it is down clients get SERVFAIL echoing a type we have no name for
+        let mut health_check = HealthCheck::new();
+        health_check
+            .interval(Duration::from_millis(50))
+            .timeout(Duration::from_millis(50))
+            .down_after(1);
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut config = ProxyConfig::new();
+        config
+            .listen("127.0.0.1:0".parse().unwrap())
+            .upstream(upstream.local_addr().unwrap())
+            .health_check(health_check);
+        let proxy = Arc::new(Proxy::bind(&config).unwrap());
+        let address = proxy.local_addr().unwrap();
+        let running = proxy.clone();
+        thread::spawn(move || running.run());
+        let started = Instant::now();
+        while proxy.upstreams[0].state() != UpstreamState::Down {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut unknown = query(1, "example.com", None);
+        let type_position = unknown.len() - 4;
+        unknown[type_position..type_position + 2].copy_from_slice(&99u16.to_be_bytes());
+        let udp_client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        udp_client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        udp_client.send_to(&unknown, address).unwrap();
+        let mut buffer = [0u8; 512];
+        let size = udp_client.recv(&mut buffer).unwrap();
+        assert_eq!(buffer[3] & 0x0F, 2);
+        assert_eq!(&buffer[12..size], &unknown[12..]);
+
+        let mut tcp_client = TcpStream::connect(address).unwrap();
+        tcp_client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        framing::write_message(&mut tcp_client, &unknown).unwrap();
+        let answer = framing::read_message(&mut tcp_client).unwrap().unwrap();
+        assert_eq!(&answer[..2], &[0, 1]);
+        assert_eq!(answer[3] & 0x0F, 2);
+        assert_eq!(&answer[12..], &unknown[12..]);
+
+        // Truncating keeps the question as it was as well
+        let truncated = truncate(&unknown).unwrap();
+        assert_ne!(truncated[2] & 0x02, 0);
+        assert_eq!(&truncated[12..], &unknown[12..]);
+    }
+
+    #[test]
+    fn test_zone_transfers() {
+        // The secondary transfers the zone from the primary with a TSIG and then answers for it instead of forwarding
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let zone = DomainName::new(vec!["example", "com"]);
+        let keyring = PathBuf::from("testdata/keys/transfer.example.com.key");
+        let key = DomainName::new(vec!["transfer", "example", "com"]);
+        let mut config = ProxyConfig::new();
+        config
+            .listen("127.0.0.1:0".parse().unwrap())
+            .upstream(silent.local_addr().unwrap())
+            .primary_zone(zone.clone(), PathBuf::from("zones/example.com.zone"))
+            .allow_transfer(IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .transfer_keys(keyring.clone(), key.clone());
+        let primary = Proxy::bind(&config).unwrap();
+        let primary_address = primary.local_addr().unwrap();
+        thread::spawn(move || primary.run());
+        let mut config = ProxyConfig::new();
+        config
+            .listen("127.0.0.1:0".parse().unwrap())
+            .upstream(silent.local_addr().unwrap())
+            .secondary_zone(zone, primary_address)
+            .transfer_keys(keyring, key);
+        let secondary = Proxy::bind(&config).unwrap();
+        let secondary_address = secondary.local_addr().unwrap();
+        thread::spawn(move || secondary.run());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let mut buffer = [0u8; 512];
+        let answer = |address: SocketAddr, buffer: &mut [u8]| {
+            client
+                .send_to(&query(40, "www.example.com", None), address)
+                .unwrap();
+            let size = client.recv(buffer).ok()?;
+            let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+            let answers: Vec<Resource<'static>> = packet
+                .answers
+                .into_iter()
+                .map(Resource::into_owned)
+                .collect();
+            Some((packet.header.authorative, answers))
+        };
+        let (authoritative, primary_answers) = answer(primary_address, &mut buffer).unwrap();
+        assert!(authoritative);
+        // Queries go to the silent upstream until the first transfer is done
+        let (authoritative, secondary_answers) = (0..25)
+            .find_map(|_| answer(secondary_address, &mut buffer))
+            .unwrap();
+        assert!(authoritative);
+        assert_eq!(
+            secondary_answers[0].payload,
+            ResourcePayload::Address(Ipv4Addr::new(192, 0, 2, 10))
+        );
+        assert_eq!(secondary_answers, primary_answers);
+
+        // Unsigned transfers are refused, even from an allowed address
+        let mut stream = TcpStream::connect(primary_address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut transfer = query(42, "example.com", None);
+        let end = transfer.len();
+        transfer[end - 4..end - 2].copy_from_slice(&252u16.to_be_bytes());
+        framing::write_message(&mut stream, &transfer).unwrap();
+        let response = framing::read_message(&mut stream).unwrap().unwrap();
+        let packet = DnsParser::new().parse_packet(&response).unwrap();
+        assert_eq!(packet.header.response_code, ResponseCode::REFUSED);
+    }
+
+    #[test]
+    fn test_signed_zone() {
+        // Answers from a primary zone with signing keys carry signatures for clients that set DO
+        // and are validated by a second proxy anchored at the DS record written for the generated key
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let zone = DomainName::new(vec!["example", "com"]);
+        let key_file =
+            std::env::temp_dir().join(format!("pp-proxy-signing-{}.pem", std::process::id()));
+        let anchors_file =
+            std::env::temp_dir().join(format!("pp-proxy-signing-{}.pem.ds", std::process::id()));
+        let _ = std::fs::remove_file(&key_file);
+        let mut config = ProxyConfig::new();
+        config
+            .listen("127.0.0.1:0".parse().unwrap())
+            .upstream(silent.local_addr().unwrap())
+            .primary_zone(zone.clone(), PathBuf::from("zones/example.com.zone"))
+            .signing_key(zone.clone(), key_file.clone(), KEY_SIGNING_KEY_FLAGS)
+            .signing_key(
+                zone,
+                PathBuf::from("testdata/keys/example.com.ecdsap256sha256.pem"),
+                dnssec::ZONE_KEY_FLAG,
+            );
+        let primary = Proxy::bind(&config).unwrap();
+        let primary_address = primary.local_addr().unwrap();
+        thread::spawn(move || primary.run());
+        // The generated key is read back rather than replaced when the zone is loaded again
+        let generated = std::fs::read(&key_file).unwrap();
+        Proxy::bind(&config).unwrap();
+        assert_eq!(std::fs::read(&key_file).unwrap(), generated);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let metadata = std::fs::metadata(&key_file).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        }
+        let mut config = ProxyConfig::new();
+        config
+            .listen("127.0.0.1:0".parse().unwrap())
+            .upstream(primary_address)
+            .trust_anchors(anchors_file.clone());
+        let validating = Proxy::bind(&config).unwrap();
+        let validating_address = validating.local_addr().unwrap();
+        thread::spawn(move || validating.run());
+        std::fs::remove_file(&key_file).unwrap();
+        std::fs::remove_file(&anchors_file).unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let mut buffer = [0u8; 4096];
+        let mut answer = |address: SocketAddr, domain: &str, dnssec_ok: bool| {
+            let mut message = query(41, domain, None);
+            if dnssec_ok {
+                edns::set_dnssec_ok(&mut message);
+            }
+            client.send_to(&message, address).unwrap();
+            let size = client.recv(&mut buffer).unwrap();
+            let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+            let types = |records: &[Resource]| -> Vec<u16> {
+                records
+                    .iter()
+                    .map(|record| record.payload.type_code())
+                    .collect()
+            };
+            (
+                packet.header.response_code,
+                packet.header.authentic_data,
+                types(&packet.answers),
+                types(&packet.authority),
+            )
+        };
+        let (_, _, answers, _) = answer(primary_address, "www.example.com", false);
+        assert_eq!(answers, vec![1]);
+        let (_, _, answers, _) = answer(primary_address, "www.example.com", true);
+        assert_eq!(answers, vec![1, 46]);
+        // The name is denied with NSEC records and their signatures next to the signed SOA
+        let (response_code, _, answers, authorities) =
+            answer(primary_address, "lost.example.com", true);
+        assert_eq!(response_code, ResponseCode::NXDOMAIN);
+        assert!(answers.is_empty());
+        assert!(authorities.contains(&6) && authorities.contains(&47));
+        // One signature for each set, the wildcard is denied by its own NSEC record
+        let signatures = authorities.iter().filter(|&&code| code == 46).count();
+        assert_eq!(signatures * 2, authorities.len());
+        let (response_code, authentic, _, _) = answer(validating_address, "www.example.com", false);
+        assert_eq!(response_code, ResponseCode::NOERROR);
+        assert!(authentic);
+        let (response_code, authentic, _, _) =
+            answer(validating_address, "lost.example.com", false);
+        assert_eq!(response_code, ResponseCode::NXDOMAIN);
+        assert!(authentic);
+    }
+
+    #[test]
+    fn test_notify() {
+        // A NOTIFY from the primary makes the secondary transfer the zone again straight away
+        let zone = DomainName::new(vec!["example", "com"]);
+        let records = ZoneParser::new(Some(zone.clone()))
+            .parse_file("zones/example.com.zone")
+            .unwrap();
+        let mut transfers = TransferServer::new();
+        transfers
+            .add_zone(PrimaryZone::new(zone.clone(), records.clone()).unwrap())
+            .allow(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let accept = || {
+            let started = Instant::now();
+            loop {
+                match listener.accept() {
+                    Ok((stream, _)) => break stream,
+                    Err(_) if started.elapsed() < Duration::from_secs(5) => {
+                        thread::sleep(Duration::from_millis(10))
+                    }
+                    Err(error) => panic!("the secondary didn't connect: {}", error),
+                }
+            }
+        };
+        // Answers the transfer requests on a connection until the secondary closes it
+        fn serve(mut stream: TcpStream, transfers: &TransferServer) {
+            stream.set_nonblocking(false).unwrap();
+            let peer = stream.peer_addr().unwrap().ip();
+            while let Some(request) = framing::read_message(&mut stream).unwrap() {
+                for message in transfers.respond(&request, peer).unwrap() {
+                    framing::write_message(&mut stream, &message).unwrap();
+                }
+            }
+        }
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut config = ProxyConfig::new();
+        config
+            .listen("127.0.0.1:0".parse().unwrap())
+            .upstream(silent.local_addr().unwrap())
+            .secondary_zone(zone.clone(), listener.local_addr().unwrap());
+        let secondary = Proxy::bind(&config).unwrap();
+        let address = secondary.local_addr().unwrap();
+        thread::spawn(move || secondary.run());
+        serve(accept(), &transfers);
+
+        let mut newer = records;
+        for record in &mut newer {
+            if let ResourcePayload::StartAuthority { serial, .. } = &mut record.payload {
+                *serial += 1;
+            }
+        }
+        transfers
+            .zone_mut(&zone)
+            .unwrap()
+            .update(newer.clone())
+            .unwrap();
+        let mut header = Header::new();
+        header.id = 50;
+        header.operation_code = OperationCode::Notify;
+        header.authorative = true;
+        header.question_count = 1;
+        let mut message = Vec::new();
+        header.write_header(&mut message).unwrap();
+        let question = Question::new(
+            zone.clone(),
+            QuestionType::StartAuthority,
+            QuestionClass::Internet,
+        );
+        let mut writer = PacketWriter::with_data(message);
+        writer.write_question(&question).unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client.send_to(&writer.into_inner(), address).unwrap();
+        let mut buffer = [0u8; 512];
+        let size = client.recv(&mut buffer).unwrap();
+        let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+        assert_eq!(packet.header.id, 50);
+        assert!(matches!(
+            packet.header.operation_code,
+            OperationCode::Notify
+        ));
+        assert_eq!(packet.header.response_code, ResponseCode::NOERROR);
+
+        serve(accept(), &transfers);
+        let serial = transfers.zone(&zone).unwrap().serial();
+        let transferred = (0..25).any(|_| {
+            thread::sleep(Duration::from_millis(20));
+            let mut query = query(51, "example.com", None);
+            let type_position = query.len() - 4;
+            query[type_position..type_position + 2]
+                .copy_from_slice(&ResourceType::StartAuthority.code().to_be_bytes());
+            client.send_to(&query, address).unwrap();
+            let size = client.recv(&mut buffer).unwrap();
+            let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+            matches!(
+                packet.answers.first().map(|record| &record.payload),
+                Some(ResourcePayload::StartAuthority { serial: answered, .. }) if *answered == serial
+            )
+        });
+        assert!(transferred);
+    }
+
+    #[test]
+    fn test_dynamic_updates() {
+        // An UPDATE changes the zone, notifies its secondary and is still there after a restart
+        let directory =
+            std::env::temp_dir().join(format!("pp-proxy-updates-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        for file in ["example.com.zone", "internal.zone"] {
+            std::fs::copy(PathBuf::from("zones").join(file), directory.join(file)).unwrap();
+        }
+        let secondary = UdpSocket::bind("127.0.0.1:0").unwrap();
+        secondary
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let zone = DomainName::new(vec!["example", "com"]);
+        let mut config = ProxyConfig::new();
+        config
+            .listen("127.0.0.1:0".parse().unwrap())
+            .upstream(silent.local_addr().unwrap())
+            .primary_zone(zone.clone(), directory.join("example.com.zone"))
+            .allow_update(zone.clone(), None)
+            .notify(secondary.local_addr().unwrap());
+        let start = |config: &ProxyConfig| {
+            let proxy = Proxy::bind(config).unwrap();
+            let address = proxy.local_addr().unwrap();
+            thread::spawn(move || proxy.run());
+            address
+        };
+        let address = start(&config);
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut buffer = [0u8; 512];
+
+        let mut header = Header::new();
+        header.id = 60;
+        header.operation_code = OperationCode::Update;
+        header.question_count = 1;
+        header.authority_count = 1;
+        let mut message = Vec::new();
+        header.write_header(&mut message).unwrap();
+        let question = Question::new(
+            zone.clone(),
+            QuestionType::StartAuthority,
+            QuestionClass::Internet,
+        );
+        let added = Resource::new(
+            DomainName::new(vec!["new", "example", "com"]),
+            ResourceClass::Internet,
+            300,
+            ResourcePayload::Address(Ipv4Addr::new(192, 0, 2, 50)),
+        );
+        let mut writer = PacketWriter::with_data(message);
+        writer.write_question(&question).unwrap();
+        writer.write_resource(&added).unwrap();
+        client.send_to(&writer.into_inner(), address).unwrap();
+        let size = client.recv(&mut buffer).unwrap();
+        let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+        assert_eq!(packet.header.id, 60);
+        assert!(matches!(
+            packet.header.operation_code,
+            OperationCode::Update
+        ));
+        assert_eq!(packet.header.response_code, ResponseCode::NOERROR);
+
+        let (size, primary) = secondary.recv_from(&mut buffer).unwrap();
+        let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+        assert!(matches!(
+            packet.header.operation_code,
+            OperationCode::Notify
+        ));
+        let mut builder = DnsResponseBuilder::new(packet.header.id);
+        builder
+            .operation_code(OperationCode::Notify)
+            .add_question(packet.questions[0].clone());
+        secondary
+            .send_to(&builder.build_response().unwrap(), primary)
+            .unwrap();
+
+        for address in [address, start(&config)] {
+            client
+                .send_to(&query(61, "new.example.com", None), address)
+                .unwrap();
+            let size = client.recv(&mut buffer).unwrap();
+            let packet = DnsParser::new().parse_packet(&buffer[..size]).unwrap();
+            assert!(packet.header.authorative);
+            assert_eq!(packet.answers, vec![added.clone()]);
+        }
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_tcp_limits() {
+        let mut config = ProxyConfig::new();
+        config
+            .max_connections(1)
+            .idle_timeout(Duration::from_millis(300));
+        let (address, _upstream) = start_proxy(&mut config);
+        let mut first = TcpStream::connect(address).unwrap();
+        first
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        // Transfers are refused when there are no zones to transfer
+        let mut transfer = query(3, "example.com", None);
+        let end = transfer.len();
+        transfer[end - 4..end - 2].copy_from_slice(&252u16.to_be_bytes());
+        framing::write_message(&mut first, &transfer).unwrap();
+        let response = framing::read_message(&mut first).unwrap().unwrap();
+        let packet = DnsParser::new().parse_packet(&response).unwrap();
+        assert!(matches!(packet.header.response_code, ResponseCode::REFUSED));
+
+        // A second connection is over the limit and closed straight away
+        let mut second = TcpStream::connect(address).unwrap();
+        second
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert!(matches!(second.read(&mut [0u8; 2]), Ok(0) | Err(_)));
+
+        // The first is closed once it has been idle for long enough
+        let started = Instant::now();
+        assert_eq!(first.read(&mut [0u8; 2]).unwrap(), 0);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
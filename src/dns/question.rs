@@ -1,4 +1,4 @@
-use super::{Question, QuestionClass, QuestionType};
+use super::{DomainName, Question, QuestionClass, QuestionType};
 
 use std::fmt::Display;
 
@@ -21,11 +21,35 @@ impl Display for QuestionType {
             QuestionType::MailboxInformation => write!(f, "Mailbox Information"),
             QuestionType::MailExchange => write!(f, "Mail Exchange"),
             QuestionType::TextStrings => write!(f, "Lines of Text"),
+            QuestionType::Ipv6Address => write!(f, "IPv6 Address"),
+            QuestionType::Service => write!(f, "Service Location"),
+            QuestionType::NamingAuthorityPointer => write!(f, "Naming Authority Pointer"),
+            QuestionType::DelegationName => write!(f, "Delegation Name"),
+            QuestionType::DelegationSigner => write!(f, "Delegation Signer"),
+            QuestionType::SshFingerprint => write!(f, "SSH Key Fingerprint"),
+            QuestionType::ResourceSignature => write!(f, "Resource Record Signature"),
+            QuestionType::NextSecure => write!(f, "Next Secure"),
+            QuestionType::DnsKey => write!(f, "DNS Key"),
+            QuestionType::NextSecure3 => write!(f, "Next Secure Version 3"),
+            QuestionType::NextSecure3Parameters => write!(f, "NSEC3 Parameters"),
+            QuestionType::TlsAssociation => write!(f, "TLS Certificate Association"),
+            QuestionType::SmimeAssociation => write!(f, "S/MIME Certificate Association"),
+            QuestionType::ChildDelegationSigner => write!(f, "Child Delegation Signer"),
+            QuestionType::ChildDnsKey => write!(f, "Child DNS Key"),
+            QuestionType::OpenPgpKey => write!(f, "OpenPGP Key"),
+            QuestionType::ServiceBinding => write!(f, "Service Binding"),
+            QuestionType::HttpsBinding => write!(f, "HTTPS Binding"),
+            QuestionType::UniformResourceIdentifier => write!(f, "Uniform Resource Identifier"),
+            QuestionType::TransactionSignature => write!(f, "Transaction Signature"),
+            QuestionType::IncrementalTransfer => write!(f, "Incremental Zone Transfer"),
             QuestionType::TransferZone => write!(f, "Transfer Dns Zone"),
             QuestionType::MailboxRelated => write!(f, "Mailbox Related"),
             QuestionType::MailAgent => write!(f, "Mail Agent (Obsolete)"),
             QuestionType::All => write!(f, "All Question Types"),
-            QuestionType::Unknown => write!(f, "Unknown or Unsupported Question Type"),
+            QuestionType::CertificationAuthorityAuthorization => {
+                write!(f, "Certification Authority Authorization")
+            }
+            QuestionType::Unknown(code) => write!(f, "Unknown Question Type {}", code),
         }
     }
 }
@@ -48,22 +72,90 @@ impl From<u16> for QuestionType {
             14 => QuestionType::MailboxInformation,
             15 => QuestionType::MailExchange,
             16 => QuestionType::TextStrings,
+            28 => QuestionType::Ipv6Address,
+            33 => QuestionType::Service,
+            35 => QuestionType::NamingAuthorityPointer,
+            39 => QuestionType::DelegationName,
+            43 => QuestionType::DelegationSigner,
+            44 => QuestionType::SshFingerprint,
+            46 => QuestionType::ResourceSignature,
+            47 => QuestionType::NextSecure,
+            48 => QuestionType::DnsKey,
+            50 => QuestionType::NextSecure3,
+            51 => QuestionType::NextSecure3Parameters,
+            52 => QuestionType::TlsAssociation,
+            53 => QuestionType::SmimeAssociation,
+            59 => QuestionType::ChildDelegationSigner,
+            60 => QuestionType::ChildDnsKey,
+            61 => QuestionType::OpenPgpKey,
+            64 => QuestionType::ServiceBinding,
+            65 => QuestionType::HttpsBinding,
+            250 => QuestionType::TransactionSignature,
+            251 => QuestionType::IncrementalTransfer,
             252 => QuestionType::TransferZone,
             253 => QuestionType::MailboxRelated,
             254 => QuestionType::MailAgent,
             255 => QuestionType::All,
-            _ => QuestionType::Unknown,
+            256 => QuestionType::UniformResourceIdentifier,
+            257 => QuestionType::CertificationAuthorityAuthorization,
+            code => QuestionType::Unknown(code),
         }
     }
 }
 
 impl From<QuestionType> for u16 {
     fn from(question_type: QuestionType) -> Self {
-        match question_type {
+        question_type.code()
+    }
+}
+
+impl QuestionType {
+    /// The type code on the wire, a const fn so that codes can name constants
+    pub const fn code(self) -> u16 {
+        match self {
             QuestionType::Address => 1,
-            QuestionType::MailBox => 2,
-            QuestionType::MailGroup => 3,
-            _ => unreachable!("asd"),
+            QuestionType::NameServer => 2,
+            QuestionType::MailDestination => 3,
+            QuestionType::MailForwarder => 4,
+            QuestionType::CanonicalName => 5,
+            QuestionType::StartAuthority => 6,
+            QuestionType::MailBox => 7,
+            QuestionType::MailGroup => 8,
+            QuestionType::MailRename => 9,
+            QuestionType::Null => 10,
+            QuestionType::WellKnownService => 11,
+            QuestionType::DomainName => 12,
+            QuestionType::HostInformation => 13,
+            QuestionType::MailboxInformation => 14,
+            QuestionType::MailExchange => 15,
+            QuestionType::TextStrings => 16,
+            QuestionType::Ipv6Address => 28,
+            QuestionType::Service => 33,
+            QuestionType::NamingAuthorityPointer => 35,
+            QuestionType::DelegationName => 39,
+            QuestionType::DelegationSigner => 43,
+            QuestionType::SshFingerprint => 44,
+            QuestionType::ResourceSignature => 46,
+            QuestionType::NextSecure => 47,
+            QuestionType::DnsKey => 48,
+            QuestionType::NextSecure3 => 50,
+            QuestionType::NextSecure3Parameters => 51,
+            QuestionType::TlsAssociation => 52,
+            QuestionType::SmimeAssociation => 53,
+            QuestionType::ChildDelegationSigner => 59,
+            QuestionType::ChildDnsKey => 60,
+            QuestionType::OpenPgpKey => 61,
+            QuestionType::ServiceBinding => 64,
+            QuestionType::HttpsBinding => 65,
+            QuestionType::TransactionSignature => 250,
+            QuestionType::IncrementalTransfer => 251,
+            QuestionType::TransferZone => 252,
+            QuestionType::MailboxRelated => 253,
+            QuestionType::MailAgent => 254,
+            QuestionType::All => 255,
+            QuestionType::UniformResourceIdentifier => 256,
+            QuestionType::CertificationAuthorityAuthorization => 257,
+            QuestionType::Unknown(code) => code,
         }
     }
 }
@@ -76,7 +168,7 @@ impl Display for QuestionClass {
             QuestionClass::Chaos => write!(f, "Chaos"),
             QuestionClass::Hesiod => write!(f, "Hesiod"),
             QuestionClass::Any => write!(f, "Any Question Class"),
-            QuestionClass::Unknown => write!(f, "Unknown or Unsupported Question Class"),
+            QuestionClass::Unknown(class) => write!(f, "Unknown Question Class {}", class),
         }
     }
 }
@@ -89,7 +181,7 @@ impl From<QuestionClass> for u16 {
             QuestionClass::Chaos => 3,
             QuestionClass::Hesiod => 4,
             QuestionClass::Any => 255,
-            QuestionClass::Unknown => unreachable!("Can't create an unknown question class"),
+            QuestionClass::Unknown(class) => class,
         }
     }
 }
@@ -102,12 +194,36 @@ impl From<u16> for QuestionClass {
             3 => QuestionClass::Chaos,
             4 => QuestionClass::Hesiod,
             255 => QuestionClass::Any,
-            _ => QuestionClass::Unknown,
+            class => QuestionClass::Unknown(class),
         }
     }
 }
 
 impl<'a> Question<'a> {
+    pub fn new(
+        domain_name: DomainName<'a>,
+        question_type: QuestionType,
+        question_class: QuestionClass,
+    ) -> Question<'a> {
+        Question {
+            domain_name,
+            question_class,
+            question_type,
+        }
+    }
+
+    pub fn name(&self) -> &DomainName<'a> {
+        &self.domain_name
+    }
+
+    pub fn question_type(&self) -> QuestionType {
+        self.question_type
+    }
+
+    pub fn question_class(&self) -> QuestionClass {
+        self.question_class
+    }
+
     // TODO: Reading a question requires access to the global list of domain names
     // pub fn read_question(
     //     packet_data: &'a [u8],
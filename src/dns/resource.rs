@@ -1,5 +1,5 @@
-use super::{Resource, ResourceClass, ResourceType};
-use std::fmt::Display;
+use super::{DomainName, Resource, ResourceClass, ResourcePayload, ResourceType, ServiceBinding};
+use std::{borrow::Cow, fmt::Display};
 
 impl Display for ResourceClass {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -8,7 +8,9 @@ impl Display for ResourceClass {
             ResourceClass::CSNet => write!(f, "CSNet (Obsolete)"),
             ResourceClass::Chaos => write!(f, "Chaos"),
             ResourceClass::Hesiod => write!(f, "Hesiod"),
-            ResourceClass::Unknown => write!(f, "Unknown or Unsupported Resource Class"),
+            ResourceClass::None => write!(f, "None"),
+            ResourceClass::Any => write!(f, "Any"),
+            ResourceClass::Unknown(class) => write!(f, "Unknown Resource Class {}", class),
         }
     }
 }
@@ -20,7 +22,9 @@ impl From<ResourceClass> for u16 {
             ResourceClass::CSNet => 2,
             ResourceClass::Chaos => 3,
             ResourceClass::Hesiod => 4,
-            ResourceClass::Unknown => unreachable!("Can't create an unknown resource class"),
+            ResourceClass::None => 254,
+            ResourceClass::Any => 255,
+            ResourceClass::Unknown(class) => class,
         }
     }
 }
@@ -32,7 +36,9 @@ impl From<u16> for ResourceClass {
             2 => ResourceClass::CSNet,
             3 => ResourceClass::Chaos,
             4 => ResourceClass::Hesiod,
-            _ => ResourceClass::Unknown,
+            254 => ResourceClass::None,
+            255 => ResourceClass::Any,
+            class => ResourceClass::Unknown(class),
         }
     }
 }
@@ -56,7 +62,30 @@ impl Display for ResourceType {
             ResourceType::MailboxInformation => write!(f, "Mailbox Information"),
             ResourceType::MailExchange => write!(f, "Mail Exchange"),
             ResourceType::TextStrings => write!(f, "Lines of Text"),
-            ResourceType::Unknown => write!(f, "Unknown or Unsupported Question Type"),
+            ResourceType::Ipv6Address => write!(f, "IPv6 Address"),
+            ResourceType::Service => write!(f, "Service Location"),
+            ResourceType::NamingAuthorityPointer => write!(f, "Naming Authority Pointer"),
+            ResourceType::DelegationName => write!(f, "Delegation Name"),
+            ResourceType::DelegationSigner => write!(f, "Delegation Signer"),
+            ResourceType::SshFingerprint => write!(f, "SSH Key Fingerprint"),
+            ResourceType::ResourceSignature => write!(f, "Resource Record Signature"),
+            ResourceType::NextSecure => write!(f, "Next Secure"),
+            ResourceType::DnsKey => write!(f, "DNS Key"),
+            ResourceType::NextSecure3 => write!(f, "Next Secure Version 3"),
+            ResourceType::NextSecure3Parameters => write!(f, "NSEC3 Parameters"),
+            ResourceType::TlsAssociation => write!(f, "TLS Certificate Association"),
+            ResourceType::SmimeAssociation => write!(f, "S/MIME Certificate Association"),
+            ResourceType::ChildDelegationSigner => write!(f, "Child Delegation Signer"),
+            ResourceType::ChildDnsKey => write!(f, "Child DNS Key"),
+            ResourceType::OpenPgpKey => write!(f, "OpenPGP Key"),
+            ResourceType::ServiceBinding => write!(f, "Service Binding"),
+            ResourceType::HttpsBinding => write!(f, "HTTPS Binding"),
+            ResourceType::TransactionSignature => write!(f, "Transaction Signature"),
+            ResourceType::UniformResourceIdentifier => write!(f, "Uniform Resource Identifier"),
+            ResourceType::CertificationAuthorityAuthorization => {
+                write!(f, "Certification Authority Authorization")
+            }
+            ResourceType::Unknown(code) => write!(f, "Unknown Resource Type {}", code),
         }
     }
 }
@@ -79,20 +108,592 @@ impl From<u16> for ResourceType {
             14 => ResourceType::MailboxInformation,
             15 => ResourceType::MailExchange,
             16 => ResourceType::TextStrings,
-            _ => ResourceType::Unknown,
+            28 => ResourceType::Ipv6Address,
+            33 => ResourceType::Service,
+            35 => ResourceType::NamingAuthorityPointer,
+            39 => ResourceType::DelegationName,
+            43 => ResourceType::DelegationSigner,
+            44 => ResourceType::SshFingerprint,
+            46 => ResourceType::ResourceSignature,
+            47 => ResourceType::NextSecure,
+            48 => ResourceType::DnsKey,
+            50 => ResourceType::NextSecure3,
+            51 => ResourceType::NextSecure3Parameters,
+            52 => ResourceType::TlsAssociation,
+            53 => ResourceType::SmimeAssociation,
+            59 => ResourceType::ChildDelegationSigner,
+            60 => ResourceType::ChildDnsKey,
+            61 => ResourceType::OpenPgpKey,
+            64 => ResourceType::ServiceBinding,
+            65 => ResourceType::HttpsBinding,
+            250 => ResourceType::TransactionSignature,
+            256 => ResourceType::UniformResourceIdentifier,
+            257 => ResourceType::CertificationAuthorityAuthorization,
+            code => ResourceType::Unknown(code),
         }
     }
 }
 
-impl std::fmt::Display for Resource<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        // TODO: Add time recieved
-        writeln!(
-            f,
-            "Resource for: {} valid for {} seconds",
-            self.resource_name, self.time_to_live
-        )?;
-        writeln!(f, "{}", self.payload)?;
-        Ok(())
+impl From<ResourceType> for u16 {
+    fn from(resource_type: ResourceType) -> Self {
+        resource_type.code()
+    }
+}
+
+impl ResourceType {
+    /// The type code on the wire, a const fn so that codes can name constants
+    pub const fn code(self) -> u16 {
+        match self {
+            ResourceType::Address => 1,
+            ResourceType::NameServer => 2,
+            ResourceType::MailDestination => 3,
+            ResourceType::MailForwarder => 4,
+            ResourceType::CanonicalName => 5,
+            ResourceType::StartAuthority => 6,
+            ResourceType::MailBox => 7,
+            ResourceType::MailGroup => 8,
+            ResourceType::MailRename => 9,
+            ResourceType::Null => 10,
+            ResourceType::WellKnownService => 11,
+            ResourceType::DomainName => 12,
+            ResourceType::HostInformation => 13,
+            ResourceType::MailboxInformation => 14,
+            ResourceType::MailExchange => 15,
+            ResourceType::TextStrings => 16,
+            ResourceType::Ipv6Address => 28,
+            ResourceType::Service => 33,
+            ResourceType::NamingAuthorityPointer => 35,
+            ResourceType::DelegationName => 39,
+            ResourceType::DelegationSigner => 43,
+            ResourceType::SshFingerprint => 44,
+            ResourceType::ResourceSignature => 46,
+            ResourceType::NextSecure => 47,
+            ResourceType::DnsKey => 48,
+            ResourceType::NextSecure3 => 50,
+            ResourceType::NextSecure3Parameters => 51,
+            ResourceType::TlsAssociation => 52,
+            ResourceType::SmimeAssociation => 53,
+            ResourceType::ChildDelegationSigner => 59,
+            ResourceType::ChildDnsKey => 60,
+            ResourceType::OpenPgpKey => 61,
+            ResourceType::ServiceBinding => 64,
+            ResourceType::HttpsBinding => 65,
+            ResourceType::TransactionSignature => 250,
+            ResourceType::UniformResourceIdentifier => 256,
+            ResourceType::CertificationAuthorityAuthorization => 257,
+            ResourceType::Unknown(code) => code,
+        }
+    }
+}
+
+impl ResourceType {
+    /// The mnemonic used for this type in zone files, ie A, NS, MX
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            ResourceType::Address => "A",
+            ResourceType::NameServer => "NS",
+            ResourceType::MailDestination => "MD",
+            ResourceType::MailForwarder => "MF",
+            ResourceType::CanonicalName => "CNAME",
+            ResourceType::StartAuthority => "SOA",
+            ResourceType::MailBox => "MB",
+            ResourceType::MailGroup => "MG",
+            ResourceType::MailRename => "MR",
+            ResourceType::Null => "NULL",
+            ResourceType::WellKnownService => "WKS",
+            ResourceType::DomainName => "PTR",
+            ResourceType::HostInformation => "HINFO",
+            ResourceType::MailboxInformation => "MINFO",
+            ResourceType::MailExchange => "MX",
+            ResourceType::TextStrings => "TXT",
+            ResourceType::Ipv6Address => "AAAA",
+            ResourceType::Service => "SRV",
+            ResourceType::NamingAuthorityPointer => "NAPTR",
+            ResourceType::DelegationName => "DNAME",
+            ResourceType::DelegationSigner => "DS",
+            ResourceType::SshFingerprint => "SSHFP",
+            ResourceType::ResourceSignature => "RRSIG",
+            ResourceType::NextSecure => "NSEC",
+            ResourceType::DnsKey => "DNSKEY",
+            ResourceType::NextSecure3 => "NSEC3",
+            ResourceType::NextSecure3Parameters => "NSEC3PARAM",
+            ResourceType::TlsAssociation => "TLSA",
+            ResourceType::SmimeAssociation => "SMIMEA",
+            ResourceType::ChildDelegationSigner => "CDS",
+            ResourceType::ChildDnsKey => "CDNSKEY",
+            ResourceType::OpenPgpKey => "OPENPGPKEY",
+            ResourceType::ServiceBinding => "SVCB",
+            ResourceType::HttpsBinding => "HTTPS",
+            ResourceType::TransactionSignature => "TSIG",
+            ResourceType::UniformResourceIdentifier => "URI",
+            ResourceType::CertificationAuthorityAuthorization => "CAA",
+            ResourceType::Unknown(_) => "UNKNOWN",
+        }
+    }
+
+    /// Looks up a resource type from its zone file mnemonic, the comparison is case insensitive
+    pub fn from_mnemonic(mnemonic: &str) -> Option<ResourceType> {
+        let resource_type = match mnemonic.to_ascii_uppercase().as_str() {
+            "A" => ResourceType::Address,
+            "NS" => ResourceType::NameServer,
+            "MD" => ResourceType::MailDestination,
+            "MF" => ResourceType::MailForwarder,
+            "CNAME" => ResourceType::CanonicalName,
+            "SOA" => ResourceType::StartAuthority,
+            "MB" => ResourceType::MailBox,
+            "MG" => ResourceType::MailGroup,
+            "MR" => ResourceType::MailRename,
+            "NULL" => ResourceType::Null,
+            "WKS" => ResourceType::WellKnownService,
+            "PTR" => ResourceType::DomainName,
+            "HINFO" => ResourceType::HostInformation,
+            "MINFO" => ResourceType::MailboxInformation,
+            "MX" => ResourceType::MailExchange,
+            "TXT" => ResourceType::TextStrings,
+            "AAAA" => ResourceType::Ipv6Address,
+            "SRV" => ResourceType::Service,
+            "NAPTR" => ResourceType::NamingAuthorityPointer,
+            "DNAME" => ResourceType::DelegationName,
+            "DS" => ResourceType::DelegationSigner,
+            "SSHFP" => ResourceType::SshFingerprint,
+            "RRSIG" => ResourceType::ResourceSignature,
+            "NSEC" => ResourceType::NextSecure,
+            "DNSKEY" => ResourceType::DnsKey,
+            "NSEC3" => ResourceType::NextSecure3,
+            "NSEC3PARAM" => ResourceType::NextSecure3Parameters,
+            "TLSA" => ResourceType::TlsAssociation,
+            "SMIMEA" => ResourceType::SmimeAssociation,
+            "CDS" => ResourceType::ChildDelegationSigner,
+            "CDNSKEY" => ResourceType::ChildDnsKey,
+            "OPENPGPKEY" => ResourceType::OpenPgpKey,
+            "SVCB" => ResourceType::ServiceBinding,
+            "HTTPS" => ResourceType::HttpsBinding,
+            "TSIG" => ResourceType::TransactionSignature,
+            "URI" => ResourceType::UniformResourceIdentifier,
+            "CAA" => ResourceType::CertificationAuthorityAuthorization,
+            _ => return None,
+        };
+        Some(resource_type)
+    }
+}
+
+impl ResourceClass {
+    /// The mnemonic used for this class in zone files, ie IN
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            ResourceClass::Internet => "IN",
+            ResourceClass::CSNet => "CS",
+            ResourceClass::Chaos => "CH",
+            ResourceClass::Hesiod => "HS",
+            ResourceClass::None => "NONE",
+            ResourceClass::Any => "ANY",
+            ResourceClass::Unknown(_) => "UNKNOWN",
+        }
+    }
+
+    /// Looks up a resource class from its zone file mnemonic, the comparison is case insensitive
+    pub fn from_mnemonic(mnemonic: &str) -> Option<ResourceClass> {
+        let resource_class = match mnemonic.to_ascii_uppercase().as_str() {
+            "IN" => ResourceClass::Internet,
+            "CS" => ResourceClass::CSNet,
+            "CH" => ResourceClass::Chaos,
+            "HS" => ResourceClass::Hesiod,
+            _ => return None,
+        };
+        Some(resource_class)
+    }
+}
+
+impl<'a> ResourcePayload<'a> {
+    pub fn resource_type(&self) -> ResourceType {
+        match self {
+            ResourcePayload::Address(_) => ResourceType::Address,
+            ResourcePayload::NameServer(_) => ResourceType::NameServer,
+            ResourcePayload::MailDestination(_) => ResourceType::MailDestination,
+            ResourcePayload::MailForwarder(_) => ResourceType::MailForwarder,
+            ResourcePayload::CanonicalName(_) => ResourceType::CanonicalName,
+            ResourcePayload::StartAuthority { .. } => ResourceType::StartAuthority,
+            ResourcePayload::MailBox(_) => ResourceType::MailBox,
+            ResourcePayload::MailGroup(_) => ResourceType::MailGroup,
+            ResourcePayload::MailRename(_) => ResourceType::MailRename,
+            ResourcePayload::Null(_) => ResourceType::Null,
+            ResourcePayload::WellKnownService { .. } => ResourceType::WellKnownService,
+            ResourcePayload::DomainName(_) => ResourceType::DomainName,
+            ResourcePayload::HostInformation { .. } => ResourceType::HostInformation,
+            ResourcePayload::MailboxInformation { .. } => ResourceType::MailboxInformation,
+            ResourcePayload::MailExchange { .. } => ResourceType::MailExchange,
+            ResourcePayload::TextStrings(_) => ResourceType::TextStrings,
+            ResourcePayload::Ipv6Address(_) => ResourceType::Ipv6Address,
+            ResourcePayload::Service { .. } => ResourceType::Service,
+            ResourcePayload::NamingAuthorityPointer { .. } => ResourceType::NamingAuthorityPointer,
+            ResourcePayload::DelegationName(_) => ResourceType::DelegationName,
+            ResourcePayload::DelegationSigner { .. } => ResourceType::DelegationSigner,
+            ResourcePayload::SshFingerprint { .. } => ResourceType::SshFingerprint,
+            ResourcePayload::ResourceSignature { .. } => ResourceType::ResourceSignature,
+            ResourcePayload::NextSecure { .. } => ResourceType::NextSecure,
+            ResourcePayload::DnsKey { .. } => ResourceType::DnsKey,
+            ResourcePayload::NextSecure3 { .. } => ResourceType::NextSecure3,
+            ResourcePayload::NextSecure3Parameters { .. } => ResourceType::NextSecure3Parameters,
+            ResourcePayload::TlsAssociation { .. } => ResourceType::TlsAssociation,
+            ResourcePayload::SmimeAssociation { .. } => ResourceType::SmimeAssociation,
+            ResourcePayload::ChildDelegationSigner { .. } => ResourceType::ChildDelegationSigner,
+            ResourcePayload::ChildDnsKey { .. } => ResourceType::ChildDnsKey,
+            ResourcePayload::OpenPgpKey(_) => ResourceType::OpenPgpKey,
+            ResourcePayload::UniformResourceIdentifier { .. } => {
+                ResourceType::UniformResourceIdentifier
+            }
+            ResourcePayload::CertificationAuthorityAuthorization { .. } => {
+                ResourceType::CertificationAuthorityAuthorization
+            }
+            ResourcePayload::ServiceBinding(_) => ResourceType::ServiceBinding,
+            ResourcePayload::HttpsBinding(_) => ResourceType::HttpsBinding,
+            ResourcePayload::TransactionSignature { .. } => ResourceType::TransactionSignature,
+            ResourcePayload::Unknown { resource_type, .. } => ResourceType::Unknown(*resource_type),
+        }
+    }
+
+    /// The numeric resource type of this payload, unlike resource_type this is also valid for unknown payloads
+    pub fn type_code(&self) -> u16 {
+        match self {
+            ResourcePayload::Unknown { resource_type, .. } => *resource_type,
+            known_payload => u16::from(known_payload.resource_type()),
+        }
+    }
+
+    /// The SVCB or HTTPS contents of the payload, used by rules that rewrite service parameters
+    pub fn service_binding_mut(&mut self) -> Option<&mut ServiceBinding<'a>> {
+        match self {
+            ResourcePayload::ServiceBinding(binding) | ResourcePayload::HttpsBinding(binding) => {
+                Some(binding)
+            }
+            _ => None,
+        }
+    }
+
+    /// Copies any data borrowed from a packet so the payload can outlive it
+    pub fn into_owned(self) -> ResourcePayload<'static> {
+        fn owned_bytes(bytes: Cow<[u8]>) -> Cow<'static, [u8]> {
+            Cow::Owned(bytes.into_owned())
+        }
+        match self {
+            ResourcePayload::Address(address) => ResourcePayload::Address(address),
+            ResourcePayload::NameServer(name) => ResourcePayload::NameServer(name.into_owned()),
+            ResourcePayload::MailDestination(name) => {
+                ResourcePayload::MailDestination(name.into_owned())
+            }
+            ResourcePayload::MailForwarder(name) => {
+                ResourcePayload::MailForwarder(name.into_owned())
+            }
+            ResourcePayload::CanonicalName(name) => {
+                ResourcePayload::CanonicalName(name.into_owned())
+            }
+            ResourcePayload::StartAuthority {
+                primary_name_server,
+                responsible_mailbox,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => ResourcePayload::StartAuthority {
+                primary_name_server: primary_name_server.into_owned(),
+                responsible_mailbox: responsible_mailbox.into_owned(),
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            },
+            ResourcePayload::MailBox(name) => ResourcePayload::MailBox(name.into_owned()),
+            ResourcePayload::MailGroup(name) => ResourcePayload::MailGroup(name.into_owned()),
+            ResourcePayload::MailRename(name) => ResourcePayload::MailRename(name.into_owned()),
+            ResourcePayload::Null(data) => ResourcePayload::Null(owned_bytes(data)),
+            ResourcePayload::WellKnownService {
+                address,
+                protocol,
+                bitmap,
+            } => ResourcePayload::WellKnownService {
+                address,
+                protocol,
+                bitmap: owned_bytes(bitmap),
+            },
+            ResourcePayload::DomainName(name) => ResourcePayload::DomainName(name.into_owned()),
+            ResourcePayload::HostInformation {
+                cpu,
+                operating_system,
+            } => ResourcePayload::HostInformation {
+                cpu: owned_bytes(cpu),
+                operating_system: owned_bytes(operating_system),
+            },
+            ResourcePayload::MailboxInformation {
+                responsible_mailbox,
+                error_mailbox,
+            } => ResourcePayload::MailboxInformation {
+                responsible_mailbox: responsible_mailbox.into_owned(),
+                error_mailbox: error_mailbox.into_owned(),
+            },
+            ResourcePayload::MailExchange {
+                preference,
+                exchange,
+            } => ResourcePayload::MailExchange {
+                preference,
+                exchange: exchange.into_owned(),
+            },
+            ResourcePayload::TextStrings(strings) => {
+                ResourcePayload::TextStrings(strings.into_iter().map(owned_bytes).collect())
+            }
+            ResourcePayload::Ipv6Address(address) => ResourcePayload::Ipv6Address(address),
+            ResourcePayload::Service {
+                priority,
+                weight,
+                port,
+                target,
+            } => ResourcePayload::Service {
+                priority,
+                weight,
+                port,
+                target: target.into_owned(),
+            },
+            ResourcePayload::NamingAuthorityPointer {
+                order,
+                preference,
+                flags,
+                services,
+                regular_expression,
+                replacement,
+            } => ResourcePayload::NamingAuthorityPointer {
+                order,
+                preference,
+                flags: owned_bytes(flags),
+                services: owned_bytes(services),
+                regular_expression: owned_bytes(regular_expression),
+                replacement: replacement.into_owned(),
+            },
+            ResourcePayload::DelegationName(target) => {
+                ResourcePayload::DelegationName(target.into_owned())
+            }
+            ResourcePayload::DelegationSigner {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            } => ResourcePayload::DelegationSigner {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest: owned_bytes(digest),
+            },
+            ResourcePayload::ChildDelegationSigner {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            } => ResourcePayload::ChildDelegationSigner {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest: owned_bytes(digest),
+            },
+            ResourcePayload::ResourceSignature {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer_name,
+                signature,
+            } => ResourcePayload::ResourceSignature {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer_name: signer_name.into_owned(),
+                signature: owned_bytes(signature),
+            },
+            ResourcePayload::NextSecure {
+                next_domain_name,
+                types,
+            } => ResourcePayload::NextSecure {
+                next_domain_name: next_domain_name.into_owned(),
+                types,
+            },
+            ResourcePayload::DnsKey {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            } => ResourcePayload::DnsKey {
+                flags,
+                protocol,
+                algorithm,
+                public_key: owned_bytes(public_key),
+            },
+            ResourcePayload::ChildDnsKey {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            } => ResourcePayload::ChildDnsKey {
+                flags,
+                protocol,
+                algorithm,
+                public_key: owned_bytes(public_key),
+            },
+            ResourcePayload::NextSecure3 {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                next_hashed_owner,
+                types,
+            } => ResourcePayload::NextSecure3 {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt: owned_bytes(salt),
+                next_hashed_owner: owned_bytes(next_hashed_owner),
+                types,
+            },
+            ResourcePayload::NextSecure3Parameters {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+            } => ResourcePayload::NextSecure3Parameters {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt: owned_bytes(salt),
+            },
+            ResourcePayload::SshFingerprint {
+                algorithm,
+                fingerprint_type,
+                fingerprint,
+            } => ResourcePayload::SshFingerprint {
+                algorithm,
+                fingerprint_type,
+                fingerprint: owned_bytes(fingerprint),
+            },
+            ResourcePayload::TlsAssociation {
+                certificate_usage,
+                selector,
+                matching_type,
+                certificate_data,
+            } => ResourcePayload::TlsAssociation {
+                certificate_usage,
+                selector,
+                matching_type,
+                certificate_data: owned_bytes(certificate_data),
+            },
+            ResourcePayload::SmimeAssociation {
+                certificate_usage,
+                selector,
+                matching_type,
+                certificate_data,
+            } => ResourcePayload::SmimeAssociation {
+                certificate_usage,
+                selector,
+                matching_type,
+                certificate_data: owned_bytes(certificate_data),
+            },
+            ResourcePayload::OpenPgpKey(key) => ResourcePayload::OpenPgpKey(owned_bytes(key)),
+            ResourcePayload::UniformResourceIdentifier {
+                priority,
+                weight,
+                target,
+            } => ResourcePayload::UniformResourceIdentifier {
+                priority,
+                weight,
+                target: owned_bytes(target),
+            },
+            ResourcePayload::CertificationAuthorityAuthorization { flags, tag, value } => {
+                ResourcePayload::CertificationAuthorityAuthorization {
+                    flags,
+                    tag: owned_bytes(tag),
+                    value: owned_bytes(value),
+                }
+            }
+            ResourcePayload::ServiceBinding(binding) => {
+                ResourcePayload::ServiceBinding(binding.into_owned())
+            }
+            ResourcePayload::HttpsBinding(binding) => {
+                ResourcePayload::HttpsBinding(binding.into_owned())
+            }
+            ResourcePayload::TransactionSignature {
+                algorithm,
+                time_signed,
+                fudge,
+                mac,
+                original_id,
+                error,
+                other_data,
+            } => ResourcePayload::TransactionSignature {
+                algorithm: algorithm.into_owned(),
+                time_signed,
+                fudge,
+                mac: owned_bytes(mac),
+                original_id,
+                error,
+                other_data: owned_bytes(other_data),
+            },
+            ResourcePayload::Unknown {
+                resource_type,
+                data,
+            } => ResourcePayload::Unknown {
+                resource_type,
+                data: owned_bytes(data),
+            },
+        }
+    }
+}
+
+impl<'a> Resource<'a> {
+    pub fn new(
+        resource_name: DomainName<'a>,
+        resource_class: ResourceClass,
+        time_to_live: u32,
+        payload: ResourcePayload<'a>,
+    ) -> Resource<'a> {
+        Resource {
+            resource_name,
+            resource_class,
+            time_to_live,
+            payload,
+        }
+    }
+
+    pub fn name(&self) -> &DomainName<'a> {
+        &self.resource_name
+    }
+
+    pub fn class(&self) -> ResourceClass {
+        self.resource_class
+    }
+
+    pub fn time_to_live(&self) -> u32 {
+        self.time_to_live
+    }
+
+    pub fn payload(&self) -> &ResourcePayload<'a> {
+        &self.payload
+    }
+
+    pub fn resource_type(&self) -> ResourceType {
+        self.payload.resource_type()
+    }
+
+    /// Copies any data borrowed from a packet so the resource can be stored after the packet is gone
+    pub fn into_owned(self) -> Resource<'static> {
+        Resource {
+            resource_name: self.resource_name.into_owned(),
+            resource_class: self.resource_class,
+            time_to_live: self.time_to_live,
+            payload: self.payload.into_owned(),
+        }
     }
 }
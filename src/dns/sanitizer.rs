@@ -0,0 +1,527 @@
+use super::{
+    authority::target, builders::DnsResponseBuilder, edns, presentation::type_mnemonic, DnsPacket,
+    DnsParser, DomainName, QuestionType, Removal, RemovalReason, Resource, ResourcePayload,
+    ResourceType, Sanitizer, Section,
+};
+use crate::error::Error;
+use std::fmt::Display;
+
+/// CNAMEs and DNAMEs in an answer are followed at most this many times
+const MAXIMUM_CHAIN_LENGTH: usize = 16;
+
+const ALL: u16 = QuestionType::All.code();
+const CANONICAL_NAME: u16 = ResourceType::CanonicalName.code();
+const DELEGATION_NAME: u16 = ResourceType::DelegationName.code();
+const NAME_SERVER: u16 = ResourceType::NameServer.code();
+const START_AUTHORITY: u16 = ResourceType::StartAuthority.code();
+const DELEGATION_SIGNER: u16 = ResourceType::DelegationSigner.code();
+const NEXT_SECURE: u16 = ResourceType::NextSecure.code();
+const NEXT_SECURE_3: u16 = ResourceType::NextSecure3.code();
+const ADDRESS: u16 = ResourceType::Address.code();
+const IPV6_ADDRESS: u16 = ResourceType::Ipv6Address.code();
+const TRANSACTION_SIGNATURE: u16 = ResourceType::TransactionSignature.code();
+
+/// The type of a record, or the type an RRSIG covers so that signatures go along with what they sign
+fn covered_type(record: &Resource) -> u16 {
+    match record.payload() {
+        ResourcePayload::ResourceSignature { type_covered, .. } => *type_covered,
+        payload => payload.type_code(),
+    }
+}
+
+/// Takes the records out of a section that have a reason to be removed, the reasons are in the order of the records
+fn remove(
+    records: &mut Vec<Resource>,
+    reasons: Vec<Option<RemovalReason>>,
+    section: Section,
+    removals: &mut Vec<Removal>,
+) {
+    let mut reasons = reasons.into_iter();
+    records.retain(|record| match reasons.next().flatten() {
+        Some(reason) => {
+            removals.push(Removal {
+                section,
+                name: record.name().clone().into_owned(),
+                type_code: record.payload().type_code(),
+                reason,
+            });
+            false
+        }
+        None => true,
+    });
+}
+
+impl Display for Section {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Section::Answer => write!(f, "answer"),
+            Section::Authority => write!(f, "authority"),
+            Section::Additional => write!(f, "additional"),
+        }
+    }
+}
+
+impl Display for RemovalReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RemovalReason::OutOfBailiwick => write!(f, "out of bailiwick"),
+            RemovalReason::OffChain => write!(f, "not on the chain from the question"),
+            RemovalReason::Unrelated => write!(f, "unrelated to the answer"),
+        }
+    }
+}
+
+impl Display for Removal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} in the {} section, {}",
+            self.name,
+            type_mnemonic(self.type_code),
+            self.section,
+            self.reason
+        )
+    }
+}
+
+impl Sanitizer {
+    /// Trusts the upstream with the names at and below the bailiwick, the root trusts it with every name
+    pub fn new(bailiwick: DomainName<'static>) -> Sanitizer {
+        Sanitizer { bailiwick }
+    }
+
+    /// Removes the records of an answer that are out of bailiwick, aren't on the CNAME and DNAME chain from the question,
+    /// or are authority and additional data nothing else refers to, returning what was removed
+    pub fn sanitize(&self, packet: &mut DnsPacket) -> Vec<Removal> {
+        let mut removals = Vec::new();
+        let (chain, on_chain) = match packet.questions.first() {
+            Some(question) => self.follow_chain(
+                &question.domain_name,
+                u16::from(question.question_type),
+                &packet.answers,
+            ),
+            None => (Vec::new(), vec![false; packet.answers.len()]),
+        };
+        let reasons = packet
+            .answers
+            .iter()
+            .zip(on_chain)
+            .map(|(record, on_chain)| self.reason(record, on_chain, RemovalReason::OffChain))
+            .collect();
+        remove(&mut packet.answers, reasons, Section::Answer, &mut removals);
+
+        // NS, SOA and DS records belong to a zone the names of the answer are in
+        let above_chain = |owner: &DomainName| chain.iter().any(|name| name.is_subdomain_of(owner));
+        let zones = packet
+            .authority
+            .iter()
+            .filter(|record| matches!(covered_type(record), NAME_SERVER | START_AUTHORITY))
+            .filter(|record| {
+                self.reason(record, above_chain(record.name()), RemovalReason::Unrelated)
+                    .is_none()
+            })
+            .map(|record| record.name().clone().into_owned())
+            .collect::<Vec<_>>();
+        let reasons = packet
+            .authority
+            .iter()
+            .map(|record| {
+                let related = match covered_type(record) {
+                    NAME_SERVER | START_AUTHORITY | DELEGATION_SIGNER => above_chain(record.name()),
+                    // Denial of existence from the zone of the answer, any zone in bailiwick when the answer doesn't name one
+                    NEXT_SECURE | NEXT_SECURE_3 => {
+                        zones.is_empty()
+                            || zones.iter().any(|zone| record.name().is_subdomain_of(zone))
+                    }
+                    _ => false,
+                };
+                self.reason(record, related, RemovalReason::Unrelated)
+            })
+            .collect();
+        remove(
+            &mut packet.authority,
+            reasons,
+            Section::Authority,
+            &mut removals,
+        );
+
+        // Addresses are only additional data for the names NS, MX and SRV records that were kept point at
+        let targets = packet
+            .answers
+            .iter()
+            .chain(packet.authority.iter())
+            .filter_map(target)
+            .map(|name| name.clone().into_owned())
+            .collect::<Vec<_>>();
+        let reasons = packet
+            .additional
+            .iter()
+            .map(|record| match covered_type(record) {
+                // The OPT and TSIG records are about the message rather than any name
+                edns::OPTIONS_TYPE | TRANSACTION_SIGNATURE => None,
+                ADDRESS | IPV6_ADDRESS => {
+                    let related = targets
+                        .iter()
+                        .any(|name| name.eq_ignore_case(record.name()));
+                    self.reason(record, related, RemovalReason::Unrelated)
+                }
+                _ => self.reason(record, false, RemovalReason::Unrelated),
+            })
+            .collect();
+        remove(
+            &mut packet.additional,
+            reasons,
+            Section::Additional,
+            &mut removals,
+        );
+        removals
+    }
+
+    /// Sanitizes an answer as it came from the upstream, it is only written again when records were removed
+    /// The OPT record is copied over as it was, a TSIG record is dropped as it no longer covers the answer
+    pub fn sanitize_message(
+        &self,
+        message: &[u8],
+    ) -> Result<(Option<Vec<u8>>, Vec<Removal>), Error> {
+        let mut packet = DnsParser::new().parse_packet(message)?;
+        let removals = self.sanitize(&mut packet);
+        if removals.is_empty() {
+            return Ok((None, removals));
+        }
+        let mut builder = DnsResponseBuilder::with_header(packet.header);
+        for question in packet.questions {
+            builder.add_question(question);
+        }
+        for answer in packet.answers {
+            builder.add_answer(answer);
+        }
+        for authority in packet.authority {
+            builder.add_authority(authority);
+        }
+        for additional in packet.additional.into_iter().filter(|record| {
+            !matches!(
+                record.payload().type_code(),
+                edns::OPTIONS_TYPE | TRANSACTION_SIGNATURE
+            )
+        }) {
+            builder.add_additional(additional);
+        }
+        let mut sanitized = builder.build_response()?;
+        edns::copy_options_record(message, &mut sanitized);
+        Ok((Some(sanitized), removals))
+    }
+
+    fn reason(
+        &self,
+        record: &Resource,
+        related: bool,
+        otherwise: RemovalReason,
+    ) -> Option<RemovalReason> {
+        if !record.name().is_subdomain_of(&self.bailiwick) {
+            Some(RemovalReason::OutOfBailiwick)
+        } else if !related {
+            Some(otherwise)
+        } else {
+            None
+        }
+    }
+
+    /// Follows CNAMEs and DNAMEs from the question name while they stay in bailiwick
+    /// Returns the names along the way and whether each answer is on the chain
+    fn follow_chain(
+        &self,
+        name: &DomainName,
+        type_code: u16,
+        answers: &[Resource],
+    ) -> (Vec<DomainName<'static>>, Vec<bool>) {
+        let mut on_chain = vec![false; answers.len()];
+        let mut chain = vec![name.clone().into_owned()];
+        while chain.len() <= MAXIMUM_CHAIN_LENGTH {
+            let name = &chain[chain.len() - 1];
+            if !name.is_subdomain_of(&self.bailiwick) {
+                break;
+            }
+            let mut next = None;
+            for (index, record) in answers.iter().enumerate() {
+                let covered = covered_type(record);
+                let matches = if record.name().eq_ignore_case(name) {
+                    covered == type_code || type_code == ALL || covered == CANONICAL_NAME
+                } else {
+                    // RFC 6672, a DNAME above the name comes along with the CNAME it synthesized
+                    covered == DELEGATION_NAME && name.is_subdomain_of(record.name())
+                };
+                if !matches {
+                    continue;
+                }
+                on_chain[index] = true;
+                match record.payload() {
+                    ResourcePayload::CanonicalName(target)
+                        if type_code != CANONICAL_NAME && type_code != ALL =>
+                    {
+                        next = Some(target)
+                    }
+                    _ => (),
+                }
+            }
+            match next {
+                // A target that is already on the chain means the CNAMEs loop
+                Some(target) if !chain.iter().any(|name| name.eq_ignore_case(target)) => {
+                    chain.push(target.clone().into_owned())
+                }
+                _ => break,
+            }
+        }
+        (chain, on_chain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{
+        tests::name, ExtendedError, Header, PacketType, Question, QuestionClass, ResourceClass,
+    };
+
+    fn record(owner: &str, payload: ResourcePayload<'static>) -> Resource<'static> {
+        Resource::new(name(owner), ResourceClass::Internet, 300, payload)
+    }
+
+    fn address(owner: &str, last: u8) -> Resource<'static> {
+        record(owner, ResourcePayload::Address([192, 0, 2, last].into()))
+    }
+
+    fn alias(owner: &str, target: &str) -> Resource<'static> {
+        record(owner, ResourcePayload::CanonicalName(name(target)))
+    }
+
+    fn response(
+        question: &str,
+        answers: Vec<Resource<'static>>,
+        authority: Vec<Resource<'static>>,
+        additional: Vec<Resource<'static>>,
+    ) -> DnsPacket<'static> {
+        let mut header = Header::new();
+        header.packet_type = PacketType::Response;
+        let question = Question::new(
+            name(question),
+            QuestionType::Address,
+            QuestionClass::Internet,
+        );
+        DnsPacket::new(header, vec![question], answers, authority, additional)
+    }
+
+    fn removed(removals: &[Removal]) -> Vec<(Section, String, RemovalReason)> {
+        removals
+            .iter()
+            .map(|removal| (removal.section, removal.name.to_string(), removal.reason))
+            .collect()
+    }
+
+    #[test]
+    fn test_bailiwick() {
+        let sanitizer = Sanitizer::new(name("example.com"));
+        let mut packet = response(
+            "www.example.com",
+            vec![address("www.example.com", 1), address("www.bank.com", 2)],
+            vec![
+                record(
+                    "example.com",
+                    ResourcePayload::NameServer(name("ns.example.com")),
+                ),
+                record(
+                    "bank.com",
+                    ResourcePayload::NameServer(name("ns.example.com")),
+                ),
+                address("www.example.com", 3),
+            ],
+            vec![
+                address("ns.example.com", 4),
+                address("ns.bank.com", 5),
+                address("mail.example.com", 6),
+            ],
+        );
+        let removals = sanitizer.sanitize(&mut packet);
+        assert_eq!(
+            removed(&removals),
+            vec![
+                (
+                    Section::Answer,
+                    String::from("www.bank.com."),
+                    RemovalReason::OutOfBailiwick
+                ),
+                (
+                    Section::Authority,
+                    String::from("bank.com."),
+                    RemovalReason::OutOfBailiwick
+                ),
+                (
+                    Section::Authority,
+                    String::from("www.example.com."),
+                    RemovalReason::Unrelated
+                ),
+                (
+                    Section::Additional,
+                    String::from("ns.bank.com."),
+                    RemovalReason::OutOfBailiwick
+                ),
+                (
+                    Section::Additional,
+                    String::from("mail.example.com."),
+                    RemovalReason::Unrelated
+                ),
+            ]
+        );
+        assert_eq!(
+            removals[0].to_string(),
+            "www.bank.com. A in the answer section, out of bailiwick"
+        );
+        assert_eq!(packet.answers, vec![address("www.example.com", 1)]);
+        assert_eq!(packet.authority.len(), 1);
+        assert_eq!(packet.additional, vec![address("ns.example.com", 4)]);
+    }
+
+    #[test]
+    fn test_chains() {
+        // The chain leaves the bailiwick, whatever the upstream says about the target is dropped
+        let sanitizer = Sanitizer::new(name("example.com"));
+        let mut packet = response(
+            "www.example.com",
+            vec![
+                alias("www.example.com", "web.example.net"),
+                address("web.example.net", 1),
+            ],
+            Vec::new(),
+            Vec::new(),
+        );
+        let removals = sanitizer.sanitize(&mut packet);
+        assert_eq!(
+            removed(&removals),
+            vec![(
+                Section::Answer,
+                String::from("web.example.net."),
+                RemovalReason::OutOfBailiwick
+            )]
+        );
+
+        // Records the chain doesn't lead to are dropped, signatures stay with what they sign
+        let sanitizer = Sanitizer::new(DomainName::root());
+        let signature = record(
+            "web.example.net",
+            ResourcePayload::ResourceSignature {
+                type_covered: ADDRESS,
+                algorithm: 13,
+                labels: 3,
+                original_ttl: 300,
+                expiration: 0,
+                inception: 0,
+                key_tag: 1,
+                signer_name: name("example.net"),
+                signature: vec![0; 64].into(),
+            },
+        );
+        let answers = vec![
+            alias("www.example.com", "web.example.net"),
+            address("web.example.net", 1),
+            signature,
+            address("www.example.org", 2),
+            alias("other.example.com", "www.example.org"),
+        ];
+        let mut packet = response("WWW.example.com", answers.clone(), Vec::new(), Vec::new());
+        let removals = sanitizer.sanitize(&mut packet);
+        assert_eq!(
+            removed(&removals),
+            vec![
+                (
+                    Section::Answer,
+                    String::from("www.example.org."),
+                    RemovalReason::OffChain
+                ),
+                (
+                    Section::Answer,
+                    String::from("other.example.com."),
+                    RemovalReason::OffChain
+                ),
+            ]
+        );
+        assert_eq!(packet.answers, answers[..3].to_vec());
+
+        // A DNAME redirects the names below it
+        let mut packet = response(
+            "a.b.example.com",
+            vec![
+                record(
+                    "b.example.com",
+                    ResourcePayload::DelegationName(name("b.example.org")),
+                ),
+                alias("a.b.example.com", "a.b.example.org"),
+                address("a.b.example.org", 1),
+                record(
+                    "c.example.com",
+                    ResourcePayload::DelegationName(name("c.example.org")),
+                ),
+            ],
+            vec![
+                record(
+                    "example.org",
+                    ResourcePayload::NameServer(name("ns.example.org")),
+                ),
+                record(
+                    "example.net",
+                    ResourcePayload::NameServer(name("ns.example.net")),
+                ),
+            ],
+            vec![address("ns.example.org", 2), address("ns.example.net", 3)],
+        );
+        let removals = sanitizer.sanitize(&mut packet);
+        assert_eq!(
+            removed(&removals),
+            vec![
+                (
+                    Section::Answer,
+                    String::from("c.example.com."),
+                    RemovalReason::OffChain
+                ),
+                (
+                    Section::Authority,
+                    String::from("example.net."),
+                    RemovalReason::Unrelated
+                ),
+                (
+                    Section::Additional,
+                    String::from("ns.example.net."),
+                    RemovalReason::Unrelated
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sanitize_message() {
+        let sanitizer = Sanitizer::new(name("example.com"));
+        let mut builder = DnsResponseBuilder::new(0x1234);
+        builder
+            .add_question(Question::new(
+                name("www.example.com"),
+                QuestionType::Address,
+                QuestionClass::Internet,
+            ))
+            .add_answer(address("www.example.com", 1))
+            .extended_error(ExtendedError::DnssecBogus);
+        let clean = builder.build_response().unwrap();
+        assert_eq!(
+            sanitizer.sanitize_message(&clean).unwrap(),
+            (None, Vec::new())
+        );
+
+        builder.add_additional(address("www.bank.com", 2));
+        let poisoned = builder.build_response().unwrap();
+        let (sanitized, removals) = sanitizer.sanitize_message(&poisoned).unwrap();
+        let sanitized = sanitized.unwrap();
+        assert_eq!(removals.len(), 1);
+        assert_eq!(sanitized, clean);
+        assert_eq!(
+            edns::options_record(&sanitized),
+            edns::options_record(&poisoned)
+        );
+    }
+}
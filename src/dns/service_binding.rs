@@ -0,0 +1,509 @@
+use super::{DomainName, ServiceBinding, ServiceParameter};
+use crate::error::{Error, ErrorKind};
+use crate::helper::decode_base64;
+use std::{
+    borrow::Cow,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+impl<'a> ServiceBinding<'a> {
+    /// Creates the payload of an SVCB or HTTPS resource, the parameters are sorted by key
+    pub fn new(
+        priority: u16,
+        target: DomainName<'a>,
+        mut parameters: Vec<ServiceParameter<'a>>,
+    ) -> ServiceBinding<'a> {
+        parameters.sort_by_key(ServiceParameter::key);
+        ServiceBinding {
+            priority,
+            target,
+            parameters,
+        }
+    }
+
+    pub fn priority(&self) -> u16 {
+        self.priority
+    }
+
+    pub fn target(&self) -> &DomainName<'a> {
+        &self.target
+    }
+
+    pub fn parameters(&self) -> &[ServiceParameter<'a>] {
+        self.parameters.as_slice()
+    }
+
+    /// In AliasMode the target is an alias for the owner and any parameters are ignored
+    pub fn is_alias(&self) -> bool {
+        self.priority == 0
+    }
+
+    pub fn parameter(&self, key: u16) -> Option<&ServiceParameter<'a>> {
+        self.parameters
+            .iter()
+            .find(|parameter| parameter.key() == key)
+    }
+
+    /// Removes the parameters with the given keys, ie to strip ECH or address hints from a response
+    /// The keys are also removed from mandatory so the resource stays self-consistent, returns true if anything was removed
+    pub fn remove_parameters(&mut self, keys: &[u16]) -> bool {
+        let total_parameters = self.parameters.len();
+        // no-default-alpn is meaningless without alpn
+        let removes_alpn = keys.contains(&ServiceParameter::ALPN);
+        self.parameters.retain(|parameter| {
+            let key = parameter.key();
+            let removed =
+                keys.contains(&key) || (removes_alpn && key == ServiceParameter::NO_DEFAULT_ALPN);
+            !removed
+        });
+        for parameter in self.parameters.iter_mut() {
+            if let ServiceParameter::Mandatory(mandatory_keys) = parameter {
+                mandatory_keys.retain(|key| !keys.contains(key));
+            }
+        }
+        self.parameters.retain(|parameter| match parameter {
+            ServiceParameter::Mandatory(mandatory_keys) => !mandatory_keys.is_empty(),
+            _ => true,
+        });
+        total_parameters != self.parameters.len()
+    }
+
+    pub fn into_owned(self) -> ServiceBinding<'static> {
+        ServiceBinding {
+            priority: self.priority,
+            target: self.target.into_owned(),
+            parameters: self
+                .parameters
+                .into_iter()
+                .map(ServiceParameter::into_owned)
+                .collect(),
+        }
+    }
+
+    /// Reads the SvcParams that follow the target name in wire format
+    pub fn read_parameters(data: &'a [u8]) -> Result<Vec<ServiceParameter<'a>>, Error> {
+        let malformed = || Error::new(ErrorKind::ReadPacketDataFailed);
+        let mut parameters = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            let header = data.get(offset..offset + 4).ok_or_else(malformed)?;
+            let key = u16::from_be_bytes([header[0], header[1]]);
+            let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+            let value = data
+                .get(offset + 4..offset + 4 + length)
+                .ok_or_else(malformed)?;
+            parameters.push(ServiceParameter::from_wire(key, value).ok_or_else(malformed)?);
+            offset += 4 + length;
+        }
+        Self::validate_parameters(&parameters).map_err(|_| malformed())?;
+        Ok(parameters)
+    }
+
+    /// Checks the rules RFC 9460 places on a set of parameters, a resource that breaks them is malformed
+    pub fn validate_parameters(parameters: &[ServiceParameter]) -> Result<(), String> {
+        for pair in parameters.windows(2) {
+            if pair[0].key() >= pair[1].key() {
+                return Err(format!(
+                    "{} appears more than once or out of order",
+                    ServiceParameter::key_mnemonic(pair[1].key())
+                ));
+            }
+        }
+        for parameter in parameters {
+            if let ServiceParameter::Mandatory(mandatory_keys) = parameter {
+                if mandatory_keys.contains(&ServiceParameter::MANDATORY) {
+                    return Err(String::from("mandatory can't list itself"));
+                }
+                if mandatory_keys.windows(2).any(|pair| pair[0] >= pair[1]) {
+                    return Err(String::from("mandatory keys must be unique"));
+                }
+                for key in mandatory_keys {
+                    if !parameters.iter().any(|parameter| parameter.key() == *key) {
+                        return Err(format!(
+                            "{} is mandatory but is missing",
+                            ServiceParameter::key_mnemonic(*key)
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> ServiceParameter<'a> {
+    pub const MANDATORY: u16 = 0;
+    pub const ALPN: u16 = 1;
+    pub const NO_DEFAULT_ALPN: u16 = 2;
+    pub const PORT: u16 = 3;
+    pub const IPV4_HINT: u16 = 4;
+    pub const ECH: u16 = 5;
+    pub const IPV6_HINT: u16 = 6;
+    // Reserved as an invalid key
+    const INVALID_KEY: u16 = 65535;
+
+    pub fn key(&self) -> u16 {
+        match self {
+            ServiceParameter::Mandatory(_) => Self::MANDATORY,
+            ServiceParameter::Alpn(_) => Self::ALPN,
+            ServiceParameter::NoDefaultAlpn => Self::NO_DEFAULT_ALPN,
+            ServiceParameter::Port(_) => Self::PORT,
+            ServiceParameter::Ipv4Hint(_) => Self::IPV4_HINT,
+            ServiceParameter::EncryptedClientHello(_) => Self::ECH,
+            ServiceParameter::Ipv6Hint(_) => Self::IPV6_HINT,
+            ServiceParameter::Unknown { key, .. } => *key,
+        }
+    }
+
+    /// The presentation name of a key, keys without a name are written as keyNNNNN
+    pub fn key_mnemonic(key: u16) -> Cow<'static, str> {
+        let mnemonic = match key {
+            Self::MANDATORY => "mandatory",
+            Self::ALPN => "alpn",
+            Self::NO_DEFAULT_ALPN => "no-default-alpn",
+            Self::PORT => "port",
+            Self::IPV4_HINT => "ipv4hint",
+            Self::ECH => "ech",
+            Self::IPV6_HINT => "ipv6hint",
+            _ => return Cow::Owned(format!("key{}", key)),
+        };
+        Cow::Borrowed(mnemonic)
+    }
+
+    pub fn key_from_mnemonic(text: &str) -> Option<u16> {
+        let key = match text {
+            "mandatory" => Self::MANDATORY,
+            "alpn" => Self::ALPN,
+            "no-default-alpn" => Self::NO_DEFAULT_ALPN,
+            "port" => Self::PORT,
+            "ipv4hint" => Self::IPV4_HINT,
+            "ech" => Self::ECH,
+            "ipv6hint" => Self::IPV6_HINT,
+            _ => {
+                let digits = text.strip_prefix("key")?;
+                // Leading zeros are not allowed so every key has exactly one form
+                if digits.starts_with('0') && digits != "0" {
+                    return None;
+                }
+                digits.parse().ok()?
+            }
+        };
+        match key {
+            Self::INVALID_KEY => None,
+            key => Some(key),
+        }
+    }
+
+    /// Decodes a value in wire format, returns None if the value is malformed for its key
+    pub fn from_wire(key: u16, value: &'a [u8]) -> Option<ServiceParameter<'a>> {
+        let parameter = match key {
+            Self::MANDATORY if !value.is_empty() && value.len().is_multiple_of(2) => {
+                ServiceParameter::Mandatory(
+                    value
+                        .chunks(2)
+                        .map(|key| u16::from_be_bytes([key[0], key[1]]))
+                        .collect(),
+                )
+            }
+            Self::ALPN => {
+                let mut protocols = Vec::new();
+                let mut offset = 0;
+                while offset < value.len() {
+                    let length = value[offset] as usize;
+                    let protocol = value.get(offset + 1..offset + 1 + length)?;
+                    if protocol.is_empty() {
+                        return None;
+                    }
+                    protocols.push(Cow::Borrowed(protocol));
+                    offset += 1 + length;
+                }
+                if protocols.is_empty() {
+                    return None;
+                }
+                ServiceParameter::Alpn(protocols)
+            }
+            Self::NO_DEFAULT_ALPN if value.is_empty() => ServiceParameter::NoDefaultAlpn,
+            Self::PORT if value.len() == 2 => {
+                ServiceParameter::Port(u16::from_be_bytes([value[0], value[1]]))
+            }
+            Self::IPV4_HINT if !value.is_empty() && value.len().is_multiple_of(4) => {
+                ServiceParameter::Ipv4Hint(
+                    value
+                        .chunks(4)
+                        .map(|octets| Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]))
+                        .collect(),
+                )
+            }
+            Self::ECH => ServiceParameter::EncryptedClientHello(Cow::Borrowed(value)),
+            Self::IPV6_HINT if !value.is_empty() && value.len().is_multiple_of(16) => {
+                ServiceParameter::Ipv6Hint(
+                    value
+                        .chunks(16)
+                        .map(|chunk| {
+                            let mut octets = [0u8; 16];
+                            octets.copy_from_slice(chunk);
+                            Ipv6Addr::from(octets)
+                        })
+                        .collect(),
+                )
+            }
+            Self::MANDATORY
+            | Self::NO_DEFAULT_ALPN
+            | Self::PORT
+            | Self::IPV4_HINT
+            | Self::IPV6_HINT
+            | Self::INVALID_KEY => return None,
+            key => ServiceParameter::Unknown {
+                key,
+                value: Cow::Borrowed(value),
+            },
+        };
+        Some(parameter)
+    }
+
+    /// Decodes a value in presentation format, the zone file escapes must already have been removed
+    pub fn from_presentation(
+        key: u16,
+        value: Option<&[u8]>,
+    ) -> Result<ServiceParameter<'static>, String> {
+        let name = Self::key_mnemonic(key);
+        let required = || format!("{} requires a value", name);
+        let text = |value: &[u8]| {
+            String::from_utf8(value.to_vec()).map_err(|_| format!("{} is not valid", name))
+        };
+        let parameter = match key {
+            Self::MANDATORY => {
+                let mut keys = split_value_list(value.ok_or_else(required)?)?
+                    .iter()
+                    .map(|item| {
+                        text(item).and_then(|item| {
+                            Self::key_from_mnemonic(&item)
+                                .ok_or_else(|| format!("{} is not a valid SvcParamKey", item))
+                        })
+                    })
+                    .collect::<Result<Vec<u16>, String>>()?;
+                keys.sort_unstable();
+                ServiceParameter::Mandatory(keys)
+            }
+            Self::ALPN => {
+                let protocols = split_value_list(value.ok_or_else(required)?)?;
+                if protocols
+                    .iter()
+                    .any(|protocol| protocol.is_empty() || protocol.len() > 255)
+                {
+                    return Err(String::from(
+                        "alpn protocols must be between 1 and 255 bytes",
+                    ));
+                }
+                ServiceParameter::Alpn(protocols.into_iter().map(Cow::Owned).collect())
+            }
+            Self::NO_DEFAULT_ALPN => match value {
+                None => ServiceParameter::NoDefaultAlpn,
+                Some(_) => return Err(String::from("no-default-alpn can't have a value")),
+            },
+            Self::PORT => {
+                let port = text(value.ok_or_else(required)?)?;
+                ServiceParameter::Port(
+                    port.parse()
+                        .map_err(|_| format!("{} is not a valid port", port))?,
+                )
+            }
+            Self::IPV4_HINT => {
+                ServiceParameter::Ipv4Hint(read_address_list(&text(value.ok_or_else(required)?)?)?)
+            }
+            Self::ECH => {
+                let config = text(value.ok_or_else(required)?)?;
+                let config = decode_base64(&config).ok_or("ech is not valid base64")?;
+                ServiceParameter::EncryptedClientHello(Cow::Owned(config))
+            }
+            Self::IPV6_HINT => {
+                ServiceParameter::Ipv6Hint(read_address_list(&text(value.ok_or_else(required)?)?)?)
+            }
+            key => ServiceParameter::Unknown {
+                key,
+                value: Cow::Owned(value.unwrap_or_default().to_vec()),
+            },
+        };
+        Ok(parameter)
+    }
+
+    /// Appends the value in wire format, the key and length are not included
+    pub fn write_value(&self, data: &mut Vec<u8>) {
+        match self {
+            ServiceParameter::Mandatory(keys) => {
+                for key in keys {
+                    data.extend_from_slice(&key.to_be_bytes());
+                }
+            }
+            ServiceParameter::Alpn(protocols) => {
+                for protocol in protocols {
+                    data.push(protocol.len() as u8);
+                    data.extend_from_slice(protocol);
+                }
+            }
+            ServiceParameter::NoDefaultAlpn => (),
+            ServiceParameter::Port(port) => data.extend_from_slice(&port.to_be_bytes()),
+            ServiceParameter::Ipv4Hint(addresses) => {
+                for address in addresses {
+                    data.extend_from_slice(&address.octets());
+                }
+            }
+            ServiceParameter::EncryptedClientHello(config) => data.extend_from_slice(config),
+            ServiceParameter::Ipv6Hint(addresses) => {
+                for address in addresses {
+                    data.extend_from_slice(&address.octets());
+                }
+            }
+            ServiceParameter::Unknown { value, .. } => data.extend_from_slice(value),
+        }
+    }
+
+    pub fn into_owned(self) -> ServiceParameter<'static> {
+        match self {
+            ServiceParameter::Mandatory(keys) => ServiceParameter::Mandatory(keys),
+            ServiceParameter::Alpn(protocols) => ServiceParameter::Alpn(
+                protocols
+                    .into_iter()
+                    .map(|protocol| Cow::Owned(protocol.into_owned()))
+                    .collect(),
+            ),
+            ServiceParameter::NoDefaultAlpn => ServiceParameter::NoDefaultAlpn,
+            ServiceParameter::Port(port) => ServiceParameter::Port(port),
+            ServiceParameter::Ipv4Hint(addresses) => ServiceParameter::Ipv4Hint(addresses),
+            ServiceParameter::EncryptedClientHello(config) => {
+                ServiceParameter::EncryptedClientHello(Cow::Owned(config.into_owned()))
+            }
+            ServiceParameter::Ipv6Hint(addresses) => ServiceParameter::Ipv6Hint(addresses),
+            ServiceParameter::Unknown { key, value } => ServiceParameter::Unknown {
+                key,
+                value: Cow::Owned(value.into_owned()),
+            },
+        }
+    }
+}
+
+/// Splits a comma separated value list, \, is a comma inside an item and \\ is a backslash
+fn split_value_list(value: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let mut items = Vec::new();
+    let mut item = Vec::new();
+    let mut bytes = value.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'\\' => item.push(
+                *bytes
+                    .next()
+                    .ok_or("A value list can't end with a backslash")?,
+            ),
+            b',' => items.push(std::mem::take(&mut item)),
+            _ => item.push(byte),
+        }
+    }
+    items.push(item);
+    Ok(items)
+}
+
+fn read_address_list<A: std::str::FromStr>(value: &str) -> Result<Vec<A>, String> {
+    value
+        .split(',')
+        .map(|address| {
+            address
+                .parse()
+                .map_err(|_| format!("{} is not a valid address", address))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{
+        builders::DnsResponseBuilder, DnsParser, Resource, ResourceClass, ResourcePayload,
+    };
+
+    fn https_resource() -> Resource<'static> {
+        let binding = ServiceBinding::new(
+            1,
+            DomainName::root(),
+            vec![
+                ServiceParameter::Ipv6Hint(vec!["2001:db8::1".parse().unwrap()]),
+                ServiceParameter::Alpn(vec![Cow::Borrowed(b"h2"), Cow::Borrowed(b"h3")]),
+                ServiceParameter::Mandatory(vec![ServiceParameter::ALPN]),
+                ServiceParameter::Port(8443),
+                ServiceParameter::Ipv4Hint(vec![Ipv4Addr::new(192, 0, 2, 1)]),
+                ServiceParameter::EncryptedClientHello(Cow::Borrowed(&[0xfe, 0x0d, 0x00])),
+                ServiceParameter::Unknown {
+                    key: 667,
+                    value: Cow::Borrowed(b"hello"),
+                },
+            ],
+        );
+        Resource::new(
+            DomainName::new(vec!["example", "com"]),
+            ResourceClass::Internet,
+            300,
+            ResourcePayload::HttpsBinding(binding),
+        )
+    }
+
+    #[test]
+    fn test_wire_round_trip() {
+        let resource = https_resource();
+        let mut builder = DnsResponseBuilder::new(3);
+        builder.add_answer(resource.clone());
+        let packet_data = builder.build_response().unwrap();
+        let mut parser = DnsParser::new();
+        let packet = parser.parse_packet(&packet_data).unwrap();
+        assert_eq!(packet.answers, vec![resource]);
+    }
+
+    #[test]
+    fn test_malformed_parameters() {
+        // port then alpn is out of order
+        let unordered = [0, 3, 0, 2, 1, 187, 0, 1, 0, 3, 2, b'h', b'2'];
+        assert!(ServiceBinding::read_parameters(&unordered).is_err());
+        // alpn is mandatory but missing
+        let missing = [0, 0, 0, 2, 0, 1, 0, 3, 0, 2, 1, 187];
+        assert!(ServiceBinding::read_parameters(&missing).is_err());
+        // A port must be exactly two bytes
+        let short_port = [0, 3, 0, 1, 1];
+        assert!(ServiceBinding::read_parameters(&short_port).is_err());
+        let valid = [0, 1, 0, 3, 2, b'h', b'2', 0, 3, 0, 2, 1, 187];
+        assert_eq!(
+            ServiceBinding::read_parameters(&valid).unwrap(),
+            vec![
+                ServiceParameter::Alpn(vec![Cow::Borrowed(b"h2")]),
+                ServiceParameter::Port(443)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_remove_parameters() {
+        // Policy rules rewrite the binding in place through the payload
+        let mut payload = https_resource().payload().clone();
+        let binding = payload.service_binding_mut().unwrap();
+        assert!(!binding.is_alias());
+        assert_eq!(
+            binding.parameter(ServiceParameter::PORT),
+            Some(&ServiceParameter::Port(8443))
+        );
+        let hints = [
+            ServiceParameter::ECH,
+            ServiceParameter::IPV4_HINT,
+            ServiceParameter::IPV6_HINT,
+        ];
+        assert!(binding.remove_parameters(&hints));
+        assert!(!binding.remove_parameters(&hints));
+        assert_eq!(binding.parameter(ServiceParameter::ECH), None);
+        let keys: Vec<u16> = binding.parameters().iter().map(|p| p.key()).collect();
+        assert_eq!(keys, vec![0, 1, 3, 667]);
+        // Removing alpn also removes it from mandatory, leaving mandatory empty
+        binding.remove_parameters(&[ServiceParameter::ALPN]);
+        let keys: Vec<u16> = binding.parameters().iter().map(|p| p.key()).collect();
+        assert_eq!(keys, vec![3, 667]);
+        assert!(ServiceBinding::validate_parameters(binding.parameters()).is_ok());
+        assert!(ResourcePayload::Address(Ipv4Addr::LOCALHOST)
+            .service_binding_mut()
+            .is_none());
+    }
+}
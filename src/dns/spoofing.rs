@@ -0,0 +1,134 @@
+use crate::error::{Error, ErrorKind};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::ops::Range;
+
+/// A transaction ID an off path attacker can't predict, RFC 5452 section 9.2
+pub fn random_id() -> Result<u16, Error> {
+    let mut id = [0u8; 2];
+    SystemRandom::new()
+        .fill(&mut id)
+        .map_err(|_| Error::new(ErrorKind::WritePacketDataFailed))?;
+    Ok(u16::from_be_bytes(id))
+}
+
+/// Where the question section of a message is, without parsing the message
+/// Names in the question section are never compressed, a message where they are has no question section we accept
+pub fn question_section(message: &[u8]) -> Option<Range<usize>> {
+    let count = u16::from_be_bytes([*message.get(4)?, *message.get(5)?]);
+    let mut position = 12;
+    for _ in 0..count {
+        loop {
+            let length = *message.get(position)? as usize;
+            position += length + 1;
+            match length {
+                0 => break,
+                1..=63 => (),
+                _ => return None,
+            }
+        }
+        position += 4;
+    }
+    Some(12..position).filter(|_| position <= message.len())
+}
+
+/// Flips the case of the letters in the question names at random, draft-vixie-dnsext-dns0x20
+/// A spoofed answer has to guess the case as well as the ID and the port
+pub fn randomize_case(message: &mut [u8]) -> Result<(), Error> {
+    let section = question_section(message).ok_or_else(|| Error::new(ErrorKind::InvalidLabel))?;
+    let mut random = vec![0u8; section.len()];
+    SystemRandom::new()
+        .fill(&mut random)
+        .map_err(|_| Error::new(ErrorKind::WritePacketDataFailed))?;
+    let mut position = section.start;
+    while position < section.end {
+        let length = message[position] as usize;
+        if length == 0 {
+            // The type and class follow the name
+            position += 5;
+            continue;
+        }
+        for index in position + 1..=position + length {
+            if message[index].is_ascii_alphabetic() && random[index - section.start] & 1 == 1 {
+                message[index] ^= 0x20;
+            }
+        }
+        position += length + 1;
+    }
+    Ok(())
+}
+
+/// Whether an answer echoes the question section of the query, RFC 5452 section 9.1
+/// The case of the names only has to match when it was randomized
+pub fn same_question(query: &[u8], answer: &[u8], exact_case: bool) -> bool {
+    let (asked, answered) = match (question_section(query), question_section(answer)) {
+        (Some(asked), Some(answered)) => (&query[asked], &answer[answered]),
+        _ => return false,
+    };
+    query[4..6] == answer[4..6]
+        && match exact_case {
+            true => asked == answered,
+            false => asked.eq_ignore_ascii_case(answered),
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A query for Example.COM A followed by an OPT record
+    const QUERY: &[u8] = &[
+        0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x07, b'E', b'x',
+        b'a', b'm', b'p', b'l', b'e', 0x03, b'C', b'O', b'M', 0x00, 0x00, 0x01, 0x00, 0x01, 0x00,
+        0x00, 0x29, 0x04, 0xD0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_question_section() {
+        assert_eq!(question_section(QUERY), Some(12..29));
+        assert_eq!(question_section(&QUERY[..20]), None);
+        // A compression pointer instead of a name
+        let mut compressed = QUERY[..12].to_vec();
+        compressed.extend_from_slice(&[0xC0, 0x0C, 0x00, 0x01, 0x00, 0x01]);
+        assert_eq!(question_section(&compressed), None);
+    }
+
+    #[test]
+    fn test_randomize_case() {
+        let mut randomized = QUERY.to_vec();
+        randomize_case(&mut randomized).unwrap();
+        assert!(randomized.eq_ignore_ascii_case(QUERY));
+        assert_eq!(&randomized[..12], &QUERY[..12]);
+        assert_eq!(&randomized[24..], &QUERY[24..]);
+        assert!(same_question(&randomized, QUERY, false));
+
+        // Ten letters agree by chance only once in a thousand tries
+        let randomizations = (0..8)
+            .map(|_| {
+                let mut randomized = QUERY.to_vec();
+                randomize_case(&mut randomized).unwrap();
+                randomized
+            })
+            .collect::<Vec<_>>();
+        assert!(randomizations.iter().any(|randomized| randomized != QUERY));
+        assert!(randomizations
+            .iter()
+            .any(|randomized| !same_question(randomized, QUERY, true)));
+    }
+
+    #[test]
+    fn test_same_question() {
+        let mut answer = QUERY[..29].to_vec();
+        answer[2] |= 0x80;
+        assert!(same_question(QUERY, &answer, true));
+        answer[14] = b'X';
+        assert!(same_question(QUERY, &answer, false));
+        assert!(!same_question(QUERY, &answer, true));
+        answer[14] = b'y';
+        assert!(!same_question(QUERY, &answer, false));
+        answer[14] = b'x';
+        // A different type
+        answer[26] = 0x1C;
+        assert!(!same_question(QUERY, &answer, false));
+        assert!(!same_question(QUERY, &QUERY[..12], false));
+    }
+}
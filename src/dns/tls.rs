@@ -0,0 +1,419 @@
+use super::{
+    proxy::lock, PinnedVerifier, StreamReader, StreamWriter, TlsReader, TlsUpstream, TlsWriter,
+};
+use crate::error::{Error, ErrorKind};
+use ring::digest::{digest, SHA256};
+use rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        WebPkiServerVerifier,
+    },
+    crypto::ring::default_provider,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    ClientConfig, ClientConnection, Connection, DigitallySignedStruct, RootCertStore, ServerConfig,
+    ServerConnection, SignatureScheme,
+};
+use std::{
+    convert::TryFrom,
+    io::{self, ErrorKind as IoErrorKind, Read, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// The ALPN protocol ID for DNS over TLS, RFC 7858 section 3.2
+pub const ALPN: &[u8] = b"dot";
+
+fn invalid(reason: String) -> Error {
+    Error::new(ErrorKind::InvalidConfiguration(reason))
+}
+
+fn invalid_data(error: rustls::Error) -> io::Error {
+    io::Error::new(IoErrorKind::InvalidData, error)
+}
+
+/// The contents of an element in DER and the position after it, only the short and long length forms are accepted
+fn der_element(data: &[u8], position: usize) -> Option<(u8, &[u8], usize)> {
+    let tag = *data.get(position)?;
+    let first = *data.get(position + 1)? as usize;
+    let (length, start) = match first {
+        0..=0x7F => (first, position + 2),
+        0x81..=0x84 => {
+            let octets = first & 0x7F;
+            let length = data
+                .get(position + 2..position + 2 + octets)?
+                .iter()
+                .fold(0usize, |length, byte| length << 8 | *byte as usize);
+            (length, position + 2 + octets)
+        }
+        _ => return None,
+    };
+    let end = start.checked_add(length)?;
+    Some((tag, data.get(start..end)?, end))
+}
+
+/// The DER of the SubjectPublicKeyInfo in a certificate, RFC 5280 section 4.1
+fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    let (_, certificate, _) = der_element(certificate, 0)?;
+    let (_, tbs_certificate, _) = der_element(certificate, 0)?;
+    let mut position = 0;
+    // The version is optional and explicitly tagged [0]
+    if der_element(tbs_certificate, 0)?.0 == 0xA0 {
+        position = der_element(tbs_certificate, 0)?.2;
+    }
+    // Skips the serial number, signature algorithm, issuer, validity and subject
+    for _ in 0..5 {
+        position = der_element(tbs_certificate, position)?.2;
+    }
+    let end = der_element(tbs_certificate, position)?.2;
+    Some(&tbs_certificate[position..end])
+}
+
+/// The pin of a certificate, the SHA-256 digest of its SubjectPublicKeyInfo, RFC 7469 section 2.4
+pub fn certificate_pin(certificate: &[u8]) -> Option<[u8; 32]> {
+    let info = subject_public_key_info(certificate)?;
+    let mut pin = [0u8; 32];
+    pin.copy_from_slice(digest(&SHA256, info).as_ref());
+    Some(pin)
+}
+
+fn read_certificates(file: &Path) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let unreadable = |_| invalid(format!("{} has no readable certificates", file.display()));
+    let certificates = CertificateDer::pem_file_iter(file)
+        .map_err(unreadable)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(unreadable)?;
+    if certificates.is_empty() {
+        return Err(invalid(format!("{} has no certificates", file.display())));
+    }
+    Ok(certificates)
+}
+
+/// The configuration clients are answered with, from PEM files, offering these ALPN protocols
+pub fn server_config(
+    certificate: &Path,
+    private_key: &Path,
+    protocols: &[&[u8]],
+) -> Result<Arc<ServerConfig>, Error> {
+    let certificates = read_certificates(certificate)?;
+    let private_key = PrivateKeyDer::from_pem_file(private_key)
+        .map_err(|_| invalid(format!("{} has no private key", private_key.display())))?;
+    let mut config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|error| invalid(error.to_string()))?
+        .with_no_client_auth()
+        .with_single_cert(certificates, private_key)
+        .map_err(|error| invalid(format!("{}: {}", certificate.display(), error)))?;
+    config.alpn_protocols = protocols.iter().map(|protocol| protocol.to_vec()).collect();
+    Ok(Arc::new(config))
+}
+
+/// Completes the handshake on a socket whose timeouts are already set
+fn handshake(
+    mut session: Connection,
+    socket: TcpStream,
+) -> Result<(StreamReader, StreamWriter), Error> {
+    let failed = |error: io::Error| Error::new(ErrorKind::ConnectionFailed(error.to_string()));
+    while session.is_handshaking() {
+        session.complete_io(&mut &socket).map_err(failed)?;
+    }
+    let session = Arc::new(Mutex::new(session));
+    let reader = TlsReader {
+        session: session.clone(),
+        socket: socket.try_clone().map_err(failed)?,
+    };
+    let writer = TlsWriter { session, socket };
+    Ok((StreamReader::Tls(reader), StreamWriter::Tls(writer)))
+}
+
+/// Takes a client through the server side of the handshake
+pub fn accept(
+    socket: TcpStream,
+    config: Arc<ServerConfig>,
+) -> Result<(StreamReader, StreamWriter), Error> {
+    let session = ServerConnection::new(config)
+        .map_err(|error| Error::new(ErrorKind::ConnectionFailed(error.to_string())))?;
+    handshake(Connection::Server(session), socket)
+}
+
+/// Takes an upstream through the client side of the handshake, which checks its certificate
+pub fn connect(
+    socket: TcpStream,
+    config: Arc<ClientConfig>,
+    name: ServerName<'static>,
+) -> Result<(StreamReader, StreamWriter), Error> {
+    let session = ClientConnection::new(config, name)
+        .map_err(|error| Error::new(ErrorKind::ConnectionFailed(error.to_string())))?;
+    handshake(Connection::Client(session), socket)
+}
+
+impl TlsUpstream {
+    /// An upstream with this name, it needs certificate authorities or pins before it can be used
+    pub fn new(name: &str) -> TlsUpstream {
+        TlsUpstream {
+            name: String::from(name),
+            certificate_authorities: None,
+            pins: Vec::new(),
+        }
+    }
+
+    /// The certificate has to chain to one of these and be valid for the name, RFC 8310 section 8.1
+    pub fn certificate_authorities(&mut self, file: PathBuf) -> &mut Self {
+        self.certificate_authorities = Some(file);
+        self
+    }
+
+    /// Adds to the pin set, a certificate matching none of them is rejected, RFC 7858 section 4.2
+    pub fn pin(&mut self, pin: [u8; 32]) -> &mut Self {
+        self.pins.push(pin);
+        self
+    }
+
+    /// The configuration the upstream is connected with and the name it is connected to
+    pub fn client_config(&self) -> Result<(Arc<ClientConfig>, ServerName<'static>), Error> {
+        let name = ServerName::try_from(self.name.clone())
+            .map_err(|_| invalid(format!("{} is not a server name", self.name)))?;
+        if self.certificate_authorities.is_none() && self.pins.is_empty() {
+            return Err(invalid(format!(
+                "{} needs certificate authorities or pins",
+                self.name
+            )));
+        }
+        let provider = Arc::new(default_provider());
+        let authorities = match &self.certificate_authorities {
+            Some(file) => {
+                let mut roots = RootCertStore::empty();
+                for certificate in read_certificates(file)? {
+                    roots
+                        .add(certificate)
+                        .map_err(|error| invalid(format!("{}: {}", file.display(), error)))?;
+                }
+                let verifier =
+                    WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                        .build()
+                        .map_err(|error| invalid(format!("{}: {}", file.display(), error)))?;
+                Some(verifier)
+            }
+            None => None,
+        };
+        let verifier = PinnedVerifier {
+            authorities,
+            pins: self.pins.clone(),
+            provider: provider.clone(),
+        };
+        let mut config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|error| invalid(error.to_string()))?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth();
+        config.alpn_protocols = vec![ALPN.to_vec()];
+        Ok((Arc::new(config), name))
+    }
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if let Some(authorities) = &self.authorities {
+            authorities.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                now,
+            )?;
+        }
+        if self.pins.is_empty() {
+            return Ok(ServerCertVerified::assertion());
+        }
+        match certificate_pin(end_entity) {
+            Some(pin) if self.pins.contains(&pin) => Ok(ServerCertVerified::assertion()),
+            Some(_) => Err(rustls::Error::General(String::from(
+                "the certificate matches none of the pins",
+            ))),
+            None => Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::BadEncoding,
+            )),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            certificate,
+            signature,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            certificate,
+            signature,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+impl TlsReader {
+    /// Reads records off the socket until there is plaintext to read, returns false once the peer has closed
+    /// Socket timeouts are passed on, so a reader can wait in short steps
+    fn fill(&mut self) -> io::Result<bool> {
+        let mut buffer = [0u8; 4096];
+        loop {
+            let state = lock(&self.session)
+                .process_new_packets()
+                .map_err(invalid_data)?;
+            if state.plaintext_bytes_to_read() > 0 {
+                return Ok(true);
+            }
+            if state.peer_has_closed() {
+                return Ok(false);
+            }
+            // The session isn't held while waiting so that answers can be written meanwhile
+            let size = self.socket.read(&mut buffer)?;
+            if size == 0 {
+                return Ok(false);
+            }
+            let mut session = lock(&self.session);
+            let mut received = &buffer[..size];
+            while !received.is_empty() {
+                session.read_tls(&mut received)?;
+                if let Err(error) = session.process_new_packets() {
+                    // Lets the peer know why with an alert
+                    let _ = session.write_tls(&mut &self.socket);
+                    return Err(invalid_data(error));
+                }
+            }
+            // Key updates and alerts are answered straight away
+            while session.wants_write() {
+                session.write_tls(&mut &self.socket)?;
+            }
+        }
+    }
+}
+
+impl StreamReader {
+    /// Waits for the next message to start arriving, returns false once the peer has closed
+    /// Gives up with the socket's timeout error when nothing arrives in time
+    pub fn wait(&mut self) -> io::Result<bool> {
+        match self {
+            StreamReader::Tcp(stream) => stream.peek(&mut [0u8]).map(|size| size > 0),
+            StreamReader::Tls(reader) => reader.fill(),
+        }
+    }
+
+    /// The protocol agreed on in the handshake, None over plain TCP or when the client offered none
+    pub fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        match self {
+            StreamReader::Tcp(_) => None,
+            StreamReader::Tls(reader) => lock(&reader.session).alpn_protocol().map(<[u8]>::to_vec),
+        }
+    }
+
+    /// Sets the read timeout of the socket underneath, both halves of a connection share it
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            StreamReader::Tcp(stream) => stream.set_read_timeout(timeout),
+            StreamReader::Tls(reader) => reader.socket.set_read_timeout(timeout),
+        }
+    }
+}
+
+impl Read for StreamReader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self {
+            StreamReader::Tcp(stream) => stream.read(buffer),
+            StreamReader::Tls(reader) => match reader.fill()? {
+                true => lock(&reader.session).reader().read(buffer),
+                false => Ok(0),
+            },
+        }
+    }
+}
+
+impl Write for StreamWriter {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        match self {
+            StreamWriter::Tcp(stream) => stream.write(buffer),
+            StreamWriter::Tls(writer) => {
+                let mut session = lock(&writer.session);
+                let size = session.writer().write(buffer)?;
+                while session.wants_write() {
+                    session.write_tls(&mut &writer.socket)?;
+                }
+                Ok(size)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            StreamWriter::Tcp(stream) => stream.flush(),
+            StreamWriter::Tls(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::decode_base64;
+
+    #[test]
+    fn test_certificate_pin() {
+        let certificate = read_certificates(Path::new("testdata/keys/tls-server.pem")).unwrap();
+        // openssl x509 -pubkey | openssl pkey -pubin -outform der | openssl dgst -sha256 -binary | base64
+        let expected = decode_base64("YMmNj85cTjqPhcL34fe36XRtfNDsOxIWmYI64VHaDAs=").unwrap();
+        assert_eq!(certificate_pin(&certificate[0]).unwrap().to_vec(), expected);
+        assert_eq!(certificate_pin(&certificate[0][..100]), None);
+    }
+
+    #[test]
+    fn test_client_config() {
+        assert!(TlsUpstream::new("dns.example.com").client_config().is_err());
+        assert!(TlsUpstream::new("not a name")
+            .pin([0u8; 32])
+            .client_config()
+            .is_err());
+        assert!(TlsUpstream::new("dns.example.com")
+            .certificate_authorities(PathBuf::from("testdata/keys/missing.pem"))
+            .client_config()
+            .is_err());
+        assert!(TlsUpstream::new("127.0.0.1")
+            .certificate_authorities(PathBuf::from("testdata/keys/tls-ca.pem"))
+            .client_config()
+            .is_ok());
+        assert!(server_config(
+            Path::new("testdata/keys/tls-server.pem"),
+            Path::new("testdata/keys/tls-ca.pem"),
+            &[ALPN]
+        )
+        .is_err());
+    }
+}
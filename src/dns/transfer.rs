@@ -0,0 +1,1068 @@
+use super::{
+    builders::{DnsResponseBuilder, PacketWriter},
+    framing, spoofing, DnsPacket, DnsParser, DomainName, Header, Keyring, PrimaryZone, Question,
+    QuestionClass, QuestionType, Resource, ResourcePayload, ResponseCode, SecondaryZone,
+    TransferOutcome, TransferServer, TsigKey, TsigSession, UpdatePolicy, ZoneDiff,
+};
+use crate::error::{Error, ErrorKind};
+use std::{
+    net::{IpAddr, SocketAddr, TcpStream},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Records are packed into messages up to this size, well below the 65535 bytes TCP framing allows
+const MAXIMUM_MESSAGE_SIZE: usize = 16_384;
+/// How many diffs a primary keeps for IXFR, secondaries with older serials get the whole zone
+const MAXIMUM_HISTORY: usize = 64;
+/// How long a secondary waits on its primary before the transfer counts as failed
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(30);
+/// Used instead of the SOA retry timer until the zone has been transferred once
+const INITIAL_RETRY: u32 = 60;
+
+/// Whether serial is newer than other using serial number arithmetic, RFC 1982
+pub fn serial_is_newer(serial: u32, other: u32) -> bool {
+    serial != other && (serial.wrapping_sub(other) as i32) > 0
+}
+
+/// Whether two records hold the same data, the TTL is ignored as RFC 1995 deletions match on data alone
+fn same_data(first: &Resource, second: &Resource) -> bool {
+    first.resource_name.eq_ignore_case(&second.resource_name)
+        && first.resource_class == second.resource_class
+        && first.payload == second.payload
+}
+
+fn serial(record: &Resource) -> Option<u32> {
+    match record.payload {
+        ResourcePayload::StartAuthority { serial, .. } => Some(serial),
+        _ => None,
+    }
+}
+
+/// The SOA at the apex of a zone
+fn start_authority<'r>(
+    zone: &DomainName,
+    records: &'r [Resource<'static>],
+) -> Option<&'r Resource<'static>> {
+    records
+        .iter()
+        .find(|record| serial(record).is_some() && record.resource_name.eq_ignore_case(zone))
+}
+
+impl ZoneDiff {
+    pub fn new(
+        from: Resource<'static>,
+        removed: Vec<Resource<'static>>,
+        to: Resource<'static>,
+        added: Vec<Resource<'static>>,
+    ) -> ZoneDiff {
+        ZoneDiff {
+            from,
+            removed,
+            to,
+            added,
+        }
+    }
+
+    /// Works out the records removed and added between two versions of a zone, including TTL changes
+    pub fn between(
+        zone: &DomainName,
+        old: &[Resource<'static>],
+        new: &[Resource<'static>],
+    ) -> Result<ZoneDiff, Error> {
+        let missing = |version| {
+            Error::new(ErrorKind::InvalidZone(format!(
+                "the {} version of {} has no SOA",
+                version, zone
+            )))
+        };
+        let from = start_authority(zone, old).ok_or_else(|| missing("old"))?;
+        let to = start_authority(zone, new).ok_or_else(|| missing("new"))?;
+        let only_in = |records: &[Resource<'static>], other: &[Resource<'static>]| {
+            records
+                .iter()
+                .filter(|record| serial(record).is_none())
+                .filter(|record| {
+                    !other.iter().any(|other| {
+                        same_data(record, other) && record.time_to_live == other.time_to_live
+                    })
+                })
+                .cloned()
+                .collect()
+        };
+        Ok(ZoneDiff::new(
+            from.clone(),
+            only_in(old, new),
+            to.clone(),
+            only_in(new, old),
+        ))
+    }
+
+    /// Applies the diff to records holding the from SOA, fails if a removed record isn't there
+    pub fn apply(&self, records: &mut Vec<Resource<'static>>) -> Result<(), Error> {
+        let mismatch = |reason: String| Err(Error::new(ErrorKind::TransferFailed(reason)));
+        let position = match records
+            .iter()
+            .position(|record| same_data(record, &self.from))
+        {
+            Some(position) => position,
+            None => return mismatch(format!("the zone doesn't hold the SOA {}", self.from)),
+        };
+        records[position] = self.to.clone();
+        for removed in &self.removed {
+            match records.iter().position(|record| same_data(record, removed)) {
+                Some(position) => {
+                    records.remove(position);
+                }
+                None => return mismatch(format!("{} can't be removed as it isn't there", removed)),
+            }
+        }
+        for added in &self.added {
+            records.retain(|record| !same_data(record, added));
+            records.push(added.clone());
+        }
+        Ok(())
+    }
+}
+
+impl PrimaryZone {
+    /// Creates a zone from its records, which must include a SOA at the apex
+    pub fn new(
+        zone: DomainName<'static>,
+        records: Vec<Resource<'static>>,
+    ) -> Result<PrimaryZone, Error> {
+        if start_authority(&zone, &records).is_none() {
+            return Err(Error::new(ErrorKind::InvalidZone(format!(
+                "{} has no SOA",
+                zone
+            ))));
+        }
+        Ok(PrimaryZone {
+            zone,
+            records,
+            history: Vec::new(),
+            secondaries: Vec::new(),
+            update_policy: UpdatePolicy::Refused,
+            journal: None,
+        })
+    }
+
+    pub fn zone(&self) -> &DomainName<'static> {
+        &self.zone
+    }
+
+    pub fn records(&self) -> &[Resource<'static>] {
+        &self.records
+    }
+
+    pub fn start_authority(&self) -> &Resource<'static> {
+        start_authority(&self.zone, &self.records).expect("A primary zone always has a SOA")
+    }
+
+    pub fn serial(&self) -> u32 {
+        serial(self.start_authority()).expect("The SOA holds a serial")
+    }
+
+    /// Replaces the records of the zone, the difference is kept so secondaries can catch up with IXFR
+    /// The serial of the new SOA must be newer than the current one
+    pub fn update(&mut self, records: Vec<Resource<'static>>) -> Result<(), Error> {
+        let diff = ZoneDiff::between(&self.zone, &self.records, &records)?;
+        let new_serial = serial(&diff.to).unwrap_or_default();
+        if !serial_is_newer(new_serial, self.serial()) {
+            return Err(Error::new(ErrorKind::InvalidZone(format!(
+                "the serial of {} must increase past {} but is {}",
+                self.zone,
+                self.serial(),
+                new_serial
+            ))));
+        }
+        if let Some(journal) = &self.journal {
+            diff.write_journal(journal)?;
+        }
+        self.remember(diff);
+        self.records = records;
+        Ok(())
+    }
+
+    /// Keeps every change in a journal file, the changes already in it that are newer than the records are applied first
+    pub fn journal<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut Self, Error> {
+        let path = path.as_ref();
+        for diff in ZoneDiff::read_journal(path)? {
+            // The zone file was written after this change
+            if !serial_is_newer(serial(&diff.to).unwrap_or_default(), self.serial()) {
+                continue;
+            }
+            diff.apply(&mut self.records)?;
+            self.remember(diff);
+        }
+        self.journal = Some(path.to_path_buf());
+        Ok(self)
+    }
+
+    fn remember(&mut self, diff: ZoneDiff) {
+        self.history.push(diff);
+        if self.history.len() > MAXIMUM_HISTORY {
+            self.history.remove(0);
+        }
+    }
+
+    /// The diffs that move a copy of the zone at serial to the current version, None when they are no longer kept
+    pub fn diffs_since(&self, serial: u32) -> Option<&[ZoneDiff]> {
+        let start = self
+            .history
+            .iter()
+            .position(|diff| self::serial(&diff.from) == Some(serial))?;
+        Some(&self.history[start..])
+    }
+
+    /// The records of an AXFR, the SOA comes first and last
+    fn full_transfer(&self) -> Vec<&Resource<'static>> {
+        let start_authority = self.start_authority();
+        let mut records = vec![start_authority];
+        records.extend(
+            self.records
+                .iter()
+                .filter(|record| serial(record).is_none()),
+        );
+        records.push(start_authority);
+        records
+    }
+
+    /// The records of an IXFR, each diff is the old SOA, removed records, the new SOA and added records
+    fn incremental_transfer<'z>(&'z self, diffs: &'z [ZoneDiff]) -> Vec<&'z Resource<'static>> {
+        let start_authority = self.start_authority();
+        let mut records = vec![start_authority];
+        for diff in diffs {
+            records.push(&diff.from);
+            records.extend(diff.removed.iter());
+            records.push(&diff.to);
+            records.extend(diff.added.iter());
+        }
+        records.push(start_authority);
+        records
+    }
+}
+
+impl TransferServer {
+    pub fn new() -> TransferServer {
+        TransferServer {
+            zones: Vec::new(),
+            allowed: Vec::new(),
+            keyring: None,
+        }
+    }
+
+    pub fn add_zone(&mut self, zone: PrimaryZone) -> &mut Self {
+        self.zones
+            .retain(|existing| !existing.zone.eq_ignore_case(&zone.zone));
+        self.zones.push(zone);
+        self
+    }
+
+    /// Stops answering for a zone and serving it
+    pub fn remove_zone(&mut self, zone: &DomainName) -> &mut Self {
+        self.zones
+            .retain(|existing| !existing.zone.eq_ignore_case(zone));
+        self
+    }
+
+    /// Allows an address to transfer zones, nobody is allowed by default
+    pub fn allow(&mut self, address: IpAddr) -> &mut Self {
+        self.allowed.push(address);
+        self
+    }
+
+    /// Requires transfer requests to be signed with a key from the keyring
+    pub fn keyring(&mut self, keyring: Keyring) -> &mut Self {
+        self.keyring = Some(keyring);
+        self
+    }
+
+    pub fn zone(&self, zone: &DomainName) -> Option<&PrimaryZone> {
+        self.zones
+            .iter()
+            .find(|primary| primary.zone.eq_ignore_case(zone))
+    }
+
+    pub fn zone_mut(&mut self, zone: &DomainName) -> Option<&mut PrimaryZone> {
+        self.zones
+            .iter_mut()
+            .find(|primary| primary.zone.eq_ignore_case(zone))
+    }
+
+    /// Answers an AXFR or IXFR request, returning every message of the answer in the order they are sent
+    pub fn respond(&self, request: &[u8], peer: IpAddr) -> Result<Vec<Vec<u8>>, Error> {
+        let packet = DnsParser::new().parse_packet(request)?;
+        // A signed request to a server without keys is answered with BADKEY
+        let no_keys = Keyring::new();
+        let mut session = self.keyring.as_ref().unwrap_or(&no_keys).session(&packet);
+        if let Some(session) = &mut session {
+            let _ = session.verify(request, &packet);
+        }
+        match self.transfer_records(&packet, peer, &session) {
+            Ok(records) => {
+                Self::pack_messages(&packet, &packet.questions[0], &records, &mut session)
+            }
+            Err(response_code) => Self::error_response(&packet, response_code, &mut session),
+        }
+    }
+
+    /// The records answering a transfer request, or the response code it is refused with
+    fn transfer_records(
+        &self,
+        packet: &DnsPacket,
+        peer: IpAddr,
+        session: &Option<TsigSession>,
+    ) -> Result<Vec<&Resource<'static>>, ResponseCode> {
+        let question = match packet.questions.as_slice() {
+            [question] => question,
+            _ => return Err(ResponseCode::FORMERR),
+        };
+        if session
+            .as_ref()
+            .is_some_and(|session| session.error().is_some())
+        {
+            return Err(ResponseCode::NOTAUTH);
+        }
+        if (self.keyring.is_some() && session.is_none()) || !self.allowed.contains(&peer) {
+            return Err(ResponseCode::REFUSED);
+        }
+        let zone = self
+            .zone(&question.domain_name)
+            .ok_or(ResponseCode::NOTAUTH)?;
+        match question.question_type {
+            QuestionType::TransferZone => Ok(zone.full_transfer()),
+            QuestionType::IncrementalTransfer => {
+                // RFC 1995 section 3, the client puts the SOA of its copy in the authority section
+                let client_serial = packet
+                    .authority
+                    .iter()
+                    .find_map(serial)
+                    .ok_or(ResponseCode::FORMERR)?;
+                if !serial_is_newer(zone.serial(), client_serial) {
+                    return Ok(vec![zone.start_authority()]);
+                }
+                Ok(match zone.diffs_since(client_serial) {
+                    Some(diffs) => zone.incremental_transfer(diffs),
+                    None => zone.full_transfer(),
+                })
+            }
+            _ => Err(ResponseCode::NOTIMP),
+        }
+    }
+
+    fn error_response(
+        packet: &DnsPacket,
+        response_code: ResponseCode,
+        session: &mut Option<TsigSession>,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let mut builder = DnsResponseBuilder::new(packet.header.id);
+        builder.response_code(response_code);
+        if let Some(question) = packet.questions.first() {
+            builder.add_question(question.clone());
+        }
+        let response = match session {
+            Some(session) => builder.build_signed_response(session)?,
+            None => builder.build_response()?,
+        };
+        Ok(vec![response])
+    }
+
+    /// Splits the records over as many messages as needed, only the first repeats the question
+    fn pack_messages(
+        packet: &DnsPacket,
+        question: &Question,
+        records: &[&Resource<'static>],
+        session: &mut Option<TsigSession>,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let mut messages = Vec::new();
+        let mut remaining = records;
+        while !remaining.is_empty() || messages.is_empty() {
+            let mut builder = DnsResponseBuilder::new(packet.header.id);
+            builder.authoritative(true);
+            let mut size = 12;
+            if messages.is_empty() {
+                builder.add_question(question.clone());
+                size += question.domain_name.wire_length() + 4;
+            }
+            let mut count = 0;
+            for record in remaining {
+                let mut writer = PacketWriter::new();
+                writer.write_resource(record)?;
+                size += writer.position();
+                // A record too big for a message of its own still goes out alone
+                if size > MAXIMUM_MESSAGE_SIZE && count > 0 {
+                    break;
+                }
+                builder.add_answer((*record).clone());
+                count += 1;
+            }
+            remaining = &remaining[count..];
+            let message = match session {
+                // RFC 8945 section 5.3.1, the messages in between need a TSIG only every hundredth message
+                Some(session) => {
+                    let message = builder.build_response()?;
+                    if remaining.is_empty() || session.skip_signature(&message).is_err() {
+                        builder.build_signed_response(session)?
+                    } else {
+                        message
+                    }
+                }
+                None => builder.build_response()?,
+            };
+            messages.push(message);
+        }
+        Ok(messages)
+    }
+}
+
+/// Collects the records of a transfer as they arrive, telling AXFR and IXFR answers apart, RFC 1995 section 4
+struct TransferReader {
+    // The serial of our copy, an incremental answer starts its first diff with it
+    current_serial: Option<u32>,
+    // Opens and closes the answer
+    start_authority: Option<Resource<'static>>,
+    // Decided by the second record of the answer
+    incremental: Option<bool>,
+    // The records of a full transfer, without the closing SOA
+    records: Vec<Resource<'static>>,
+    diffs: Vec<ZoneDiff>,
+    // Whether the SOA ending the deletions of the last diff has been seen
+    adding: bool,
+    finished: bool,
+}
+
+impl TransferReader {
+    fn new(current_serial: Option<u32>) -> TransferReader {
+        TransferReader {
+            current_serial,
+            start_authority: None,
+            incremental: None,
+            records: Vec::new(),
+            diffs: Vec::new(),
+            adding: false,
+            finished: false,
+        }
+    }
+
+    fn push(&mut self, record: Resource<'static>) -> Result<(), Error> {
+        let invalid = |reason: &str| Err(Error::new(ErrorKind::TransferFailed(reason.to_string())));
+        if self.finished {
+            return invalid("records follow the closing SOA");
+        }
+        let record_serial = serial(&record);
+        let final_serial = match &self.start_authority {
+            Some(start_authority) => serial(start_authority),
+            None if record_serial.is_some() => {
+                self.start_authority = Some(record.clone());
+                self.records.push(record);
+                return Ok(());
+            }
+            None => return invalid("the answer doesn't start with a SOA"),
+        };
+        let incremental = *self.incremental.get_or_insert(
+            record_serial.is_some()
+                && record_serial == self.current_serial
+                && record_serial != final_serial,
+        );
+        if !incremental {
+            match record_serial {
+                Some(_) if record_serial == final_serial => self.finished = true,
+                Some(_) => return invalid("a full transfer holds a second SOA"),
+                None => self.records.push(record),
+            }
+            return Ok(());
+        }
+        match (record_serial, self.diffs.last_mut()) {
+            (Some(_), Some(diff)) if !self.adding => {
+                diff.to = record;
+                self.adding = true;
+            }
+            (Some(_), Some(diff)) if serial(&diff.to) == final_serial => self.finished = true,
+            (Some(_), _) => {
+                self.diffs.push(ZoneDiff::new(
+                    record.clone(),
+                    Vec::new(),
+                    record,
+                    Vec::new(),
+                ));
+                self.adding = false;
+            }
+            (None, Some(diff)) if self.adding => diff.added.push(record),
+            (None, Some(diff)) => diff.removed.push(record),
+            (None, None) => return invalid("an incremental transfer holds records outside a diff"),
+        }
+        Ok(())
+    }
+
+    /// A lone SOA answering an IXFR means our copy is current
+    fn end_of_message(&mut self) -> bool {
+        let lone_start_authority = self.current_serial.is_some()
+            && self.incremental.is_none()
+            && self.start_authority.is_some();
+        if lone_start_authority {
+            self.finished = true;
+        }
+        lone_start_authority
+    }
+}
+
+impl SecondaryZone {
+    pub fn new(zone: DomainName<'static>, primary: SocketAddr) -> SecondaryZone {
+        SecondaryZone {
+            zone,
+            primary,
+            key: None,
+            records: Vec::new(),
+            last_success: None,
+            last_failure: None,
+            notified: false,
+            transfer_time: None,
+        }
+    }
+
+    /// Signs transfer requests with the key, the primary has to sign its answers with it too
+    pub fn key(&mut self, key: TsigKey) -> &mut Self {
+        self.key = Some(key);
+        self
+    }
+
+    pub fn zone(&self) -> &DomainName<'static> {
+        &self.zone
+    }
+
+    pub fn primary(&self) -> SocketAddr {
+        self.primary
+    }
+
+    /// The records of the zone, empty before the first transfer and once the zone has expired
+    pub fn records(&self) -> &[Resource<'static>] {
+        if self.is_expired() {
+            return &[];
+        }
+        &self.records
+    }
+
+    pub fn serial(&self) -> Option<u32> {
+        start_authority(&self.zone, &self.records).and_then(serial)
+    }
+
+    /// When the primary should next be checked, refresh after a success and retry after a failure, now after a NOTIFY
+    pub fn next_refresh(&self) -> u32 {
+        if self.notified {
+            return self.now();
+        }
+        let (refresh, retry) = match self.timers() {
+            Some((refresh, retry, _)) => (refresh, retry),
+            None => (0, INITIAL_RETRY),
+        };
+        match (self.last_success, self.last_failure) {
+            (_, Some(failure)) => failure.wrapping_add(retry),
+            (Some(success), None) => success.wrapping_add(refresh),
+            (None, None) => self.now(),
+        }
+    }
+
+    /// Whether the primary has been unreachable for longer than the SOA expire timer
+    pub fn is_expired(&self) -> bool {
+        match (self.last_success, self.timers()) {
+            (Some(success), Some((_, _, expire))) => self.now().wrapping_sub(success) >= expire,
+            _ => false,
+        }
+    }
+
+    /// Asks the primary for changes since our serial, falling back to a full transfer without a usable copy
+    pub fn refresh(&mut self) -> Result<TransferOutcome, Error> {
+        let result = self.transfer();
+        let now = self.now();
+        self.notified = false;
+        match result {
+            Ok(_) => {
+                self.last_success = Some(now);
+                self.last_failure = None;
+            }
+            Err(_) => self.last_failure = Some(now),
+        }
+        result
+    }
+
+    fn transfer(&mut self) -> Result<TransferOutcome, Error> {
+        let current_serial = if self.is_expired() {
+            None
+        } else {
+            self.serial()
+        };
+        let reader = self.request(current_serial)?;
+        if reader.incremental != Some(true) {
+            if reader.records.len() == 1 && current_serial.is_some() {
+                let primary_serial = serial(&reader.records[0]).unwrap_or_default();
+                if serial_is_newer(primary_serial, current_serial.unwrap_or_default()) {
+                    return Err(Error::new(ErrorKind::TransferFailed(String::from(
+                        "the primary has a newer serial but sent no changes",
+                    ))));
+                }
+                return Ok(TransferOutcome::UpToDate);
+            }
+            self.records = reader.records;
+            return Ok(TransferOutcome::Full);
+        }
+        let mut records = self.records.clone();
+        let applied = reader
+            .diffs
+            .iter()
+            .try_for_each(|diff| diff.apply(&mut records));
+        match applied {
+            Ok(()) => {
+                self.records = records;
+                Ok(TransferOutcome::Incremental)
+            }
+            // Our copy doesn't match what the primary thinks we hold, start again from scratch
+            Err(_) => {
+                let reader = self.request(None)?;
+                self.records = reader.records;
+                Ok(TransferOutcome::Full)
+            }
+        }
+    }
+
+    /// Sends an IXFR when we hold a copy at current_serial and an AXFR otherwise, reading the whole answer
+    fn request(&self, current_serial: Option<u32>) -> Result<TransferReader, Error> {
+        let connection_failed = |error: std::io::Error| {
+            Error::new(ErrorKind::ConnectionFailed(format!(
+                "{}: {}",
+                self.primary, error
+            )))
+        };
+        let mut stream = TcpStream::connect_timeout(&self.primary, TRANSFER_TIMEOUT)
+            .map_err(connection_failed)?;
+        stream
+            .set_read_timeout(Some(TRANSFER_TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(TRANSFER_TIMEOUT)))
+            .map_err(connection_failed)?;
+        let id = spoofing::random_id()?;
+        let question = Question {
+            domain_name: self.zone.clone(),
+            question_type: match current_serial {
+                Some(_) => QuestionType::IncrementalTransfer,
+                None => QuestionType::TransferZone,
+            },
+            question_class: QuestionClass::Internet,
+        };
+        let start_authority =
+            current_serial.and_then(|_| start_authority(&self.zone, &self.records));
+        let mut header = Header::new();
+        header.id = id;
+        header.question_count = 1;
+        header.authority_count = start_authority.is_some() as u16;
+        let mut request = Vec::with_capacity(512);
+        header.write_header(&mut request)?;
+        let mut writer = PacketWriter::with_data(request);
+        writer.write_question(&question)?;
+        if let Some(start_authority) = start_authority {
+            writer.write_resource(start_authority)?;
+        }
+        let mut request = writer.into_inner();
+        let mut session = self.key.clone().map(TsigSession::new);
+        if let Some(session) = &mut session {
+            session.sign(&mut request)?;
+        }
+        framing::write_message(&mut stream, &request)?;
+
+        let mut reader = TransferReader::new(current_serial);
+        while !reader.finished {
+            let message = framing::read_message(&mut stream)?.ok_or_else(|| {
+                Error::new(ErrorKind::TransferFailed(String::from(
+                    "the primary closed the connection part way through",
+                )))
+            })?;
+            let packet = match &mut session {
+                Some(session) => DnsParser::new().parse_signed_packet(&message, session)?,
+                None => DnsParser::new().parse_packet(&message)?,
+            };
+            if packet.header.id != id {
+                return Err(Error::new(ErrorKind::TransferFailed(String::from(
+                    "the answer is for a different request",
+                ))));
+            }
+            if packet.header.response_code != ResponseCode::NOERROR {
+                return Err(Error::new(ErrorKind::TransferFailed(format!(
+                    "the primary answered {:?}",
+                    packet.header.response_code
+                ))));
+            }
+            for record in packet.answers {
+                reader.push(record.into_owned())?;
+            }
+            reader.end_of_message();
+        }
+        // RFC 8945 section 5.3.1, the messages after the last TSIG could have come from anyone
+        if session.is_some_and(|session| !session.ends_signed()) {
+            return Err(Error::new(ErrorKind::MissingTransactionSignature));
+        }
+        Ok(reader)
+    }
+
+    /// The refresh, retry and expire timers of the SOA
+    fn timers(&self) -> Option<(u32, u32, u32)> {
+        match start_authority(&self.zone, &self.records)?.payload {
+            ResourcePayload::StartAuthority {
+                refresh,
+                retry,
+                expire,
+                ..
+            } => Some((refresh, retry, expire)),
+            _ => None,
+        }
+    }
+
+    fn now(&self) -> u32 {
+        self.transfer_time.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs() as u32)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{tests::name, zone::ZoneParser, ResourceClass, ResourceType, TsigAlgorithm};
+    use std::net::TcpListener;
+
+    const NOW: u32 = 1_700_000_000;
+
+    fn example_zone() -> Vec<Resource<'static>> {
+        ZoneParser::new(None)
+            .parse_file("zones/example.com.zone")
+            .unwrap()
+    }
+
+    /// The zone with a new serial, www moved to another address and a record added
+    fn next_version(records: &[Resource<'static>], serial: u32) -> Vec<Resource<'static>> {
+        let mut records = records.to_vec();
+        for record in &mut records {
+            if let ResourcePayload::StartAuthority { serial: old, .. } = &mut record.payload {
+                *old = serial;
+            }
+            if record.resource_name == name("www.example.com") {
+                record.payload =
+                    ResourcePayload::Address(format!("192.0.2.{}", serial % 200).parse().unwrap());
+            }
+        }
+        records.push(Resource::new(
+            name(&format!("host{}.example.com", serial)),
+            ResourceClass::Internet,
+            300,
+            ResourcePayload::Address("192.0.2.99".parse().unwrap()),
+        ));
+        records
+    }
+
+    fn request(question_type: QuestionType, client: Option<&Resource<'static>>) -> Vec<u8> {
+        let mut header = Header::new();
+        header.id = 7;
+        header.question_count = 1;
+        header.authority_count = client.is_some() as u16;
+        let mut request = Vec::new();
+        header.write_header(&mut request).unwrap();
+        let question = Question {
+            domain_name: name("example.com"),
+            question_type,
+            question_class: QuestionClass::Internet,
+        };
+        let mut writer = PacketWriter::with_data(request);
+        writer.write_question(&question).unwrap();
+        if let Some(client) = client {
+            writer.write_resource(client).unwrap();
+        }
+        writer.into_inner()
+    }
+
+    fn answers(messages: &[Vec<u8>]) -> Vec<Resource<'static>> {
+        messages
+            .iter()
+            .flat_map(|message| {
+                let packet = DnsParser::new().parse_packet(message).unwrap();
+                assert_eq!(packet.header.response_code, ResponseCode::NOERROR);
+                packet
+                    .answers
+                    .into_iter()
+                    .map(Resource::into_owned)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    fn response_code(messages: &[Vec<u8>]) -> ResponseCode {
+        assert_eq!(messages.len(), 1);
+        DnsParser::new()
+            .parse_packet(&messages[0])
+            .unwrap()
+            .header
+            .response_code
+    }
+
+    #[test]
+    fn test_primary_answers() {
+        let original = example_zone();
+        let local: IpAddr = "127.0.0.1".parse().unwrap();
+        let mut server = TransferServer::new();
+        server
+            .add_zone(PrimaryZone::new(name("example.com"), original.clone()).unwrap())
+            .allow(local);
+        let first_soa = server
+            .zone(&name("example.com"))
+            .unwrap()
+            .start_authority()
+            .clone();
+
+        let full = answers(
+            &server
+                .respond(&request(QuestionType::TransferZone, None), local)
+                .unwrap(),
+        );
+        assert_eq!(full.len(), original.len() + 1);
+        assert_eq!(full[0], first_soa);
+        assert_eq!(full[full.len() - 1], first_soa);
+
+        // A secondary with the current serial only gets the SOA back
+        let current = request(QuestionType::IncrementalTransfer, Some(&first_soa));
+        assert_eq!(
+            answers(&server.respond(&current, local).unwrap()),
+            std::slice::from_ref(&first_soa)
+        );
+
+        let zone = server.zone_mut(&name("example.com")).unwrap();
+        assert!(zone.update(next_version(&original, 2021020201)).is_err());
+        zone.update(next_version(&original, 2021020202)).unwrap();
+        let second_soa = zone.start_authority().clone();
+        let incremental = answers(&server.respond(&current, local).unwrap());
+        let www = |address: &str| {
+            Resource::new(
+                name("www.example.com"),
+                ResourceClass::Internet,
+                300,
+                ResourcePayload::Address(address.parse().unwrap()),
+            )
+        };
+        let added = Resource::new(
+            name("host2021020202.example.com"),
+            ResourceClass::Internet,
+            300,
+            ResourcePayload::Address("192.0.2.99".parse().unwrap()),
+        );
+        assert_eq!(
+            incremental,
+            [
+                second_soa.clone(),
+                first_soa.clone(),
+                www("192.0.2.10"),
+                second_soa.clone(),
+                www("192.0.2.2"),
+                added,
+                second_soa.clone()
+            ]
+        );
+
+        // Serials the primary has no history for get the whole zone
+        let mut unknown = first_soa.clone();
+        if let ResourcePayload::StartAuthority { serial, .. } = &mut unknown.payload {
+            *serial = 1;
+        }
+        let unknown = request(QuestionType::IncrementalTransfer, Some(&unknown));
+        assert_eq!(
+            answers(&server.respond(&unknown, local).unwrap()).len(),
+            original.len() + 2
+        );
+
+        let stranger: IpAddr = "192.0.2.50".parse().unwrap();
+        let transfer = request(QuestionType::TransferZone, None);
+        assert_eq!(
+            response_code(&server.respond(&transfer, stranger).unwrap()),
+            ResponseCode::REFUSED
+        );
+        assert_eq!(
+            response_code(
+                &server
+                    .respond(&request(QuestionType::IncrementalTransfer, None), local)
+                    .unwrap()
+            ),
+            ResponseCode::FORMERR
+        );
+
+        // Large zones are split over several messages
+        let mut large = original.clone();
+        large.extend((0..2000).map(|index| {
+            Resource::new(
+                name(&format!("host{}.example.com", index)),
+                ResourceClass::Internet,
+                300,
+                ResourcePayload::Address("192.0.2.1".parse().unwrap()),
+            )
+        }));
+        server.add_zone(PrimaryZone::new(name("example.com"), large.clone()).unwrap());
+        let messages = server.respond(&transfer, local).unwrap();
+        assert!(messages.len() > 1);
+        assert!(messages
+            .iter()
+            .all(|message| message.len() <= MAXIMUM_MESSAGE_SIZE + 512));
+        assert_eq!(answers(&messages).len(), large.len() + 1);
+
+        // Only the first and last messages of a signed transfer carry a TSIG, the last covers the ones before it
+        let keyring = Keyring::from_file("testdata/keys/transfer.example.com.key").unwrap();
+        let key = keyring.get(&name("transfer.example.com")).unwrap().clone();
+        server.keyring(keyring);
+        let mut client = TsigSession::new(key);
+        let mut signed_transfer = transfer.clone();
+        client.sign(&mut signed_transfer).unwrap();
+        let messages = server.respond(&signed_transfer, local).unwrap();
+        let signed: Vec<bool> = messages
+            .iter()
+            .map(|message| {
+                let packet = DnsParser::new().parse_packet(message).unwrap();
+                packet.additional.iter().any(|record| {
+                    record.payload.type_code() == ResourceType::TransactionSignature.code()
+                })
+            })
+            .collect();
+        assert!(messages.len() > 2);
+        assert!(signed[0] && signed[messages.len() - 1]);
+        assert!(!signed[1..messages.len() - 1].contains(&true));
+        for message in &messages {
+            DnsParser::new()
+                .parse_signed_packet(message, &mut client)
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_secondary_over_loopback() {
+        let original = example_zone();
+        let mut keyring = Keyring::from_file("testdata/keys/transfer.example.com.key").unwrap();
+        let key = keyring.get(&name("transfer.example.com")).unwrap().clone();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut primary = TransferServer::new();
+        primary
+            .add_zone(PrimaryZone::new(name("example.com"), original.clone()).unwrap())
+            .allow("127.0.0.1".parse().unwrap())
+            .keyring(keyring.clone());
+        let mut secondary = SecondaryZone::new(name("example.com"), listener.local_addr().unwrap());
+        secondary.key(key);
+        secondary.transfer_time = Some(NOW);
+        assert_eq!(secondary.next_refresh(), NOW);
+
+        // Each refresh is one connection to the primary
+        let refresh = |primary: &TransferServer, secondary: &mut SecondaryZone| {
+            std::thread::scope(|scope| {
+                let server = scope.spawn(|| {
+                    let (mut stream, peer) = listener.accept().unwrap();
+                    while let Some(request) = framing::read_message(&mut stream).unwrap() {
+                        for message in primary.respond(&request, peer.ip()).unwrap() {
+                            framing::write_message(&mut stream, &message).unwrap();
+                        }
+                    }
+                });
+                let outcome = secondary.refresh();
+                server.join().unwrap();
+                outcome
+            })
+        };
+        assert_eq!(
+            refresh(&primary, &mut secondary).unwrap(),
+            TransferOutcome::Full
+        );
+        assert_eq!(secondary.serial(), Some(2021020201));
+        assert_eq!(secondary.records().len(), original.len());
+        // The SOA refresh timer is two hours
+        assert_eq!(secondary.next_refresh(), NOW + 7200);
+        assert_eq!(
+            refresh(&primary, &mut secondary).unwrap(),
+            TransferOutcome::UpToDate
+        );
+
+        let zone = primary.zone_mut(&name("example.com")).unwrap();
+        zone.update(next_version(&original, 2021020202)).unwrap();
+        let latest = next_version(&next_version(&original, 2021020202), 2021020203);
+        zone.update(latest.clone()).unwrap();
+        assert_eq!(
+            refresh(&primary, &mut secondary).unwrap(),
+            TransferOutcome::Incremental
+        );
+        assert_eq!(secondary.serial(), Some(2021020203));
+        let mut held = secondary.records().to_vec();
+        let mut expected = latest;
+        let order =
+            |first: &Resource, second: &Resource| first.to_string().cmp(&second.to_string());
+        held.sort_by(order);
+        expected.sort_by(order);
+        assert_eq!(held, expected);
+
+        // A primary with a different secret is rejected, and the zone expires after two weeks without the primary
+        keyring.add(TsigKey::new(
+            name("transfer.example.com"),
+            TsigAlgorithm::HmacSha256,
+            vec![1; 32],
+        ));
+        primary.keyring(keyring);
+        secondary.transfer_time = Some(NOW + 7200);
+        assert!(refresh(&primary, &mut secondary).is_err());
+        assert_eq!(secondary.next_refresh(), NOW + 7200 + 1800);
+        assert!(!secondary.records().is_empty());
+        secondary.transfer_time = Some(NOW + 14 * 86400);
+        assert!(secondary.is_expired());
+        assert!(secondary.records().is_empty());
+    }
+
+    #[test]
+    fn test_unsigned_closing_message() {
+        let keyring = Keyring::from_file("testdata/keys/transfer.example.com.key").unwrap();
+        let key = keyring.get(&name("transfer.example.com")).unwrap().clone();
+        let mut large = example_zone();
+        large.extend((0..2000).map(|index| {
+            Resource::new(
+                name(&format!("host{}.example.com", index)),
+                ResourceClass::Internet,
+                300,
+                ResourcePayload::Address("192.0.2.1".parse().unwrap()),
+            )
+        }));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut primary = TransferServer::new();
+        primary
+            .add_zone(PrimaryZone::new(name("example.com"), large).unwrap())
+            .allow("127.0.0.1".parse().unwrap())
+            .keyring(keyring);
+        let mut secondary = SecondaryZone::new(name("example.com"), listener.local_addr().unwrap());
+        secondary.key(key);
+        secondary.transfer_time = Some(NOW);
+
+        // The TSIG of the closing message is stripped, the unsigned messages before it go unchecked
+        let outcome = std::thread::scope(|scope| {
+            let server = scope.spawn(|| {
+                let (mut stream, peer) = listener.accept().unwrap();
+                while let Some(request) = framing::read_message(&mut stream).unwrap() {
+                    let mut messages = primary.respond(&request, peer.ip()).unwrap();
+                    assert!(messages.len() > 2);
+                    let last = messages.last_mut().unwrap();
+                    let owner = b"\x08transfer\x07example\x03com\x00\x00\xfa";
+                    let start = last
+                        .windows(owner.len())
+                        .rposition(|window| window == owner)
+                        .unwrap();
+                    last.truncate(start);
+                    last[11] -= 1;
+                    for message in &messages {
+                        framing::write_message(&mut stream, message).unwrap();
+                    }
+                }
+            });
+            let outcome = secondary.refresh();
+            server.join().unwrap();
+            outcome
+        });
+        assert!(outcome.is_err());
+        assert_eq!(secondary.serial(), None);
+        assert!(secondary.records().is_empty());
+    }
+}
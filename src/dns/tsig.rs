@@ -0,0 +1,721 @@
+use super::{
+    builders::PacketWriter, DnsPacket, DnsParser, DomainName, Keyring, PreviousNames, Resource,
+    ResourceClass, ResourcePayload, ResponseCode, TsigAlgorithm, TsigKey, TsigSession,
+};
+use crate::error::{Error, ErrorKind};
+use crate::helper::decode_base64;
+use ring::hmac;
+use std::{
+    borrow::Cow,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The seconds the signing time may differ from the current time, as recommended by RFC 8945 section 10
+const FUDGE: u16 = 300;
+/// RFC 8945 section 5.3.1, at most 99 unsigned messages may follow a signed one
+const MAXIMUM_UNSIGNED_MESSAGES: usize = 99;
+
+impl TsigAlgorithm {
+    /// The name identifying the algorithm in a TSIG record, ie hmac-sha256
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            TsigAlgorithm::HmacSha256 => "hmac-sha256",
+            TsigAlgorithm::HmacSha384 => "hmac-sha384",
+            TsigAlgorithm::HmacSha512 => "hmac-sha512",
+        }
+    }
+
+    /// Looks up an algorithm from its name, the comparison is case insensitive and ignores a trailing dot
+    pub fn from_mnemonic(mnemonic: &str) -> Option<TsigAlgorithm> {
+        let algorithm = match mnemonic.trim_end_matches('.').to_ascii_lowercase().as_str() {
+            "hmac-sha256" => TsigAlgorithm::HmacSha256,
+            "hmac-sha384" => TsigAlgorithm::HmacSha384,
+            "hmac-sha512" => TsigAlgorithm::HmacSha512,
+            _ => return None,
+        };
+        Some(algorithm)
+    }
+
+    pub fn name(&self) -> DomainName<'static> {
+        DomainName::new(vec![self.mnemonic()])
+    }
+
+    /// The length of an untruncated MAC
+    pub fn mac_length(&self) -> usize {
+        self.hmac().digest_algorithm().output_len()
+    }
+
+    fn hmac(&self) -> hmac::Algorithm {
+        match self {
+            TsigAlgorithm::HmacSha256 => hmac::HMAC_SHA256,
+            TsigAlgorithm::HmacSha384 => hmac::HMAC_SHA384,
+            TsigAlgorithm::HmacSha512 => hmac::HMAC_SHA512,
+        }
+    }
+}
+
+impl TsigKey {
+    pub fn new(name: DomainName<'static>, algorithm: TsigAlgorithm, secret: Vec<u8>) -> TsigKey {
+        TsigKey {
+            name,
+            algorithm,
+            secret,
+        }
+    }
+
+    fn mac(&self, data: &[u8]) -> Vec<u8> {
+        let key = hmac::Key::new(self.algorithm.hmac(), &self.secret);
+        hmac::sign(&key, data).as_ref().to_vec()
+    }
+
+    fn verify_mac(&self, data: &[u8], mac: &[u8]) -> bool {
+        let key = hmac::Key::new(self.algorithm.hmac(), &self.secret);
+        hmac::verify(&key, data, mac).is_ok()
+    }
+}
+
+impl Keyring {
+    pub fn new() -> Keyring {
+        Keyring::default()
+    }
+
+    /// Reads the key statements of a BIND style key file, as written by tsig-keygen
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Keyring, Error> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|_| {
+            Error::new(ErrorKind::InvalidKeyring(format!(
+                "{} could not be read",
+                path.display()
+            )))
+        })?;
+        Keyring::parse(&text).map_err(|reason| {
+            Error::new(ErrorKind::InvalidKeyring(format!(
+                "{}: {}",
+                path.display(),
+                reason
+            )))
+        })
+    }
+
+    /// Parses key statements of the form key "name" { algorithm hmac-sha256; secret "base64"; };
+    pub fn parse(text: &str) -> Result<Keyring, String> {
+        let mut keyring = Keyring::new();
+        let tokens = key_file_tokens(text)?;
+        let mut tokens = tokens.iter().map(String::as_str);
+        while let Some(token) = tokens.next() {
+            if token != "key" {
+                return Err(format!("expected a key statement but found {}", token));
+            }
+            let name = tokens
+                .next()
+                .ok_or_else(|| String::from("a key statement needs a name"))?;
+            let name = DomainName::new(
+                name.split('.')
+                    .filter(|label| !label.is_empty())
+                    .map(String::from)
+                    .collect(),
+            );
+            if tokens.next() != Some("{") {
+                return Err(format!("expected {{ after the key name {}", name));
+            }
+            let (mut algorithm, mut secret) = (None, None);
+            loop {
+                let option = tokens
+                    .next()
+                    .ok_or_else(|| format!("the key {} is missing a closing }}", name))?;
+                if option == "}" {
+                    break;
+                }
+                let value = tokens
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", option))?;
+                match option {
+                    "algorithm" => {
+                        algorithm = Some(
+                            TsigAlgorithm::from_mnemonic(value)
+                                .ok_or_else(|| format!("{} is not a supported algorithm", value))?,
+                        )
+                    }
+                    "secret" => {
+                        secret = Some(
+                            decode_base64(value)
+                                .ok_or_else(|| format!("the secret of {} is not base64", name))?,
+                        )
+                    }
+                    _ => return Err(format!("{} is not a key option", option)),
+                }
+                if tokens.next() != Some(";") {
+                    return Err(format!("expected ; after {} {}", option, value));
+                }
+            }
+            if tokens.next() != Some(";") {
+                return Err(format!("expected ; after the key {}", name));
+            }
+            let algorithm =
+                algorithm.ok_or_else(|| format!("the key {} has no algorithm", name))?;
+            let secret = secret.ok_or_else(|| format!("the key {} has no secret", name))?;
+            keyring.add(TsigKey::new(name, algorithm, secret));
+        }
+        Ok(keyring)
+    }
+
+    /// Adds a key, replacing any key with the same name
+    pub fn add(&mut self, key: TsigKey) -> &mut Self {
+        self.keys
+            .retain(|existing| !existing.name.eq_ignore_case(&key.name));
+        self.keys.push(key);
+        self
+    }
+
+    pub fn get(&self, name: &DomainName) -> Option<&TsigKey> {
+        self.keys.iter().find(|key| key.name.eq_ignore_case(name))
+    }
+
+    /// Starts a session with the key named in a request, None when the request isn't signed
+    /// An unknown key still gets a session so that BADKEY can be sent in the response
+    pub fn session(&self, packet: &DnsPacket) -> Option<TsigSession> {
+        let (name, algorithm) = match &transaction_signature(packet)? {
+            Resource {
+                resource_name,
+                payload: ResourcePayload::TransactionSignature { algorithm, .. },
+                ..
+            } => (resource_name, algorithm),
+            _ => return None,
+        };
+        let key = self
+            .get(name)
+            .filter(|key| key.algorithm.name().eq_ignore_case(algorithm));
+        match key {
+            Some(key) => Some(TsigSession::new(key.clone())),
+            None => {
+                // The response echoes the key name, there is no secret to sign it with
+                let algorithm = TsigAlgorithm::from_mnemonic(&algorithm.to_string())
+                    .unwrap_or(TsigAlgorithm::HmacSha256);
+                let unknown_key = TsigKey::new(name.clone().into_owned(), algorithm, Vec::new());
+                let mut session = TsigSession::new(unknown_key);
+                session.error = Some(ResponseCode::BADKEY);
+                Some(session)
+            }
+        }
+    }
+}
+
+impl TsigSession {
+    pub fn new(key: TsigKey) -> TsigSession {
+        TsigSession {
+            key,
+            previous_mac: None,
+            signed_messages: 0,
+            unsigned_messages: Vec::new(),
+            unsigned_count: 0,
+            peer_time: None,
+            error: None,
+            signing_time: None,
+        }
+    }
+
+    /// Why the request failed verification, a response to it should use NOTAUTH
+    pub fn error(&self) -> Option<ResponseCode> {
+        self.error
+    }
+
+    /// Returns true if the last message received was signed, the final message of a TCP sequence must be
+    pub fn ends_signed(&self) -> bool {
+        self.unsigned_count == 0
+    }
+
+    /// Appends a TSIG record to a complete message and counts it in the additional section
+    /// Responses to a request with a bad key or MAC carry the error and an empty MAC, as they can't be signed
+    pub fn sign(&mut self, message: &mut Vec<u8>) -> Result<(), Error> {
+        if message.len() < 12 {
+            return Err(Error::new(ErrorKind::WritePacketDataFailed));
+        }
+        let now = self.now();
+        let (time_signed, mac, other_data) = match self.error {
+            Some(ResponseCode::BADTIME) => {
+                // RFC 8945 section 5.2.3, the request time is echoed and the server time sent as other data
+                let time_signed = self.peer_time.unwrap_or(now);
+                let other_data = now.to_be_bytes()[2..].to_vec();
+                let mac = self
+                    .key
+                    .mac(&self.mac_input(message, time_signed, &other_data));
+                (time_signed, mac, other_data)
+            }
+            Some(_) => (now, Vec::new(), Vec::new()),
+            None => (
+                now,
+                self.key.mac(&self.mac_input(message, now, &[])),
+                Vec::new(),
+            ),
+        };
+        let original_id = u16::from_be_bytes([message[0], message[1]]);
+        let additional_count = u16::from_be_bytes([message[10], message[11]])
+            .checked_add(1)
+            .ok_or_else(|| Error::new(ErrorKind::WritePacketDataFailed))?;
+        message[10..12].copy_from_slice(&additional_count.to_be_bytes());
+        if !mac.is_empty() {
+            self.previous_mac = Some(mac.clone());
+            self.signed_messages += 1;
+            self.unsigned_messages.clear();
+            self.unsigned_count = 0;
+        }
+        let record = Resource::new(
+            self.key.name.clone(),
+            ResourceClass::Any,
+            0,
+            ResourcePayload::TransactionSignature {
+                algorithm: self.key.algorithm.name(),
+                time_signed,
+                fudge: FUDGE,
+                mac: Cow::Owned(mac),
+                original_id,
+                error: self.error.map_or(0, u16::from),
+                other_data: Cow::Owned(other_data),
+            },
+        );
+        // A fresh writer knows no earlier names, so the TSIG names are never compressed
+        let mut writer = PacketWriter::with_data(std::mem::take(message));
+        writer.write_resource(&record)?;
+        *message = writer.into_inner();
+        Ok(())
+    }
+
+    /// Sends a message of a TCP sequence without a TSIG, the next signed message covers it
+    pub fn skip_signature(&mut self, message: &[u8]) -> Result<(), Error> {
+        if self.signed_messages < 2 || self.unsigned_count >= MAXIMUM_UNSIGNED_MESSAGES {
+            return Err(Error::new(ErrorKind::MissingTransactionSignature));
+        }
+        self.unsigned_messages.extend_from_slice(message);
+        self.unsigned_count += 1;
+        Ok(())
+    }
+
+    /// Checks the TSIG of a parsed message against the raw data it was parsed from
+    /// Unsigned messages are accepted in a TCP sequence once the first response has been verified
+    /// A failure is kept in the session, so that the response to a request reports it
+    pub fn verify(&mut self, packet_data: &[u8], packet: &DnsPacket) -> Result<(), Error> {
+        if let Some(error) = self.error {
+            return Err(Error::new(ErrorKind::TransactionSignatureFailed(error)));
+        }
+        let signature = match transaction_signature(packet) {
+            Some(signature) => signature,
+            None if self.signed_messages >= 2
+                && self.unsigned_count < MAXIMUM_UNSIGNED_MESSAGES =>
+            {
+                self.unsigned_messages.extend_from_slice(packet_data);
+                self.unsigned_count += 1;
+                return Ok(());
+            }
+            None => return Err(Error::new(ErrorKind::MissingTransactionSignature)),
+        };
+        let (algorithm, time_signed, fudge, mac, original_id, error, other_data) =
+            match &signature.payload {
+                ResourcePayload::TransactionSignature {
+                    algorithm,
+                    time_signed,
+                    fudge,
+                    mac,
+                    original_id,
+                    error,
+                    other_data,
+                } => (
+                    algorithm,
+                    *time_signed,
+                    *fudge,
+                    mac,
+                    *original_id,
+                    *error,
+                    other_data,
+                ),
+                _ => unreachable!("transaction_signature only returns TSIG records"),
+            };
+        if !signature.resource_name.eq_ignore_case(&self.key.name)
+            || !algorithm.eq_ignore_case(&self.key.algorithm.name())
+        {
+            return self.fail(ResponseCode::BADKEY);
+        }
+        if mac.is_empty() && error != 0 {
+            // The other side couldn't verify our message and so couldn't sign its reply
+            return Err(Error::new(ErrorKind::TransactionSignatureFailed(
+                ResponseCode::from(error as u8),
+            )));
+        }
+        if mac.len() != self.key.algorithm.mac_length() {
+            return self.fail(ResponseCode::BADTRUNC);
+        }
+        let mut message = packet_data[..signature_position(packet_data, packet)?].to_vec();
+        message[0..2].copy_from_slice(&original_id.to_be_bytes());
+        let additional_count = u16::from_be_bytes([message[10], message[11]]) - 1;
+        message[10..12].copy_from_slice(&additional_count.to_be_bytes());
+        let mut input = self.mac_prefix();
+        input.extend_from_slice(&self.unsigned_messages);
+        input.extend_from_slice(&message);
+        self.write_variables(&mut input, time_signed, fudge, error, other_data);
+        if !self.key.verify_mac(&input, mac) {
+            return self.fail(ResponseCode::BADSIG);
+        }
+        self.previous_mac = Some(mac.to_vec());
+        self.signed_messages += 1;
+        self.unsigned_messages.clear();
+        self.unsigned_count = 0;
+        self.peer_time = Some(time_signed);
+        if self.now().abs_diff(time_signed) > fudge as u64 {
+            return self.fail(ResponseCode::BADTIME);
+        }
+        if error != 0 {
+            return Err(Error::new(ErrorKind::TransactionSignatureFailed(
+                ResponseCode::from(error as u8),
+            )));
+        }
+        Ok(())
+    }
+
+    fn fail(&mut self, error: ResponseCode) -> Result<(), Error> {
+        self.error = Some(error);
+        Err(Error::new(ErrorKind::TransactionSignatureFailed(error)))
+    }
+
+    /// The data covered by the MAC of an outgoing message
+    fn mac_input(&self, message: &[u8], time_signed: u64, other_data: &[u8]) -> Vec<u8> {
+        let mut input = self.mac_prefix();
+        input.extend_from_slice(&self.unsigned_messages);
+        input.extend_from_slice(message);
+        let error = self.error.map_or(0, u16::from);
+        self.write_variables(&mut input, time_signed, FUDGE, error, other_data);
+        input
+    }
+
+    /// The MAC of the previous message, every message after the request is chained to it
+    fn mac_prefix(&self) -> Vec<u8> {
+        let mut prefix = Vec::new();
+        if let Some(previous_mac) = &self.previous_mac {
+            prefix.extend_from_slice(&(previous_mac.len() as u16).to_be_bytes());
+            prefix.extend_from_slice(previous_mac);
+        }
+        prefix
+    }
+
+    /// Writes the TSIG variables of RFC 8945 section 4.3.3, only the timers after the first response
+    fn write_variables(
+        &self,
+        input: &mut Vec<u8>,
+        time_signed: u64,
+        fudge: u16,
+        error: u16,
+        other_data: &[u8],
+    ) {
+        let time_signed = &time_signed.to_be_bytes()[2..];
+        if self.signed_messages >= 2 {
+            input.extend_from_slice(time_signed);
+            input.extend_from_slice(&fudge.to_be_bytes());
+            return;
+        }
+        let algorithm = self.key.algorithm.name();
+        let mut writer = PacketWriter::canonical();
+        // Writing to a Vec can't fail
+        let _ = writer.write_name(&self.key.name, false);
+        let _ = writer.write_u16(u16::from(ResourceClass::Any));
+        let _ = writer.write_u32(0);
+        let _ = writer.write_name(&algorithm, false);
+        input.extend_from_slice(&writer.into_inner());
+        input.extend_from_slice(time_signed);
+        input.extend_from_slice(&fudge.to_be_bytes());
+        input.extend_from_slice(&error.to_be_bytes());
+        input.extend_from_slice(&(other_data.len() as u16).to_be_bytes());
+        input.extend_from_slice(other_data);
+    }
+
+    fn now(&self) -> u64 {
+        self.signing_time.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs())
+        })
+    }
+}
+
+/// Splits a key file into words, quoted strings and the punctuation { } ;, comments start with # or //
+fn key_file_tokens(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut characters = text.chars().peekable();
+    while let Some(character) = characters.next() {
+        match character {
+            '#' => {
+                characters
+                    .by_ref()
+                    .take_while(|&next| next != '\n')
+                    .for_each(drop);
+            }
+            '/' if characters.peek() == Some(&'/') => {
+                characters
+                    .by_ref()
+                    .take_while(|&next| next != '\n')
+                    .for_each(drop);
+            }
+            '{' | '}' | ';' => tokens.push(character.to_string()),
+            '"' => {
+                let quoted: String = characters
+                    .by_ref()
+                    .take_while(|&next| next != '"')
+                    .collect();
+                tokens.push(quoted);
+            }
+            character if character.is_whitespace() => (),
+            character => {
+                let mut word = character.to_string();
+                while let Some(&next) = characters.peek() {
+                    if next.is_whitespace() || "{};\"#".contains(next) {
+                        break;
+                    }
+                    word.push(next);
+                    characters.next();
+                }
+                tokens.push(word);
+            }
+        }
+    }
+    if text.matches('"').count() % 2 == 1 {
+        return Err(String::from("a quoted string is never closed"));
+    }
+    Ok(tokens)
+}
+
+/// The TSIG record of a message, RFC 8945 only allows it as the last additional record
+fn transaction_signature<'p, 'a>(packet: &'p DnsPacket<'a>) -> Option<&'p Resource<'a>> {
+    packet
+        .additional
+        .last()
+        .filter(|record| matches!(record.payload, ResourcePayload::TransactionSignature { .. }))
+}
+
+/// Finds where the TSIG record starts by reading every record before it again
+fn signature_position(packet_data: &[u8], packet: &DnsPacket) -> Result<usize, Error> {
+    let header = &packet.header;
+    let mut parser = DnsParser::new();
+    let mut previous_names = PreviousNames::new();
+    parser.read_header(packet_data)?;
+    for _ in 0..header.question_count {
+        parser.read_question(packet_data, &mut previous_names)?;
+    }
+    let records = header.answer_count as usize
+        + header.authority_count as usize
+        + header.additional_count as usize
+        - 1;
+    for _ in 0..records {
+        parser.read_answer(packet_data, &mut previous_names)?;
+    }
+    Ok(parser.position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::builders::{DnsQueryBuilder, DnsResponseBuilder};
+    use crate::dns::tests::name;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn keyring() -> Keyring {
+        Keyring::from_file("testdata/keys/transfer.example.com.key").unwrap()
+    }
+
+    fn client(keyring: &Keyring) -> TsigSession {
+        let key = keyring.get(&name("transfer.example.com")).unwrap();
+        let mut session = TsigSession::new(key.clone());
+        session.signing_time = Some(NOW);
+        session
+    }
+
+    /// The session a server starts for a request, along with the outcome of verifying it
+    fn server(keyring: &Keyring, query: &[u8], time: u64) -> TsigSession {
+        let request = DnsParser::new().parse_packet(query).unwrap();
+        let mut session = keyring.session(&request).unwrap();
+        session.signing_time = Some(time);
+        let _ = session.verify(query, &request);
+        session
+    }
+
+    fn signed_query(session: &mut TsigSession) -> Vec<u8> {
+        DnsQueryBuilder::new()
+            .write_id(Some(0x1234))
+            .unwrap()
+            .request_address("example.com")
+            .unwrap()
+            .build_signed_query(session)
+            .unwrap()
+    }
+
+    fn signed_response(session: &mut TsigSession) -> Vec<u8> {
+        DnsResponseBuilder::new(0x1234)
+            .add_additional(Resource::new(
+                name("www.example.com"),
+                ResourceClass::Internet,
+                300,
+                ResourcePayload::Address("192.0.2.1".parse().unwrap()),
+            ))
+            .build_signed_response(session)
+            .unwrap()
+    }
+
+    fn failure(result: Result<DnsPacket, Error>) -> ErrorKind {
+        result.err().unwrap().kind().clone()
+    }
+
+    #[test]
+    fn test_keyring_file() {
+        let keyring = keyring();
+        let key = keyring.get(&name("TRANSFER.example.com")).unwrap();
+        assert_eq!(key.algorithm, TsigAlgorithm::HmacSha256);
+        assert_eq!(key.secret.len(), 32);
+        let update = keyring.get(&name("update.example.com")).unwrap();
+        assert_eq!(update.algorithm, TsigAlgorithm::HmacSha512);
+        assert!(keyring.get(&name("example.com")).is_none());
+        assert!(
+            Keyring::parse("key \"a.example\" { algorithm hmac-md5; secret \"AA==\"; };").is_err()
+        );
+        assert!(Keyring::parse("key \"a.example\" { secret \"AA==\"; };").is_err());
+        assert!(
+            Keyring::parse("key \"a.example\" { algorithm hmac-sha256; secret \"AA==\"; }")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_signed_transaction() {
+        let keyring = keyring();
+        let mut client = client(&keyring);
+        let query = signed_query(&mut client);
+        let request = DnsParser::new().parse_packet(&query).unwrap();
+        assert_eq!(request.header.additional_count, 1);
+        let mut server = server(&keyring, &query, NOW + 10);
+        assert_eq!(server.error(), None);
+        let response = signed_response(&mut server);
+        let packet = DnsParser::new()
+            .parse_signed_packet(&response, &mut client)
+            .unwrap();
+        assert_eq!(packet.additional.len(), 2);
+        assert_eq!(packet.header.response_code, ResponseCode::NOERROR);
+
+        // Any change to the message breaks the MAC
+        let mut client = self::client(&keyring);
+        let query = signed_query(&mut client);
+        let mut tampered = signed_response(&mut self::server(&keyring, &query, NOW));
+        tampered[20] ^= 1;
+        assert_eq!(
+            failure(DnsParser::new().parse_signed_packet(&tampered, &mut client)),
+            ErrorKind::TransactionSignatureFailed(ResponseCode::BADSIG)
+        );
+
+        // A response that isn't signed is rejected
+        let unsigned = DnsResponseBuilder::new(0x1234).build_response().unwrap();
+        assert_eq!(
+            failure(DnsParser::new().parse_signed_packet(&unsigned, &mut self::client(&keyring))),
+            ErrorKind::MissingTransactionSignature
+        );
+    }
+
+    #[test]
+    fn test_rejected_requests() {
+        let keyring = keyring();
+
+        // The server clock is too far from the client, the BADTIME response is still signed
+        let mut client = client(&keyring);
+        let query = signed_query(&mut client);
+        let mut server = server(&keyring, &query, NOW + 301);
+        assert_eq!(server.error(), Some(ResponseCode::BADTIME));
+        let response = signed_response(&mut server);
+        let packet = DnsParser::new().parse_packet(&response).unwrap();
+        assert_eq!(packet.header.response_code, ResponseCode::NOTAUTH);
+        match &packet.additional[1].payload {
+            ResourcePayload::TransactionSignature {
+                time_signed,
+                error,
+                other_data,
+                ..
+            } => {
+                assert_eq!(*time_signed, NOW);
+                assert_eq!(*error, 18);
+                assert_eq!(other_data.as_ref(), &(NOW + 301).to_be_bytes()[2..]);
+            }
+            other => panic!("Expected TSIG but found {:?}", other),
+        }
+        assert_eq!(
+            failure(DnsParser::new().parse_signed_packet(&response, &mut client)),
+            ErrorKind::TransactionSignatureFailed(ResponseCode::BADTIME)
+        );
+
+        // The server doesn't know the key, its response can't be signed
+        let mut stranger = TsigSession::new(TsigKey::new(
+            name("stranger.example.com"),
+            TsigAlgorithm::HmacSha256,
+            vec![7; 32],
+        ));
+        stranger.signing_time = Some(NOW);
+        let query = signed_query(&mut stranger);
+        let mut server = self::server(&keyring, &query, NOW);
+        assert_eq!(server.error(), Some(ResponseCode::BADKEY));
+        let response = signed_response(&mut server);
+        assert_eq!(
+            failure(DnsParser::new().parse_signed_packet(&response, &mut stranger)),
+            ErrorKind::TransactionSignatureFailed(ResponseCode::BADKEY)
+        );
+
+        // The right key name with the wrong secret
+        let mut forger = TsigSession::new(TsigKey::new(
+            name("transfer.example.com"),
+            TsigAlgorithm::HmacSha256,
+            vec![7; 32],
+        ));
+        forger.signing_time = Some(NOW);
+        let query = signed_query(&mut forger);
+        let server = self::server(&keyring, &query, NOW);
+        assert_eq!(server.error(), Some(ResponseCode::BADSIG));
+        let unsigned = DnsQueryBuilder::new()
+            .request_address("example.com")
+            .unwrap()
+            .build_query()
+            .unwrap();
+        let request = DnsParser::new().parse_packet(&unsigned).unwrap();
+        assert!(keyring.session(&request).is_none());
+    }
+
+    #[test]
+    fn test_message_sequence() {
+        let keyring = keyring();
+        let mut client = client(&keyring);
+        let query = signed_query(&mut client);
+        let mut server = server(&keyring, &query, NOW);
+
+        // A zone transfer where the middle message is left unsigned
+        let first = signed_response(&mut server);
+        let second = DnsResponseBuilder::new(0x1234).build_response().unwrap();
+        server.skip_signature(&second).unwrap();
+        let third = signed_response(&mut server);
+        for message in [&first, &second, &third] {
+            DnsParser::new()
+                .parse_signed_packet(message, &mut client)
+                .unwrap();
+        }
+
+        // Dropping or reordering messages breaks the chain
+        for sequence in [[&first, &third], [&third, &first]] {
+            let mut receiver = self::client(&keyring);
+            assert_eq!(signed_query(&mut receiver), query);
+            let mut parser = DnsParser::new();
+            let results: Vec<_> = sequence
+                .iter()
+                .map(|message| parser.parse_signed_packet(message, &mut receiver).is_ok())
+                .collect();
+            assert_eq!(results, [sequence[0] == &first, false]);
+        }
+
+        // The first response must be signed
+        let mut client = self::client(&keyring);
+        signed_query(&mut client);
+        assert_eq!(
+            failure(DnsParser::new().parse_signed_packet(&second, &mut client)),
+            ErrorKind::MissingTransactionSignature
+        );
+    }
+}
@@ -0,0 +1,763 @@
+use super::{
+    builders::DnsResponseBuilder, transfer::serial_is_newer, zone::ZoneParser, DnsPacket,
+    DnsParser, DomainName, Keyring, OperationCode, PrimaryZone, QuestionType, Resource,
+    ResourceClass, ResourcePayload, ResourceType, ResponseCode, TransferServer, TsigSession,
+    UpdatePolicy, ZoneDiff,
+};
+use crate::error::{Error, ErrorKind};
+use std::{
+    fs::OpenOptions,
+    io::{ErrorKind as IoErrorKind, Write},
+    path::Path,
+};
+
+/// Types from here on are meta types and question only types, they can't be added to a zone
+const FIRST_META_TYPE: u16 = 128;
+/// Type ANY, matches every RRset at a name in prerequisites and deletions
+const ANY_TYPE: u16 = 255;
+
+/// A diff being read from a journal, the new SOA is missing until its first add
+type PartialDiff = (
+    Resource<'static>,
+    Vec<Resource<'static>>,
+    Option<Resource<'static>>,
+    Vec<Resource<'static>>,
+);
+
+/// Whether the record has no RDATA, which is how UPDATE refers to a whole RRset
+fn is_empty(record: &Resource) -> bool {
+    matches!(&record.payload, ResourcePayload::Unknown { data, .. } if data.is_empty())
+}
+
+fn name_in_use(records: &[Resource<'static>], name: &DomainName) -> bool {
+    records
+        .iter()
+        .any(|record| record.resource_name.eq_ignore_case(name))
+}
+
+fn rrset<'r>(
+    records: &'r [Resource<'static>],
+    name: &'r DomainName,
+    type_code: u16,
+) -> impl Iterator<Item = &'r Resource<'static>> {
+    records.iter().filter(move |record| {
+        record.resource_name.eq_ignore_case(name) && record.payload.type_code() == type_code
+    })
+}
+
+fn start_authority_serial(record: &Resource) -> Option<u32> {
+    match record.payload {
+        ResourcePayload::StartAuthority { serial, .. } => Some(serial),
+        _ => None,
+    }
+}
+
+/// Checks the prerequisite section against the zone, RFC 2136 section 3.2
+fn check_prerequisites(zone: &PrimaryZone, prerequisites: &[Resource]) -> Result<(), ResponseCode> {
+    let records = zone.records();
+    // Records with the zone class must match whole RRsets exactly, they are checked once all have been seen
+    let mut values: Vec<&Resource> = Vec::new();
+    for prerequisite in prerequisites {
+        if prerequisite.time_to_live != 0 {
+            return Err(ResponseCode::FORMERR);
+        }
+        let name = &prerequisite.resource_name;
+        if !name.is_subdomain_of(zone.zone()) {
+            return Err(ResponseCode::NOTZONE);
+        }
+        let type_code = prerequisite.payload.type_code();
+        match prerequisite.resource_class {
+            ResourceClass::Any | ResourceClass::None if !is_empty(prerequisite) => {
+                return Err(ResponseCode::FORMERR)
+            }
+            ResourceClass::Any if type_code == ANY_TYPE => {
+                if !name_in_use(records, name) {
+                    return Err(ResponseCode::NXDOMAIN);
+                }
+            }
+            ResourceClass::Any => {
+                if rrset(records, name, type_code).next().is_none() {
+                    return Err(ResponseCode::NXRRSET);
+                }
+            }
+            ResourceClass::None if type_code == ANY_TYPE => {
+                if name_in_use(records, name) {
+                    return Err(ResponseCode::YXDOMAIN);
+                }
+            }
+            ResourceClass::None => {
+                if rrset(records, name, type_code).next().is_some() {
+                    return Err(ResponseCode::YXRRSET);
+                }
+            }
+            ResourceClass::Internet => values.push(prerequisite),
+            _ => return Err(ResponseCode::FORMERR),
+        }
+    }
+    for prerequisite in &values {
+        let name = &prerequisite.resource_name;
+        let type_code = prerequisite.payload.type_code();
+        let expected: Vec<&&Resource> = values
+            .iter()
+            .filter(|other| {
+                other.resource_name.eq_ignore_case(name) && other.payload.type_code() == type_code
+            })
+            .collect();
+        let existing: Vec<&Resource<'static>> = rrset(records, name, type_code).collect();
+        let same_set = existing.len() == expected.len()
+            && existing
+                .iter()
+                .all(|record| expected.iter().any(|other| other.payload == record.payload));
+        if !same_set {
+            return Err(ResponseCode::NXRRSET);
+        }
+    }
+    Ok(())
+}
+
+/// Checks the update section before anything is changed so that an UPDATE is applied whole or not at all, RFC 2136 section 3.4.1
+fn prescan(zone: &PrimaryZone, updates: &[Resource]) -> Result<(), ResponseCode> {
+    for update in updates {
+        if !update.resource_name.is_subdomain_of(zone.zone()) {
+            return Err(ResponseCode::NOTZONE);
+        }
+        let type_code = update.payload.type_code();
+        let valid = match update.resource_class {
+            ResourceClass::Internet => type_code < FIRST_META_TYPE && !is_empty(update),
+            ResourceClass::Any => {
+                update.time_to_live == 0
+                    && is_empty(update)
+                    && (type_code < FIRST_META_TYPE || type_code == ANY_TYPE)
+            }
+            ResourceClass::None => update.time_to_live == 0 && type_code < FIRST_META_TYPE,
+            _ => false,
+        };
+        if !valid {
+            return Err(ResponseCode::FORMERR);
+        }
+    }
+    Ok(())
+}
+
+/// Applies one record of the update section, returns whether the zone changed, RFC 2136 section 3.4.2
+fn apply_update(
+    zone: &DomainName,
+    records: &mut Vec<Resource<'static>>,
+    update: &Resource,
+) -> bool {
+    let name = &update.resource_name;
+    let type_code = update.payload.type_code();
+    let at_apex = name.eq_ignore_case(zone);
+    // The SOA and NS records at the apex can only be replaced, never deleted
+    let protected = |record: &Resource| {
+        at_apex
+            && matches!(
+                record.payload.resource_type(),
+                ResourceType::StartAuthority | ResourceType::NameServer
+            )
+    };
+    let count = records.len();
+    match update.resource_class {
+        ResourceClass::Internet => {
+            let update = update.clone().into_owned();
+            if let Some(serial) = start_authority_serial(&update) {
+                // Only a newer SOA at the apex replaces the current one
+                let position = records.iter().position(|record| {
+                    at_apex
+                        && record.resource_name.eq_ignore_case(zone)
+                        && start_authority_serial(record)
+                            .is_some_and(|current| serial_is_newer(serial, current))
+                });
+                return match position {
+                    Some(position) => {
+                        records[position] = update;
+                        true
+                    }
+                    None => false,
+                };
+            }
+            let is_alias = type_code == u16::from(ResourceType::CanonicalName);
+            let conflicts = records.iter().any(|record| {
+                record.resource_name.eq_ignore_case(name)
+                    && (record.payload.type_code() == u16::from(ResourceType::CanonicalName))
+                        != is_alias
+            });
+            if conflicts {
+                return false;
+            }
+            if is_alias {
+                // A name holds a single CNAME, a new one replaces it
+                records.retain(|record| {
+                    !record.resource_name.eq_ignore_case(name)
+                        || record.payload.type_code() != type_code
+                        || record.payload == update.payload
+                });
+            }
+            let duplicate = records.iter().position(|record| {
+                record.resource_name.eq_ignore_case(name) && record.payload == update.payload
+            });
+            match duplicate {
+                Some(position) if records[position].time_to_live == update.time_to_live => false,
+                Some(position) => {
+                    records[position].time_to_live = update.time_to_live;
+                    true
+                }
+                None => {
+                    records.push(update);
+                    true
+                }
+            }
+        }
+        ResourceClass::Any => {
+            records.retain(|record| {
+                !record.resource_name.eq_ignore_case(name)
+                    || protected(record)
+                    || (type_code != ANY_TYPE && record.payload.type_code() != type_code)
+            });
+            records.len() != count
+        }
+        ResourceClass::None => {
+            if type_code == u16::from(ResourceType::StartAuthority) {
+                return false;
+            }
+            let name_servers = rrset(records, zone, u16::from(ResourceType::NameServer)).count();
+            let position = records.iter().position(|record| {
+                record.resource_name.eq_ignore_case(name) && record.payload == update.payload
+            });
+            match position {
+                // The last name server of the zone stays
+                Some(_) if at_apex && name_servers == 1 && protected(update) => false,
+                Some(position) => {
+                    records.remove(position);
+                    true
+                }
+                None => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+impl ZoneDiff {
+    /// Appends the diff to a journal, one record per line marked del or add in the order of an IXFR
+    pub fn write_journal(&self, path: &Path) -> Result<(), Error> {
+        let mut text = format!("del\t{}\n", self.from);
+        for record in &self.removed {
+            text.push_str(&format!("del\t{}\n", record));
+        }
+        text.push_str(&format!("add\t{}\n", self.to));
+        for record in &self.added {
+            text.push_str(&format!("add\t{}\n", record));
+        }
+        let write_failed =
+            |_| Error::new(ErrorKind::ZoneFileWriteFailed(path.display().to_string()));
+        let mut journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(write_failed)?;
+        journal
+            .write_all(text.as_bytes())
+            .and_then(|_| journal.flush())
+            .map_err(write_failed)
+    }
+
+    /// Reads back the diffs of a journal oldest first, a journal that doesn't exist yet has none
+    pub fn read_journal(path: &Path) -> Result<Vec<ZoneDiff>, Error> {
+        let file = path.display().to_string();
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == IoErrorKind::NotFound => return Ok(Vec::new()),
+            Err(_) => return Err(Error::new(ErrorKind::ZoneFileReadFailed(file))),
+        };
+        let invalid = |line: usize, reason: &str| {
+            Error::new(ErrorKind::InvalidZoneFile {
+                file: file.clone(),
+                line: line + 1,
+                reason: String::from(reason),
+            })
+        };
+        let mut diffs = Vec::new();
+        let mut current: Option<PartialDiff> = None;
+        for (line, entry) in text.lines().enumerate() {
+            if entry.trim().is_empty() {
+                continue;
+            }
+            let (operation, record) = match entry.split_once('\t') {
+                Some(parts) => parts,
+                None => return Err(invalid(line, "expected del or add followed by a record")),
+            };
+            let mut records = ZoneParser::new(None).parse_str(record, &file)?;
+            let record = match (records.pop(), records.is_empty()) {
+                (Some(record), true) => record,
+                _ => return Err(invalid(line, "expected a single record")),
+            };
+            let is_start_authority = start_authority_serial(&record).is_some();
+            if operation == "del" && is_start_authority {
+                if let Some(diff) = current.take() {
+                    diffs.push(Self::journal_diff(diff).map_err(|reason| invalid(line, reason))?);
+                }
+                current = Some((record, Vec::new(), None, Vec::new()));
+                continue;
+            }
+            match (operation, is_start_authority, &mut current) {
+                ("del", false, Some((_, removed, None, _))) => removed.push(record),
+                ("add", true, Some((_, _, to @ None, _))) => *to = Some(record),
+                ("add", false, Some((_, _, Some(_), added))) => added.push(record),
+                _ => return Err(invalid(line, "the record is out of order")),
+            }
+        }
+        if let Some(diff) = current {
+            let line = text.lines().count();
+            diffs.push(Self::journal_diff(diff).map_err(|reason| invalid(line, reason))?);
+        }
+        Ok(diffs)
+    }
+
+    fn journal_diff((from, removed, to, added): PartialDiff) -> Result<ZoneDiff, &'static str> {
+        match to {
+            Some(to) => Ok(ZoneDiff::new(from, removed, to, added)),
+            None => Err("the change has no new SOA"),
+        }
+    }
+}
+
+impl PrimaryZone {
+    /// Decides who may change the zone with UPDATE, nobody by default
+    pub fn update_policy(&mut self, update_policy: UpdatePolicy) -> &mut Self {
+        self.update_policy = update_policy;
+        self
+    }
+}
+
+impl TransferServer {
+    /// Applies an UPDATE to one of the zones and answers it, RFC 2136 section 3
+    /// The serial is increased when the UPDATE didn't do so itself, secondaries are notified of the change
+    pub fn answer_update(&mut self, request: &[u8]) -> Result<Vec<u8>, Error> {
+        let packet = DnsParser::new().parse_packet(request)?;
+        let position = packet.questions.first().and_then(|question| {
+            self.zones
+                .iter()
+                .position(|zone| zone.zone.eq_ignore_case(&question.domain_name))
+        });
+        // Zones that don't ask for signed updates accept no keys
+        let no_keys = Keyring::new();
+        let keyring = match position.map(|position| &self.zones[position].update_policy) {
+            Some(UpdatePolicy::Signed(keyring)) => keyring,
+            _ => &no_keys,
+        };
+        let mut session = keyring.session(&packet);
+        if let Some(session) = &mut session {
+            let _ = session.verify(request, &packet);
+        }
+        let response_code = match self.updated_records(position, &packet, &session) {
+            Ok(Some((zone, records))) => match self.update_zone(&zone, records) {
+                Ok(()) => ResponseCode::NOERROR,
+                Err(_) => ResponseCode::SERVFAIL,
+            },
+            Ok(None) => ResponseCode::NOERROR,
+            Err(response_code) => response_code,
+        };
+        let mut builder = DnsResponseBuilder::new(packet.header.id);
+        builder
+            .operation_code(OperationCode::Update)
+            .response_code(response_code);
+        if let Some(question) = packet.questions.first() {
+            builder.add_question(question.clone());
+        }
+        match &mut session {
+            Some(session) => builder.build_signed_response(session),
+            None => builder.build_response(),
+        }
+    }
+
+    /// The records of the zone after the UPDATE, None when nothing changed, or the response code it fails with
+    fn updated_records(
+        &self,
+        position: Option<usize>,
+        packet: &DnsPacket,
+        session: &Option<TsigSession>,
+    ) -> Result<Option<(DomainName<'static>, Vec<Resource<'static>>)>, ResponseCode> {
+        let is_update = matches!(packet.header.operation_code, OperationCode::Update);
+        match packet.questions.as_slice() {
+            [question]
+                if is_update && matches!(question.question_type, QuestionType::StartAuthority) => {}
+            _ => return Err(ResponseCode::FORMERR),
+        }
+        if session
+            .as_ref()
+            .is_some_and(|session| session.error().is_some())
+        {
+            return Err(ResponseCode::NOTAUTH);
+        }
+        let zone = &self.zones[position.ok_or(ResponseCode::NOTAUTH)?];
+        match (&zone.update_policy, session) {
+            (UpdatePolicy::Refused, _) | (UpdatePolicy::Signed(_), None) => {
+                return Err(ResponseCode::REFUSED)
+            }
+            _ => (),
+        }
+        // The prerequisites are in the answer section and the updates in the authority section
+        check_prerequisites(zone, &packet.answers)?;
+        prescan(zone, &packet.authority)?;
+        let mut records = zone.records().to_vec();
+        let mut changed = false;
+        for update in &packet.authority {
+            changed |= apply_update(zone.zone(), &mut records, update);
+        }
+        if !changed {
+            return Ok(None);
+        }
+        let serial = zone.serial();
+        for record in &mut records {
+            match &mut record.payload {
+                ResourcePayload::StartAuthority { serial: new, .. }
+                    if record.resource_name.eq_ignore_case(zone.zone())
+                        && !serial_is_newer(*new, serial) =>
+                {
+                    *new = serial.wrapping_add(1)
+                }
+                _ => (),
+            }
+        }
+        Ok(Some((zone.zone().clone(), records)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{
+        builders::PacketWriter, tests::name, Header, Question, QuestionClass, TsigAlgorithm,
+        TsigKey,
+    };
+    use std::borrow::Cow;
+
+    fn example_zone() -> PrimaryZone {
+        let records = ZoneParser::new(None)
+            .parse_file("zones/example.com.zone")
+            .unwrap();
+        PrimaryZone::new(name("example.com"), records).unwrap()
+    }
+
+    fn record(
+        owner: &str,
+        resource_class: ResourceClass,
+        time_to_live: u32,
+        payload: ResourcePayload<'static>,
+    ) -> Resource<'static> {
+        Resource::new(name(owner), resource_class, time_to_live, payload)
+    }
+
+    /// A record without RDATA, as used to refer to a whole RRset
+    fn empty(owner: &str, resource_class: ResourceClass, resource_type: u16) -> Resource<'static> {
+        record(
+            owner,
+            resource_class,
+            0,
+            ResourcePayload::Unknown {
+                resource_type,
+                data: Cow::Borrowed(&[]),
+            },
+        )
+    }
+
+    fn address(
+        owner: &str,
+        resource_class: ResourceClass,
+        time_to_live: u32,
+        address: &str,
+    ) -> Resource<'static> {
+        record(
+            owner,
+            resource_class,
+            time_to_live,
+            ResourcePayload::Address(address.parse().unwrap()),
+        )
+    }
+
+    fn update_message(zone: &str, prerequisites: &[Resource], updates: &[Resource]) -> Vec<u8> {
+        let mut header = Header::new();
+        header.id = 0x4321;
+        header.operation_code = OperationCode::Update;
+        header.question_count = 1;
+        header.answer_count = prerequisites.len() as u16;
+        header.authority_count = updates.len() as u16;
+        let mut message = Vec::new();
+        header.write_header(&mut message).unwrap();
+        let question = Question {
+            domain_name: name(zone),
+            question_type: QuestionType::StartAuthority,
+            question_class: QuestionClass::Internet,
+        };
+        let mut writer = PacketWriter::with_data(message);
+        writer.write_question(&question).unwrap();
+        for record in prerequisites.iter().chain(updates) {
+            writer.write_resource(record).unwrap();
+        }
+        writer.into_inner()
+    }
+
+    fn answer(server: &mut TransferServer, request: &[u8]) -> ResponseCode {
+        let response = server.answer_update(request).unwrap();
+        let packet = DnsParser::new().parse_packet(&response).unwrap();
+        assert_eq!(packet.header.id, 0x4321);
+        assert!(matches!(
+            packet.header.operation_code,
+            OperationCode::Update
+        ));
+        packet.header.response_code
+    }
+
+    fn addresses(server: &TransferServer, owner: &str) -> Vec<String> {
+        let zone = server.zone(&name("example.com")).unwrap();
+        rrset(zone.records(), &name(owner), 1)
+            .map(|record| record.payload.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_update_zone() {
+        let mut server = TransferServer::new();
+        let mut zone = example_zone();
+        let first_serial = zone.serial();
+        zone.update_policy(UpdatePolicy::Anyone);
+        server.add_zone(zone);
+        let serial = |server: &TransferServer| server.zone(&name("example.com")).unwrap().serial();
+
+        // www exists so the new host is added and the serial moves on by one
+        let request = update_message(
+            "example.com",
+            &[empty("www.example.com", ResourceClass::Any, ANY_TYPE)],
+            &[address(
+                "new.example.com",
+                ResourceClass::Internet,
+                300,
+                "192.0.2.50",
+            )],
+        );
+        assert_eq!(answer(&mut server, &request), ResponseCode::NOERROR);
+        assert_eq!(addresses(&server, "new.example.com"), ["192.0.2.50"]);
+        assert_eq!(serial(&server), first_serial + 1);
+        assert!(server
+            .zone(&name("example.com"))
+            .unwrap()
+            .diffs_since(first_serial)
+            .is_some());
+
+        // Failed prerequisites leave the zone alone
+        let refused = [
+            (
+                empty("www.example.com", ResourceClass::None, ANY_TYPE),
+                ResponseCode::YXDOMAIN,
+            ),
+            (
+                empty("missing.example.com", ResourceClass::Any, ANY_TYPE),
+                ResponseCode::NXDOMAIN,
+            ),
+            (
+                empty("www.example.com", ResourceClass::None, 1),
+                ResponseCode::YXRRSET,
+            ),
+            (
+                empty("www.example.com", ResourceClass::Any, 28),
+                ResponseCode::NXRRSET,
+            ),
+            (
+                address("www.example.com", ResourceClass::Internet, 0, "192.0.2.11"),
+                ResponseCode::NXRRSET,
+            ),
+            (
+                address("www.example.org", ResourceClass::Any, 0, "192.0.2.11"),
+                ResponseCode::NOTZONE,
+            ),
+            (
+                address("www.example.com", ResourceClass::Any, 0, "192.0.2.10"),
+                ResponseCode::FORMERR,
+            ),
+        ];
+        for (prerequisite, response_code) in refused.iter() {
+            let request = update_message(
+                "example.com",
+                std::slice::from_ref(prerequisite),
+                &[address(
+                    "new.example.com",
+                    ResourceClass::Internet,
+                    300,
+                    "192.0.2.51",
+                )],
+            );
+            assert_eq!(answer(&mut server, &request), *response_code);
+        }
+        assert_eq!(addresses(&server, "new.example.com"), ["192.0.2.50"]);
+        assert_eq!(serial(&server), first_serial + 1);
+
+        // The exact RRset passes, then a record is added, one deleted and www removed entirely
+        let request = update_message(
+            "example.com",
+            &[address(
+                "www.example.com",
+                ResourceClass::Internet,
+                0,
+                "192.0.2.10",
+            )],
+            &[
+                address(
+                    "new.example.com",
+                    ResourceClass::Internet,
+                    300,
+                    "192.0.2.51",
+                ),
+                address("new.example.com", ResourceClass::None, 0, "192.0.2.50"),
+                empty("www.example.com", ResourceClass::Any, 1),
+            ],
+        );
+        assert_eq!(answer(&mut server, &request), ResponseCode::NOERROR);
+        assert_eq!(addresses(&server, "new.example.com"), ["192.0.2.51"]);
+        assert!(addresses(&server, "www.example.com").is_empty());
+        assert_eq!(serial(&server), first_serial + 2);
+
+        // The apex keeps its SOA and name servers, and names outside the zone can't be touched
+        let request = update_message(
+            "example.com",
+            &[],
+            &[empty("example.com", ResourceClass::Any, ANY_TYPE)],
+        );
+        assert_eq!(answer(&mut server, &request), ResponseCode::NOERROR);
+        let zone = server.zone(&name("example.com")).unwrap();
+        assert_eq!(rrset(zone.records(), &name("example.com"), 2).count(), 2);
+        assert_eq!(rrset(zone.records(), &name("example.com"), 16).count(), 0);
+        let request = update_message(
+            "example.com",
+            &[],
+            &[address(
+                "www.example.org",
+                ResourceClass::Internet,
+                300,
+                "192.0.2.1",
+            )],
+        );
+        assert_eq!(answer(&mut server, &request), ResponseCode::NOTZONE);
+        let request = update_message("example.org", &[], &[]);
+        assert_eq!(answer(&mut server, &request), ResponseCode::NOTAUTH);
+
+        // Adding an address that is already there changes nothing
+        let before = serial(&server);
+        let request = update_message(
+            "example.com",
+            &[],
+            &[address(
+                "new.example.com",
+                ResourceClass::Internet,
+                300,
+                "192.0.2.51",
+            )],
+        );
+        assert_eq!(answer(&mut server, &request), ResponseCode::NOERROR);
+        assert_eq!(serial(&server), before);
+    }
+
+    #[test]
+    fn test_update_authorization() {
+        let key = TsigKey::new(
+            name("update.example.com"),
+            TsigAlgorithm::HmacSha256,
+            vec![3; 32],
+        );
+        let mut keyring = Keyring::new();
+        keyring.add(key.clone());
+        let mut server = TransferServer::new();
+        server.add_zone(example_zone());
+        let request = update_message(
+            "example.com",
+            &[],
+            &[address(
+                "new.example.com",
+                ResourceClass::Internet,
+                300,
+                "192.0.2.50",
+            )],
+        );
+        assert_eq!(answer(&mut server, &request), ResponseCode::REFUSED);
+
+        server
+            .zone_mut(&name("example.com"))
+            .unwrap()
+            .update_policy(UpdatePolicy::Signed(keyring));
+        assert_eq!(answer(&mut server, &request), ResponseCode::REFUSED);
+        let mut wrong_key = request.clone();
+        TsigSession::new(TsigKey::new(
+            name("update.example.com"),
+            TsigAlgorithm::HmacSha256,
+            vec![4; 32],
+        ))
+        .sign(&mut wrong_key)
+        .unwrap();
+        assert_eq!(answer(&mut server, &wrong_key), ResponseCode::NOTAUTH);
+        assert!(addresses(&server, "new.example.com").is_empty());
+
+        let mut signed = request;
+        let mut session = TsigSession::new(key);
+        session.sign(&mut signed).unwrap();
+        let response = server.answer_update(&signed).unwrap();
+        let packet = DnsParser::new()
+            .parse_signed_packet(&response, &mut session)
+            .unwrap();
+        assert_eq!(packet.header.response_code, ResponseCode::NOERROR);
+        assert_eq!(addresses(&server, "new.example.com"), ["192.0.2.50"]);
+    }
+
+    #[test]
+    fn test_journal() {
+        let journal = std::env::temp_dir().join(format!("pp-journal-{}.jnl", std::process::id()));
+        let _ = std::fs::remove_file(&journal);
+        let mut zone = example_zone();
+        zone.update_policy(UpdatePolicy::Anyone)
+            .journal(&journal)
+            .unwrap();
+        let first_serial = zone.serial();
+        let mut server = TransferServer::new();
+        server.add_zone(zone);
+        for (number, host) in ["192.0.2.50", "192.0.2.51"].iter().enumerate() {
+            let request = update_message(
+                "example.com",
+                &[],
+                &[
+                    address("new.example.com", ResourceClass::Internet, 300, host),
+                    address(
+                        &format!("host{}.example.com", number),
+                        ResourceClass::Internet,
+                        60,
+                        host,
+                    ),
+                ],
+            );
+            assert_eq!(answer(&mut server, &request), ResponseCode::NOERROR);
+        }
+        let request = update_message(
+            "example.com",
+            &[],
+            &[address(
+                "new.example.com",
+                ResourceClass::None,
+                0,
+                "192.0.2.50",
+            )],
+        );
+        assert_eq!(answer(&mut server, &request), ResponseCode::NOERROR);
+
+        // Loading the zone file again and replaying the journal gets back to the same zone
+        let mut restarted = example_zone();
+        restarted.journal(&journal).unwrap();
+        let updated = server.zone(&name("example.com")).unwrap();
+        assert_eq!(restarted.serial(), first_serial + 3);
+        assert_eq!(restarted.records().len(), updated.records().len());
+        assert!(restarted
+            .records()
+            .iter()
+            .all(|record| updated.records().contains(record)));
+        assert_eq!(restarted.diffs_since(first_serial).unwrap().len(), 3);
+        assert_eq!(ZoneDiff::read_journal(&journal).unwrap().len(), 3);
+        std::fs::remove_file(&journal).unwrap();
+    }
+}
@@ -0,0 +1,513 @@
+use super::{
+    builders::PacketWriter, framing, proxy::lock, spoofing, tls, DnsParser, DomainName, Header,
+    HealthCheck, Question, QuestionClass, QuestionType, ResourceType, ResponseCode, StreamReader,
+    StreamWriter, TlsUpstream, Upstream, UpstreamHealth, UpstreamState, UpstreamStrategy,
+};
+use crate::error::{Error, ErrorKind};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::{
+    fmt::Display,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// How long it takes an SRTT that isn't measured again to count for half, so that slow upstreams are tried again now and then
+const SRTT_HALF_LIFE: Duration = Duration::from_secs(10);
+
+fn unspecified(address: SocketAddr) -> SocketAddr {
+    match address {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    }
+}
+
+impl UpstreamStrategy {
+    pub fn from_name(name: &str) -> Option<UpstreamStrategy> {
+        let strategy = match name.to_ascii_lowercase().as_str() {
+            "failover" => UpstreamStrategy::Failover,
+            "round-robin" => UpstreamStrategy::RoundRobin,
+            "weighted" => UpstreamStrategy::Weighted,
+            "lowest-latency" => UpstreamStrategy::LowestLatency,
+            _ => return None,
+        };
+        Some(strategy)
+    }
+
+    /// Picks the index of an upstream that is up, None when every upstream is down
+    /// The turn counts queries and decides whose turn it is for round robin
+    pub fn choose(&self, upstreams: &[Upstream], turn: usize) -> Option<usize> {
+        let candidates = (0..upstreams.len())
+            .filter(|index| upstreams[*index].state() == UpstreamState::Up)
+            .collect::<Vec<_>>();
+        let first = *candidates.first()?;
+        let chosen = match self {
+            UpstreamStrategy::Failover => first,
+            UpstreamStrategy::RoundRobin => candidates[turn % candidates.len()],
+            UpstreamStrategy::Weighted => {
+                let total = candidates
+                    .iter()
+                    .map(|index| upstreams[*index].weight as u64)
+                    .sum::<u64>();
+                let mut random = [0u8; 8];
+                if total == 0 || SystemRandom::new().fill(&mut random).is_err() {
+                    return Some(candidates[turn % candidates.len()]);
+                }
+                let mut point = u64::from_be_bytes(random) % total;
+                let mut chosen = first;
+                for index in &candidates {
+                    let weight = upstreams[*index].weight as u64;
+                    if point < weight {
+                        chosen = *index;
+                        break;
+                    }
+                    point -= weight;
+                }
+                chosen
+            }
+            UpstreamStrategy::LowestLatency => {
+                // Upstreams that haven't answered yet come first so that they get measured
+                let now = Instant::now();
+                *candidates
+                    .iter()
+                    .min_by_key(|index| upstreams[**index].aged_srtt(now))
+                    .unwrap_or(&first)
+            }
+        };
+        Some(chosen)
+    }
+}
+
+impl Display for UpstreamState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpstreamState::Up => write!(f, "up"),
+            UpstreamState::Down => write!(f, "down"),
+        }
+    }
+}
+
+impl HealthCheck {
+    /// Asks for the NS records of the root every five seconds, three failures mark an upstream down and two successes up
+    pub fn new() -> HealthCheck {
+        HealthCheck {
+            name: DomainName::root(),
+            question_type: ResourceType::NameServer,
+            interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(2),
+            down_after: 3,
+            up_after: 2,
+        }
+    }
+
+    pub fn name(&mut self, name: DomainName<'static>) -> &mut Self {
+        self.name = name;
+        self
+    }
+
+    pub fn question_type(&mut self, question_type: ResourceType) -> &mut Self {
+        self.question_type = question_type;
+        self
+    }
+
+    pub fn interval(&mut self, interval: Duration) -> &mut Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn down_after(&mut self, down_after: u32) -> &mut Self {
+        self.down_after = down_after.max(1);
+        self
+    }
+
+    pub fn up_after(&mut self, up_after: u32) -> &mut Self {
+        self.up_after = up_after.max(1);
+        self
+    }
+
+    fn query(&self, id: u16) -> Result<Vec<u8>, Error> {
+        let mut header = Header::new();
+        header.id = id;
+        header.recursion_desired = true;
+        header.question_count = 1;
+        let mut message = Vec::with_capacity(512);
+        header.write_header(&mut message)?;
+        let question = Question {
+            domain_name: self.name.clone(),
+            question_type: QuestionType::from(u16::from(self.question_type)),
+            question_class: QuestionClass::Internet,
+        };
+        let mut writer = PacketWriter::with_data(message);
+        writer.write_question(&question)?;
+        Ok(writer.into_inner())
+    }
+
+    /// Sends a probe and returns how long the answer took, over TLS to a DNS over TLS upstream
+    /// and otherwise from a socket of its own
+    /// Only NOERROR and NXDOMAIN count as answers, an upstream answering SERVFAIL or REFUSED can't resolve
+    pub fn probe(&self, upstream: &Upstream) -> Result<Duration, Error> {
+        let query = self.query(spoofing::random_id()?)?;
+        let sent = Instant::now();
+        let answer = upstream.exchange(&query, self.timeout)?;
+        let header = DnsParser::new().read_header(&answer)?;
+        match header.response_code {
+            ResponseCode::NOERROR | ResponseCode::NXDOMAIN => Ok(sent.elapsed()),
+            response_code => Err(Error::new(ErrorKind::ConnectionFailed(format!(
+                "{}: the probe was answered with {:?}",
+                upstream.address, response_code
+            )))),
+        }
+    }
+}
+
+impl Upstream {
+    /// An upstream starts out up
+    pub fn new(address: SocketAddr, weight: u32) -> Upstream {
+        Upstream {
+            address,
+            weight,
+            tls: None,
+            streams: Mutex::new(Vec::new()),
+            health: Mutex::new(UpstreamHealth {
+                srtt: None,
+                measured: Instant::now(),
+                state: UpstreamState::Up,
+                failures: 0,
+                successes: 0,
+            }),
+        }
+    }
+
+    /// A DNS over TLS upstream, every query is sent to it over TLS
+    pub fn over_tls(
+        address: SocketAddr,
+        weight: u32,
+        tls: &TlsUpstream,
+    ) -> Result<Upstream, Error> {
+        let mut upstream = Upstream::new(address, weight);
+        upstream.tls = Some(tls.client_config()?);
+        Ok(upstream)
+    }
+
+    pub fn is_tls(&self) -> bool {
+        self.tls.is_some()
+    }
+
+    /// Sends a query outside of the proxy's own forwarding and waits for its answer, used by probes and key lookups
+    /// A DNS over TLS upstream is asked over a connection of its own, so that the handshake and the certificate are checked too
+    /// A truncated answer over UDP is asked for again over TCP
+    pub fn exchange(&self, query: &[u8], timeout: Duration) -> Result<Vec<u8>, Error> {
+        if self.tls.is_some() {
+            return self.exchange_over_stream(query, timeout);
+        }
+        let answer = self.exchange_over_udp(query, timeout)?;
+        // TC is the second lowest bit of the third byte
+        match answer[2] & 0x02 {
+            0 => Ok(answer),
+            _ => self.exchange_over_stream(query, timeout),
+        }
+    }
+
+    /// Whether a message answers the query
+    fn answers(query: &[u8], answer: &[u8]) -> bool {
+        answer.len() >= 12
+            && answer[..2] == query[..2]
+            && spoofing::same_question(query, answer, false)
+    }
+
+    fn exchange_over_udp(&self, query: &[u8], timeout: Duration) -> Result<Vec<u8>, Error> {
+        let connection_failed = |error: std::io::Error| {
+            Error::new(ErrorKind::ConnectionFailed(format!(
+                "{}: {}",
+                self.address, error
+            )))
+        };
+        let socket = UdpSocket::bind(unspecified(self.address)).map_err(connection_failed)?;
+        let sent = Instant::now();
+        socket
+            .send_to(query, self.address)
+            .map_err(connection_failed)?;
+        let mut buffer = [0u8; 4096];
+        loop {
+            let remaining = timeout.saturating_sub(sent.elapsed());
+            if remaining.is_zero() {
+                return Err(Error::new(ErrorKind::ConnectionFailed(format!(
+                    "{}: the query wasn't answered in time",
+                    self.address
+                ))));
+            }
+            socket
+                .set_read_timeout(Some(remaining))
+                .map_err(connection_failed)?;
+            let (size, from) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(error)
+                    if matches!(
+                        error.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue
+                }
+                Err(error) => return Err(connection_failed(error)),
+            };
+            if from == self.address && Upstream::answers(query, &buffer[..size]) {
+                return Ok(buffer[..size].to_vec());
+            }
+        }
+    }
+
+    fn exchange_over_stream(&self, query: &[u8], timeout: Duration) -> Result<Vec<u8>, Error> {
+        let (mut reader, mut writer) = self.connect(timeout)?;
+        framing::write_message(&mut writer, query)?;
+        loop {
+            match framing::read_message(&mut reader)? {
+                Some(answer) if Upstream::answers(query, &answer) => return Ok(answer),
+                Some(_) => continue,
+                None => {
+                    return Err(Error::new(ErrorKind::ConnectionFailed(format!(
+                        "{}: closed before the query was answered",
+                        self.address
+                    ))))
+                }
+            }
+        }
+    }
+
+    /// Opens a connection with both timeouts set, the handshake is done for a DNS over TLS upstream
+    pub fn connect(&self, timeout: Duration) -> Result<(StreamReader, StreamWriter), Error> {
+        let connection_failed = |error: std::io::Error| {
+            Error::new(ErrorKind::ConnectionFailed(format!(
+                "{}: {}",
+                self.address, error
+            )))
+        };
+        let socket = TcpStream::connect_timeout(&self.address, timeout)
+            .and_then(|socket| {
+                socket.set_nodelay(true)?;
+                socket.set_read_timeout(Some(timeout))?;
+                socket.set_write_timeout(Some(timeout))?;
+                Ok(socket)
+            })
+            .map_err(connection_failed)?;
+        match &self.tls {
+            Some((config, name)) => tls::connect(socket, config.clone(), name.clone()),
+            None => {
+                let reader = socket.try_clone().map_err(connection_failed)?;
+                Ok((StreamReader::Tcp(reader), StreamWriter::Tcp(socket)))
+            }
+        }
+    }
+
+    /// Binds a socket for a single query on a port the system picks at random, RFC 5452 section 9.2
+    /// The socket is connected so that datagrams from anywhere but the upstream never reach us
+    pub fn socket(&self) -> Result<UdpSocket, Error> {
+        let connection_failed = |error: std::io::Error| {
+            Error::new(ErrorKind::ConnectionFailed(format!(
+                "{}: {}",
+                self.address, error
+            )))
+        };
+        let socket = UdpSocket::bind(unspecified(self.address)).map_err(connection_failed)?;
+        socket.connect(self.address).map_err(connection_failed)?;
+        Ok(socket)
+    }
+
+    /// The SRTT halved for every half life since it was last measured, zero until the upstream has answered
+    fn aged_srtt(&self, now: Instant) -> Duration {
+        let health = lock(&self.health);
+        let age = now.saturating_duration_since(health.measured);
+        health.srtt.map_or(Duration::ZERO, |srtt| {
+            srtt.mul_f64(0.5f64.powf(age.as_secs_f64() / SRTT_HALF_LIFE.as_secs_f64()))
+        })
+    }
+
+    pub fn state(&self) -> UpstreamState {
+        lock(&self.health).state
+    }
+
+    /// Records how long an answer to a client query took, RFC 6298 section 2
+    pub fn answered(&self, round_trip: Duration) {
+        let mut health = lock(&self.health);
+        health.srtt = Some(match health.srtt {
+            Some(srtt) => srtt * 7 / 8 + round_trip / 8,
+            None => round_trip,
+        });
+        health.measured = Instant::now();
+        if health.state == UpstreamState::Up {
+            health.failures = 0;
+        }
+    }
+
+    /// Records a client query that wasn't answered in time, which counts as an answer taking the whole timeout
+    /// Returns the state the upstream changed to, if it did
+    pub fn failed(&self, timeout: Duration, health_check: &HealthCheck) -> Option<UpstreamState> {
+        let mut health = lock(&self.health);
+        health.srtt = Some(match health.srtt {
+            Some(srtt) => srtt * 7 / 8 + timeout / 8,
+            None => timeout,
+        });
+        health.measured = Instant::now();
+        record_failure(&mut health, health_check)
+    }
+
+    /// Records the result of a probe, only probes bring an upstream that is down back up
+    /// Returns the state the upstream changed to, if it did
+    pub fn probed(
+        &self,
+        result: &Result<Duration, Error>,
+        health_check: &HealthCheck,
+    ) -> Option<UpstreamState> {
+        let mut health = lock(&self.health);
+        if result.is_err() {
+            return record_failure(&mut health, health_check);
+        }
+        health.failures = 0;
+        health.successes += 1;
+        if health.state == UpstreamState::Down && health.successes >= health_check.up_after {
+            return change_state(&mut health, UpstreamState::Up);
+        }
+        None
+    }
+}
+
+fn record_failure(
+    health: &mut UpstreamHealth,
+    health_check: &HealthCheck,
+) -> Option<UpstreamState> {
+    health.successes = 0;
+    health.failures += 1;
+    if health.state == UpstreamState::Up && health.failures >= health_check.down_after {
+        return change_state(health, UpstreamState::Down);
+    }
+    None
+}
+
+fn change_state(health: &mut UpstreamHealth, state: UpstreamState) -> Option<UpstreamState> {
+    health.state = state;
+    health.failures = 0;
+    health.successes = 0;
+    Some(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upstreams(weights: &[u32]) -> Vec<Upstream> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(index, weight)| {
+                let address = SocketAddr::from(([192, 0, 2, index as u8 + 1], 53));
+                Upstream::new(address, *weight)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_strategies() {
+        let upstreams = upstreams(&[1, 3]);
+        let choices = |strategy: UpstreamStrategy| {
+            (0..400)
+                .map(|turn| strategy.choose(&upstreams, turn).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert!(choices(UpstreamStrategy::Failover)
+            .iter()
+            .all(|index| *index == 0));
+        assert_eq!(&choices(UpstreamStrategy::RoundRobin)[..4], &[0, 1, 0, 1]);
+        let second = choices(UpstreamStrategy::Weighted)
+            .iter()
+            .filter(|index| **index == 1)
+            .count();
+        assert!((240..360).contains(&second), "{} of 400", second);
+
+        // The faster upstream gets the queries once both have been measured
+        upstreams[0].answered(Duration::from_millis(80));
+        assert_eq!(
+            UpstreamStrategy::LowestLatency.choose(&upstreams, 0),
+            Some(1)
+        );
+        upstreams[1].answered(Duration::from_millis(20));
+        assert_eq!(
+            UpstreamStrategy::LowestLatency.choose(&upstreams, 0),
+            Some(1)
+        );
+        assert!(!choices(UpstreamStrategy::LowestLatency).contains(&0));
+        // The slower one is worth measuring again once its SRTT hasn't been for long enough
+        lock(&upstreams[0].health).measured -= SRTT_HALF_LIFE * 3;
+        assert_eq!(
+            UpstreamStrategy::LowestLatency.choose(&upstreams, 0),
+            Some(0)
+        );
+
+        // Traffic moves away from an upstream that is down, with every upstream down there is nothing to choose
+        let health_check = HealthCheck::new();
+        for _ in 0..health_check.down_after {
+            upstreams[0].failed(Duration::from_secs(5), &health_check);
+        }
+        assert_eq!(upstreams[0].state(), UpstreamState::Down);
+        assert_eq!(UpstreamStrategy::Failover.choose(&upstreams, 0), Some(1));
+        assert_eq!(UpstreamStrategy::RoundRobin.choose(&upstreams, 0), Some(1));
+        assert_eq!(UpstreamStrategy::RoundRobin.choose(&upstreams, 1), Some(1));
+        for _ in 0..health_check.down_after {
+            upstreams[1].failed(Duration::from_secs(5), &health_check);
+        }
+        assert_eq!(UpstreamStrategy::Failover.choose(&upstreams, 0), None);
+    }
+
+    #[test]
+    fn test_circuit_breaking() {
+        let upstream = &upstreams(&[1])[0];
+        let mut health_check = HealthCheck::new();
+        health_check.down_after(2).up_after(3);
+        let success = Ok(Duration::from_millis(10));
+        let failure = Err(Error::new(ErrorKind::ConnectionFailed(String::new())));
+
+        // Failures only count when they come in a row
+        upstream.probed(&failure, &health_check);
+        upstream.probed(&success, &health_check);
+        upstream.failed(Duration::from_secs(1), &health_check);
+        assert_eq!(upstream.state(), UpstreamState::Up);
+        assert_eq!(
+            upstream.probed(&failure, &health_check),
+            Some(UpstreamState::Down)
+        );
+
+        // Late answers to client queries don't bring it back up, successful probes in a row do
+        upstream.answered(Duration::from_millis(10));
+        upstream.probed(&success, &health_check);
+        upstream.probed(&success, &health_check);
+        upstream.probed(&failure, &health_check);
+        upstream.probed(&success, &health_check);
+        upstream.probed(&success, &health_check);
+        assert_eq!(upstream.state(), UpstreamState::Down);
+        assert_eq!(
+            upstream.probed(&success, &health_check),
+            Some(UpstreamState::Up)
+        );
+        assert_eq!(upstream.state(), UpstreamState::Up);
+    }
+
+    #[test]
+    fn test_smoothed_round_trip() {
+        let upstream = &upstreams(&[1])[0];
+        assert_eq!(lock(&upstream.health).srtt, None);
+        upstream.answered(Duration::from_millis(80));
+        assert_eq!(lock(&upstream.health).srtt, Some(Duration::from_millis(80)));
+        upstream.answered(Duration::from_millis(160));
+        assert_eq!(lock(&upstream.health).srtt, Some(Duration::from_millis(90)));
+        upstream.failed(Duration::from_millis(890), &HealthCheck::new());
+        assert_eq!(
+            lock(&upstream.health).srtt,
+            Some(Duration::from_millis(190))
+        );
+        assert_eq!(upstream.state(), UpstreamState::Up);
+    }
+}
@@ -0,0 +1,1514 @@
+use super::{
+    builders::DnsResponseBuilder,
+    dnssec::{self, OPT_OUT_FLAG, ZONE_KEY_FLAG},
+    edns,
+    zone::ZoneParser,
+    DnsPacket, DnsParser, DomainName, ExtendedError, QuestionType, RecordSet, Resource,
+    ResourcePayload, ResourceType, ResponseCode, TrustAnchors, TypeBitmap, ValidationResult,
+    Validator,
+};
+use crate::error::{Error, ErrorKind};
+use crate::helper::decode_base32_hex;
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// RFC 9276 lets validators treat NSEC3 records with more iterations than this as insecure
+const MAXIMUM_ITERATIONS: u16 = 150;
+// The CNAME chain in an answer is only followed this far
+const MAXIMUM_CHAIN_LENGTH: usize = 16;
+
+const NAME_SERVER: u16 = ResourceType::NameServer.code();
+const CANONICAL_NAME: u16 = ResourceType::CanonicalName.code();
+const START_AUTHORITY: u16 = ResourceType::StartAuthority.code();
+const DELEGATION_SIGNER: u16 = ResourceType::DelegationSigner.code();
+const DELEGATION_NAME: u16 = ResourceType::DelegationName.code();
+const DNS_KEY: u16 = ResourceType::DnsKey.code();
+const NEXT_SECURE: u16 = ResourceType::NextSecure.code();
+const NEXT_SECURE_3: u16 = ResourceType::NextSecure3.code();
+const ALL: u16 = QuestionType::All.code();
+const TRANSACTION_SIGNATURE: u16 = ResourceType::TransactionSignature.code();
+
+impl TrustAnchors {
+    /// Reads the anchors from a zone file that holds nothing but DS and DNSKEY records
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<TrustAnchors, Error> {
+        let anchors = ZoneParser::new(None).parse_file(path)?;
+        TrustAnchors::new(anchors)
+    }
+
+    pub fn new(anchors: Vec<Resource<'static>>) -> Result<TrustAnchors, Error> {
+        for anchor in &anchors {
+            match anchor.payload {
+                ResourcePayload::DelegationSigner { .. } | ResourcePayload::DnsKey { .. } => (),
+                _ => {
+                    return Err(Error::new(ErrorKind::InvalidTrustAnchor(format!(
+                        "{} has a {} record, only DS and DNSKEY records can be anchors",
+                        anchor.resource_name,
+                        anchor.resource_type().mnemonic()
+                    ))))
+                }
+            }
+        }
+        Ok(TrustAnchors { anchors })
+    }
+
+    /// The deepest zone with an anchor at or above the name
+    fn closest_zone(&self, name: &DomainName) -> Option<&DomainName<'static>> {
+        self.anchors
+            .iter()
+            .map(|anchor| &anchor.resource_name)
+            .filter(|zone| name.is_subdomain_of(zone))
+            .max_by_key(|zone| zone.len())
+    }
+
+    /// Returns true if an anchor for the zone is a copy of the key or a DS record matching it
+    fn vouches_for(&self, zone: &DomainName, key: &ResourcePayload) -> bool {
+        self.anchors
+            .iter()
+            .filter(|anchor| anchor.resource_name.eq_ignore_case(zone))
+            .any(|anchor| match &anchor.payload {
+                ResourcePayload::DnsKey { .. } => key == &anchor.payload,
+                delegation => delegation_matches(zone, delegation, key),
+            })
+    }
+}
+
+impl ValidationResult {
+    /// Combines the results for two parts of a response, the least secure result wins
+    pub fn combine(self, other: ValidationResult) -> ValidationResult {
+        let rank = |result: &ValidationResult| match result {
+            ValidationResult::Secure => 0,
+            ValidationResult::Indeterminate => 1,
+            ValidationResult::Insecure => 2,
+            ValidationResult::Bogus(_) => 3,
+        };
+        if rank(&other) > rank(&self) {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+impl<'r, 'a> RecordSet<'r, 'a> {
+    /// Groups records into RRsets, signatures join the RRset of the type they cover
+    pub fn group(records: &'r [Resource<'a>]) -> Vec<RecordSet<'r, 'a>> {
+        let mut sets: Vec<RecordSet> = Vec::new();
+        for record in records {
+            let (resource_type, is_signature) = match &record.payload {
+                ResourcePayload::ResourceSignature { type_covered, .. } => (*type_covered, true),
+                payload => (payload.type_code(), false),
+            };
+            let position = sets.iter().position(|set| {
+                set.resource_type == resource_type && set.name.eq_ignore_case(&record.resource_name)
+            });
+            let set = match position {
+                Some(position) => &mut sets[position],
+                None => {
+                    sets.push(RecordSet {
+                        name: &record.resource_name,
+                        resource_type,
+                        records: Vec::new(),
+                        signatures: Vec::new(),
+                    });
+                    sets.last_mut().unwrap()
+                }
+            };
+            if is_signature {
+                set.signatures.push(record);
+            } else {
+                set.records.push(record);
+            }
+        }
+        // Signatures without the records they cover prove nothing
+        sets.retain(|set| !set.records.is_empty());
+        sets
+    }
+
+    /// The label count from a signature showing the records were expanded from a wildcard
+    fn wildcard_labels(&self) -> Option<usize> {
+        let owner_labels = self.records[0].signature_labels();
+        self.signatures
+            .iter()
+            .filter_map(|signature| match signature.payload {
+                ResourcePayload::ResourceSignature { labels, .. } => Some(labels as usize),
+                _ => None,
+            })
+            .find(|labels| *labels < owner_labels)
+    }
+}
+
+impl Validator {
+    pub fn new(trust_anchors: TrustAnchors) -> Validator {
+        Validator {
+            trust_anchors,
+            trusted_keys: Vec::new(),
+            trusted_delegations: Vec::new(),
+            insecure_zones: Vec::new(),
+            validation_time: None,
+        }
+    }
+
+    /// Validates the answer to the first question of a response
+    /// DNSKEY and DS records in the response extend the chain of trust and are remembered for later responses
+    pub fn validate(&mut self, packet: &DnsPacket) -> ValidationResult {
+        let question = match packet.questions.first() {
+            Some(question) => question,
+            None => return ValidationResult::Indeterminate,
+        };
+        let question_type = u16::from(question.question_type);
+        let answers = RecordSet::group(&packet.answers);
+        let authority = RecordSet::group(&packet.authority);
+        self.learn(&answers, &authority, &question.domain_name, question_type);
+
+        let mut result = ValidationResult::Secure;
+        let mut denials = Vec::new();
+        // The NS records of a referral belong to the child zone and are never signed by the parent
+        let signed_sets = answers.iter().chain(
+            authority
+                .iter()
+                .filter(|set| set.resource_type != NAME_SERVER || !set.signatures.is_empty()),
+        );
+        for set in signed_sets {
+            let status = self.check_record_set(set);
+            let is_denial = matches!(set.resource_type, NEXT_SECURE | NEXT_SECURE_3);
+            if is_denial && status == ValidationResult::Secure {
+                denials.extend(set.records.iter().copied());
+            }
+            result = result.combine(status);
+        }
+        // RFC 4035 section 5.3.4, an answer expanded from a wildcard needs proof that the name itself does not exist
+        for set in &answers {
+            if self.zone_status(set.name) != ValidationResult::Secure {
+                continue;
+            }
+            if let Some(labels) = set.wildcard_labels() {
+                result = result.combine(prove_wildcard_expansion(set.name, labels, &denials));
+            }
+        }
+
+        // Anything missing at the end of a CNAME chain has to be denied
+        let mut name = &question.domain_name;
+        for _ in 0..MAXIMUM_CHAIN_LENGTH {
+            let target = answers
+                .iter()
+                .filter(|set| set.resource_type == CANONICAL_NAME && set.name.eq_ignore_case(name))
+                .find_map(|set| match &set.records[0].payload {
+                    ResourcePayload::CanonicalName(target) => Some(target),
+                    _ => None,
+                });
+            match target {
+                Some(target) => name = target,
+                None => break,
+            }
+        }
+        let answered = answers.iter().any(|set| {
+            set.name.eq_ignore_case(name)
+                && (set.resource_type == question_type || question_type == ALL)
+        });
+        if !answered {
+            let proof = match packet.header.response_code {
+                ResponseCode::NXDOMAIN => prove_name_error(name, &denials),
+                _ => prove_no_data(name, question_type, &denials),
+            };
+            result = match self.zone_status(name) {
+                ValidationResult::Secure => result.combine(proof),
+                status => result.combine(status),
+            };
+        }
+        result
+    }
+
+    /// Validates a response as it came from the upstream and writes it again, secure data gets the AD bit
+    /// Signed records keep their TTLs only as long as the signatures allow, RFC 4035 section 5.3.3
+    /// A bogus response becomes SERVFAIL with an extended error, the OPT record of any other is copied over as it was
+    pub fn validate_message(
+        &mut self,
+        message: &[u8],
+    ) -> Result<(Vec<u8>, ValidationResult), Error> {
+        let mut packet = DnsParser::new().parse_packet(message)?;
+        let result = self.validate(&packet);
+        for section in [
+            &mut packet.answers,
+            &mut packet.authority,
+            &mut packet.additional,
+        ] {
+            self.cap_time_to_live(section);
+        }
+        let mut builder = DnsResponseBuilder::with_header(packet.header);
+        for question in packet.questions {
+            builder.add_question(question);
+        }
+        for answer in packet.answers {
+            builder.add_answer(answer);
+        }
+        for authority in packet.authority {
+            builder.add_authority(authority);
+        }
+        for additional in packet.additional.into_iter().filter(|record| {
+            !matches!(
+                record.payload.type_code(),
+                edns::OPTIONS_TYPE | TRANSACTION_SIGNATURE
+            )
+        }) {
+            builder.add_additional(additional);
+        }
+        let mut validated = builder.validation_result(result).build_response()?;
+        if !matches!(result, ValidationResult::Bogus(_)) {
+            edns::copy_options_record(message, &mut validated);
+        }
+        Ok((validated, result))
+    }
+
+    /// The zones that signed records in a response and are expected to be secure, but whose keys aren't trusted yet
+    pub fn missing_keys(&self, packet: &DnsPacket) -> Vec<DomainName<'static>> {
+        let mut zones: Vec<DomainName<'static>> = Vec::new();
+        for record in packet.answers.iter().chain(&packet.authority) {
+            let signer_name = match &record.payload {
+                ResourcePayload::ResourceSignature { signer_name, .. } => signer_name,
+                _ => continue,
+            };
+            let missing = record.resource_name.is_subdomain_of(signer_name)
+                && self.zone_status(signer_name) == ValidationResult::Secure
+                && self.keys(signer_name).is_none()
+                && !zones.iter().any(|zone| zone.eq_ignore_case(signer_name));
+            if missing {
+                zones.push(signer_name.clone().into_owned());
+            }
+        }
+        zones
+    }
+
+    /// The type to ask for next to get the keys of a zone, its DS records come first unless an anchor vouches for the keys
+    /// Nothing is asked once the keys are trusted or when the zone isn't expected to be secure
+    pub fn next_key_query(&self, zone: &DomainName) -> Option<u16> {
+        if self.zone_status(zone) != ValidationResult::Secure || self.keys(zone).is_some() {
+            return None;
+        }
+        let anchored = self
+            .trust_anchors
+            .anchors
+            .iter()
+            .any(|anchor| anchor.resource_name.eq_ignore_case(zone));
+        if anchored || self.delegation(zone).is_some() {
+            Some(DNS_KEY)
+        } else {
+            Some(DELEGATION_SIGNER)
+        }
+    }
+
+    /// Lowers the TTL of signed records to the original TTL and the time left before expiration of their signatures
+    fn cap_time_to_live(&self, records: &mut [Resource]) {
+        let now = self.now();
+        let caps: Vec<(DomainName<'static>, u16, u32)> = records
+            .iter()
+            .filter_map(|record| match &record.payload {
+                ResourcePayload::ResourceSignature {
+                    type_covered,
+                    original_ttl,
+                    expiration,
+                    ..
+                } => {
+                    let remaining = (expiration.wrapping_sub(now) as i32).max(0) as u32;
+                    Some((
+                        record.resource_name.clone().into_owned(),
+                        *type_covered,
+                        (*original_ttl).min(remaining),
+                    ))
+                }
+                _ => None,
+            })
+            .collect();
+        for record in records.iter_mut() {
+            let resource_type = match &record.payload {
+                ResourcePayload::ResourceSignature { type_covered, .. } => *type_covered,
+                payload => payload.type_code(),
+            };
+            for (owner, type_covered, cap) in &caps {
+                if *type_covered == resource_type && owner.eq_ignore_case(&record.resource_name) {
+                    record.time_to_live = record.time_to_live.min(*cap);
+                }
+            }
+        }
+    }
+
+    /// Extends the chain of trust with the DNSKEY and DS records in a response, along with any proof that a delegation is unsigned
+    fn learn(
+        &mut self,
+        answers: &[RecordSet],
+        authority: &[RecordSet],
+        question_name: &DomainName,
+        question_type: u16,
+    ) {
+        // Keys and delegations can arrive in any order so keep going until nothing new is learnt
+        loop {
+            let mut learned = false;
+            for set in answers.iter().chain(authority) {
+                learned |= match set.resource_type {
+                    DNS_KEY => self.learn_keys(set),
+                    DELEGATION_SIGNER => self.learn_delegation(set),
+                    _ => false,
+                };
+            }
+            if !learned {
+                break;
+            }
+        }
+        // A referral names the child zone with its NS records, a DS query names it in the question
+        let mut delegations: Vec<&DomainName> = authority
+            .iter()
+            .filter(|set| set.resource_type == NAME_SERVER && set.signatures.is_empty())
+            .map(|set| set.name)
+            .collect();
+        if question_type == DELEGATION_SIGNER {
+            delegations.push(question_name);
+        }
+        let denials: Vec<&Resource> = answers
+            .iter()
+            .chain(authority)
+            .filter(|set| matches!(set.resource_type, NEXT_SECURE | NEXT_SECURE_3))
+            .filter(|set| self.check_record_set(set) == ValidationResult::Secure)
+            .flat_map(|set| set.records.iter().copied())
+            .collect();
+        for name in delegations {
+            let known = self.zone_status(name) != ValidationResult::Secure
+                || self.delegation(name).is_some();
+            if !known && prove_unsigned_delegation(name, &denials) {
+                self.insecure_zones.push(name.clone().into_owned());
+            }
+        }
+    }
+
+    /// Trusts a DNSKEY RRset once it is signed by a key that a trust anchor or secure DS record vouches for
+    fn learn_keys(&mut self, set: &RecordSet) -> bool {
+        let zone = set.name;
+        if self.keys(zone).is_some() {
+            return false;
+        }
+        let delegations = self.delegation(zone).unwrap_or(&[]);
+        let entry_points: Vec<&ResourcePayload> = set
+            .records
+            .iter()
+            .map(|record| &record.payload)
+            .filter(|key| {
+                self.trust_anchors.vouches_for(zone, key)
+                    || delegations
+                        .iter()
+                        .any(|delegation| delegation_matches(zone, delegation, key))
+            })
+            .collect();
+        if entry_points.is_empty() || self.verify_record_set(set, zone, &entry_points).is_err() {
+            return false;
+        }
+        let zone_keys = set
+            .records
+            .iter()
+            .filter(|record| {
+                matches!(record.payload, ResourcePayload::DnsKey { flags, .. } if flags & ZONE_KEY_FLAG != 0)
+            })
+            .map(|record| record.payload.clone().into_owned())
+            .collect();
+        self.trusted_keys
+            .push((zone.clone().into_owned(), zone_keys));
+        true
+    }
+
+    /// Trusts a DS RRset once it is signed by the parent zone
+    fn learn_delegation(&mut self, set: &RecordSet) -> bool {
+        if self.delegation(set.name).is_some()
+            || self.check_record_set(set) != ValidationResult::Secure
+        {
+            return false;
+        }
+        let delegations: Vec<ResourcePayload<'static>> = set
+            .records
+            .iter()
+            .map(|record| record.payload.clone().into_owned())
+            .collect();
+        // RFC 4035 section 5.2, a zone is treated as unsigned when none of its DS records can be used
+        let usable = delegations.iter().any(|delegation| match delegation {
+            ResourcePayload::DelegationSigner {
+                algorithm,
+                digest_type,
+                ..
+            } => {
+                dnssec::is_supported_algorithm(*algorithm)
+                    && dnssec::is_supported_digest(*digest_type)
+            }
+            _ => false,
+        });
+        if !usable {
+            self.insecure_zones.push(set.name.clone().into_owned());
+        }
+        self.trusted_delegations
+            .push((set.name.clone().into_owned(), delegations));
+        true
+    }
+
+    /// Validates a single RRset against the keys of the zone that signed it
+    fn check_record_set(&self, set: &RecordSet) -> ValidationResult {
+        // The signer is the zone the records belong to, so it has to be at or above the owner
+        let zone = set
+            .signatures
+            .iter()
+            .filter_map(|signature| match &signature.payload {
+                ResourcePayload::ResourceSignature { signer_name, .. } => Some(signer_name),
+                _ => None,
+            })
+            .filter(|signer_name| set.name.is_subdomain_of(signer_name))
+            .max_by_key(|signer_name| signer_name.len());
+        let zone = match zone {
+            Some(zone) => zone,
+            None => {
+                return match self.zone_status(set.name) {
+                    ValidationResult::Secure => {
+                        ValidationResult::Bogus(ExtendedError::RrsigsMissing)
+                    }
+                    status => status,
+                }
+            }
+        };
+        match self.keys(zone) {
+            Some(keys) => {
+                let keys: Vec<&ResourcePayload> = keys.iter().collect();
+                match self.verify_record_set(set, zone, &keys) {
+                    Ok(()) => ValidationResult::Secure,
+                    Err(extended_error) => ValidationResult::Bogus(extended_error),
+                }
+            }
+            None => match self.zone_status(zone) {
+                ValidationResult::Secure => ValidationResult::Bogus(ExtendedError::DnsKeyMissing),
+                status => status,
+            },
+        }
+    }
+
+    /// Looks for a signature over the RRset made by one of the keys, returning why none could be verified
+    fn verify_record_set(
+        &self,
+        set: &RecordSet,
+        zone: &DomainName,
+        keys: &[&ResourcePayload],
+    ) -> Result<(), ExtendedError> {
+        let now = self.now();
+        let owner_labels = set.records[0].signature_labels();
+        let mut reason = ExtendedError::RrsigsMissing;
+        for signature in &set.signatures {
+            let (algorithm, labels, expiration, inception, key_tag, signer_name, signature_data) =
+                match &signature.payload {
+                    ResourcePayload::ResourceSignature {
+                        algorithm,
+                        labels,
+                        expiration,
+                        inception,
+                        key_tag,
+                        signer_name,
+                        signature,
+                        ..
+                    } => (
+                        *algorithm,
+                        *labels,
+                        *expiration,
+                        *inception,
+                        *key_tag,
+                        signer_name,
+                        signature,
+                    ),
+                    _ => continue,
+                };
+            if !signer_name.eq_ignore_case(zone) || labels as usize > owner_labels {
+                continue;
+            }
+            // RFC 4034 section 3.1.5, the times use serial number arithmetic so they keep working past 2106
+            if (now.wrapping_sub(inception) as i32) < 0 {
+                reason = ExtendedError::SignatureNotYetValid;
+                continue;
+            }
+            if (expiration.wrapping_sub(now) as i32) < 0 {
+                reason = ExtendedError::SignatureExpired;
+                continue;
+            }
+            if !dnssec::is_supported_algorithm(algorithm) {
+                reason = ExtendedError::UnsupportedDnsKeyAlgorithm;
+                continue;
+            }
+            let data = match dnssec::signed_data(&signature.payload, &set.records) {
+                Ok(data) => data,
+                Err(_) => {
+                    reason = ExtendedError::DnssecBogus;
+                    continue;
+                }
+            };
+            let mut found_key = false;
+            for key in keys {
+                let (flags, public_key) = match key {
+                    ResourcePayload::DnsKey {
+                        flags,
+                        protocol: 3,
+                        algorithm: key_algorithm,
+                        public_key,
+                    } if *key_algorithm == algorithm && key.key_tag() == Some(key_tag) => {
+                        (*flags, public_key)
+                    }
+                    _ => continue,
+                };
+                found_key = true;
+                if flags & ZONE_KEY_FLAG == 0 {
+                    reason = ExtendedError::NoZoneKeyBitSet;
+                } else if dnssec::verify_signature(algorithm, public_key, &data, signature_data) {
+                    return Ok(());
+                } else {
+                    reason = ExtendedError::DnssecBogus;
+                }
+            }
+            if !found_key && reason == ExtendedError::RrsigsMissing {
+                reason = ExtendedError::DnsKeyMissing;
+            }
+        }
+        Err(reason)
+    }
+
+    /// The status a name should have before any of its records are checked
+    /// Names below a trust anchor are expected to be secure unless a zone above them was proven to be unsigned
+    fn zone_status(&self, name: &DomainName) -> ValidationResult {
+        let anchor = match self.trust_anchors.closest_zone(name) {
+            Some(anchor) => anchor,
+            None => return ValidationResult::Indeterminate,
+        };
+        let insecure = self.insecure_zones.iter().any(|zone| {
+            name.is_subdomain_of(zone) && zone.is_subdomain_of(anchor) && zone.len() > anchor.len()
+        });
+        if insecure {
+            ValidationResult::Insecure
+        } else {
+            ValidationResult::Secure
+        }
+    }
+
+    fn keys(&self, zone: &DomainName) -> Option<&[ResourcePayload<'static>]> {
+        self.trusted_keys
+            .iter()
+            .find(|(name, _)| name.eq_ignore_case(zone))
+            .map(|(_, keys)| keys.as_slice())
+    }
+
+    fn delegation(&self, zone: &DomainName) -> Option<&[ResourcePayload<'static>]> {
+        self.trusted_delegations
+            .iter()
+            .find(|(name, _)| name.eq_ignore_case(zone))
+            .map(|(_, delegations)| delegations.as_slice())
+    }
+
+    fn now(&self) -> u32 {
+        self.validation_time.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs() as u32)
+        })
+    }
+}
+
+/// Checks a DS record against a DNSKEY as described in RFC 4035 section 5.2
+fn delegation_matches(
+    zone: &DomainName,
+    delegation: &ResourcePayload,
+    key: &ResourcePayload,
+) -> bool {
+    match (delegation, key) {
+        (
+            ResourcePayload::DelegationSigner {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            },
+            ResourcePayload::DnsKey {
+                algorithm: key_algorithm,
+                ..
+            },
+        ) => {
+            algorithm == key_algorithm
+                && key.key_tag() == Some(*key_tag)
+                && dnssec::delegation_digest(zone, key, *digest_type)
+                    .is_some_and(|key_digest| key_digest == digest.as_ref())
+        }
+        _ => false,
+    }
+}
+
+/// Proves a name does not exist and that no wildcard could have been used instead, RFC 4035 section 5.4 and RFC 5155 section 8.4
+pub fn prove_name_error(name: &DomainName, denials: &[&Resource]) -> ValidationResult {
+    let denials = &outside_delegations(name, denials);
+    let covering = next_secure(denials).find(|(owner, next, _)| nsec_covers(owner, next, name));
+    if let Some((owner, next, _)) = covering {
+        // The closest encloser is the longest ancestor the name shares with either end of the NSEC
+        let closest_encloser = [owner, next]
+            .iter()
+            .map(|other| common_ancestor(name, other))
+            .max_by_key(|ancestor| ancestor.len())
+            .unwrap();
+        let wildcard = wildcard(&closest_encloser);
+        if next_secure(denials).any(|(owner, next, _)| nsec_covers(owner, next, &wildcard)) {
+            return ValidationResult::Secure;
+        }
+    }
+    if has_excessive_iterations(denials) {
+        return ValidationResult::Insecure;
+    }
+    match closest_encloser_proof(name, denials) {
+        Some((closest_encloser, opt_out)) => {
+            let wildcard = wildcard(&closest_encloser);
+            if !denials.iter().any(|record| nsec3_covers(record, &wildcard)) {
+                ValidationResult::Bogus(ExtendedError::NsecMissing)
+            } else if opt_out {
+                // RFC 5155 section 9.2, an opt-out span may hide an unsigned delegation
+                ValidationResult::Insecure
+            } else {
+                ValidationResult::Secure
+            }
+        }
+        None => ValidationResult::Bogus(ExtendedError::NsecMissing),
+    }
+}
+
+/// Proves a name exists without any records of the type, RFC 4035 section 5.4 and RFC 5155 section 8.5 to 8.7
+pub fn prove_no_data(
+    name: &DomainName,
+    question_type: u16,
+    denials: &[&Resource],
+) -> ValidationResult {
+    let denials = &outside_delegations(name, denials);
+    let lacks_type = |types: &TypeBitmap| {
+        // The parent side of a delegation can only deny DS records
+        let delegation = types.contains(NAME_SERVER) && !types.contains(START_AUTHORITY);
+        !types.contains(question_type)
+            && !types.contains(CANONICAL_NAME)
+            && (!delegation || question_type == DELEGATION_SIGNER)
+    };
+    if let Some((_, _, types)) =
+        next_secure(denials).find(|(owner, _, _)| owner.eq_ignore_case(name))
+    {
+        return match lacks_type(types) {
+            true => ValidationResult::Secure,
+            false => ValidationResult::Bogus(ExtendedError::NsecMissing),
+        };
+    }
+    // A wildcard that matched the name but has no records of the type
+    let covering = next_secure(denials).find(|(owner, next, _)| nsec_covers(owner, next, name));
+    if let Some((owner, next, _)) = covering {
+        // An empty non-terminal, the next name is below it
+        if next.is_subdomain_of(name) {
+            return ValidationResult::Secure;
+        }
+        let wildcard = [owner, next]
+            .iter()
+            .map(|other| wildcard(&common_ancestor(name, other)))
+            .max_by_key(|wildcard| wildcard.len())
+            .unwrap();
+        let matching = next_secure(denials).find(|(owner, _, _)| owner.eq_ignore_case(&wildcard));
+        if matches!(matching, Some((_, _, types)) if lacks_type(types)) {
+            return ValidationResult::Secure;
+        }
+    }
+    if has_excessive_iterations(denials) {
+        return ValidationResult::Insecure;
+    }
+    if let Some(types) = denials
+        .iter()
+        .find(|record| nsec3_matches(record, name))
+        .and_then(|record| next_secure_3_types(record))
+    {
+        return match lacks_type(types) {
+            true => ValidationResult::Secure,
+            false => ValidationResult::Bogus(ExtendedError::NsecMissing),
+        };
+    }
+    match closest_encloser_proof(name, denials) {
+        // RFC 5155 section 8.6, opt-out only covers the DS records of unsigned delegations
+        Some((_, true)) if question_type == DELEGATION_SIGNER => ValidationResult::Insecure,
+        Some((closest_encloser, _)) => {
+            let wildcard = wildcard(&closest_encloser);
+            let matching = denials
+                .iter()
+                .find(|record| nsec3_matches(record, &wildcard))
+                .and_then(|record| next_secure_3_types(record));
+            match matching {
+                Some(types) if lacks_type(types) => ValidationResult::Secure,
+                _ => ValidationResult::Bogus(ExtendedError::NsecMissing),
+            }
+        }
+        None => ValidationResult::Bogus(ExtendedError::NsecMissing),
+    }
+}
+
+/// Proves the owner of records expanded from a wildcard does not exist itself
+fn prove_wildcard_expansion(
+    owner: &DomainName,
+    labels: usize,
+    denials: &[&Resource],
+) -> ValidationResult {
+    if next_secure(denials).any(|(nsec_owner, next, _)| nsec_covers(nsec_owner, next, owner)) {
+        return ValidationResult::Secure;
+    }
+    if has_excessive_iterations(denials) {
+        return ValidationResult::Insecure;
+    }
+    // The next closer name is the source of synthesis with one more label from the owner
+    let owner_labels = owner.labels();
+    let next_closer = DomainName::from(&owner_labels[owner_labels.len() - labels - 1..]);
+    match denials
+        .iter()
+        .find(|record| nsec3_covers(record, &next_closer))
+    {
+        Some(record) if is_opt_out(record) => ValidationResult::Insecure,
+        Some(_) => ValidationResult::Secure,
+        None => ValidationResult::Bogus(ExtendedError::NsecMissing),
+    }
+}
+
+/// Proves a delegation has no DS records, so the child zone is unsigned
+fn prove_unsigned_delegation(name: &DomainName, denials: &[&Resource]) -> bool {
+    let unsigned = |types: &TypeBitmap| {
+        types.contains(NAME_SERVER)
+            && !types.contains(DELEGATION_SIGNER)
+            && !types.contains(START_AUTHORITY)
+    };
+    let nsec =
+        next_secure(denials).any(|(owner, _, types)| owner.eq_ignore_case(name) && unsigned(types));
+    let nsec3 = denials
+        .iter()
+        .filter(|record| nsec3_matches(record, name))
+        .filter_map(|record| next_secure_3_types(record))
+        .any(unsigned);
+    nsec || nsec3 || matches!(closest_encloser_proof(name, denials), Some((_, true)))
+}
+
+/// Finds the closest encloser of a name that does not exist, RFC 5155 section 8.3
+/// Returns the closest encloser and whether the NSEC3 covering the next closer name has the opt-out flag
+fn closest_encloser_proof<'n>(
+    name: &'n DomainName,
+    denials: &[&Resource],
+) -> Option<(DomainName<'n>, bool)> {
+    let labels = name.labels();
+    for index in 1..=labels.len() {
+        let closest_encloser = DomainName::from(&labels[index..]);
+        if denials
+            .iter()
+            .any(|record| nsec3_matches(record, &closest_encloser))
+        {
+            let next_closer = DomainName::from(&labels[index - 1..]);
+            return denials
+                .iter()
+                .find(|record| nsec3_covers(record, &next_closer))
+                .map(|record| (closest_encloser, is_opt_out(record)));
+        }
+    }
+    None
+}
+
+/// Leaves out the NSEC and NSEC3 records of delegations and DNAMEs above the name, RFC 6840 section 4.1
+/// They come from the parent side of a cut and say nothing about the names below it
+fn outside_delegations<'r, 'a>(
+    name: &DomainName,
+    denials: &[&'r Resource<'a>],
+) -> Vec<&'r Resource<'a>> {
+    let cut = |types: &TypeBitmap| {
+        (types.contains(NAME_SERVER) && !types.contains(START_AUTHORITY))
+            || types.contains(DELEGATION_NAME)
+    };
+    let labels = name.labels();
+    denials
+        .iter()
+        .copied()
+        .filter(|record| match &record.payload {
+            ResourcePayload::NextSecure { types, .. } => {
+                !cut(types)
+                    || !name.is_subdomain_of(&record.resource_name)
+                    || name.eq_ignore_case(&record.resource_name)
+            }
+            ResourcePayload::NextSecure3 { types, .. } => {
+                !cut(types)
+                    || !(1..labels.len())
+                        .any(|index| nsec3_matches(record, &DomainName::from(&labels[index..])))
+            }
+            _ => true,
+        })
+        .collect()
+}
+
+fn next_secure<'r, 'a>(
+    denials: &'r [&'r Resource<'a>],
+) -> impl Iterator<Item = (&'r DomainName<'a>, &'r DomainName<'a>, &'r TypeBitmap)> {
+    denials.iter().filter_map(|record| match &record.payload {
+        ResourcePayload::NextSecure {
+            next_domain_name,
+            types,
+        } => Some((&record.resource_name, next_domain_name, types)),
+        _ => None,
+    })
+}
+
+/// Returns true if the name falls between the owner and next name of an NSEC record
+pub fn nsec_covers(owner: &DomainName, next: &DomainName, name: &DomainName) -> bool {
+    let after_owner = owner.canonical_cmp(name) == Ordering::Less;
+    let before_next = name.canonical_cmp(next) == Ordering::Less;
+    // The last NSEC in a zone points back to the apex
+    match owner.canonical_cmp(next) {
+        Ordering::Less => after_owner && before_next,
+        _ => (after_owner || before_next) && name.is_subdomain_of(next),
+    }
+}
+
+fn next_secure_3_types<'r>(record: &'r Resource) -> Option<&'r TypeBitmap> {
+    match &record.payload {
+        ResourcePayload::NextSecure3 { types, .. } => Some(types),
+        _ => None,
+    }
+}
+
+pub fn is_opt_out(record: &Resource) -> bool {
+    matches!(record.payload, ResourcePayload::NextSecure3 { flags, .. } if flags & OPT_OUT_FLAG != 0)
+}
+
+pub fn has_excessive_iterations(denials: &[&Resource]) -> bool {
+    denials.iter().any(|record| {
+        matches!(record.payload, ResourcePayload::NextSecure3 { iterations, .. } if iterations > MAXIMUM_ITERATIONS)
+    })
+}
+
+/// Hashes a name with the parameters of an NSEC3 record, returning the owner hash, the name hash and the next hash
+fn nsec3_hashes<'r>(
+    record: &'r Resource,
+    name: &DomainName,
+) -> Option<(Vec<u8>, Vec<u8>, &'r [u8])> {
+    let (hash_algorithm, iterations, salt, next_hashed_owner) = match &record.payload {
+        ResourcePayload::NextSecure3 {
+            hash_algorithm,
+            iterations,
+            salt,
+            next_hashed_owner,
+            ..
+        } => (*hash_algorithm, *iterations, salt, next_hashed_owner),
+        _ => return None,
+    };
+    // An NSEC3 record only speaks for names in its own zone
+    let (owner_label, zone) = record.resource_name.labels().split_first()?;
+    if !name.is_subdomain_of(&DomainName::from(zone)) {
+        return None;
+    }
+    let owner_hash = decode_base32_hex(owner_label)?;
+    let hash = dnssec::nsec3_hash(name, hash_algorithm, iterations, salt)?;
+    Some((owner_hash, hash, next_hashed_owner))
+}
+
+pub fn nsec3_matches(record: &Resource, name: &DomainName) -> bool {
+    nsec3_hashes(record, name).is_some_and(|(owner_hash, hash, _)| owner_hash == hash)
+}
+
+pub fn nsec3_covers(record: &Resource, name: &DomainName) -> bool {
+    nsec3_hashes(record, name).is_some_and(|(owner_hash, hash, next_hash)| {
+        let after_owner = owner_hash < hash;
+        let before_next = hash.as_slice() < next_hash;
+        // The last NSEC3 in a zone wraps around to the first hash
+        match owner_hash.as_slice() < next_hash {
+            true => after_owner && before_next,
+            false => after_owner || before_next,
+        }
+    })
+}
+
+/// The longest name that both names are equal to or below
+fn common_ancestor<'n>(name: &'n DomainName, other: &DomainName) -> DomainName<'n> {
+    let labels = name.labels();
+    let shared = labels
+        .iter()
+        .rev()
+        .zip(other.labels().iter().rev())
+        .take_while(|(label, other_label)| label.eq_ignore_ascii_case(other_label))
+        .count();
+    DomainName::from(&labels[labels.len() - shared..])
+}
+
+pub fn wildcard<'n>(closest_encloser: &DomainName<'n>) -> DomainName<'n> {
+    let mut labels = vec![Cow::Borrowed("*")];
+    labels.extend_from_slice(closest_encloser.labels());
+    DomainName::Labels(labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{
+        builders::DnsResponseBuilder, tests::name, zone::ZoneWriter, DnsParser, Question,
+        QuestionClass, ResourceClass, SigningKey,
+    };
+    use crate::helper::encode_base32_hex;
+
+    // Every signature in the test zones is valid from September 2020 until March 2030
+    const INCEPTION: u32 = 1_600_000_000;
+    const EXPIRATION: u32 = 1_900_000_000;
+    const NOW: u32 = 1_700_000_000;
+
+    /// The RSA key is read from a file as ring can't generate RSA keys
+    fn test_key(zone: &str, flags: u16, algorithm: u8) -> SigningKey {
+        match algorithm {
+            dnssec::RSA_SHA256 => SigningKey::from_file(
+                name(zone),
+                flags,
+                algorithm,
+                "testdata/keys/example.com.rsasha256.pem",
+            ),
+            _ => SigningKey::generate(name(zone), flags, algorithm),
+        }
+        .unwrap()
+    }
+
+    /// A signed zone, the DNSKEY RRset is signed with the first key and everything else with the last
+    struct TestZone {
+        keys: Vec<SigningKey>,
+        records: Vec<Resource<'static>>,
+    }
+
+    impl TestZone {
+        fn new(keys: Vec<SigningKey>, mut records: Vec<Resource<'static>>) -> TestZone {
+            records.extend(keys.iter().map(|key| key.dnskey(3600)));
+            let mut signatures = Vec::new();
+            for set in RecordSet::group(&records) {
+                let key = match set.resource_type {
+                    DNS_KEY => keys.first().unwrap(),
+                    _ => keys.last().unwrap(),
+                };
+                // Delegations and glue belong to the child zone
+                let delegated =
+                    set.resource_type == NAME_SERVER && !set.name.eq_ignore_case(key.zone());
+                if !delegated {
+                    signatures.push(key.sign(&set.records, INCEPTION, EXPIRATION).unwrap());
+                }
+            }
+            records.extend(signatures);
+            TestZone { keys, records }
+        }
+
+        /// The records of a type at a name along with the signatures covering them
+        fn select(&self, owner: &str, resource_type: ResourceType) -> Vec<Resource<'static>> {
+            let owner = name(owner);
+            let type_code = u16::from(resource_type);
+            self.records
+                .iter()
+                .filter(|record| record.name().eq_ignore_case(&owner))
+                .filter(|record| match record.payload() {
+                    ResourcePayload::ResourceSignature { type_covered, .. } => {
+                        *type_covered == type_code
+                    }
+                    payload => payload.type_code() == type_code,
+                })
+                .cloned()
+                .collect()
+        }
+    }
+
+    fn zone_records(origin: &str, text: &str) -> Vec<Resource<'static>> {
+        ZoneParser::new(Some(name(origin)))
+            .parse_str(&format!("$TTL 3600\n{}", text), "test.zone")
+            .unwrap()
+    }
+
+    /// Builds the NSEC3 chain for a zone, each name is listed with the types that exist there
+    fn nsec3_chain(zone: &str, names: &[(&str, &str)]) -> String {
+        let mut hashes: Vec<(Vec<u8>, &str)> = names
+            .iter()
+            .map(|(owner, types)| (dnssec::nsec3_hash(&name(owner), 1, 0, &[]).unwrap(), *types))
+            .collect();
+        hashes.sort();
+        let mut text = String::new();
+        for (index, (hash, types)) in hashes.iter().enumerate() {
+            let next = &hashes[(index + 1) % hashes.len()].0;
+            text.push_str(&format!(
+                "{}.{}. NSEC3 1 0 0 - {} {}\n",
+                encode_base32_hex(hash).to_lowercase(),
+                zone,
+                encode_base32_hex(next),
+                types
+            ));
+        }
+        text
+    }
+
+    /// example.com is signed with an ECDSA P-256 KSK and an RSA ZSK and uses NSEC
+    /// secure.example.com is signed with Ed25519 and hashed.example.com with ECDSA P-384 and NSEC3
+    /// insecure.example.com is an unsigned delegation
+    fn test_zones() -> (TestZone, TestZone, TestZone) {
+        let secure = TestZone::new(
+            vec![test_key("secure.example.com", 257, dnssec::ED25519)],
+            zone_records(
+                "secure.example.com",
+                concat!(
+                    "@ SOA ns hostmaster 1 7200 3600 1209600 300\n",
+                    "@ NS ns\n",
+                    "ns A 192.0.2.54\n",
+                    "www AAAA 2001:db8::1\n",
+                    "@ NSEC ns SOA NS RRSIG NSEC DNSKEY\n",
+                    "ns NSEC www A RRSIG NSEC\n",
+                    "www NSEC @ AAAA RRSIG NSEC\n",
+                ),
+            ),
+        );
+        let chain = nsec3_chain(
+            "hashed.example.com",
+            &[
+                ("hashed.example.com", "SOA NS RRSIG DNSKEY NSEC3PARAM"),
+                ("www.hashed.example.com", "A RRSIG"),
+            ],
+        );
+        let hashed = TestZone::new(
+            vec![test_key("hashed.example.com", 257, dnssec::ECDSA_P384_SHA384)],
+            zone_records(
+                "hashed.example.com",
+                &format!(
+                    "@ SOA ns hostmaster 1 7200 3600 1209600 300\n@ NS ns\n@ NSEC3PARAM 1 0 0 -\nwww A 192.0.2.2\n{}",
+                    chain
+                ),
+            ),
+        );
+        let mut records = zone_records(
+            "example.com",
+            concat!(
+                "@ SOA ns hostmaster 1 7200 3600 1209600 300\n",
+                "@ NS ns\n",
+                "ns A 192.0.2.53\n",
+                "www A 192.0.2.1\n",
+                "*.wild TXT \"wildcard\"\n",
+                "secure NS ns.secure\n",
+                "hashed NS ns.hashed\n",
+                "insecure NS ns.insecure\n",
+                "@ NSEC hashed SOA NS RRSIG NSEC DNSKEY\n",
+                "hashed NSEC insecure NS DS RRSIG NSEC\n",
+                "insecure NSEC ns NS RRSIG NSEC\n",
+                "ns NSEC secure A RRSIG NSEC\n",
+                "secure NSEC *.wild NS DS RRSIG NSEC\n",
+                "*.wild NSEC www TXT RRSIG NSEC\n",
+                "www NSEC @ A RRSIG NSEC\n",
+            ),
+        );
+        records.push(
+            secure.keys[0]
+                .delegation(dnssec::DIGEST_SHA256, 3600)
+                .unwrap(),
+        );
+        records.push(
+            hashed.keys[0]
+                .delegation(dnssec::DIGEST_SHA256, 3600)
+                .unwrap(),
+        );
+        let parent = TestZone::new(
+            vec![
+                test_key("example.com", 257, dnssec::ECDSA_P256_SHA256),
+                test_key("example.com", 256, dnssec::RSA_SHA256),
+            ],
+            records,
+        );
+        (parent, secure, hashed)
+    }
+
+    fn respond(
+        validator: &mut Validator,
+        question: &str,
+        question_type: QuestionType,
+        response_code: ResponseCode,
+        answers: Vec<Resource<'static>>,
+        authority: Vec<Resource<'static>>,
+    ) -> ValidationResult {
+        let mut builder = DnsResponseBuilder::new(1);
+        builder
+            .add_question(Question::new(
+                name(question),
+                question_type,
+                QuestionClass::Internet,
+            ))
+            .response_code(response_code);
+        for answer in answers {
+            builder.add_answer(answer);
+        }
+        for record in authority {
+            builder.add_authority(record);
+        }
+        let packet_data = builder.build_response().unwrap();
+        let mut parser = DnsParser::new();
+        let packet = parser.parse_packet(&packet_data).unwrap();
+        validator.validate(&packet)
+    }
+
+    /// A validator anchored at example.com that has already validated the keys for example.com
+    fn anchored_validator(parent: &TestZone) -> Validator {
+        let anchor_file =
+            std::env::temp_dir().join(format!("pp-anchors-{}.zone", std::process::id()));
+        ZoneWriter::new(None)
+            .write_file(
+                &[parent.keys[0]
+                    .delegation(dnssec::DIGEST_SHA256, 3600)
+                    .unwrap()],
+                &anchor_file,
+            )
+            .unwrap();
+        let trust_anchors = TrustAnchors::from_file(&anchor_file).unwrap();
+        std::fs::remove_file(&anchor_file).unwrap();
+        let mut validator = Validator::new(trust_anchors);
+        validator.validation_time = Some(NOW);
+        let keys = parent.select("example.com", ResourceType::DnsKey);
+        let result = respond(
+            &mut validator,
+            "example.com",
+            QuestionType::DnsKey,
+            ResponseCode::NOERROR,
+            keys,
+            vec![],
+        );
+        assert_eq!(result, ValidationResult::Secure);
+        validator
+    }
+
+    #[test]
+    fn test_chain_of_trust() {
+        let (parent, secure, hashed) = test_zones();
+        let mut validator = anchored_validator(&parent);
+        let answer = parent.select("www.example.com", ResourceType::Address);
+        let result = respond(
+            &mut validator,
+            "www.example.com",
+            QuestionType::Address,
+            ResponseCode::NOERROR,
+            answer,
+            vec![],
+        );
+        assert_eq!(result, ValidationResult::Secure);
+        // Each child is reached through its DS records in the parent, then its own DNSKEY records
+        for (child, host, resource_type, question_type) in [
+            (
+                &secure,
+                "www.secure.example.com",
+                ResourceType::Ipv6Address,
+                QuestionType::Ipv6Address,
+            ),
+            (
+                &hashed,
+                "www.hashed.example.com",
+                ResourceType::Address,
+                QuestionType::Address,
+            ),
+        ] {
+            let zone = child.keys[0].zone().to_string();
+            let delegation = parent.select(&zone, ResourceType::DelegationSigner);
+            let result = respond(
+                &mut validator,
+                &zone,
+                QuestionType::DelegationSigner,
+                ResponseCode::NOERROR,
+                delegation,
+                vec![],
+            );
+            assert_eq!(result, ValidationResult::Secure);
+            let keys = child.select(&zone, ResourceType::DnsKey);
+            let result = respond(
+                &mut validator,
+                &zone,
+                QuestionType::DnsKey,
+                ResponseCode::NOERROR,
+                keys,
+                vec![],
+            );
+            assert_eq!(result, ValidationResult::Secure);
+            let answer = child.select(host, resource_type);
+            let result = respond(
+                &mut validator,
+                host,
+                question_type,
+                ResponseCode::NOERROR,
+                answer,
+                vec![],
+            );
+            assert_eq!(result, ValidationResult::Secure);
+        }
+        // Without the DS records the child keys can't be trusted
+        let mut validator = anchored_validator(&parent);
+        let keys = secure.select("secure.example.com", ResourceType::DnsKey);
+        let result = respond(
+            &mut validator,
+            "secure.example.com",
+            QuestionType::DnsKey,
+            ResponseCode::NOERROR,
+            keys,
+            vec![],
+        );
+        assert_eq!(
+            result,
+            ValidationResult::Bogus(ExtendedError::DnsKeyMissing)
+        );
+    }
+
+    #[test]
+    fn test_bogus_answers() {
+        let (parent, _, _) = test_zones();
+        let mut validator = anchored_validator(&parent);
+        let mut answer = parent.select("www.example.com", ResourceType::Address);
+        let signed = answer.clone();
+        answer[0] = Resource::new(
+            name("www.example.com"),
+            ResourceClass::Internet,
+            3600,
+            ResourcePayload::Address([192, 0, 2, 66].into()),
+        );
+        let result = respond(
+            &mut validator,
+            "www.example.com",
+            QuestionType::Address,
+            ResponseCode::NOERROR,
+            answer,
+            vec![],
+        );
+        assert_eq!(result, ValidationResult::Bogus(ExtendedError::DnssecBogus));
+        let unsigned = signed[..1].to_vec();
+        let result = respond(
+            &mut validator,
+            "www.example.com",
+            QuestionType::Address,
+            ResponseCode::NOERROR,
+            unsigned,
+            vec![],
+        );
+        assert_eq!(
+            result,
+            ValidationResult::Bogus(ExtendedError::RrsigsMissing)
+        );
+        validator.validation_time = Some(EXPIRATION + 1);
+        let result = respond(
+            &mut validator,
+            "www.example.com",
+            QuestionType::Address,
+            ResponseCode::NOERROR,
+            signed.clone(),
+            vec![],
+        );
+        assert_eq!(
+            result,
+            ValidationResult::Bogus(ExtendedError::SignatureExpired)
+        );
+        validator.validation_time = Some(INCEPTION - 1);
+        let result = respond(
+            &mut validator,
+            "www.example.com",
+            QuestionType::Address,
+            ResponseCode::NOERROR,
+            signed,
+            vec![],
+        );
+        assert_eq!(
+            result,
+            ValidationResult::Bogus(ExtendedError::SignatureNotYetValid)
+        );
+    }
+
+    #[test]
+    fn test_wildcard_expansion() {
+        let (parent, _, _) = test_zones();
+        let mut validator = anchored_validator(&parent);
+        // The wildcard records are answered under the name that was asked for
+        let answer: Vec<Resource> = parent
+            .select("*.wild.example.com", ResourceType::TextStrings)
+            .into_iter()
+            .map(|record| {
+                Resource::new(
+                    name("host.wild.example.com"),
+                    record.class(),
+                    record.time_to_live(),
+                    record.payload().clone(),
+                )
+            })
+            .collect();
+        let result = respond(
+            &mut validator,
+            "host.wild.example.com",
+            QuestionType::TextStrings,
+            ResponseCode::NOERROR,
+            answer.clone(),
+            vec![],
+        );
+        assert_eq!(result, ValidationResult::Bogus(ExtendedError::NsecMissing));
+        let proof = parent.select("*.wild.example.com", ResourceType::NextSecure);
+        let result = respond(
+            &mut validator,
+            "host.wild.example.com",
+            QuestionType::TextStrings,
+            ResponseCode::NOERROR,
+            answer,
+            proof,
+        );
+        assert_eq!(result, ValidationResult::Secure);
+    }
+
+    #[test]
+    fn test_nsec_denial() {
+        let (parent, _, _) = test_zones();
+        let mut validator = anchored_validator(&parent);
+        let mut authority = parent.select("example.com", ResourceType::StartAuthority);
+        authority.extend(parent.select("insecure.example.com", ResourceType::NextSecure));
+        let result = respond(
+            &mut validator,
+            "missing.example.com",
+            QuestionType::Address,
+            ResponseCode::NXDOMAIN,
+            vec![],
+            authority.clone(),
+        );
+        // The NSEC proving there is no *.example.com wildcard is missing
+        assert_eq!(result, ValidationResult::Bogus(ExtendedError::NsecMissing));
+        authority.extend(parent.select("example.com", ResourceType::NextSecure));
+        let result = respond(
+            &mut validator,
+            "missing.example.com",
+            QuestionType::Address,
+            ResponseCode::NXDOMAIN,
+            vec![],
+            authority,
+        );
+        assert_eq!(result, ValidationResult::Secure);
+        let mut authority = parent.select("example.com", ResourceType::StartAuthority);
+        authority.extend(parent.select("www.example.com", ResourceType::NextSecure));
+        let result = respond(
+            &mut validator,
+            "www.example.com",
+            QuestionType::MailExchange,
+            ResponseCode::NOERROR,
+            vec![],
+            authority.clone(),
+        );
+        assert_eq!(result, ValidationResult::Secure);
+        // The NSEC shows www.example.com has A records so it can't deny them
+        let result = respond(
+            &mut validator,
+            "www.example.com",
+            QuestionType::Address,
+            ResponseCode::NOERROR,
+            vec![],
+            authority,
+        );
+        assert_eq!(result, ValidationResult::Bogus(ExtendedError::NsecMissing));
+    }
+
+    #[test]
+    fn test_nsec3_denial() {
+        let (parent, _, hashed) = test_zones();
+        let mut validator = anchored_validator(&parent);
+        let delegation = parent.select("hashed.example.com", ResourceType::DelegationSigner);
+        respond(
+            &mut validator,
+            "hashed.example.com",
+            QuestionType::DelegationSigner,
+            ResponseCode::NOERROR,
+            delegation,
+            vec![],
+        );
+        let keys = hashed.select("hashed.example.com", ResourceType::DnsKey);
+        respond(
+            &mut validator,
+            "hashed.example.com",
+            QuestionType::DnsKey,
+            ResponseCode::NOERROR,
+            keys,
+            vec![],
+        );
+        let mut authority = hashed.select("hashed.example.com", ResourceType::StartAuthority);
+        for record in &hashed.records {
+            if record.resource_type() == ResourceType::NextSecure3 {
+                authority
+                    .extend(hashed.select(&record.name().to_string(), ResourceType::NextSecure3));
+            }
+        }
+        let result = respond(
+            &mut validator,
+            "missing.hashed.example.com",
+            QuestionType::Address,
+            ResponseCode::NXDOMAIN,
+            vec![],
+            authority.clone(),
+        );
+        assert_eq!(result, ValidationResult::Secure);
+        let result = respond(
+            &mut validator,
+            "www.hashed.example.com",
+            QuestionType::TextStrings,
+            ResponseCode::NOERROR,
+            vec![],
+            authority.clone(),
+        );
+        assert_eq!(result, ValidationResult::Secure);
+        let result = respond(
+            &mut validator,
+            "www.hashed.example.com",
+            QuestionType::Address,
+            ResponseCode::NOERROR,
+            vec![],
+            authority,
+        );
+        assert_eq!(result, ValidationResult::Bogus(ExtendedError::NsecMissing));
+    }
+
+    #[test]
+    fn test_insecure_and_indeterminate() {
+        let (parent, _, _) = test_zones();
+        let mut validator = anchored_validator(&parent);
+        let unsigned = zone_records("insecure.example.com", "www A 192.0.2.3\n");
+        let result = respond(
+            &mut validator,
+            "www.insecure.example.com",
+            QuestionType::Address,
+            ResponseCode::NOERROR,
+            unsigned.clone(),
+            vec![],
+        );
+        assert_eq!(
+            result,
+            ValidationResult::Bogus(ExtendedError::RrsigsMissing)
+        );
+        // A referral proving the delegation has no DS records makes the child insecure
+        let mut referral = parent.select("insecure.example.com", ResourceType::NameServer);
+        referral.extend(parent.select("insecure.example.com", ResourceType::NextSecure));
+        let result = respond(
+            &mut validator,
+            "www.insecure.example.com",
+            QuestionType::Address,
+            ResponseCode::NOERROR,
+            vec![],
+            referral,
+        );
+        assert_eq!(result, ValidationResult::Insecure);
+        let result = respond(
+            &mut validator,
+            "www.insecure.example.com",
+            QuestionType::Address,
+            ResponseCode::NOERROR,
+            unsigned,
+            vec![],
+        );
+        assert_eq!(result, ValidationResult::Insecure);
+        let outside = zone_records("example.org", "www A 192.0.2.4\n");
+        let result = respond(
+            &mut validator,
+            "www.example.org",
+            QuestionType::Address,
+            ResponseCode::NOERROR,
+            outside,
+            vec![],
+        );
+        assert_eq!(result, ValidationResult::Indeterminate);
+    }
+
+    #[test]
+    fn test_trust_anchors_only_hold_keys() {
+        let records = zone_records("example.com", "www A 192.0.2.1\n");
+        let error = TrustAnchors::new(records).err().unwrap();
+        assert!(matches!(error.kind(), ErrorKind::InvalidTrustAnchor(_)));
+    }
+}
@@ -0,0 +1,50 @@
+use super::{DomainName, ResourceClass};
+
+mod parser;
+mod tokens;
+mod writer;
+
+/// Reads resources from RFC 1035 master files, tracking the state that entries inherit from the entries before them
+pub struct ZoneParser {
+    // The origin used to complete relative names and to replace @
+    origin: Option<DomainName<'static>>,
+    // The TTL set with a $TTL directive
+    default_ttl: Option<u32>,
+    // Values from the previous entry, used when an entry omits its owner, TTL or class
+    last_owner: Option<DomainName<'static>>,
+    last_ttl: Option<u32>,
+    last_class: ResourceClass,
+    // How many $INCLUDE files deep we currently are, used to stop include loops
+    include_depth: usize,
+}
+
+/// Writes resources out as a zone file that can be read back by the ZoneParser
+pub struct ZoneWriter {
+    // Names under the origin are written relative to it
+    origin: Option<DomainName<'static>>,
+    // Written as $TTL, resources with this TTL omit it
+    default_ttl: Option<u32>,
+}
+
+/// A single field of a zone file entry, escape sequences are left in place so that names and strings can decode them differently
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+/// A logical entry in a zone file, parentheses allow an entry to span multiple lines
+#[derive(Debug)]
+struct Entry {
+    tokens: Vec<Token>,
+    // The line the entry started on
+    line: usize,
+    // An entry starting with whitespace uses the owner of the previous entry
+    starts_with_blank: bool,
+}
+
+/// Splits the text of a zone file into entries
+struct Tokenizer<'a> {
+    characters: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+}
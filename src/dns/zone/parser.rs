@@ -0,0 +1,1270 @@
+use super::{Entry, Token, Tokenizer, ZoneParser};
+use crate::dns::{
+    DnsParser, DomainName, PreviousNames, Resource, ResourceClass, ResourcePayload, ResourceType,
+    ServiceBinding, ServiceParameter, TypeBitmap,
+};
+use crate::error::{Error, ErrorKind};
+use crate::helper::{decode_base32_hex, decode_base64, decode_hex, parse_timestamp};
+use std::{
+    borrow::Cow,
+    convert::TryFrom,
+    net::{Ipv4Addr, Ipv6Addr},
+    path::Path,
+};
+
+// Stops a file that includes itself from recursing forever
+const MAXIMUM_INCLUDE_DEPTH: usize = 16;
+// The longest a label can be in wire format
+const MAXIMUM_LABEL_LENGTH: usize = 63;
+// The longest a domain name can be in wire format
+const MAXIMUM_NAME_LENGTH: usize = 255;
+
+impl ZoneParser {
+    /// Creates a parser, the origin is used for relative names until the zone file sets its own with $ORIGIN
+    pub fn new(origin: Option<DomainName<'static>>) -> ZoneParser {
+        ZoneParser {
+            origin,
+            default_ttl: None,
+            last_owner: None,
+            last_ttl: None,
+            last_class: ResourceClass::Internet,
+            include_depth: 0,
+        }
+    }
+
+    /// Reads all the resources in a zone file along with any files it includes
+    pub fn parse_file<P: AsRef<Path>>(&mut self, path: P) -> Result<Vec<Resource<'static>>, Error> {
+        let mut resources = Vec::new();
+        self.read_file(path.as_ref(), &mut resources)?;
+        Ok(resources)
+    }
+
+    /// Reads all the resources in zone file text, file_name is used in errors and to locate included files
+    pub fn parse_str(
+        &mut self,
+        text: &str,
+        file_name: &str,
+    ) -> Result<Vec<Resource<'static>>, Error> {
+        let mut resources = Vec::new();
+        self.read_text(text, Path::new(file_name), &mut resources)?;
+        Ok(resources)
+    }
+
+    fn read_file(
+        &mut self,
+        path: &Path,
+        resources: &mut Vec<Resource<'static>>,
+    ) -> Result<(), Error> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|_| Error::new(ErrorKind::ZoneFileReadFailed(path.display().to_string())))?;
+        self.read_text(&text, path, resources)
+    }
+
+    fn read_text(
+        &mut self,
+        text: &str,
+        path: &Path,
+        resources: &mut Vec<Resource<'static>>,
+    ) -> Result<(), Error> {
+        let file = path.display().to_string();
+        let mut tokenizer = Tokenizer::new(text);
+        while let Some(entry) = tokenizer.next_entry() {
+            let entry = entry.map_err(|(line, reason)| Self::syntax_error(&file, line, reason))?;
+            let first_token = &entry.tokens[0];
+            if !entry.starts_with_blank && !first_token.quoted && first_token.text.starts_with('$')
+            {
+                self.read_directive(&entry, path, resources)?;
+            } else {
+                let resource = self
+                    .read_resource(&entry)
+                    .map_err(|reason| Self::syntax_error(&file, entry.line, reason))?;
+                resources.push(resource);
+            }
+        }
+        Ok(())
+    }
+
+    fn syntax_error<R: Into<String>>(file: &str, line: usize, reason: R) -> Error {
+        Error::new(ErrorKind::InvalidZoneFile {
+            file: file.to_string(),
+            line,
+            reason: reason.into(),
+        })
+    }
+
+    fn read_directive(
+        &mut self,
+        entry: &Entry,
+        path: &Path,
+        resources: &mut Vec<Resource<'static>>,
+    ) -> Result<(), Error> {
+        let file = path.display().to_string();
+        let error = |reason: &str| Self::syntax_error(&file, entry.line, reason);
+        let arguments = &entry.tokens[1..];
+        match entry.tokens[0].text.to_ascii_uppercase().as_str() {
+            "$ORIGIN" => {
+                let name = arguments
+                    .first()
+                    .ok_or_else(|| error("$ORIGIN requires a domain name"))?;
+                let origin = self.read_name(name).map_err(|reason| error(&reason))?;
+                self.origin = Some(origin);
+            }
+            "$TTL" => {
+                let ttl = arguments
+                    .first()
+                    .ok_or_else(|| error("$TTL requires a time to live"))?;
+                let ttl = read_ttl(&ttl.text).map_err(|reason| error(&reason))?;
+                self.default_ttl = Some(ttl);
+            }
+            "$INCLUDE" => {
+                let include = arguments
+                    .first()
+                    .ok_or_else(|| error("$INCLUDE requires a file name"))?;
+                let include = read_character_string(&include.text)
+                    .ok()
+                    .and_then(|bytes| String::from_utf8(bytes).ok())
+                    .ok_or_else(|| error("$INCLUDE file name is not valid"))?;
+                if self.include_depth >= MAXIMUM_INCLUDE_DEPTH {
+                    return Err(error("$INCLUDE files are nested too deeply"));
+                }
+                let include_origin = match arguments.get(1) {
+                    Some(name) => Some(self.read_name(name).map_err(|reason| error(&reason))?),
+                    None => None,
+                };
+                // Included files are relative to the file including them
+                let include_path = path.parent().unwrap_or_else(|| Path::new("")).join(include);
+                // The origin and current owner revert once the included file has been read
+                let saved_origin = self.origin.clone();
+                let saved_owner = self.last_owner.clone();
+                if include_origin.is_some() {
+                    self.origin = include_origin;
+                }
+                self.include_depth += 1;
+                let result = self.read_file(&include_path, resources);
+                self.include_depth -= 1;
+                self.origin = saved_origin;
+                self.last_owner = saved_owner;
+                result?;
+            }
+            _ => return Err(error("Unknown or unsupported directive")),
+        }
+        Ok(())
+    }
+
+    fn read_resource(&mut self, entry: &Entry) -> Result<Resource<'static>, String> {
+        let tokens = entry.tokens.as_slice();
+        let mut index = 0;
+        let owner = if entry.starts_with_blank {
+            self.last_owner
+                .clone()
+                .ok_or("The first resource in a zone must have an owner")?
+        } else {
+            index += 1;
+            self.read_name(&tokens[0])?
+        };
+        // The TTL and class are both optional and can appear in either order
+        let mut ttl = None;
+        let mut class = None;
+        while let Some(token) = tokens.get(index) {
+            if ttl.is_none()
+                && token
+                    .text
+                    .starts_with(|character: char| character.is_ascii_digit())
+            {
+                ttl = Some(read_ttl(&token.text)?);
+            } else if class.is_none() && read_class(&token.text).is_some() {
+                class = read_class(&token.text);
+            } else {
+                break;
+            }
+            index += 1;
+        }
+        let resource_type = tokens.get(index).ok_or("Missing resource type")?;
+        let resource_type = read_type(&resource_type.text)?;
+        let payload = self.read_payload(resource_type, &tokens[index + 1..])?;
+        let class = class.unwrap_or(self.last_class);
+        let ttl = match (ttl, self.default_ttl.or(self.last_ttl), &payload) {
+            (Some(ttl), _, _) => {
+                self.last_ttl = Some(ttl);
+                ttl
+            }
+            (None, Some(ttl), _) => ttl,
+            // Without any TTL the minimum of the SOA is used, as older zone files relied on this
+            (None, None, ResourcePayload::StartAuthority { minimum, .. }) => {
+                self.last_ttl = Some(*minimum);
+                *minimum
+            }
+            (None, None, _) => {
+                return Err(String::from("No TTL was given and no $TTL has been set"))
+            }
+        };
+        self.last_owner = Some(owner.clone());
+        self.last_class = class;
+        Ok(Resource::new(owner, class, ttl, payload))
+    }
+
+    /// Reads a domain name, relative names are completed with the current origin
+    fn read_name(&self, token: &Token) -> Result<DomainName<'static>, String> {
+        if token.text == "@" && !token.quoted {
+            return self
+                .origin
+                .clone()
+                .ok_or_else(|| String::from("@ was used but no origin has been set"));
+        }
+        let (labels, absolute) = read_labels(&token.text)?;
+        let name = DomainName::new(labels);
+        let name = if absolute {
+            name
+        } else {
+            match &self.origin {
+                Some(origin) => name.append(origin),
+                None => {
+                    return Err(format!(
+                        "{} is relative but no origin has been set",
+                        token.text
+                    ))
+                }
+            }
+        };
+        if name.wire_length() > MAXIMUM_NAME_LENGTH {
+            return Err(format!("{} is longer than 255 bytes", token.text));
+        }
+        Ok(name)
+    }
+
+    fn read_payload(
+        &self,
+        resource_type: u16,
+        tokens: &[Token],
+    ) -> Result<ResourcePayload<'static>, String> {
+        if let Some(first) = tokens.first() {
+            if first.text == "\\#" && !first.quoted {
+                return read_generic_payload(resource_type, &tokens[1..]);
+            }
+        }
+        let payload = match ResourceType::from(resource_type) {
+            ResourceType::Address => {
+                let [address] = expect_fields::<1>(tokens)?;
+                ResourcePayload::Address(read_address::<Ipv4Addr>(address)?)
+            }
+            ResourceType::Ipv6Address => {
+                let [address] = expect_fields::<1>(tokens)?;
+                ResourcePayload::Ipv6Address(read_address::<Ipv6Addr>(address)?)
+            }
+            ResourceType::NameServer => ResourcePayload::NameServer(self.read_single_name(tokens)?),
+            ResourceType::MailDestination => {
+                ResourcePayload::MailDestination(self.read_single_name(tokens)?)
+            }
+            ResourceType::MailForwarder => {
+                ResourcePayload::MailForwarder(self.read_single_name(tokens)?)
+            }
+            ResourceType::CanonicalName => {
+                ResourcePayload::CanonicalName(self.read_single_name(tokens)?)
+            }
+            ResourceType::DelegationName => {
+                ResourcePayload::DelegationName(self.read_single_name(tokens)?)
+            }
+            ResourceType::MailBox => ResourcePayload::MailBox(self.read_single_name(tokens)?),
+            ResourceType::MailGroup => ResourcePayload::MailGroup(self.read_single_name(tokens)?),
+            ResourceType::MailRename => ResourcePayload::MailRename(self.read_single_name(tokens)?),
+            ResourceType::DomainName => ResourcePayload::DomainName(self.read_single_name(tokens)?),
+            ResourceType::StartAuthority => {
+                let [primary_name_server, responsible_mailbox, serial, refresh, retry, expire, minimum] =
+                    expect_fields::<7>(tokens)?;
+                ResourcePayload::StartAuthority {
+                    primary_name_server: self.read_name(primary_name_server)?,
+                    responsible_mailbox: self.read_name(responsible_mailbox)?,
+                    serial: read_number(serial)?,
+                    refresh: read_ttl(&refresh.text)?,
+                    retry: read_ttl(&retry.text)?,
+                    expire: read_ttl(&expire.text)?,
+                    minimum: read_ttl(&minimum.text)?,
+                }
+            }
+            ResourceType::Null => {
+                return Err(String::from(
+                    "NULL resources can only be written as generic RDATA",
+                ))
+            }
+            ResourceType::WellKnownService => {
+                if tokens.len() < 2 {
+                    return Err(String::from("WKS requires an address and a protocol"));
+                }
+                let address = read_address::<Ipv4Addr>(&tokens[0])?;
+                let protocol = match tokens[1].text.to_ascii_uppercase().as_str() {
+                    "TCP" => 6,
+                    "UDP" => 17,
+                    _ => read_number(&tokens[1])?,
+                };
+                let mut bitmap = Vec::new();
+                for service in &tokens[2..] {
+                    let port: u16 = read_number(service)?;
+                    let byte = port as usize / 8;
+                    if bitmap.len() <= byte {
+                        bitmap.resize(byte + 1, 0);
+                    }
+                    bitmap[byte] |= 0b10000000 >> (port % 8);
+                }
+                ResourcePayload::WellKnownService {
+                    address,
+                    protocol,
+                    bitmap: Cow::Owned(bitmap),
+                }
+            }
+            ResourceType::HostInformation => {
+                let [cpu, operating_system] = expect_fields::<2>(tokens)?;
+                ResourcePayload::HostInformation {
+                    cpu: Cow::Owned(read_character_string(&cpu.text)?),
+                    operating_system: Cow::Owned(read_character_string(&operating_system.text)?),
+                }
+            }
+            ResourceType::MailboxInformation => {
+                let [responsible_mailbox, error_mailbox] = expect_fields::<2>(tokens)?;
+                ResourcePayload::MailboxInformation {
+                    responsible_mailbox: self.read_name(responsible_mailbox)?,
+                    error_mailbox: self.read_name(error_mailbox)?,
+                }
+            }
+            ResourceType::MailExchange => {
+                let [preference, exchange] = expect_fields::<2>(tokens)?;
+                ResourcePayload::MailExchange {
+                    preference: read_number(preference)?,
+                    exchange: self.read_name(exchange)?,
+                }
+            }
+            ResourceType::TextStrings => {
+                if tokens.is_empty() {
+                    return Err(String::from("TXT requires at least one string"));
+                }
+                let strings = tokens
+                    .iter()
+                    .map(|token| read_character_string(&token.text).map(Cow::Owned))
+                    .collect::<Result<Vec<_>, String>>()?;
+                ResourcePayload::TextStrings(strings)
+            }
+            ResourceType::Service => {
+                let [priority, weight, port, target] = expect_fields::<4>(tokens)?;
+                ResourcePayload::Service {
+                    priority: read_number(priority)?,
+                    weight: read_number(weight)?,
+                    port: read_number(port)?,
+                    target: self.read_name(target)?,
+                }
+            }
+            ResourceType::NamingAuthorityPointer => {
+                let [order, preference, flags, services, regular_expression, replacement] =
+                    expect_fields::<6>(tokens)?;
+                ResourcePayload::NamingAuthorityPointer {
+                    order: read_number(order)?,
+                    preference: read_number(preference)?,
+                    flags: Cow::Owned(read_character_string(&flags.text)?),
+                    services: Cow::Owned(read_character_string(&services.text)?),
+                    regular_expression: Cow::Owned(read_character_string(
+                        &regular_expression.text,
+                    )?),
+                    replacement: self.read_name(replacement)?,
+                }
+            }
+            ResourceType::UniformResourceIdentifier => {
+                let [priority, weight, target] = expect_fields::<3>(tokens)?;
+                let target = decode_escapes(&target.text)?
+                    .into_iter()
+                    .map(|(byte, _)| byte)
+                    .collect::<Vec<u8>>();
+                if target.is_empty() {
+                    return Err(String::from("URI target can't be empty"));
+                }
+                ResourcePayload::UniformResourceIdentifier {
+                    priority: read_number(priority)?,
+                    weight: read_number(weight)?,
+                    target: Cow::Owned(target),
+                }
+            }
+            ResourceType::DelegationSigner | ResourceType::ChildDelegationSigner => {
+                if tokens.len() < 4 {
+                    return Err(String::from(
+                        "DS requires a key tag, an algorithm, a digest type and a digest",
+                    ));
+                }
+                let key_tag = read_number(&tokens[0])?;
+                let algorithm = read_number(&tokens[1])?;
+                let digest_type = read_number(&tokens[2])?;
+                let digest = Cow::Owned(read_hex(&tokens[3..])?);
+                match ResourceType::from(resource_type) {
+                    ResourceType::DelegationSigner => ResourcePayload::DelegationSigner {
+                        key_tag,
+                        algorithm,
+                        digest_type,
+                        digest,
+                    },
+                    _ => ResourcePayload::ChildDelegationSigner {
+                        key_tag,
+                        algorithm,
+                        digest_type,
+                        digest,
+                    },
+                }
+            }
+            ResourceType::DnsKey | ResourceType::ChildDnsKey => {
+                if tokens.len() < 4 {
+                    return Err(String::from(
+                        "DNSKEY requires flags, a protocol, an algorithm and a public key",
+                    ));
+                }
+                let flags = read_number(&tokens[0])?;
+                let protocol = read_number(&tokens[1])?;
+                let algorithm = read_number(&tokens[2])?;
+                let public_key = Cow::Owned(read_base64(&tokens[3..])?);
+                match ResourceType::from(resource_type) {
+                    ResourceType::DnsKey => ResourcePayload::DnsKey {
+                        flags,
+                        protocol,
+                        algorithm,
+                        public_key,
+                    },
+                    _ => ResourcePayload::ChildDnsKey {
+                        flags,
+                        protocol,
+                        algorithm,
+                        public_key,
+                    },
+                }
+            }
+            ResourceType::ResourceSignature => {
+                if tokens.len() < 9 {
+                    return Err(String::from("RRSIG requires nine fields"));
+                }
+                ResourcePayload::ResourceSignature {
+                    type_covered: read_type(&tokens[0].text)?,
+                    algorithm: read_number(&tokens[1])?,
+                    labels: read_number(&tokens[2])?,
+                    original_ttl: read_number(&tokens[3])?,
+                    expiration: read_signature_time(&tokens[4])?,
+                    inception: read_signature_time(&tokens[5])?,
+                    key_tag: read_number(&tokens[6])?,
+                    signer_name: self.read_name(&tokens[7])?,
+                    signature: Cow::Owned(read_base64(&tokens[8..])?),
+                }
+            }
+            ResourceType::NextSecure => {
+                let next_domain_name = tokens.first().ok_or("NSEC requires a next name")?;
+                ResourcePayload::NextSecure {
+                    next_domain_name: self.read_name(next_domain_name)?,
+                    types: read_types(&tokens[1..])?,
+                }
+            }
+            ResourceType::NextSecure3 => {
+                if tokens.len() < 5 {
+                    return Err(String::from(
+                        "NSEC3 requires an algorithm, flags, iterations, a salt and the next hash",
+                    ));
+                }
+                let next_hashed_owner = decode_base32_hex(&tokens[4].text)
+                    .filter(|hash| !hash.is_empty() && hash.len() <= 255)
+                    .ok_or_else(|| format!("{} is not a valid hash", tokens[4].text))?;
+                ResourcePayload::NextSecure3 {
+                    hash_algorithm: read_number(&tokens[0])?,
+                    flags: read_number(&tokens[1])?,
+                    iterations: read_number(&tokens[2])?,
+                    salt: Cow::Owned(read_salt(&tokens[3])?),
+                    next_hashed_owner: Cow::Owned(next_hashed_owner),
+                    types: read_types(&tokens[5..])?,
+                }
+            }
+            ResourceType::NextSecure3Parameters => {
+                let [hash_algorithm, flags, iterations, salt] = expect_fields::<4>(tokens)?;
+                ResourcePayload::NextSecure3Parameters {
+                    hash_algorithm: read_number(hash_algorithm)?,
+                    flags: read_number(flags)?,
+                    iterations: read_number(iterations)?,
+                    salt: Cow::Owned(read_salt(salt)?),
+                }
+            }
+            ResourceType::SshFingerprint => {
+                if tokens.len() < 3 {
+                    return Err(String::from(
+                        "SSHFP requires an algorithm, a fingerprint type and a fingerprint",
+                    ));
+                }
+                ResourcePayload::SshFingerprint {
+                    algorithm: read_number(&tokens[0])?,
+                    fingerprint_type: read_number(&tokens[1])?,
+                    fingerprint: Cow::Owned(read_hex(&tokens[2..])?),
+                }
+            }
+            ResourceType::TlsAssociation | ResourceType::SmimeAssociation => {
+                if tokens.len() < 4 {
+                    return Err(String::from(
+                        "TLSA and SMIMEA require a usage, a selector, a matching type and data",
+                    ));
+                }
+                let certificate_usage = read_number(&tokens[0])?;
+                let selector = read_number(&tokens[1])?;
+                let matching_type = read_number(&tokens[2])?;
+                let certificate_data = Cow::Owned(read_hex(&tokens[3..])?);
+                match ResourceType::from(resource_type) {
+                    ResourceType::TlsAssociation => ResourcePayload::TlsAssociation {
+                        certificate_usage,
+                        selector,
+                        matching_type,
+                        certificate_data,
+                    },
+                    _ => ResourcePayload::SmimeAssociation {
+                        certificate_usage,
+                        selector,
+                        matching_type,
+                        certificate_data,
+                    },
+                }
+            }
+            ResourceType::OpenPgpKey => {
+                ResourcePayload::OpenPgpKey(Cow::Owned(read_base64(tokens)?))
+            }
+            ResourceType::CertificationAuthorityAuthorization => {
+                let [flags, tag, value] = expect_fields::<3>(tokens)?;
+                let tag = read_character_string(&tag.text)?;
+                // RFC 8659 limits tags to ASCII letters and digits
+                if tag.is_empty() || tag.len() > 15 || !tag.iter().all(u8::is_ascii_alphanumeric) {
+                    return Err(String::from("CAA tags must be 1 to 15 letters or digits"));
+                }
+                let value = decode_escapes(&value.text)?
+                    .into_iter()
+                    .map(|(byte, _)| byte)
+                    .collect::<Vec<u8>>();
+                ResourcePayload::CertificationAuthorityAuthorization {
+                    flags: read_number(flags)?,
+                    tag: Cow::Owned(tag),
+                    value: Cow::Owned(value),
+                }
+            }
+            ResourceType::ServiceBinding | ResourceType::HttpsBinding => {
+                if tokens.len() < 2 {
+                    return Err(String::from(
+                        "SVCB and HTTPS require a priority and a target",
+                    ));
+                }
+                let binding = ServiceBinding::new(
+                    read_number(&tokens[0])?,
+                    self.read_name(&tokens[1])?,
+                    read_service_parameters(&tokens[2..])?,
+                );
+                match ResourceType::from(resource_type) {
+                    ResourceType::ServiceBinding => ResourcePayload::ServiceBinding(binding),
+                    _ => ResourcePayload::HttpsBinding(binding),
+                }
+            }
+            ResourceType::TransactionSignature => {
+                return Err(String::from(
+                    "TSIG only exists in messages and can't appear in a zone",
+                ))
+            }
+            ResourceType::Unknown(_) => {
+                return Err(format!(
+                    "TYPE{} is not a known type and must use generic RDATA",
+                    resource_type
+                ))
+            }
+        };
+        Ok(payload)
+    }
+
+    fn read_single_name(&self, tokens: &[Token]) -> Result<DomainName<'static>, String> {
+        let [name] = expect_fields::<1>(tokens)?;
+        self.read_name(name)
+    }
+}
+
+/// Reads RFC 3597 generic RDATA, ie \# <length> <hex data>, known types are decoded as if they came from a packet
+fn read_generic_payload(
+    resource_type: u16,
+    tokens: &[Token],
+) -> Result<ResourcePayload<'static>, String> {
+    let length: usize = match tokens.first() {
+        Some(length) => read_number(length)?,
+        None => return Err(String::from("Generic RDATA requires a length")),
+    };
+    let hex: String = tokens[1..]
+        .iter()
+        .map(|token| token.text.as_str())
+        .collect();
+    let data = decode_hex(&hex).ok_or("Generic RDATA is not valid hexadecimal")?;
+    if data.len() != length {
+        return Err(format!(
+            "Generic RDATA length was {} but {} bytes were given",
+            length,
+            data.len()
+        ));
+    }
+    if let ResourceType::Unknown(_) = ResourceType::from(resource_type) {
+        return Ok(ResourcePayload::Unknown {
+            resource_type,
+            data: Cow::Owned(data),
+        });
+    }
+    let mut parser = DnsParser::new();
+    let mut previous_names = PreviousNames::new();
+    let payload = parser
+        .read_payload(&data, resource_type, data.len(), &mut previous_names)
+        .map_err(|_| format!("Generic RDATA is not valid for TYPE{}", resource_type))?;
+    Ok(payload.into_owned())
+}
+
+/// Reads SvcParams written as key=value or just key for parameters without a value
+fn read_service_parameters(tokens: &[Token]) -> Result<Vec<ServiceParameter<'static>>, String> {
+    let mut parameters = Vec::new();
+    let mut index = 0;
+    while let Some(token) = tokens.get(index) {
+        index += 1;
+        let (key, value) = match token.text.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (token.text.as_str(), None),
+        };
+        // A quoted value becomes a token of its own, ie alpn="h2,h3"
+        let value = match (value, tokens.get(index)) {
+            (Some(""), Some(quoted)) if quoted.quoted => {
+                index += 1;
+                Some(quoted.text.as_str())
+            }
+            (value, _) => value,
+        };
+        let key = ServiceParameter::key_from_mnemonic(key)
+            .ok_or_else(|| format!("{} is not a valid SvcParamKey", key))?;
+        let value = match value {
+            Some(value) => Some(read_character_string(value)?),
+            None => None,
+        };
+        parameters.push(ServiceParameter::from_presentation(key, value.as_deref())?);
+    }
+    parameters.sort_by_key(ServiceParameter::key);
+    ServiceBinding::validate_parameters(&parameters)?;
+    Ok(parameters)
+}
+
+/// Reads hexadecimal data that can be split across several fields
+fn read_hex(tokens: &[Token]) -> Result<Vec<u8>, String> {
+    let hex: String = tokens.iter().map(|token| token.text.as_str()).collect();
+    decode_hex(&hex).ok_or_else(|| format!("{} is not valid hexadecimal", hex))
+}
+
+/// Reads base64 data that can be split across several fields
+fn read_base64(tokens: &[Token]) -> Result<Vec<u8>, String> {
+    if tokens.is_empty() {
+        return Err(String::from("Expected base64 data"));
+    }
+    let base64: String = tokens.iter().map(|token| token.text.as_str()).collect();
+    decode_base64(&base64).ok_or_else(|| format!("{} is not valid base64", base64))
+}
+
+fn read_types(tokens: &[Token]) -> Result<TypeBitmap, String> {
+    let types = tokens
+        .iter()
+        .map(|token| read_type(&token.text))
+        .collect::<Result<Vec<u16>, String>>()?;
+    Ok(TypeBitmap::new(types))
+}
+
+/// Reads an NSEC3 salt, - is used when there is no salt
+fn read_salt(token: &Token) -> Result<Vec<u8>, String> {
+    if token.text == "-" {
+        return Ok(Vec::new());
+    }
+    decode_hex(&token.text)
+        .filter(|salt| salt.len() <= 255)
+        .ok_or_else(|| format!("{} is not a valid salt", token.text))
+}
+
+/// Reads an RRSIG time either as YYYYMMDDHHmmSS or as seconds since the epoch
+fn read_signature_time(token: &Token) -> Result<u32, String> {
+    match token.text.len() {
+        14 => parse_timestamp(&token.text)
+            .ok_or_else(|| format!("{} is not a valid timestamp", token.text)),
+        _ => read_number(token),
+    }
+}
+
+fn expect_fields<const COUNT: usize>(tokens: &[Token]) -> Result<&[Token; COUNT], String> {
+    <&[Token; COUNT]>::try_from(tokens)
+        .map_err(|_| format!("Expected {} fields but found {}", COUNT, tokens.len()))
+}
+
+fn read_number<N: std::str::FromStr>(token: &Token) -> Result<N, String> {
+    token
+        .text
+        .parse()
+        .map_err(|_| format!("{} is not a valid number", token.text))
+}
+
+fn read_address<A: std::str::FromStr>(token: &Token) -> Result<A, String> {
+    token
+        .text
+        .parse()
+        .map_err(|_| format!("{} is not a valid address", token.text))
+}
+
+fn read_type(text: &str) -> Result<u16, String> {
+    if let Some(resource_type) = ResourceType::from_mnemonic(text) {
+        return Ok(u16::from(resource_type));
+    }
+    // RFC 3597 allows any type to be written as TYPE followed by its number
+    match text.get(..4) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("TYPE") => text[4..]
+            .parse()
+            .map_err(|_| format!("{} is not a valid resource type", text)),
+        _ => Err(format!("{} is not a known resource type", text)),
+    }
+}
+
+fn read_class(text: &str) -> Option<ResourceClass> {
+    if let Some(class) = ResourceClass::from_mnemonic(text) {
+        return Some(class);
+    }
+    match text.get(..5) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("CLASS") => {
+            text[5..].parse::<u16>().ok().map(ResourceClass::from)
+        }
+        _ => None,
+    }
+}
+
+/// Reads a TTL either as plain seconds or with units, ie 1h30m
+fn read_ttl(text: &str) -> Result<u32, String> {
+    let invalid = || format!("{} is not a valid time to live", text);
+    if let Ok(seconds) = text.parse() {
+        return Ok(seconds);
+    }
+    let mut total: u32 = 0;
+    let mut current: Option<u32> = None;
+    for character in text.chars() {
+        if let Some(digit) = character.to_digit(10) {
+            let value = current.unwrap_or(0);
+            current = Some(
+                value
+                    .checked_mul(10)
+                    .and_then(|value| value.checked_add(digit))
+                    .ok_or_else(invalid)?,
+            );
+            continue;
+        }
+        let multiplier = match character.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 60 * 60 * 24,
+            'w' => 60 * 60 * 24 * 7,
+            _ => return Err(invalid()),
+        };
+        let value = current.take().ok_or_else(invalid)?;
+        total = value
+            .checked_mul(multiplier)
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(invalid)?;
+    }
+    // A trailing number without a unit is in seconds
+    if let Some(value) = current {
+        total = total.checked_add(value).ok_or_else(invalid)?;
+    }
+    Ok(total)
+}
+
+/// Decodes the escapes in a field, \X is the character X and \DDD is the byte with decimal value DDD
+fn decode_escapes(text: &str) -> Result<Vec<(u8, bool)>, String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] != b'\\' {
+            decoded.push((bytes[index], false));
+            index += 1;
+            continue;
+        }
+        let digits = bytes.get(index + 1..index + 4);
+        match digits {
+            Some(digits) if digits.iter().all(u8::is_ascii_digit) => {
+                let value = digits
+                    .iter()
+                    .fold(0u32, |value, digit| value * 10 + (digit - b'0') as u32);
+                if value > 255 {
+                    return Err(format!("\\{} is not a valid escape", value));
+                }
+                decoded.push((value as u8, true));
+                index += 4;
+            }
+            _ => match bytes.get(index + 1) {
+                Some(escaped) => {
+                    decoded.push((*escaped, true));
+                    index += 2;
+                }
+                None => return Err(String::from("A field can't end with a backslash")),
+            },
+        }
+    }
+    Ok(decoded)
+}
+
+fn read_character_string(text: &str) -> Result<Vec<u8>, String> {
+    let string: Vec<u8> = decode_escapes(text)?
+        .into_iter()
+        .map(|(byte, _)| byte)
+        .collect();
+    if string.len() > 255 {
+        return Err(String::from(
+            "Character strings can't be longer than 255 bytes",
+        ));
+    }
+    Ok(string)
+}
+
+/// Splits a name into its labels, returns true if the name was absolute ie ended with a dot
+fn read_labels(text: &str) -> Result<(Vec<String>, bool), String> {
+    if text == "." {
+        return Ok((Vec::new(), true));
+    }
+    let mut labels = Vec::new();
+    let mut label = Vec::new();
+    let mut absolute = false;
+    let decoded = decode_escapes(text)?;
+    for (position, (byte, escaped)) in decoded.iter().enumerate() {
+        if *byte == b'.' && !*escaped {
+            if label.is_empty() {
+                return Err(format!("{} contains an empty label", text));
+            }
+            labels.push(finish_label(std::mem::take(&mut label))?);
+            absolute = position == decoded.len() - 1;
+        } else {
+            label.push(*byte);
+        }
+    }
+    if !label.is_empty() {
+        labels.push(finish_label(label)?);
+    }
+    Ok((labels, absolute))
+}
+
+fn finish_label(label: Vec<u8>) -> Result<String, String> {
+    if label.len() > MAXIMUM_LABEL_LENGTH {
+        return Err(String::from("Labels can't be longer than 63 bytes"));
+    }
+    String::from_utf8(label).map_err(|_| String::from("Labels must be valid UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn origin() -> Option<DomainName<'static>> {
+        Some(DomainName::new(vec!["example", "com"]))
+    }
+
+    #[test]
+    fn test_parse_zone_file() {
+        let mut parser = ZoneParser::new(None);
+        let resources = parser.parse_file("zones/example.com.zone").unwrap();
+        let apex = DomainName::new(vec!["example", "com"]);
+        let soa = &resources[0];
+        assert_eq!(soa.name(), &apex);
+        assert_eq!(soa.time_to_live(), 3600);
+        match soa.payload() {
+            ResourcePayload::StartAuthority {
+                serial,
+                refresh,
+                minimum,
+                ..
+            } => {
+                assert_eq!(*serial, 2021020201);
+                assert_eq!(*refresh, 7200);
+                assert_eq!(*minimum, 3600);
+            }
+            other => panic!("Expected an SOA but found {:?}", other),
+        }
+        // The second name server omits the owner so inherits the apex
+        assert_eq!(resources[2].name(), &apex);
+        assert_eq!(resources[2].resource_type(), ResourceType::NameServer);
+        let www = resources
+            .iter()
+            .find(|resource| resource.name() == &DomainName::new(vec!["www", "example", "com"]))
+            .unwrap();
+        assert_eq!(www.time_to_live(), 300);
+        assert_eq!(
+            www.payload(),
+            &ResourcePayload::Address(Ipv4Addr::new(192, 0, 2, 10))
+        );
+        // Records from the include use the origin given to $INCLUDE
+        let internal = resources
+            .iter()
+            .find(|resource| {
+                resource.name() == &DomainName::new(vec!["db", "internal", "example", "com"])
+            })
+            .unwrap();
+        assert_eq!(
+            internal.payload(),
+            &ResourcePayload::Address(Ipv4Addr::new(10, 0, 0, 5))
+        );
+        // The origin reverts once the include is finished
+        let last = resources.last().unwrap();
+        assert_eq!(
+            last.name(),
+            &DomainName::new(vec!["after", "example", "com"])
+        );
+    }
+
+    #[test]
+    fn test_parse_text_strings() {
+        let zone = "$TTL 60\n@ TXT \"v=spf1 -all\" \"say \\\"hi\\\"\" plain\\032text ; comment\n";
+        let mut parser = ZoneParser::new(origin());
+        let resources = parser.parse_str(zone, "text.zone").unwrap();
+        let expected = vec![
+            Cow::Borrowed(&b"v=spf1 -all"[..]),
+            Cow::Borrowed(&b"say \"hi\""[..]),
+            Cow::Borrowed(&b"plain text"[..]),
+        ];
+        assert_eq!(
+            resources[0].payload(),
+            &ResourcePayload::TextStrings(expected)
+        );
+    }
+
+    #[test]
+    fn test_parse_parentheses_and_ttl_units() {
+        let zone = "@ 1h IN SOA ns1 hostmaster ( 1 ; serial\n 2h 30m\n 1w\n 1d )\n";
+        let mut parser = ZoneParser::new(origin());
+        let resources = parser.parse_str(zone, "soa.zone").unwrap();
+        assert_eq!(resources.len(), 1);
+        assert_eq!(resources[0].time_to_live(), 3600);
+        match resources[0].payload() {
+            ResourcePayload::StartAuthority {
+                primary_name_server,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                ..
+            } => {
+                assert_eq!(
+                    primary_name_server,
+                    &DomainName::new(vec!["ns1", "example", "com"])
+                );
+                assert_eq!(*refresh, 7200);
+                assert_eq!(*retry, 1800);
+                assert_eq!(*expire, 604800);
+                assert_eq!(*minimum, 86400);
+            }
+            other => panic!("Expected an SOA but found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_generic_rdata() {
+        let zone = "$TTL 60\na TYPE1 \\# 4 C0000201\nb TYPE731 \\# 3 ABCDEF\nc CLASS1 MX \\# 4 000A 0000\nd CLASS731 TYPE731 \\# 0\n";
+        let mut parser = ZoneParser::new(origin());
+        let resources = parser.parse_str(zone, "generic.zone").unwrap();
+        assert_eq!(
+            resources[0].payload(),
+            &ResourcePayload::Address(Ipv4Addr::new(192, 0, 2, 1))
+        );
+        assert_eq!(
+            resources[1].payload(),
+            &ResourcePayload::Unknown {
+                resource_type: 731,
+                data: Cow::Owned(vec![0xAB, 0xCD, 0xEF])
+            }
+        );
+        assert_eq!(
+            resources[2].payload(),
+            &ResourcePayload::MailExchange {
+                preference: 10,
+                exchange: DomainName::root()
+            }
+        );
+        // The number of a class without a mnemonic is kept
+        assert_eq!(resources[3].class(), ResourceClass::Unknown(731));
+    }
+
+    #[test]
+    fn test_parse_service_records() {
+        let zone = "$TTL 60\n_sip._udp SRV 10 60 5060 sip\n@ NAPTR 100 10 \"S\" \"SIP+D2U\" \"\" _sip._udp\n_ftp._tcp URI 10 1 \"ftp://ftp1.example.com/public\"\n";
+        let mut parser = ZoneParser::new(origin());
+        let resources = parser.parse_str(zone, "service.zone").unwrap();
+        assert_eq!(
+            resources[0].payload(),
+            &ResourcePayload::Service {
+                priority: 10,
+                weight: 60,
+                port: 5060,
+                target: DomainName::new(vec!["sip", "example", "com"])
+            }
+        );
+        assert_eq!(
+            resources[1].payload(),
+            &ResourcePayload::NamingAuthorityPointer {
+                order: 100,
+                preference: 10,
+                flags: Cow::Borrowed(b"S"),
+                services: Cow::Borrowed(b"SIP+D2U"),
+                regular_expression: Cow::Borrowed(b""),
+                replacement: DomainName::new(vec!["_sip", "_udp", "example", "com"])
+            }
+        );
+        assert_eq!(
+            resources[2].to_string(),
+            "_ftp._tcp.example.com.\t60\tIN\tURI\t10 1 \"ftp://ftp1.example.com/public\""
+        );
+    }
+
+    #[test]
+    fn test_parse_security_records() {
+        let zone = concat!(
+            "$TTL 60\n",
+            "@ CAA 0 issue \"ca.example.net; account=230123\"\n",
+            "@ CAA 128 iodef \"mailto:security@example.com\"\n",
+            "_443._tcp.www TLSA 3 1 1 ( 0C72AC70B745AC19998811B131D662C9\n",
+            "    AC69DBDBE7CB23E5B514B56664C5D3D6 )\n",
+            "host SSHFP 4 2 123456789abcdef67890123456789abcdef67890123456789abcdef123456789\n",
+            "key OPENPGPKEY AQID BAUG\n",
+            "mail SMIMEA 3 0 1 ABCDEF\n",
+        );
+        let mut parser = ZoneParser::new(origin());
+        let resources = parser.parse_str(zone, "security.zone").unwrap();
+        assert_eq!(
+            resources[0].payload(),
+            &ResourcePayload::CertificationAuthorityAuthorization {
+                flags: 0,
+                tag: Cow::Borrowed(b"issue"),
+                value: Cow::Borrowed(b"ca.example.net; account=230123"),
+            }
+        );
+        match resources[2].payload() {
+            ResourcePayload::TlsAssociation {
+                certificate_usage,
+                selector,
+                matching_type,
+                certificate_data,
+            } => {
+                assert_eq!((*certificate_usage, *selector, *matching_type), (3, 1, 1));
+                assert_eq!(certificate_data.len(), 32);
+            }
+            other => panic!("Expected TLSA but found {:?}", other),
+        }
+        assert_eq!(
+            resources[4].payload(),
+            &ResourcePayload::OpenPgpKey(Cow::Borrowed(&[1, 2, 3, 4, 5, 6]))
+        );
+        assert_eq!(
+            resources[3].to_string(),
+            "host.example.com.\t60\tIN\tSSHFP\t4 2 123456789ABCDEF67890123456789ABCDEF67890123456789ABCDEF123456789"
+        );
+        let text: String = resources
+            .iter()
+            .map(|resource| format!("{}\n", resource))
+            .collect();
+        let reread = ZoneParser::new(None)
+            .parse_str(&text, "printed.zone")
+            .unwrap();
+        assert_eq!(resources, reread);
+        assert!(parser
+            .parse_str("@ CAA 0 is-sue \"ca.example.net\"\n", "tag.zone")
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_dnssec_records() {
+        // Examples from RFC 4034 and RFC 5155
+        let zone = concat!(
+            "$ORIGIN example.com.\n",
+            "$TTL 86400\n",
+            "dskey DNSKEY 256 3 5 ( AQOeiiR0GOMYkDshWoSKz9Xz\n",
+            "    fwJr1AYtsmx3TGkJaNXVbfi/ 2pHm822aJ5iI9BMzNXxeYCmZ\n",
+            "    DRD99WYwYqUSdjMmmAphXdvx egXd/M5+X7OrzKBaMbCVdFLU\n",
+            "    Uh6DhweJBjEVv5f2wwjM9Xzc nOf+EPbtG9DMBmADjFDc2w/r\n",
+            "    ljwvFw== ) ; key id = 60485\n",
+            "dskey DS 60485 5 1 ( 2BB183AF5F22588179A53B0A 98631FAD1A292118 )\n",
+            "host RRSIG A 5 3 86400 20030322173103 ( 20030220173103 2642 example.com.\n",
+            "    oJB1W6WNGv+ldvQ3WDG0MQkg5IEhjRip8WTr PYGv07h108dUKGMeDPKijVCHX3DDKdfb+v6o\n",
+            "    B9wfuh3DTJXUAfI/M0zmO/zz8bW0Rznl8O3t GNazPwQKkRN20XPXV6nwwfoXmJQbsLNrLfkG\n",
+            "    J5D6fwFm8nN+6pBzeDQfsS3Ap3o= )\n",
+            "alfa NSEC host.example.com. ( A MX RRSIG NSEC TYPE1234 )\n",
+            "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom NSEC3 1 1 12 aabbccdd (\n",
+            "    2t7b4g4vsa5smi47k61mv5bv1a22bojr MX DNSKEY NS SOA NSEC3PARAM RRSIG )\n",
+            "@ NSEC3PARAM 1 0 12 aabbccdd\n",
+            "@ CDS 0 0 0 00\n",
+        );
+        let mut parser = ZoneParser::new(None);
+        let resources = parser.parse_str(zone, "dnssec.zone").unwrap();
+        assert_eq!(resources[0].payload().key_tag(), Some(60485));
+        match resources[1].payload() {
+            ResourcePayload::DelegationSigner {
+                key_tag, digest, ..
+            } => {
+                assert_eq!(*key_tag, 60485);
+                assert_eq!(digest.len(), 20);
+            }
+            other => panic!("Expected DS but found {:?}", other),
+        }
+        match resources[2].payload() {
+            ResourcePayload::ResourceSignature {
+                type_covered,
+                expiration,
+                inception,
+                signer_name,
+                ..
+            } => {
+                assert_eq!(*type_covered, 1);
+                assert_eq!(*expiration, 1048354263);
+                assert_eq!(*inception, 1045762263);
+                assert_eq!(signer_name, &DomainName::new(vec!["example", "com"]));
+            }
+            other => panic!("Expected RRSIG but found {:?}", other),
+        }
+        match resources[3].payload() {
+            ResourcePayload::NextSecure { types, .. } => {
+                assert_eq!(types.types(), &[1, 15, 46, 47, 1234]);
+            }
+            other => panic!("Expected NSEC but found {:?}", other),
+        }
+        match resources[4].payload() {
+            ResourcePayload::NextSecure3 {
+                iterations,
+                salt,
+                next_hashed_owner,
+                types,
+                ..
+            } => {
+                assert_eq!(*iterations, 12);
+                assert_eq!(salt.as_ref(), &[0xaa, 0xbb, 0xcc, 0xdd]);
+                assert_eq!(next_hashed_owner.len(), 20);
+                assert!(types.contains(51));
+            }
+            other => panic!("Expected NSEC3 but found {:?}", other),
+        }
+        assert_eq!(
+            resources[2].payload().to_string().split(' ').nth(4),
+            Some("20030322173103")
+        );
+        assert!(resources[4]
+            .payload()
+            .to_string()
+            .contains("2T7B4G4VSA5SMI47K61MV5BV1A22BOJR NS SOA MX RRSIG DNSKEY NSEC3PARAM"));
+        let text: String = resources
+            .iter()
+            .map(|resource| format!("{}\n", resource))
+            .collect();
+        let reread = ZoneParser::new(None)
+            .parse_str(&text, "printed.zone")
+            .unwrap();
+        assert_eq!(resources, reread);
+    }
+
+    #[test]
+    fn test_parse_service_binding() {
+        let zone = concat!(
+            "$TTL 60\n",
+            "@ HTTPS 0 pool.svc\n",
+            "svc SVCB 16 foo.example.org. ( alpn=h2,h3-19 mandatory=ipv4hint,alpn\n",
+            "    ipv4hint=192.0.2.1 )\n",
+            "escaped SVCB 1 foo.example.org. key667=\"hello\\210qoo\" alpn=\"f\\\\\\\\oo\\\\,bar,h2\"\n",
+        );
+        let mut parser = ZoneParser::new(origin());
+        let resources = parser.parse_str(zone, "binding.zone").unwrap();
+        match resources[0].payload() {
+            ResourcePayload::HttpsBinding(binding) => {
+                assert!(binding.is_alias());
+                assert!(binding.parameters().is_empty());
+            }
+            other => panic!("Expected HTTPS but found {:?}", other),
+        }
+        match resources[1].payload() {
+            ResourcePayload::ServiceBinding(binding) => {
+                assert_eq!(
+                    binding.parameters(),
+                    &[
+                        ServiceParameter::Mandatory(vec![1, 4]),
+                        ServiceParameter::Alpn(vec![
+                            Cow::Borrowed(&b"h2"[..]),
+                            Cow::Borrowed(&b"h3-19"[..])
+                        ]),
+                        ServiceParameter::Ipv4Hint(vec![Ipv4Addr::new(192, 0, 2, 1)]),
+                    ]
+                );
+            }
+            other => panic!("Expected SVCB but found {:?}", other),
+        }
+        // The escaping example from RFC 9460 appendix D
+        match resources[2].payload() {
+            ResourcePayload::ServiceBinding(binding) => {
+                assert_eq!(
+                    binding.parameters(),
+                    &[
+                        ServiceParameter::Alpn(vec![
+                            Cow::Borrowed(&b"f\\oo,bar"[..]),
+                            Cow::Borrowed(&b"h2"[..])
+                        ]),
+                        ServiceParameter::Unknown {
+                            key: 667,
+                            value: Cow::Borrowed(&b"hello\xd2qoo"[..])
+                        },
+                    ]
+                );
+            }
+            other => panic!("Expected SVCB but found {:?}", other),
+        }
+        // Printing and parsing again gives the same resources
+        let text: String = resources
+            .iter()
+            .map(|resource| format!("{}\n", resource))
+            .collect();
+        let reread = ZoneParser::new(None)
+            .parse_str(&text, "printed.zone")
+            .unwrap();
+        assert_eq!(resources, reread);
+        assert!(text.contains("alpn=\"h2,h3-19\""));
+        // Parameters can't repeat and mandatory keys must be present
+        assert!(parser
+            .parse_str("@ SVCB 1 . port=1 port=2\n", "repeat.zone")
+            .is_err());
+        assert!(parser
+            .parse_str("@ SVCB 1 . mandatory=port\n", "mandatory.zone")
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_escaped_name() {
+        let zone = "$TTL 60\nfirst\\.last.people A 192.0.2.1\n";
+        let mut parser = ZoneParser::new(origin());
+        let resources = parser.parse_str(zone, "escaped.zone").unwrap();
+        assert_eq!(
+            resources[0].name(),
+            &DomainName::new(vec!["first.last", "people", "example", "com"])
+        );
+    }
+
+    #[test]
+    fn test_error_reports_file_and_line() {
+        let zone = "$TTL 60\n@ A 192.0.2.1\n\nwww A 192.0.2.300\n";
+        let mut parser = ZoneParser::new(origin());
+        let error = parser.parse_str(zone, "broken.zone").unwrap_err();
+        match error.kind() {
+            ErrorKind::InvalidZoneFile { file, line, .. } => {
+                assert_eq!(file, "broken.zone");
+                assert_eq!(*line, 4);
+            }
+            other => panic!("Unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_missing_ttl() {
+        let mut parser = ZoneParser::new(origin());
+        assert!(parser.parse_str("www A 192.0.2.1\n", "ttl.zone").is_err());
+    }
+
+    #[test]
+    fn test_unbalanced_parentheses() {
+        let mut parser = ZoneParser::new(origin());
+        let error = parser
+            .parse_str("$TTL 60\n@ MX ( 10\n mail\n", "paren.zone")
+            .unwrap_err();
+        match error.kind() {
+            ErrorKind::InvalidZoneFile { line, .. } => assert_eq!(*line, 2),
+            other => panic!("Unexpected error {:?}", other),
+        }
+    }
+}
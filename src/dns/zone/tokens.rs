@@ -0,0 +1,134 @@
+use super::{Entry, Token, Tokenizer};
+
+impl<'a> Tokenizer<'a> {
+    pub fn new(text: &'a str) -> Tokenizer<'a> {
+        Tokenizer {
+            characters: text.chars().peekable(),
+            line: 1,
+        }
+    }
+
+    /// Returns the next entry with at least one token, errors are returned as the line and reason
+    pub fn next_entry(&mut self) -> Option<Result<Entry, (usize, String)>> {
+        let mut tokens = Vec::new();
+        let mut parentheses = 0;
+        let mut entry_line = self.line;
+        let mut starts_with_blank = false;
+        let mut at_line_start = true;
+        while let Some(&character) = self.characters.peek() {
+            if at_line_start && parentheses == 0 && tokens.is_empty() {
+                // Only the first line of an entry decides if the owner was omitted
+                entry_line = self.line;
+                starts_with_blank = character == ' ' || character == '\t';
+            }
+            at_line_start = false;
+            match character {
+                '\n' => {
+                    self.characters.next();
+                    self.line += 1;
+                    at_line_start = true;
+                    if parentheses == 0 && !tokens.is_empty() {
+                        break;
+                    }
+                }
+                ' ' | '\t' | '\r' => {
+                    self.characters.next();
+                }
+                ';' => {
+                    // Comments run to the end of the line, the newline still ends the entry
+                    while let Some(&comment) = self.characters.peek() {
+                        if comment == '\n' {
+                            break;
+                        }
+                        self.characters.next();
+                    }
+                }
+                '(' => {
+                    self.characters.next();
+                    parentheses += 1;
+                }
+                ')' => {
+                    self.characters.next();
+                    if parentheses == 0 {
+                        return Some(Err((
+                            self.line,
+                            String::from("Unbalanced closing parenthesis"),
+                        )));
+                    }
+                    parentheses -= 1;
+                }
+                '"' => {
+                    self.characters.next();
+                    match self.read_quoted() {
+                        Ok(text) => tokens.push(Token { text, quoted: true }),
+                        Err(reason) => return Some(Err((self.line, reason))),
+                    }
+                }
+                _ => {
+                    let text = self.read_unquoted();
+                    tokens.push(Token {
+                        text,
+                        quoted: false,
+                    });
+                }
+            }
+        }
+        if parentheses > 0 {
+            return Some(Err((
+                entry_line,
+                String::from("Parenthesis was never closed"),
+            )));
+        }
+        if tokens.is_empty() {
+            return None;
+        }
+        Some(Ok(Entry {
+            tokens,
+            line: entry_line,
+            starts_with_blank,
+        }))
+    }
+
+    fn read_unquoted(&mut self) -> String {
+        let mut text = String::new();
+        while let Some(&character) = self.characters.peek() {
+            match character {
+                ' ' | '\t' | '\r' | '\n' | ';' | '(' | ')' | '"' => break,
+                '\\' => {
+                    // Keep the escape so the field can be decoded later, the escaped character never ends the token
+                    self.characters.next();
+                    text.push('\\');
+                    if let Some(escaped) = self.characters.next() {
+                        text.push(escaped);
+                    }
+                }
+                _ => {
+                    self.characters.next();
+                    text.push(character);
+                }
+            }
+        }
+        text
+    }
+
+    fn read_quoted(&mut self) -> Result<String, String> {
+        let mut text = String::new();
+        while let Some(character) = self.characters.next() {
+            match character {
+                '"' => return Ok(text),
+                '\n' => break,
+                '\\' => {
+                    text.push('\\');
+                    match self.characters.next() {
+                        Some('\n') | None => break,
+                        Some(escaped) => text.push(escaped),
+                    }
+                }
+                _ => text.push(character),
+            }
+        }
+        Err(String::from(
+            "Quoted string was not terminated on the same line",
+        ))
+    }
+}
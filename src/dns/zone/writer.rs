@@ -0,0 +1,225 @@
+use super::ZoneWriter;
+use crate::dns::{
+    presentation::{class_mnemonic, type_mnemonic},
+    DomainName, Presentation, Resource, ResourceType,
+};
+use crate::error::{Error, ErrorKind};
+use std::{cmp::Ordering, io::Write, path::Path};
+
+impl ZoneWriter {
+    /// Creates a writer, names at or below the origin are written relative to it
+    pub fn new(origin: Option<DomainName<'static>>) -> ZoneWriter {
+        ZoneWriter {
+            origin,
+            default_ttl: None,
+        }
+    }
+
+    /// Writes a $TTL directive, resources using this TTL will leave it out
+    pub fn default_ttl(&mut self, ttl: u32) -> &mut Self {
+        self.default_ttl = Some(ttl);
+        self
+    }
+
+    pub fn write_file<P: AsRef<Path>>(&self, resources: &[Resource], path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let write_failed =
+            || Error::new(ErrorKind::ZoneFileWriteFailed(path.display().to_string()));
+        let mut file = std::fs::File::create(path).map_err(|_| write_failed())?;
+        self.write_zone(resources, &mut file)
+            .map_err(|_| write_failed())?;
+        file.flush().map_err(|_| write_failed())
+    }
+
+    /// Writes resources grouped into RRsets, ordered by name with the SOA first at each name
+    pub fn write_zone<W: Write>(
+        &self,
+        resources: &[Resource],
+        writer: &mut W,
+    ) -> Result<(), Error> {
+        let zone_name = self
+            .origin
+            .as_ref()
+            .map(ToString::to_string)
+            .unwrap_or_else(|| String::from("without an origin"));
+        let write_failed = |_| Error::new(ErrorKind::ZoneFileWriteFailed(zone_name.clone()));
+        let mut ordered: Vec<&Resource> = resources.iter().collect();
+        ordered.sort_by(|first, second| Self::compare_resources(first, second));
+
+        // Each row is owner, TTL, class, type and then the data which is not padded
+        let origin = self.origin.as_ref();
+        let mut rows = Vec::with_capacity(ordered.len());
+        let mut previous_owner: Option<&DomainName> = None;
+        for resource in ordered {
+            // The owner is only written when it changes, the following resources inherit it
+            let owner = match previous_owner {
+                Some(previous) if previous.eq_ignore_case(resource.name()) => String::new(),
+                _ => Presentation::new(resource.name(), origin).to_string(),
+            };
+            previous_owner = Some(resource.name());
+            let ttl = match self.default_ttl {
+                Some(ttl) if ttl == resource.time_to_live() => String::new(),
+                _ => resource.time_to_live().to_string(),
+            };
+            rows.push([
+                owner,
+                ttl,
+                class_mnemonic(resource.class()).into_owned(),
+                type_mnemonic(resource.payload().type_code()).into_owned(),
+                Presentation::new(resource.payload(), origin).to_string(),
+            ]);
+        }
+
+        let mut widths = [0; 4];
+        for row in &rows {
+            for (width, column) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(column.len());
+            }
+        }
+
+        if let Some(origin) = origin {
+            writeln!(writer, "$ORIGIN {}", origin).map_err(write_failed)?;
+        }
+        if let Some(ttl) = self.default_ttl {
+            writeln!(writer, "$TTL {}", ttl).map_err(write_failed)?;
+        }
+        for [owner, ttl, class, resource_type, data] in &rows {
+            writeln!(
+                writer,
+                "{:<owner_width$} {:<ttl_width$} {:<class_width$} {:<type_width$} {}",
+                owner,
+                ttl,
+                class,
+                resource_type,
+                data,
+                owner_width = widths[0],
+                ttl_width = widths[1],
+                class_width = widths[2],
+                type_width = widths[3],
+            )
+            .map_err(write_failed)?;
+        }
+        Ok(())
+    }
+
+    fn compare_resources(first: &Resource, second: &Resource) -> Ordering {
+        // false orders before true so the SOA is placed first
+        let not_authority =
+            |resource: &Resource| resource.resource_type() != ResourceType::StartAuthority;
+        first
+            .name()
+            .canonical_cmp(second.name())
+            .then_with(|| not_authority(first).cmp(&not_authority(second)))
+            .then_with(|| {
+                first
+                    .payload()
+                    .type_code()
+                    .cmp(&second.payload().type_code())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{zone::ZoneParser, ResourceClass, ResourcePayload};
+    use std::{borrow::Cow, net::Ipv4Addr};
+
+    fn origin() -> DomainName<'static> {
+        DomainName::new(vec!["example", "com"])
+    }
+
+    fn sorted(mut resources: Vec<Resource<'static>>) -> Vec<Resource<'static>> {
+        resources.sort_by_key(|resource| resource.to_string());
+        resources
+    }
+
+    #[test]
+    fn test_round_trip_zone() {
+        let resources = ZoneParser::new(None)
+            .parse_file("zones/example.com.zone")
+            .unwrap();
+        let mut output = Vec::new();
+        ZoneWriter::new(Some(origin()))
+            .default_ttl(3600)
+            .write_zone(&resources, &mut output)
+            .unwrap();
+        let text = String::from_utf8(output).unwrap();
+        let reread = ZoneParser::new(None)
+            .parse_str(&text, "written.zone")
+            .unwrap();
+        assert_eq!(sorted(resources), sorted(reread));
+        // The SOA comes first and repeated owners are left blank
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "$ORIGIN example.com.");
+        assert!(lines[2].starts_with("@"));
+        assert!(lines[2].contains("SOA"));
+        assert!(lines[3].starts_with(' '));
+    }
+
+    #[test]
+    fn test_escaped_round_trip() {
+        let resources = vec![
+            Resource::new(
+                DomainName::new(vec!["first.last", "$people", "example", "com"]),
+                ResourceClass::Internet,
+                60,
+                ResourcePayload::TextStrings(vec![
+                    Cow::Borrowed(&b"quote \" and \\ slash"[..]),
+                    Cow::Borrowed(&b""[..]),
+                    Cow::Borrowed(&b"\x07bell"[..]),
+                ]),
+            ),
+            Resource::new(
+                DomainName::new(vec!["unknown", "example", "com"]),
+                ResourceClass::Internet,
+                60,
+                ResourcePayload::Unknown {
+                    resource_type: 65280,
+                    data: Cow::Borrowed(&[1, 2, 3][..]),
+                },
+            ),
+            Resource::new(
+                DomainName::new(vec!["private", "example", "com"]),
+                ResourceClass::from(65280),
+                60,
+                ResourcePayload::Unknown {
+                    resource_type: 65280,
+                    data: Cow::Borrowed(&[][..]),
+                },
+            ),
+        ];
+        let mut output = Vec::new();
+        ZoneWriter::new(Some(origin()))
+            .write_zone(&resources, &mut output)
+            .unwrap();
+        let text = String::from_utf8(output).unwrap();
+        assert!(text.contains("first\\.last.\\$people"));
+        assert!(text.contains("TYPE65280"));
+        assert!(text.contains("CLASS65280"));
+        assert!(text.contains("\\# 3 010203"));
+        let reread = ZoneParser::new(None)
+            .parse_str(&text, "escaped.zone")
+            .unwrap();
+        assert_eq!(sorted(resources), sorted(reread));
+    }
+
+    #[test]
+    fn test_dig_style_display() {
+        let resource = Resource::new(
+            DomainName::new(vec!["www", "example", "com"]),
+            ResourceClass::Internet,
+            300,
+            ResourcePayload::Address(Ipv4Addr::new(192, 0, 2, 10)),
+        );
+        assert_eq!(
+            resource.to_string(),
+            "www.example.com.\t300\tIN\tA\t192.0.2.10"
+        );
+        let origin = origin();
+        assert_eq!(
+            Presentation::new(&resource, Some(&origin)).to_string(),
+            "www\t300\tIN\tA\t192.0.2.10"
+        );
+    }
+}
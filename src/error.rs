@@ -1,9 +1,45 @@
+use crate::dns::ResponseCode;
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ErrorKind {
     ExceededPacketSize,
     ReadPacketDataFailed,
     WritePacketDataFailed,
     InvalidLabel,
+    // A zone file could not be parsed, records the file and line the problem was found on
+    InvalidZoneFile {
+        file: String,
+        line: usize,
+        reason: String,
+    },
+    // A zone file or one of its includes could not be read
+    ZoneFileReadFailed(String),
+    // A zone file could not be written
+    ZoneFileWriteFailed(String),
+    // A trust anchor file contained something other than DS or DNSKEY records
+    InvalidTrustAnchor(String),
+    // A signing key could not be read or does not match its algorithm
+    InvalidSigningKey(String),
+    // Zone data could not be signed
+    SigningFailed,
+    // A keyring file could not be read or holds an invalid key
+    InvalidKeyring(String),
+    // A signed message was expected but there was no TSIG record
+    MissingTransactionSignature,
+    // The TSIG of a message was rejected, holds BADKEY, BADSIG, BADTIME or BADTRUNC
+    TransactionSignatureFailed(ResponseCode),
+    // A zone is missing its SOA or an update would move its serial backwards
+    InvalidZone(String),
+    // A TCP connection could not be made or broke off part way through a message
+    ConnectionFailed(String),
+    // A zone transfer was refused or the primary sent something that isn't a valid transfer
+    TransferFailed(String),
+    // A secondary didn't acknowledge a NOTIFY or answered it with an error
+    NotifyFailed(String),
+    // A command line option is missing its value or the value can't be used
+    InvalidConfiguration(String),
+    // An HTTP request could not be parsed or an HTTP/2 peer broke the protocol
+    HttpFailed(String),
 }
 #[derive(Debug)]
 pub struct Error {
@@ -14,15 +50,36 @@ impl Error {
     pub fn new(kind: ErrorKind) -> Error {
         Error { kind }
     }
+
+    // Only the tests look at the kind, the proxy displays its errors
+    #[cfg(test)]
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self.kind {
+        match &self.kind {
             ErrorKind::ExceededPacketSize => write!(f, "While trying to read from a raw dns packet, the 512 byte length was exceeded"),
             ErrorKind::ReadPacketDataFailed => write!(f, "Failed to read packet data, this is caused by an underlying io error"),
             ErrorKind::WritePacketDataFailed => write!(f, "Failed to write packet data when creating a DNS packet"),
             ErrorKind::InvalidLabel => write!(f, "A label can consist of only letters, numbers and a hyphen, it must start with a letter and end in a letter or number"),
+            ErrorKind::InvalidZoneFile { file, line, reason } => write!(f, "{}:{}: {}", file, line, reason),
+            ErrorKind::ZoneFileReadFailed(file) => write!(f, "Failed to read the zone file {}", file),
+            ErrorKind::ZoneFileWriteFailed(file) => write!(f, "Failed to write the zone file {}", file),
+            ErrorKind::InvalidTrustAnchor(reason) => write!(f, "Invalid trust anchor: {}", reason),
+            ErrorKind::InvalidSigningKey(reason) => write!(f, "Invalid signing key: {}", reason),
+            ErrorKind::SigningFailed => write!(f, "Failed to sign zone data"),
+            ErrorKind::InvalidKeyring(reason) => write!(f, "Invalid keyring: {}", reason),
+            ErrorKind::MissingTransactionSignature => write!(f, "The message is not signed with TSIG"),
+            ErrorKind::TransactionSignatureFailed(code) => write!(f, "TSIG verification failed with {:?}", code),
+            ErrorKind::InvalidZone(reason) => write!(f, "Invalid zone: {}", reason),
+            ErrorKind::ConnectionFailed(reason) => write!(f, "Connection failed: {}", reason),
+            ErrorKind::TransferFailed(reason) => write!(f, "Zone transfer failed: {}", reason),
+            ErrorKind::NotifyFailed(reason) => write!(f, "NOTIFY failed: {}", reason),
+            ErrorKind::InvalidConfiguration(reason) => write!(f, "Invalid configuration: {}", reason),
+            ErrorKind::HttpFailed(reason) => write!(f, "HTTP failed: {}", reason),
         }
     }
 }
@@ -1 +1,179 @@
+use std::convert::TryFrom;
 
+/// Decodes a string of hexadecimal digits into bytes, whitespace between digits is not allowed
+pub fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let high = (pair[0] as char).to_digit(16)?;
+            let low = (pair[1] as char).to_digit(16)?;
+            Some((high * 16 + low) as u8)
+        })
+        .collect()
+}
+
+/// Encodes bytes as upper case hexadecimal digits
+pub fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes bytes using the standard base64 alphabet with padding
+pub fn encode_base64(data: &[u8]) -> String {
+    let mut text = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let group = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for index in 0..4 {
+            if index <= chunk.len() {
+                let sextet = (group >> (18 - index * 6)) & 0b111111;
+                text.push(BASE64_ALPHABET[sextet as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+/// Decodes standard base64 with padding, whitespace is not allowed
+pub fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut data = Vec::with_capacity(text.len() / 4 * 3);
+    let groups = text.as_bytes().chunks(4);
+    let total_groups = groups.len();
+    for (group_index, group) in groups.enumerate() {
+        let padding = group.iter().rev().take_while(|&&byte| byte == b'=').count();
+        // Padding can only end the final group and never replaces more than two characters
+        if padding > 2 || (padding > 0 && group_index + 1 != total_groups) {
+            return None;
+        }
+        let mut value = 0u32;
+        for &byte in &group[..4 - padding] {
+            let sextet = BASE64_ALPHABET.iter().position(|&letter| letter == byte)?;
+            value = value << 6 | sextet as u32;
+        }
+        value <<= 6 * padding;
+        let bytes = value.to_be_bytes();
+        data.extend_from_slice(&bytes[1..4 - padding]);
+    }
+    Some(data)
+}
+
+const BASE32_HEX_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHIJKLMNOPQRSTUV";
+
+/// Encodes bytes using the base32 extended hex alphabet without padding, as used for NSEC3 hashes
+pub fn encode_base32_hex(data: &[u8]) -> String {
+    let mut text = String::with_capacity(data.len().div_ceil(5) * 8);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for byte in data {
+        buffer = buffer << 8 | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            text.push(BASE32_HEX_ALPHABET[(buffer >> bits) as usize & 0b11111] as char);
+        }
+    }
+    if bits > 0 {
+        text.push(BASE32_HEX_ALPHABET[(buffer << (5 - bits)) as usize & 0b11111] as char);
+    }
+    text
+}
+
+/// Decodes base32 extended hex without padding, the comparison is case insensitive
+pub fn decode_base32_hex(text: &str) -> Option<Vec<u8>> {
+    let mut data = Vec::with_capacity(text.len() * 5 / 8);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for character in text.bytes() {
+        let value = BASE32_HEX_ALPHABET
+            .iter()
+            .position(|&letter| letter == character.to_ascii_uppercase())?;
+        buffer = buffer << 5 | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            data.push((buffer >> bits) as u8);
+        }
+    }
+    // Any bits left over are padding and must be zero
+    if buffer & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+    Some(data)
+}
+
+/// Converts seconds since the epoch to a YYYYMMDDHHmmSS timestamp in UTC, as used by RRSIG
+pub fn format_timestamp(seconds: u32) -> String {
+    let days = seconds / 86400;
+    let remaining = seconds % 86400;
+    let (year, month, day) = civil_from_days(days as i64);
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        remaining / 3600,
+        remaining % 3600 / 60,
+        remaining % 60
+    )
+}
+
+/// Reads a YYYYMMDDHHmmSS timestamp in UTC, returning seconds since the epoch
+pub fn parse_timestamp(text: &str) -> Option<u32> {
+    if text.len() != 14 || !text.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let field = |range: std::ops::Range<usize>| text[range].parse::<i64>().ok();
+    let (year, month, day) = (field(0..4)?, field(4..6)?, field(6..8)?);
+    let (hour, minute, second) = (field(8..10)?, field(10..12)?, field(12..14)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    if second > 59 {
+        return None;
+    }
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    u32::try_from(seconds).ok()
+}
+
+// Howard Hinnant's algorithms for converting between days since the epoch and the proleptic Gregorian calendar
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
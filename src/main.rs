@@ -1,8 +1,51 @@
 mod dns;
 mod error;
 mod helper;
+use dns::{Proxy, ProxyConfig};
+
 fn main() {
-    println!("Server Up");
-    // When we recieve a raw dns packet we can .as_dns() to create a DnsPacket
-    // Remove RawPacket and just create DnsPacket
+    let config = match ProxyConfig::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("{}", error);
+            eprintln!(
+                "Usage: pp [--listen ADDRESS:PORT] [--upstream ADDRESS:PORT[,WEIGHT]]... \
+                 [--strategy failover|round-robin|weighted|lowest-latency] [--timeout SECONDS] \
+                 [--idle-timeout SECONDS] [--max-connections COUNT] [--max-pipelined COUNT] \
+                 [--probe-name NAME] [--probe-type TYPE] [--probe-interval SECONDS] \
+                 [--probe-timeout SECONDS] [--down-after COUNT] [--up-after COUNT] \
+                 [--randomize-case true|false] [--bailiwick NAME] [--tls-listen ADDRESS:PORT] \
+                 [--tls-certificate FILE --tls-key FILE] [--tls-upstream ADDRESS:PORT#NAME[,WEIGHT]]... \
+                 [--tls-ca FILE] [--tls-pin BASE64]... [--https-listen ADDRESS:PORT] \
+                 [--trust-anchors FILE] [--primary-zone FILE#NAME]... \
+                 [--secondary-zone ADDRESS:PORT#NAME]... [--allow-transfer ADDRESS]... \
+                 [--transfer-keys FILE#NAME] [--notify ADDRESS:PORT]... [--allow-update NAME]... \
+                 [--update-keys FILE#NAME]... [--signing-key FILE#NAME]... \
+                 [--zone-signing-key FILE#NAME]... [--signing-algorithm NUMBER] \
+                 [--signature-lifetime SECONDS] [--denial nsec|nsec3|compact] [--verbose true|false]"
+            );
+            std::process::exit(2);
+        }
+    };
+    let proxy = match Proxy::bind(&config) {
+        Ok(proxy) => proxy,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+    match proxy.local_addr() {
+        Ok(address) => println!("Server Up on {}", address),
+        Err(_) => println!("Server Up"),
+    }
+    if let Some(address) = proxy.tls_local_addr() {
+        println!("DNS over TLS on {}", address);
+    }
+    if let Some(address) = proxy.https_local_addr() {
+        println!("DNS over HTTPS on {}", address);
+    }
+    if let Err(error) = proxy.run() {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
 }